@@ -0,0 +1,541 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::OnceLock;
+
+/**
+ * # 静态表
+ * RFC 7541附录A，下标从1开始
+ */
+const STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+/**
+ * # Huffman编码表
+ * RFC 7541附录B，按符号(0-255，256为EOS)排列的(编码, 位数)
+ */
+const HUFFMAN: [(u32, u8); 257] = [
+    (0x1ff8, 13), (0x7fffd8, 23), (0xfffffe2, 28), (0xfffffe3, 28), (0xfffffe4, 28), (0xfffffe5, 28),
+    (0xfffffe6, 28), (0xfffffe7, 28), (0xfffffe8, 28), (0xffffea, 24), (0x3ffffffc, 30), (0xfffffe9, 28),
+    (0xfffffea, 28), (0x3ffffffd, 30), (0xfffffeb, 28), (0xfffffec, 28), (0xfffffed, 28), (0xfffffee, 28),
+    (0xfffffef, 28), (0xffffff0, 28), (0xffffff1, 28), (0xffffff2, 28), (0x3ffffffe, 30), (0xffffff3, 28),
+    (0xffffff4, 28), (0xffffff5, 28), (0xffffff6, 28), (0xffffff7, 28), (0xffffff8, 28), (0xffffff9, 28),
+    (0xffffffa, 28), (0xffffffb, 28), (0x14, 6), (0x3f8, 10), (0x3f9, 10), (0xffa, 12),
+    (0x1ff9, 13), (0x15, 6), (0xf8, 8), (0x7fa, 11), (0x3fa, 10), (0x3fb, 10),
+    (0xf9, 8), (0x7fb, 11), (0xfa, 8), (0x16, 6), (0x17, 6), (0x18, 6),
+    (0x0, 5), (0x1, 5), (0x2, 5), (0x19, 6), (0x1a, 6), (0x1b, 6),
+    (0x1c, 6), (0x1d, 6), (0x1e, 6), (0x1f, 6), (0x5c, 7), (0xfb, 8),
+    (0x7ffc, 15), (0x20, 6), (0xffb, 12), (0x3fc, 10), (0x1ffa, 13), (0x21, 6),
+    (0x5d, 7), (0x5e, 7), (0x5f, 7), (0x60, 7), (0x61, 7), (0x62, 7),
+    (0x63, 7), (0x64, 7), (0x65, 7), (0x66, 7), (0x67, 7), (0x68, 7),
+    (0x69, 7), (0x6a, 7), (0x6b, 7), (0x6c, 7), (0x6d, 7), (0x6e, 7),
+    (0x6f, 7), (0x70, 7), (0x71, 7), (0x72, 7), (0xfc, 8), (0x73, 7),
+    (0xfd, 8), (0x1ffb, 13), (0x7fff0, 19), (0x1ffc, 13), (0x3ffc, 14), (0x22, 6),
+    (0x7ffd, 15), (0x3, 5), (0x23, 6), (0x4, 5), (0x24, 6), (0x5, 5),
+    (0x25, 6), (0x26, 6), (0x27, 6), (0x6, 5), (0x74, 7), (0x75, 7),
+    (0x28, 6), (0x29, 6), (0x2a, 6), (0x7, 5), (0x2b, 6), (0x76, 7),
+    (0x2c, 6), (0x8, 5), (0x9, 5), (0x2d, 6), (0x77, 7), (0x78, 7),
+    (0x79, 7), (0x7a, 7), (0x7b, 7), (0x7ffe, 15), (0x7fc, 11), (0x3ffd, 14),
+    (0x1ffd, 13), (0xffffffc, 28), (0xfffe6, 20), (0x3fffd2, 22), (0xfffe7, 20), (0xfffe8, 20),
+    (0x3fffd3, 22), (0x3fffd4, 22), (0x3fffd5, 22), (0x7fffd9, 23), (0x3fffd6, 22), (0x7fffda, 23),
+    (0x7fffdb, 23), (0x7fffdc, 23), (0x7fffdd, 23), (0x7fffde, 23), (0xffffeb, 24), (0x7fffdf, 23),
+    (0xffffec, 24), (0xffffed, 24), (0x3fffd7, 22), (0x7fffe0, 23), (0xffffee, 24), (0x7fffe1, 23),
+    (0x7fffe2, 23), (0x7fffe3, 23), (0x7fffe4, 23), (0x1fffdc, 21), (0x3fffd8, 22), (0x7fffe5, 23),
+    (0x3fffd9, 22), (0x7fffe6, 23), (0x7fffe7, 23), (0xffffef, 24), (0x3fffda, 22), (0x1fffdd, 21),
+    (0xfffe9, 20), (0x3fffdb, 22), (0x3fffdc, 22), (0x7fffe8, 23), (0x7fffe9, 23), (0x1fffde, 21),
+    (0x7fffea, 23), (0x3fffdd, 22), (0x3fffde, 22), (0xfffff0, 24), (0x1fffdf, 21), (0x3fffdf, 22),
+    (0x7fffeb, 23), (0x7fffec, 23), (0x1fffe0, 21), (0x1fffe1, 21), (0x3fffe0, 22), (0x1fffe2, 21),
+    (0x7fffed, 23), (0x3fffe1, 22), (0x7fffee, 23), (0x7fffef, 23), (0xfffea, 20), (0x3fffe2, 22),
+    (0x3fffe3, 22), (0x3fffe4, 22), (0x7ffff0, 23), (0x3fffe5, 22), (0x3fffe6, 22), (0x7ffff1, 23),
+    (0x3ffffe0, 26), (0x3ffffe1, 26), (0xfffeb, 20), (0x7fff1, 19), (0x3fffe7, 22), (0x7ffff2, 23),
+    (0x3fffe8, 22), (0x1ffffec, 25), (0x3ffffe2, 26), (0x3ffffe3, 26), (0x3ffffe4, 26), (0x7ffffde, 27),
+    (0x7ffffdf, 27), (0x3ffffe5, 26), (0xfffff1, 24), (0x1ffffed, 25), (0x7fff2, 19), (0x1fffe3, 21),
+    (0x3ffffe6, 26), (0x7ffffe0, 27), (0x7ffffe1, 27), (0x3ffffe7, 26), (0x7ffffe2, 27), (0xfffff2, 24),
+    (0x1fffe4, 21), (0x1fffe5, 21), (0x3ffffe8, 26), (0x3ffffe9, 26), (0xffffffd, 28), (0x7ffffe3, 27),
+    (0x7ffffe4, 27), (0x7ffffe5, 27), (0xfffec, 20), (0xfffff3, 24), (0xfffed, 20), (0x1fffe6, 21),
+    (0x3fffe9, 22), (0x1fffe7, 21), (0x1fffe8, 21), (0x7ffff3, 23), (0x3fffea, 22), (0x3fffeb, 22),
+    (0x1ffffee, 25), (0x1ffffef, 25), (0xfffff4, 24), (0xfffff5, 24), (0x3ffffea, 26), (0x7ffff4, 23),
+    (0x3ffffeb, 26), (0x7ffffe6, 27), (0x3ffffec, 26), (0x3ffffed, 26), (0x7ffffe7, 27), (0x7ffffe8, 27),
+    (0x7ffffe9, 27), (0x7ffffea, 27), (0x7ffffeb, 27), (0xffffffe, 28), (0x7ffffec, 27), (0x7ffffed, 27),
+    (0x7ffffee, 27), (0x7ffffef, 27), (0x7fffff0, 27), (0x3ffffee, 26), (0x3fffffff, 30),];
+
+/**
+ * # 解码错误
+ * 任何一种都说明对端的编码状态已经无法同步，HTTP/2中应当作为连接错误(COMPRESSION_ERROR)处理，
+ * `HeaderListTooLarge`除外
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HpackError {
+    Truncated,
+    IntegerOverflow,
+    InvalidIndex,
+    InvalidHuffman,
+    InvalidTableSize,
+    HeaderListTooLarge,
+}
+
+impl fmt::Display for HpackError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = match self {
+            HpackError::Truncated => "header block is truncated",
+            HpackError::IntegerOverflow => "integer is too large",
+            HpackError::InvalidIndex => "table index is out of range",
+            HpackError::InvalidHuffman => "invalid Huffman code",
+            HpackError::InvalidTableSize => "dynamic table size update exceeds the limit",
+            HpackError::HeaderListTooLarge => "header list is too large",
+        };
+        write!(f, "{}", text)
+    }
+}
+
+/**
+ * Huffman解码树，`children`为0表示没有这个分支(根节点不会是任何节点的子节点)
+ */
+struct Node {
+    children: [u16; 2],
+    symbol: Option<u16>,
+}
+
+fn huffman_tree() -> &'static [Node] {
+    static TREE: OnceLock<Vec<Node>> = OnceLock::new();
+    TREE.get_or_init(|| {
+        let mut tree = vec![Node { children: [0, 0], symbol: None }];
+        for (symbol, &(code, len)) in HUFFMAN.iter().enumerate() {
+            let mut node = 0;
+            for i in (0..len).rev() {
+                let bit = ((code >> i) & 1) as usize;
+                if tree[node].children[bit] == 0 {
+                    tree.push(Node { children: [0, 0], symbol: None });
+                    tree[node].children[bit] = (tree.len() - 1) as u16;
+                }
+                node = tree[node].children[bit] as usize;
+            }
+            tree[node].symbol = Some(symbol as u16);
+        }
+        tree
+    })
+}
+
+/**
+ * 解码Huffman编码的字符串，结尾的填充必须是不超过7位的全1，不能出现EOS
+ */
+fn huffman_decode(data: &[u8]) -> Result<Vec<u8>, HpackError> {
+    let tree = huffman_tree();
+    let mut out = Vec::with_capacity(data.len() * 8 / 5);
+    let mut node = 0;
+    let mut pending = 0;
+    let mut all_ones = true;
+    for byte in data {
+        for i in (0..8).rev() {
+            let bit = ((byte >> i) & 1) as usize;
+            node = tree[node].children[bit] as usize;
+            if node == 0 {
+                return Err(HpackError::InvalidHuffman);
+            }
+            pending += 1;
+            all_ones &= bit == 1;
+            if let Some(symbol) = tree[node].symbol {
+                if symbol == 256 {
+                    return Err(HpackError::InvalidHuffman);
+                }
+                out.push(symbol as u8);
+                node = 0;
+                pending = 0;
+                all_ones = true;
+            }
+        }
+    }
+    if pending > 7 || !all_ones {
+        return Err(HpackError::InvalidHuffman);
+    }
+    Ok(out)
+}
+
+/**
+ * Huffman编码，测试中用来构造客户端发送的数据
+ */
+#[cfg(test)]
+fn huffman_encode(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let (mut acc, mut bits) = (0u64, 0u32);
+    for &byte in data {
+        let (code, len) = HUFFMAN[byte as usize];
+        acc = (acc << len) | code as u64;
+        bits += len as u32;
+        while bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+        }
+    }
+    if bits > 0 {
+        out.push(((acc << (8 - bits)) | (0xff >> bits)) as u8);
+    }
+    out
+}
+
+/**
+ * 读取一个带`prefix`位前缀的整数(RFC 7541 5.1)，返回值和消耗的字节数
+ */
+fn decode_int(buf: &[u8], prefix: u8) -> Result<(usize, usize), HpackError> {
+    let max = (1usize << prefix) - 1;
+    let first = *buf.first().ok_or(HpackError::Truncated)? as usize & max;
+    if first < max {
+        return Ok((first, 1));
+    }
+    let mut value = max;
+    for (i, &byte) in buf.iter().enumerate().skip(1) {
+        let shift = 7 * (i - 1);
+        if shift > 28 {
+            return Err(HpackError::IntegerOverflow);
+        }
+        value += ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return Ok((value, i + 1));
+        }
+    }
+    Err(HpackError::Truncated)
+}
+
+/**
+ * 写入一个带`prefix`位前缀的整数，`flags`为第一个字节中前缀以外的高位
+ */
+fn encode_int(value: usize, prefix: u8, flags: u8, out: &mut Vec<u8>) {
+    let max = (1usize << prefix) - 1;
+    if value < max {
+        out.push(flags | value as u8);
+        return;
+    }
+    out.push(flags | max as u8);
+    let mut rest = value - max;
+    while rest >= 0x80 {
+        out.push((rest & 0x7f) as u8 | 0x80);
+        rest >>= 7;
+    }
+    out.push(rest as u8);
+}
+
+/**
+ * 读取一个字符串字面量(RFC 7541 5.2)，返回内容和消耗的字节数
+ */
+fn decode_string(buf: &[u8]) -> Result<(String, usize), HpackError> {
+    let huffman = buf.first().ok_or(HpackError::Truncated)? & 0x80 != 0;
+    let (len, used) = decode_int(buf, 7)?;
+    let raw = buf.get(used..used + len).ok_or(HpackError::Truncated)?;
+    let bytes = if huffman { huffman_decode(raw)? } else { raw.to_vec() };
+    Ok((String::from_utf8_lossy(&bytes).into_owned(), used + len))
+}
+
+fn encode_string(s: &str, out: &mut Vec<u8>) {
+    encode_int(s.len(), 7, 0, out);
+    out.extend_from_slice(s.as_bytes());
+}
+
+/**
+ * 表项占用的大小：名字和值的字节数再加32(RFC 7541 4.1)
+ */
+fn entry_size(name: &str, value: &str) -> usize {
+    name.len() + value.len() + 32
+}
+
+/**
+# Decoder
+HPACK解码器，维护对端编码器对应的动态表，同一个连接上的所有头部块必须按顺序交给同一个解码器
+```rust
+use http::hpack::Decoder;
+
+let mut decoder = Decoder::new(4096);
+// 静态表第2项和第4项
+let headers = decoder.decode(&[0x82, 0x84], 16384).unwrap();
+assert_eq!(headers, vec![(":method".to_string(), "GET".to_string()), (":path".to_string(), "/".to_string())]);
+```
+ */
+pub struct Decoder {
+    dynamic: VecDeque<(String, String)>,
+    size: usize,
+    capacity: usize,
+    max_capacity: usize,
+}
+
+impl Decoder {
+    /**
+     * `max_table_size`为通过SETTINGS_HEADER_TABLE_SIZE告诉对端的上限
+     */
+    pub fn new(max_table_size: usize) -> Self {
+        Decoder { dynamic: VecDeque::new(), size: 0, capacity: max_table_size, max_capacity: max_table_size }
+    }
+
+    fn get(&self, index: usize) -> Result<(&str, &str), HpackError> {
+        if index == 0 {
+            return Err(HpackError::InvalidIndex);
+        }
+        if index <= STATIC_TABLE.len() {
+            return Ok(STATIC_TABLE[index - 1]);
+        }
+        self.dynamic.get(index - STATIC_TABLE.len() - 1)
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .ok_or(HpackError::InvalidIndex)
+    }
+
+    fn evict(&mut self) {
+        while self.size > self.capacity {
+            match self.dynamic.pop_back() {
+                Some((name, value)) => self.size -= entry_size(&name, &value),
+                None => break,
+            }
+        }
+    }
+
+    fn insert(&mut self, name: String, value: String) {
+        self.size += entry_size(&name, &value);
+        self.dynamic.push_front((name, value));
+        self.evict();
+    }
+
+    /**
+     * 解码一个完整的头部块，按出现顺序返回(名字, 值)。
+     * 解码后的大小(按RFC 7540 6.5.2计算)超过`max_list_size`时返回`HeaderListTooLarge`，
+     * 超过之后不再保存解出的头部，重复引用一个很大的表项也不会占用更多内存，
+     * 但动态表仍然正确更新，连接可以继续使用
+     */
+    pub fn decode(&mut self, mut block: &[u8], max_list_size: usize) -> Result<Vec<(String, String)>, HpackError> {
+        let mut headers = Vec::new();
+        let mut list_size = 0;
+        let mut started = false;
+        while let Some(&first) = block.first() {
+            if first & 0x80 != 0 {
+                let (index, used) = decode_int(block, 7)?;
+                block = &block[used..];
+                let (name, value) = self.get(index)?;
+                list_size += entry_size(name, value);
+                if list_size <= max_list_size {
+                    headers.push((name.to_string(), value.to_string()));
+                }
+            } else if first & 0xe0 == 0x20 {
+                // 动态表大小更新只能出现在头部块的开头
+                let (size, used) = decode_int(block, 5)?;
+                if size > self.max_capacity || started {
+                    return Err(HpackError::InvalidTableSize);
+                }
+                block = &block[used..];
+                self.capacity = size;
+                self.evict();
+                continue;
+            } else {
+                // 带索引的字面量前缀6位，不索引和永不索引的前缀4位
+                let indexing = first & 0xc0 == 0x40;
+                let (index, used) = decode_int(block, if indexing { 6 } else { 4 })?;
+                block = &block[used..];
+                let name = if index == 0 {
+                    let (name, used) = decode_string(block)?;
+                    block = &block[used..];
+                    name
+                } else {
+                    self.get(index)?.0.to_string()
+                };
+                let (value, used) = decode_string(block)?;
+                block = &block[used..];
+                list_size += entry_size(&name, &value);
+                if indexing {
+                    self.insert(name.clone(), value.clone());
+                }
+                if list_size <= max_list_size {
+                    headers.push((name, value));
+                }
+            }
+            started = true;
+        }
+        if list_size > max_list_size {
+            return Err(HpackError::HeaderListTooLarge);
+        }
+        Ok(headers)
+    }
+}
+
+/**
+ * # 编码头部块
+ * 不使用动态表也不使用Huffman编码：完全匹配静态表的用索引，名字在静态表中的用名字索引，
+ * 其余按不索引的字面量写出，所以编码不需要任何状态
+ */
+pub fn encode<'a>(headers: impl IntoIterator<Item = (&'a str, &'a str)>, out: &mut Vec<u8>) {
+    for (name, value) in headers {
+        if let Some(i) = STATIC_TABLE.iter().position(|&(n, v)| n == name && v == value) {
+            encode_int(i + 1, 7, 0x80, out);
+            continue;
+        }
+        match STATIC_TABLE.iter().position(|&(n, _)| n == name) {
+            Some(i) => encode_int(i + 1, 4, 0, out),
+            None => {
+                out.push(0);
+                encode_string(name, out);
+            }
+        }
+        encode_string(value, out);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pairs(headers: &[(&str, &str)]) -> Vec<(String, String)> {
+        headers.iter().map(|&(n, v)| (n.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn test_integer() {
+        // RFC 7541 C.1
+        let mut out = Vec::new();
+        encode_int(1337, 5, 0, &mut out);
+        assert_eq!(out, [0x1f, 0x9a, 0x0a]);
+        assert_eq!(decode_int(&out, 5), Ok((1337, 3)));
+        assert_eq!(decode_int(&[0x0a], 5), Ok((10, 1)));
+        assert_eq!(decode_int(&[0x1f, 0x9a], 5), Err(HpackError::Truncated));
+        assert_eq!(decode_int(&[0x1f, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01], 5), Err(HpackError::IntegerOverflow));
+    }
+
+    #[test]
+    fn test_huffman() {
+        // RFC 7541 C.4.1
+        let encoded = [0xf1, 0xe3, 0xc2, 0xe5, 0xf2, 0x3a, 0x6b, 0xa0, 0xab, 0x90, 0xf4, 0xff];
+        assert_eq!(huffman_decode(&encoded).unwrap(), b"www.example.com");
+        assert_eq!(huffman_encode(b"www.example.com"), encoded);
+        let all: Vec<u8> = (0..=255).collect();
+        assert_eq!(huffman_decode(&huffman_encode(&all)).unwrap(), all);
+        // 填充不是全1，或者超过7位
+        assert_eq!(huffman_decode(&[0xf1, 0xe3, 0xc2, 0xe5, 0xf2, 0x3a, 0x6b, 0xa0, 0xab, 0x90, 0xf4, 0xfe]), Err(HpackError::InvalidHuffman));
+        assert_eq!(huffman_decode(&[0xff]), Err(HpackError::InvalidHuffman));
+    }
+
+    #[test]
+    fn test_decode_requests_with_huffman() {
+        // RFC 7541 C.4，三个请求共用一个动态表
+        let mut decoder = Decoder::new(4096);
+        let first = [0x82, 0x86, 0x84, 0x41, 0x8c, 0xf1, 0xe3, 0xc2, 0xe5, 0xf2, 0x3a, 0x6b, 0xa0, 0xab, 0x90, 0xf4, 0xff];
+        assert_eq!(decoder.decode(&first, 16384).unwrap(), pairs(&[
+            (":method", "GET"), (":scheme", "http"), (":path", "/"), (":authority", "www.example.com"),
+        ]));
+        assert_eq!(decoder.size, 57);
+
+        let second = [0x82, 0x86, 0x84, 0xbe, 0x58, 0x86, 0xa8, 0xeb, 0x10, 0x64, 0x9c, 0xbf];
+        assert_eq!(decoder.decode(&second, 16384).unwrap(), pairs(&[
+            (":method", "GET"), (":scheme", "http"), (":path", "/"), (":authority", "www.example.com"),
+            ("cache-control", "no-cache"),
+        ]));
+
+        let third = [
+            0x82, 0x87, 0x85, 0xbf, 0x40, 0x88, 0x25, 0xa8, 0x49, 0xe9, 0x5b, 0xa9, 0x7d, 0x7f, 0x89, 0x25,
+            0xa8, 0x49, 0xe9, 0x5b, 0xb8, 0xe8, 0xb4, 0xbf,
+        ];
+        assert_eq!(decoder.decode(&third, 16384).unwrap(), pairs(&[
+            (":method", "GET"), (":scheme", "https"), (":path", "/index.html"), (":authority", "www.example.com"),
+            ("custom-key", "custom-value"),
+        ]));
+        assert_eq!(decoder.size, 164);
+        assert_eq!(decoder.decode(&[0xbf], 16384).unwrap(), pairs(&[("cache-control", "no-cache")]));
+        assert_eq!(decoder.decode(&[0xc1], 16384), Err(HpackError::InvalidIndex));
+    }
+
+    #[test]
+    fn test_table_size_and_limits() {
+        let mut decoder = Decoder::new(4096);
+        decoder.decode(&[0x40, 0x01, b'a', 0x01, b'b'], 16384).unwrap();
+        assert_eq!(decoder.dynamic.len(), 1);
+        // 大小更新为0清空动态表，超过上限或者不在开头都是错误
+        decoder.decode(&[0x20], 16384).unwrap();
+        assert!(decoder.dynamic.is_empty());
+        assert_eq!(decoder.decode(&[0x3f, 0xe2, 0x1f], 16384), Err(HpackError::InvalidTableSize));
+        assert_eq!(decoder.decode(&[0x82, 0x20], 16384), Err(HpackError::InvalidTableSize));
+
+        assert_eq!(decoder.decode(&[0x82, 0x84], 60), Err(HpackError::HeaderListTooLarge));
+        assert_eq!(decoder.decode(&[0x00, 0x05, b'h', b'e'], 16384), Err(HpackError::Truncated));
+    }
+
+    #[test]
+    fn test_repeated_index_bomb() {
+        // 一个约4KB的表项，然后用一个字节的索引重复引用它两万次
+        let mut decoder = Decoder::new(4096);
+        let value = "x".repeat(4000);
+        let mut block = vec![0x40, 0x01, b'a'];
+        encode_int(value.len(), 7, 0, &mut block);
+        block.extend_from_slice(value.as_bytes());
+        block.extend(std::iter::repeat_n(0xbe, 20_000));
+        // 超过上限之后出现的带索引字面量仍然要加入动态表
+        block.extend_from_slice(&[0x40, 0x01, b'b', 0x01, b'c']);
+        assert_eq!(decoder.decode(&block, 16384), Err(HpackError::HeaderListTooLarge));
+
+        // 动态表与对端编码器保持一致，连接可以继续使用
+        assert_eq!(decoder.dynamic.len(), 2);
+        let headers = decoder.decode(&[0xbe, 0xbf], 16384).unwrap();
+        assert_eq!(headers[0], ("b".to_string(), "c".to_string()));
+        assert_eq!(headers[1].1.len(), 4000);
+    }
+
+    #[test]
+    fn test_encode_roundtrip() {
+        let headers = [(":status", "200"), (":status", "418"), ("content-type", "text/html"), ("x-request-id", "7")];
+        let mut out = Vec::new();
+        encode(headers.iter().copied(), &mut out);
+        assert_eq!(out[0], 0x88);
+        assert_eq!(Decoder::new(4096).decode(&out, 16384).unwrap(), pairs(&headers));
+    }
+}
@@ -1,9 +1,17 @@
+use std::any::{Any, TypeId};
 use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
 pub enum Method {
     Get,
     Post,
+    Put,
+    Patch,
+    Delete,
+    Head,
+    Options,
     Uninitialized,
 }
 
@@ -12,14 +20,35 @@ impl From<&str> for Method {
         match s {
             "GET" => Method::Get,
             "POST" => Method::Post,
+            "PUT" => Method::Put,
+            "PATCH" => Method::Patch,
+            "DELETE" => Method::Delete,
+            "HEAD" => Method::Head,
+            "OPTIONS" => Method::Options,
             _ => Method::Uninitialized,
         }
     }
 }
 
+impl Method {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Method::Get => "GET",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Patch => "PATCH",
+            Method::Delete => "DELETE",
+            Method::Head => "HEAD",
+            Method::Options => "OPTIONS",
+            Method::Uninitialized => "",
+        }
+    }
+}
 
-#[derive(Debug, PartialEq)]
+
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum  Version {
+    V1_0,
     V1_1,
     V2_0,
     Uninitialized,
@@ -28,18 +57,153 @@ pub enum  Version {
 impl From<&str> for Version {
     fn from(s : &str) -> Version {
         match s {
+            "HTTP/1.0" => Version::V1_0,
             "HTTP/1.1" => Version::V1_1,
+            "HTTP/2" | "HTTP/2.0" => Version::V2_0,
             _ => Version::Uninitialized,
         }
     }
 }
 
+impl Version {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Version::V1_0 => "HTTP/1.0",
+            Version::V1_1 => "HTTP/1.1",
+            Version::V2_0 => "HTTP/2",
+            Version::Uninitialized => "",
+        }
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub enum Resource {
     Path(String),
 
 }
 
+impl Resource {
+    /**
+     * 去掉查询串和片段后的路径部分，例如`/docs/?a=1`得到`/docs/`
+     */
+    pub fn path(&self) -> &str {
+        let Resource::Path(s) = self;
+        let end = s.find(['?', '#']).unwrap_or(s.len());
+        &s[..end]
+    }
+
+    /**
+     * `?`之后的查询串，不包含`?`本身
+     */
+    pub fn query(&self) -> Option<&str> {
+        let Resource::Path(s) = self;
+        let s = s.split('#').next().unwrap_or("");
+        s.split_once('?').map(|(_, q)| q)
+    }
+}
+
+/**
+ * # 解析查询串
+ * `a=1&b=x%20y`解析为`{"a": "1", "b": "x y"}`，`+`视为空格，重复的键以最后一个为准
+ */
+pub fn parse_query(query: &str) -> HashMap<String, String> {
+    query.split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (k, v) = pair.split_once('=').unwrap_or((pair, ""));
+            let decode = |s: &str| {
+                let s = s.replace('+', " ");
+                percent_decode(&s).unwrap_or(s)
+            };
+            (decode(k), decode(v))
+        })
+        .collect()
+}
+
+/**
+ * # 百分号编码
+ * 对路径段进行编码，保留字母数字和`-._~`
+ */
+pub fn percent_encode(s: &str) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(b as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    encoded
+}
+
+/**
+ * # 百分号解码
+ * 把`%20`这样的转义还原成原始字节，非法的转义原样保留。
+ * 解码结果不是合法utf-8时返回`None`
+ */
+pub fn percent_decode(s: &str) -> Option<String> {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok();
+            if let Some(b) = hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                decoded.push(b);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8(decoded).ok()
+}
+
+/**
+# Extensions
+按类型存放的附加数据，例如服务启动时注入的共享状态。
+值用`Arc`保存，克隆一份`Extensions`只会增加引用计数
+```rust
+use http::httprequest::Extensions;
+use std::sync::Arc;
+
+struct AppState { name: &'static str }
+
+let mut ext = Extensions::default();
+ext.insert(Arc::new(AppState { name: "demo" }));
+assert_eq!(ext.get::<AppState>().unwrap().name, "demo");
+assert!(ext.get::<String>().is_none());
+```
+ */
+#[derive(Clone, Default)]
+pub struct Extensions {
+    map: HashMap<TypeId, Arc<dyn Any + Send + Sync>>,
+}
+
+impl Extensions {
+    pub fn insert<T: Send + Sync + 'static>(&mut self, value: Arc<T>) {
+        self.map.insert(TypeId::of::<T>(), value);
+    }
+
+    pub fn get<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.map.get(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_ref::<T>())
+    }
+
+    pub fn get_arc<T: Send + Sync + 'static>(&self) -> Option<Arc<T>> {
+        self.map.get(&TypeId::of::<T>())
+            .and_then(|value| value.clone().downcast::<T>().ok())
+    }
+}
+
+impl fmt::Debug for Extensions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Extensions").field("len", &self.map.len()).finish()
+    }
+}
+
 #[derive(Debug)]
 pub struct HttpRequst {
     pub method: Method,
@@ -47,6 +211,42 @@ pub struct HttpRequst {
     pub resource: Resource,
     pub headers: HashMap<String, String>,
     pub msg_body: String,
+    /** 路由匹配得到的路径参数，例如`/api/characters/:name`中的`name` */
+    pub params: HashMap<String, String>,
+    /** 服务端注入的附加数据，见[`Extensions`] */
+    pub extensions: Extensions,
+}
+
+impl HttpRequst {
+    /**
+     * 按名字读取请求头，不区分大小写，去掉两端空白
+     */
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|(k, _)| k.trim().eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.trim())
+    }
+
+    /**
+     * 路由匹配得到的路径参数
+     */
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params.get(name).map(|v| v.as_str())
+    }
+
+    /**
+     * 服务启动时注入的共享状态，见[`Extensions`]
+     */
+    pub fn state<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.extensions.get::<T>()
+    }
+
+    /**
+     * 解析后的查询参数，见[`parse_query`]
+     */
+    pub fn query_params(&self) -> HashMap<String, String> {
+        self.resource.query().map(parse_query).unwrap_or_default()
+    }
 }
 
 impl From<String> for HttpRequst {
@@ -55,10 +255,17 @@ impl From<String> for HttpRequst {
         let mut parsed_version = Version::V1_1;
         let mut parsed_resource = Resource::Path("".to_string());
         let mut parsed_headers = HashMap::new();
-        let mut parsed_msg_body = "";
-
-        for line in req.lines() {
-            if line.contains("HTTP") {
+        // 请求头和请求体以第一个空行分隔，请求体原样保留，JSON这样的内容中也有冒号和换行
+        let (head, parsed_msg_body) = match req.find("\r\n\r\n") {
+            Some(pos) => (&req[..pos], &req[pos + 4..]),
+            None => match req.find("\n\n") {
+                Some(pos) => (&req[..pos], &req[pos + 2..]),
+                None => (req.as_str(), ""),
+            },
+        };
+
+        for (i, line) in head.lines().enumerate() {
+            if i == 0 && line.contains("HTTP") {
                 let (method, resource, version) = process_req_line(line);
                 parsed_method = method;
                 parsed_resource = resource;
@@ -66,10 +273,6 @@ impl From<String> for HttpRequst {
             } else if line.contains(":") {
                 let (key, value) = process_header_line(line);
                 parsed_headers.insert(key, value);
-            } else if line.len() == 0 {
-
-            } else {
-                parsed_msg_body = line;
             }
         }
 
@@ -79,6 +282,8 @@ impl From<String> for HttpRequst {
             resource: parsed_resource,
             headers: parsed_headers,
             msg_body: parsed_msg_body.to_string(),
+            params: HashMap::new(),
+            extensions: Extensions::default(),
         }
     }
 }
@@ -103,18 +308,11 @@ fn process_req_line(s: &str) -> (Method, Resource, Version) {
  * 传入header行，进行解析，返回(key, value)
  */
 fn process_header_line(s: &str) -> (String, String) {
-    let mut header_items = s.split(":");
-    let mut key = String::from("");
-    let mut value = String::from("");
-    if let Some(k) = header_items.next() {
-        key = k.to_string();
+    // 只在第一个冒号处分割，`Host: localhost:3000`、`Origin: https://...`的值中也有冒号
+    match s.split_once(':') {
+        Some((key, value)) => (key.to_string(), value.to_string()),
+        None => (s.to_string(), String::new()),
     }
-
-    if let Some(v) = header_items.next() {
-        value = v.to_string();
-    }
-
-    (key, value)
 }
 
 #[cfg(test)]
@@ -125,12 +323,22 @@ mod tests {
     fn test_method_into() {
         let m : Method = "GET".into();
         assert_eq!(m, Method::Get);
+        let m : Method = "DELETE".into();
+        assert_eq!(m, Method::Delete);
+        assert_eq!(Method::Patch.as_str(), "PATCH");
     }
 
     #[test]
     fn test_version_into() {
         let v: Version = "HTTP/1.1".into();
         assert_eq!(v, Version::V1_1);
+        let v: Version = "HTTP/2.0".into();
+        assert_eq!(v, Version::V2_0);
+        let v: Version = "HTTP/1.0".into();
+        assert_eq!(v, Version::V1_0);
+        assert_eq!(v.as_str(), "HTTP/1.0");
+        let v: Version = "HTTP/3".into();
+        assert_eq!(v, Version::Uninitialized);
     }
 
     #[test]
@@ -153,6 +361,25 @@ mod tests {
     }
 
 
+    #[test]
+    fn test_resource_path_and_query() {
+        let res = Resource::Path("/img/characters/hutao%20v2.jpg?size=small#top".into());
+        assert_eq!(res.path(), "/img/characters/hutao%20v2.jpg");
+        assert_eq!(res.query(), Some("size=small"));
+        assert_eq!(percent_decode(res.path()).unwrap(), "/img/characters/hutao v2.jpg");
+        assert_eq!(percent_decode("100%").unwrap(), "100%");
+        assert_eq!(percent_decode("%zz%41").unwrap(), "%zzA");
+    }
+
+    #[test]
+    fn test_parse_query() {
+        let q = parse_query("sort=size&order=desc&name=hu+tao%21&flag");
+        assert_eq!(q.get("sort").map(|s| s.as_str()), Some("size"));
+        assert_eq!(q.get("name").map(|s| s.as_str()), Some("hu tao!"));
+        assert_eq!(q.get("flag").map(|s| s.as_str()), Some(""));
+        assert_eq!(percent_encode("hu tao.jpg"), "hu%20tao.jpg");
+    }
+
     #[test]
 
     fn test_read_http() {
@@ -164,7 +391,7 @@ mod tests {
         ");
             
         let mut headers_expected = HashMap::new();
-        headers_expected.insert("Host".into(), " localhost".into());
+        headers_expected.insert("Host".into(), " localhost:3000".into());
         headers_expected.insert("Accept".into(), " */*".into());
         headers_expected.insert("User-Agent".into(), " curl/7.71.1".into());
 
@@ -174,6 +401,17 @@ mod tests {
         assert_eq!(Version::V1_1, req.version);
         assert_eq!(Resource::Path("/greeting".to_string()), req.resource);
         assert_eq!(headers_expected, req.headers);
+        assert_eq!(req.msg_body, "");
+    }
+
+    #[test]
+    fn test_read_body() {
+        let body = "{\n  \"name\": \"胡桃\",\r\n  \"level\": 90\n}\n\nHTTP";
+        let s = format!("POST /api/characters HTTP/1.1\r\nContent-Type: application/json\r\n\r\n{}", body);
+        let req: HttpRequst = s.into();
+        assert_eq!(req.method, Method::Post);
+        assert_eq!(req.headers.len(), 1);
+        assert_eq!(req.msg_body, body);
     }
 
 }
\ No newline at end of file
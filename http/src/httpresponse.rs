@@ -7,7 +7,7 @@ use std::io::{Result, Write};
 RespnseBody 用于封装需要用不同方式处理的http body
  * ## Binary(Vec<u8>)
  * 用于二进制数据的封装。例如图片等用文本格式会损失信息的数据
-```rust
+```rust,ignore
 if full_path.ends_with(".jpg") { 
     contents = match fs::read(full_path) {
             Ok(data) => Some(ResponseBody::Binary(data)),
@@ -18,7 +18,7 @@ if full_path.ends_with(".jpg") {
  * 
 ## Text(String)
  * 用于文本数据的封装。例如js,html,css等文本数据
-```rust
+```rust,ignore
 if full_path.ends_with(".html") { 
     contents = match fs::read_to_string(full_path) {
             Ok(txt) => Some(ResponseBody::Text(txt)),
@@ -40,7 +40,17 @@ impl ResponseBody {
     fn to_bytes(&self) -> Option<&[u8]> {
         match &self {
             ResponseBody::Text(txt) => Some(txt.as_bytes()),
-            ResponseBody::Binary(data) => Some(&data)
+            ResponseBody::Binary(data) => Some(data)
+        }
+    }
+
+    /**
+     * 以字节形式查看body内容，例如用于内容嗅探
+     */
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            ResponseBody::Text(txt) => txt.as_bytes(),
+            ResponseBody::Binary(data) => data,
         }
     }
 }
@@ -51,22 +61,69 @@ impl ResponseBody {
  */
 
 #[derive(Debug, PartialEq, Clone)]
-pub struct HttpResponse<'a> {
-    version: &'a str,
-    status_code: &'a str,
-    status_text: &'a str,
-    headers: Option<HashMap<&'a str, &'a str>>,
+pub struct HttpResponse {
+    version: &'static str,
+    status_code: &'static str,
+    status_text: &'static str,
+    headers: Option<HashMap<String, String>>,
     body: Option<ResponseBody>,
 }
 
+/**
+ * 状态码对应的原因短语，未知状态码沿用原来的`Not Found`
+ */
+pub fn status_text(status_code: &str) -> &'static str {
+    match status_code {
+        "100" => "Continue",
+        "101" => "Switching Protocols",
+        "200" => "OK",
+        "201" => "Created",
+        "202" => "Accepted",
+        "204" => "No Content",
+        "206" => "Partial Content",
+        "301" => "Moved Permanently",
+        "302" => "Found",
+        "303" => "See Other",
+        "304" => "Not Modified",
+        "307" => "Temporary Redirect",
+        "308" => "Permanent Redirect",
+        "400" => "Bad Request",
+        "401" => "Unauthorized",
+        "403" => "Forbidden",
+        "404" => "Not Found",
+        "405" => "Method Not Allowed",
+        "406" => "Not Acceptable",
+        "408" => "Request Timeout",
+        "409" => "Conflict",
+        "411" => "Length Required",
+        "412" => "Precondition Failed",
+        "413" => "Content Too Large",
+        "414" => "URI Too Long",
+        "415" => "Unsupported Media Type",
+        "416" => "Range Not Satisfiable",
+        "417" => "Expectation Failed",
+        "422" => "Unprocessable Content",
+        "426" => "Upgrade Required",
+        "429" => "Too Many Requests",
+        "431" => "Request Header Fields Too Large",
+        "500" => "Internal Server Error",
+        "501" => "Not Implemented",
+        "502" => "Bad Gateway",
+        "503" => "Service Unavailable",
+        "504" => "Gateway Timeout",
+        "505" => "HTTP Version Not Supported",
+        _ => "Not Found",
+    }
+}
+
 
 
-impl<'a> Default for HttpResponse<'a> {
+impl Default for HttpResponse {
     fn default() -> Self {
         Self {
-            version: "HTTP/1.1".into(),
-            status_code: "200".into(),
-            status_text: "OK".into(),
+            version: "HTTP/1.1",
+            status_code: "200",
+            status_text: "OK",
             headers: None,
             body: None,
         }
@@ -76,31 +133,25 @@ impl<'a> Default for HttpResponse<'a> {
 
 
 
-impl<'a> HttpResponse<'a> {
+impl HttpResponse {
     pub fn new(
-        status_code : &'a str,
-        headers: Option<HashMap<&'a str, &'a str>>,
+        status_code : &'static str,
+        headers: Option<HashMap<&str, &str>>,
         body: Option<ResponseBody>
-    ) -> HttpResponse<'a> {
-        let mut response: HttpResponse<'a> = HttpResponse::default();
+    ) -> HttpResponse {
+        let mut response: HttpResponse = HttpResponse::default();
         if status_code != "200" {
-            response.status_code = status_code.into();
+            response.status_code = status_code;
         }
         response.headers = match &headers {
-            Some(_h) => headers,
+            Some(h) => Some(h.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()),
             None => {
                 let mut h = HashMap::new();
-                h.insert("Content-Type", "text/html");
+                h.insert("Content-Type".to_string(), "text/html".to_string());
                 Some(h)
             }
         };
-        response.status_text = match response.status_code {
-            "200" => "OK".into(),
-            "400" => "Bad Request".into(),
-            "404" => "Not Found".into(),
-            "500" => "Internal Server Error".into(),
-            _ => "Not Found".into(),
-        };
+        response.status_text = status_text(response.status_code);
 
         response.body = body;
         response
@@ -119,20 +170,79 @@ impl<'a> HttpResponse<'a> {
         Ok(())
     }
 
-    fn version(&self) -> &str {
+    /**
+     * 只发送状态行和响应头，用于HEAD请求，`Content-Length`仍然是body的长度
+     */
+    pub fn send_head(&self, write_stream: &mut impl Write) -> Result<()> {
+        let _ = write_stream.write_all(self.head(true).as_bytes());
+        Ok(())
+    }
+
+    /**
+     * 只发送状态行和响应头，不带`Content-Length`，body由调用者随后直接写入，
+     * 以关闭连接结束，例如事件流
+     */
+    pub fn send_stream_head(&self, write_stream: &mut impl Write) -> Result<()> {
+        write_stream.write_all(self.head(false).as_bytes())
+    }
+
+    /**
+     * 设置响应的协议版本，例如回应HTTP/1.0请求时使用`HTTP/1.0`，默认为`HTTP/1.1`
+     */
+    pub fn set_version(&mut self, version: &'static str) {
+        self.version = version;
+    }
+
+    /**
+     * 设置(或覆盖)一个响应头，值可以是运行时生成的字符串，例如`Location`
+     */
+    pub fn set_header(&mut self, key: &str, value: &str) {
+        self.headers
+            .get_or_insert_with(HashMap::new)
+            .insert(key.to_string(), value.to_string());
+    }
+
+    /**
+     * 按名字读取响应头，不区分大小写
+     */
+    pub fn header(&self, key: &str) -> Option<&str> {
+        self.headers.as_ref()?.iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /**
+     * 按任意顺序遍历所有响应头，不包括`Content-Length`(发送时根据body计算)
+     */
+    pub fn header_fields(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.headers.iter().flatten().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    pub fn version(&self) -> &str {
         self.version
     }
 
-    fn status_code(&self) -> &str {
+    pub fn status_code(&self) -> &'static str {
         self.status_code
     }
 
+    pub fn body(&self) -> Option<&ResponseBody> {
+        self.body.as_ref()
+    }
+
+    /**
+     * 替换响应体，例如压缩之后，`Content-Length`发送时重新计算
+     */
+    pub fn set_body(&mut self, body: Option<ResponseBody>) {
+        self.body = body;
+    }
+
     fn status_text(&self) -> &str {
         self.status_text
     }
 
     fn headers(&self) -> String {
-        let map: HashMap<&str, &str> = self.headers.clone().unwrap();
+        let map: HashMap<String, String> = self.headers.clone().unwrap_or_default();
         let mut header_string: String = "".into();
         for (k, v) in map.iter() {
             header_string = format!("{}{}:{}\r\n", header_string, k, v);
@@ -142,32 +252,40 @@ impl<'a> HttpResponse<'a> {
 
 
     fn bodylen(&self) -> usize{
-        let len = &self.body.clone().map(|b| {
+        let len = &self.body.as_ref().map(|b| {
             match b {
                 ResponseBody::Text(txt) => txt.len(),
                 ResponseBody::Binary(data) => data.len(),
             }
         });
 
-        len.unwrap()
+        len.unwrap_or(0)
     }
 
 
-    fn to_bytes(&self) -> Vec<u8> {
-        let mut buffer = Vec::new();
-        let headers = format!(
-            "{} {} {}\r\n{}Content-Length: {}\r\n\r\n",
+    fn head(&self, with_length: bool) -> String {
+        // 1xx响应没有body，也不能带Content-Length
+        let length = if !with_length || self.status_code.starts_with('1') {
+            String::new()
+        } else {
+            format!("Content-Length: {}\r\n", self.bodylen())
+        };
+        format!(
+            "{} {} {}\r\n{}{}\r\n",
             &self.version(),
             &self.status_code(),
             &self.status_text(),
             &self.headers(),
-            &self.bodylen(),
-        );
+            length,
+        )
+    }
 
-        let _ = buffer.write_all(headers.as_bytes());
-        let _ = buffer.write_all(self.body.as_ref().unwrap()
-            .to_bytes().unwrap()
-        );
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        let _ = buffer.write_all(self.head(true).as_bytes());
+        if let Some(body) = self.body.as_ref().and_then(|b| b.to_bytes()) {
+            let _ = buffer.write_all(body);
+        }
         buffer
     }
 
@@ -192,7 +310,7 @@ mod tests {
             status_text: "OK",
             headers: {
                 let mut h = HashMap::new();
-                h.insert("Content-Type", "text/html");
+                h.insert("Content-Type".to_string(), "text/html".to_string());
                 Some(h)
             },
             body: Some(ResponseBody::Text("xxxx".into()))
@@ -215,7 +333,7 @@ mod tests {
             status_text: "Not Found",
             headers: {
                 let mut h = HashMap::new();
-                h.insert("Content-Type", "text/html");
+                h.insert("Content-Type".to_string(), "text/html".to_string());
                 Some(h)
             },
             body: Some(ResponseBody::Text("xxxx".into()))
@@ -224,6 +342,35 @@ mod tests {
 
     }
 
+    #[test]
+    fn test_response_redirect_without_body() {
+        let mut response = HttpResponse::new("301", None, None);
+        response.set_header("Location", "/docs/");
+
+        assert_eq!(response.header("location"), Some("/docs/"));
+        let bytes = String::from_utf8(response.to_bytes()).unwrap();
+        assert!(bytes.starts_with("HTTP/1.1 301 Moved Permanently\r\n"));
+        assert!(bytes.contains("Location:/docs/\r\n"));
+        assert!(bytes.ends_with("Content-Length: 0\r\n\r\n"));
+    }
+
+    #[test]
+    fn test_response_version_and_head() {
+        let mut response = HttpResponse::new("200", None, Some(ResponseBody::Text("xxxx".into())));
+        response.set_version("HTTP/1.0");
+        let mut out = Vec::new();
+        response.send_head(&mut out).unwrap();
+        let head = String::from_utf8(out).unwrap();
+        assert!(head.starts_with("HTTP/1.0 200 OK\r\n"));
+        assert!(head.ends_with("Content-Length: 4\r\n\r\n"));
+
+        let mut out = Vec::new();
+        HttpResponse::new("101", None, None).send_response(&mut out).unwrap();
+        response.send_stream_head(&mut out).unwrap();
+        let heads = String::from_utf8(out).unwrap();
+        assert!(!heads.contains("Content-Length"));
+    }
+
     // #[test]
     // fn test_http_response_creation() {
     //     let response_expected = HttpResponse {
@@ -1,2 +1,4 @@
+pub mod hpack;
 pub mod httprequest;
-pub mod httpresponse;
\ No newline at end of file
+pub mod httpresponse;
+pub mod mime;
\ No newline at end of file
@@ -0,0 +1,428 @@
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+/**
+ * # 默认扩展名表
+ * (扩展名, MIME类型)，文本类型在注册时会自动追加`; charset=utf-8`
+ */
+const DEFAULT_TYPES: &[(&str, &str)] = &[
+    // 文本
+    ("html", "text/html"),
+    ("htm", "text/html"),
+    ("css", "text/css"),
+    ("js", "text/javascript"),
+    ("mjs", "text/javascript"),
+    ("txt", "text/plain"),
+    ("text", "text/plain"),
+    ("log", "text/plain"),
+    ("md", "text/markdown"),
+    ("csv", "text/csv"),
+    ("tsv", "text/tab-separated-values"),
+    ("ics", "text/calendar"),
+    ("vtt", "text/vtt"),
+    ("xml", "application/xml"),
+    ("json", "application/json"),
+    ("map", "application/json"),
+    ("jsonld", "application/ld+json"),
+    ("webmanifest", "application/manifest+json"),
+    ("yaml", "application/yaml"),
+    ("yml", "application/yaml"),
+    ("toml", "application/toml"),
+    ("xhtml", "application/xhtml+xml"),
+    ("rss", "application/rss+xml"),
+    ("atom", "application/atom+xml"),
+    ("svg", "image/svg+xml"),
+    // 图片
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("avif", "image/avif"),
+    ("bmp", "image/bmp"),
+    ("ico", "image/vnd.microsoft.icon"),
+    ("tif", "image/tiff"),
+    ("tiff", "image/tiff"),
+    // 字体
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("ttf", "font/ttf"),
+    ("otf", "font/otf"),
+    ("eot", "application/vnd.ms-fontobject"),
+    // 音视频
+    ("mp3", "audio/mpeg"),
+    ("wav", "audio/wav"),
+    ("ogg", "audio/ogg"),
+    ("oga", "audio/ogg"),
+    ("flac", "audio/flac"),
+    ("aac", "audio/aac"),
+    ("m4a", "audio/mp4"),
+    ("weba", "audio/webm"),
+    ("mp4", "video/mp4"),
+    ("m4v", "video/mp4"),
+    ("webm", "video/webm"),
+    ("ogv", "video/ogg"),
+    ("mov", "video/quicktime"),
+    ("avi", "video/x-msvideo"),
+    ("mkv", "video/x-matroska"),
+    // 压缩包与文档
+    ("zip", "application/zip"),
+    ("gz", "application/gzip"),
+    ("tgz", "application/gzip"),
+    ("bz2", "application/x-bzip2"),
+    ("xz", "application/x-xz"),
+    ("zst", "application/zstd"),
+    ("7z", "application/x-7z-compressed"),
+    ("tar", "application/x-tar"),
+    ("rar", "application/vnd.rar"),
+    ("pdf", "application/pdf"),
+    ("rtf", "application/rtf"),
+    ("doc", "application/msword"),
+    ("docx", "application/vnd.openxmlformats-officedocument.wordprocessingml.document"),
+    ("xls", "application/vnd.ms-excel"),
+    ("xlsx", "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet"),
+    ("ppt", "application/vnd.ms-powerpoint"),
+    ("pptx", "application/vnd.openxmlformats-officedocument.presentationml.presentation"),
+    ("odt", "application/vnd.oasis.opendocument.text"),
+    ("epub", "application/epub+zip"),
+    // 其它
+    ("wasm", "application/wasm"),
+    ("bin", "application/octet-stream"),
+    ("exe", "application/octet-stream"),
+    ("iso", "application/octet-stream"),
+    ("deb", "application/vnd.debian.binary-package"),
+    ("rpm", "application/x-rpm"),
+    ("apk", "application/vnd.android.package-archive"),
+    ("jar", "application/java-archive"),
+];
+
+/**
+ * 无法识别的类型统一按二进制流处理
+ */
+pub const DEFAULT_MIME: &str = "application/octet-stream";
+
+/**
+ * # MimeType
+ * - `essence`: 不带参数的类型，如`text/html`
+ * - `content_type`: 用作`Content-Type`头的完整值，如`text/html; charset=utf-8`
+ * - `text`: 是否为文本，文本按`ResponseBody::Text`读取
+ */
+#[derive(Debug, PartialEq, Clone)]
+pub struct MimeType {
+    pub essence: String,
+    pub content_type: String,
+    pub text: bool,
+}
+
+impl MimeType {
+    pub fn new(essence: &str) -> MimeType {
+        let essence = essence.trim().to_ascii_lowercase();
+        let text = is_text_essence(&essence);
+        let content_type = if text {
+            format!("{}; charset=utf-8", essence)
+        } else {
+            essence.clone()
+        };
+        MimeType {
+            essence,
+            content_type,
+            text,
+        }
+    }
+}
+
+/**
+ * 判断一个MIME类型是否为文本：`text/`开头、各种json/xml以及javascript
+ */
+fn is_text_essence(essence: &str) -> bool {
+    essence.starts_with("text/")
+        || essence.ends_with("+json")
+        || essence.ends_with("+xml")
+        || matches!(
+            essence,
+            "application/json"
+                | "application/xml"
+                | "application/javascript"
+                | "application/yaml"
+                | "application/toml"
+        )
+}
+
+/**
+# MimeRegistry
+扩展名到MIME类型的映射表，`Default`带有常见扩展名，可以通过[`MimeRegistry::insert`]
+或者[`MimeRegistry::load_mime_types`]覆盖
+```rust
+use http::mime::MimeRegistry;
+
+let mut registry = MimeRegistry::default();
+registry.insert("rs", "text/x-rust");
+assert_eq!(registry.content_type("main.rs"), "text/x-rust; charset=utf-8");
+assert_eq!(registry.content_type("logo.svg"), "image/svg+xml; charset=utf-8");
+```
+ */
+#[derive(Debug, Clone)]
+pub struct MimeRegistry {
+    types: HashMap<String, MimeType>,
+    sniff: bool,
+}
+
+impl Default for MimeRegistry {
+    fn default() -> Self {
+        let mut registry = MimeRegistry::empty();
+        for (ext, essence) in DEFAULT_TYPES {
+            registry.insert(ext, essence);
+        }
+        registry
+    }
+}
+
+impl MimeRegistry {
+    /**
+     * 一个没有任何扩展名的表
+     */
+    pub fn empty() -> Self {
+        MimeRegistry {
+            types: HashMap::new(),
+            sniff: false,
+        }
+    }
+
+    /**
+     * 注册(或覆盖)一个扩展名，扩展名不区分大小写，可以带前导`.`
+     */
+    pub fn insert(&mut self, ext: &str, essence: &str) {
+        let ext = ext.trim_start_matches('.').to_ascii_lowercase();
+        self.types.insert(ext, MimeType::new(essence));
+    }
+
+    /**
+     * 开启后，没有扩展名或扩展名未知的文件会根据内容猜测类型，见[`sniff`]
+     */
+    pub fn set_sniff(&mut self, sniff: bool) {
+        self.sniff = sniff;
+    }
+
+    pub fn sniff_enabled(&self) -> bool {
+        self.sniff
+    }
+
+    /**
+     * # 读取mime.types格式的覆盖表
+     * 每行一个类型，后面跟若干扩展名，`#`开头为注释
+     ```text
+     text/x-rust    rs
+     image/jxl      jxl
+     ```
+     * 返回成功读取的扩展名个数
+     */
+    pub fn load_mime_types(&mut self, contents: &str) -> usize {
+        let mut count = 0;
+        for line in contents.lines() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            let mut words = line.split_whitespace();
+            if let Some(essence) = words.next() {
+                if !essence.contains('/') {
+                    continue;
+                }
+                for ext in words {
+                    self.insert(ext.trim_end_matches(';'), essence);
+                    count += 1;
+                }
+            }
+        }
+        count
+    }
+
+    /**
+     * 按扩展名查找
+     */
+    pub fn lookup(&self, ext: &str) -> Option<&MimeType> {
+        self.types.get(&ext.trim_start_matches('.').to_ascii_lowercase())
+    }
+
+    /**
+     * 按文件路径查找，取最后一个`.`之后的部分作为扩展名
+     */
+    pub fn lookup_path(&self, path: &str) -> Option<&MimeType> {
+        let file_name = path.rsplit('/').next().unwrap_or(path);
+        match file_name.rsplit_once('.') {
+            Some((stem, ext)) if !stem.is_empty() => self.lookup(ext),
+            _ => None,
+        }
+    }
+
+    /**
+     * 文件路径对应的`Content-Type`，未知类型返回[`DEFAULT_MIME`]
+     */
+    pub fn content_type(&self, path: &str) -> &str {
+        self.lookup_path(path)
+            .map(|m| m.content_type.as_str())
+            .unwrap_or(DEFAULT_MIME)
+    }
+
+    /**
+     * 文件是否应该当作文本读取
+     */
+    pub fn is_text(&self, path: &str) -> bool {
+        self.lookup_path(path).map(|m| m.text).unwrap_or(false)
+    }
+
+    /**
+     * 先按扩展名查找，找不到并且开启了sniff时，根据内容猜测
+     */
+    pub fn content_type_for(&self, path: &str, data: &[u8]) -> &str {
+        match self.lookup_path(path) {
+            Some(m) => m.content_type.as_str(),
+            None if self.sniff => sniff(data).unwrap_or(DEFAULT_MIME),
+            None => DEFAULT_MIME,
+        }
+    }
+}
+
+/**
+ * # 内容嗅探
+ * 根据文件头部的魔数猜测类型，识别不了的合法utf-8文本按`text/plain`处理
+ */
+pub fn sniff(data: &[u8]) -> Option<&'static str> {
+    const SIGNATURES: &[(&[u8], &str)] = &[
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+        (b"%PDF-", "application/pdf"),
+        (b"PK\x03\x04", "application/zip"),
+        (b"\x1f\x8b", "application/gzip"),
+        (b"\0asm", "application/wasm"),
+        (b"wOFF", "font/woff"),
+        (b"wOF2", "font/woff2"),
+        (b"OggS", "audio/ogg"),
+        (b"ID3", "audio/mpeg"),
+        (b"\x1a\x45\xdf\xa3", "video/webm"),
+    ];
+
+    for (magic, essence) in SIGNATURES {
+        if data.starts_with(magic) {
+            return Some(essence);
+        }
+    }
+    if is_bmp(data) {
+        return Some("image/bmp");
+    }
+    if data.len() >= 12 && &data[0..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+        return Some("image/webp");
+    }
+    if data.len() >= 12 && &data[4..8] == b"ftyp" {
+        return Some("video/mp4");
+    }
+
+    let head = &data[..data.len().min(512)];
+    let text = match std::str::from_utf8(head) {
+        Ok(s) => s,
+        // 截断时最后一个字符可能不完整
+        Err(e) if e.valid_up_to() + 4 > head.len() => {
+            std::str::from_utf8(&head[..e.valid_up_to()]).unwrap_or("")
+        }
+        Err(_) => return None,
+    };
+    if text.contains('\0') {
+        return None;
+    }
+    let lower = text.trim_start().to_ascii_lowercase();
+    if lower.starts_with("<!doctype html") || lower.starts_with("<html") {
+        Some("text/html; charset=utf-8")
+    } else if lower.starts_with("<svg") {
+        Some("image/svg+xml; charset=utf-8")
+    } else if lower.starts_with("<?xml") {
+        Some("application/xml; charset=utf-8")
+    } else if lower.starts_with('{') || lower.starts_with('[') {
+        Some("application/json; charset=utf-8")
+    } else {
+        Some("text/plain; charset=utf-8")
+    }
+}
+
+/**
+ * `BM`开头的文本很常见，还要求文件头的各个字段合理：保留字段为0，
+ * 信息头是已知的大小，像素数据在两个头之后，文件大小(可以为0)不小于像素数据的偏移
+ */
+fn is_bmp(data: &[u8]) -> bool {
+    if data.len() < 18 || !data.starts_with(b"BM") {
+        return false;
+    }
+    let u32_at = |i: usize| u32::from_le_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]);
+    let (size, reserved, offset, dib) = (u32_at(2), u32_at(6), u32_at(10), u32_at(14));
+    reserved == 0
+        && matches!(dib, 12 | 40 | 52 | 56 | 64 | 108 | 124)
+        && offset >= 14 + dib
+        && (size == 0 || size >= offset)
+}
+
+static REGISTRY: OnceLock<MimeRegistry> = OnceLock::new();
+
+/**
+ * 全局注册表，未通过[`set_registry`]设置时使用`MimeRegistry::default()`
+ */
+pub fn registry() -> &'static MimeRegistry {
+    REGISTRY.get_or_init(MimeRegistry::default)
+}
+
+/**
+ * 在启动时设置全局注册表，只能设置一次，已经设置过则原样返回
+ */
+pub fn set_registry(registry: MimeRegistry) -> Result<(), MimeRegistry> {
+    REGISTRY.set(registry)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_lookup() {
+        let registry = MimeRegistry::default();
+        assert_eq!(registry.content_type("index.html"), "text/html; charset=utf-8");
+        assert_eq!(registry.content_type("/img/hutao.JPG"), "image/jpeg");
+        assert_eq!(registry.content_type("font.woff2"), "font/woff2");
+        assert_eq!(registry.content_type("song.mp3"), "audio/mpeg");
+        assert_eq!(registry.content_type("data.json"), "application/json; charset=utf-8");
+        assert_eq!(registry.content_type("unknown.abc"), DEFAULT_MIME);
+        assert_eq!(registry.content_type(".bashrc"), DEFAULT_MIME);
+        assert!(registry.is_text("sakura.js"));
+        assert!(!registry.is_text("test.zip"));
+    }
+
+    #[test]
+    fn test_mime_types_override() {
+        let mut registry = MimeRegistry::default();
+        let n = registry.load_mime_types("\
+            # comment\n\
+            text/x-rust rs\n\
+            application/x-custom zip cst\n\
+        ");
+        assert_eq!(n, 3);
+        assert_eq!(registry.content_type("lib.rs"), "text/x-rust; charset=utf-8");
+        assert_eq!(registry.content_type("test.zip"), "application/x-custom");
+    }
+
+    #[test]
+    fn test_sniff() {
+        assert_eq!(sniff(b"\x89PNG\r\n\x1a\n...."), Some("image/png"));
+        assert_eq!(sniff(b"<!DOCTYPE html><html>"), Some("text/html; charset=utf-8"));
+        assert_eq!(sniff(b"hello"), Some("text/plain; charset=utf-8"));
+        assert_eq!(sniff(b"\x00\x01\x02\xff"), None);
+
+        // BMP要求文件头合理，`BM`开头的文本仍然是文本
+        let mut bmp = b"BM".to_vec();
+        for field in [70u32, 0, 54, 40] {
+            bmp.extend_from_slice(&field.to_le_bytes());
+        }
+        assert_eq!(sniff(&bmp), Some("image/bmp"));
+        assert_eq!(sniff(b"BMW owners club meeting notes"), Some("text/plain; charset=utf-8"));
+
+        let mut registry = MimeRegistry::default();
+        assert_eq!(registry.content_type_for("README", b"hello"), DEFAULT_MIME);
+        registry.set_sniff(true);
+        assert_eq!(registry.content_type_for("README", b"hello"), "text/plain; charset=utf-8");
+    }
+}
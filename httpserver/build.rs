@@ -0,0 +1,93 @@
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/**
+ * # 资源嵌入
+ * 开启`embed-assets`特性时，把`public/`和`data/`下的文件写成`$OUT_DIR/assets.rs`中的静态表，
+ * 由`src/embed.rs`通过`include!`引入。
+ * 同时为每个文件计算ETag，并把已经存在的`<file>.br`/`<file>.gz`作为预压缩版本一起嵌入
+ */
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    if env::var_os("CARGO_FEATURE_EMBED_ASSETS").is_none() {
+        return;
+    }
+
+    let manifest_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+    let out_path = PathBuf::from(env::var("OUT_DIR").unwrap()).join("assets.rs");
+    let mut out = fs::File::create(&out_path).unwrap();
+
+    for (name, dir) in [("ASSETS", "public"), ("DATA", "data")] {
+        let root = manifest_dir.join(dir);
+        println!("cargo:rerun-if-changed={}", root.display());
+
+        let mut files = Vec::new();
+        collect(&root, &root, &mut files);
+        files.sort();
+
+        writeln!(out, "static {}: &[Asset] = &[", name).unwrap();
+        for rel in &files {
+            // 预压缩文件跟随原文件一起嵌入，不单独出现在表中
+            let compressed = [".br", ".gz"].iter().any(|ext| {
+                rel.strip_suffix(ext).map(|orig| files.contains(&orig.to_string())).unwrap_or(false)
+            });
+            if compressed {
+                continue;
+            }
+            let full_path = root.join(rel);
+            println!("cargo:rerun-if-changed={}", full_path.display());
+            let data = fs::read(&full_path).unwrap();
+            let variant = |ext: &str| {
+                let candidate = format!("{}.{}", rel, ext);
+                if files.contains(&candidate) {
+                    format!("Some(include_bytes!({:?}))", root.join(candidate))
+                } else {
+                    "None".to_string()
+                }
+            };
+            writeln!(
+                out,
+                "    Asset {{ path: {:?}, data: include_bytes!({:?}), etag: \"\\\"{:016x}\\\"\", br: {}, gzip: {} }},",
+                rel,
+                full_path,
+                fnv1a(&data),
+                variant("br"),
+                variant("gz"),
+            )
+            .unwrap();
+        }
+        writeln!(out, "];").unwrap();
+    }
+}
+
+/**
+ * 递归收集目录下的文件，返回用`/`分隔的相对路径
+ */
+fn collect(root: &Path, dir: &Path, files: &mut Vec<String>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            collect(root, &path, files);
+        } else if let Ok(rel) = path.strip_prefix(root) {
+            let rel: Vec<String> = rel.components()
+                .map(|c| c.as_os_str().to_string_lossy().into_owned())
+                .collect();
+            files.push(rel.join("/"));
+        }
+    }
+}
+
+fn fnv1a(data: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for b in data {
+        hash ^= *b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
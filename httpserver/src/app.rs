@@ -0,0 +1,278 @@
+use super::cache::StaticCache;
+use super::characters::{CharacterHandler, OrderStatus};
+use super::config::{ApiConfig, Config, ConfigError, ListenerConfig, Storage};
+use super::handler::{PageNotFoundHandler, StaticPageHandler, WebServiceHandler};
+use super::listener::ListenAddr;
+use super::middleware::{Compress, Cors, Logger, RequestId, Timing};
+use super::problem::ProblemDetails;
+use super::repository::{JsonFile, Memory, Repository};
+use super::router::Router;
+use super::state::AppState;
+use super::tls::{HttpsRedirect, TlsAcceptor};
+use super::websocket::Broadcast;
+use http::httprequest::Extensions;
+use std::net::ToSocketAddrs;
+use std::sync::Arc;
+
+/**
+# App
+根据[`Config`]构建好的路由表和共享状态，构建过程中读取数据文件、挂载静态目录，
+任何一步失败都返回指向对应配置键的[`ConfigError`]。
+`routers`与`config.listeners`一一对应，所有监听器共用同一份状态和静态文件缓存，
+配置了`[tls]`时`tls`为加载好的证书
+```rust,ignore
+let app = App::from_config(&config)?;
+let mut routers = app.routers.into_iter();
+let server = Server::new(&config.listeners[0].addr, routers.next().unwrap()).with_state(app.state);
+```
+ */
+pub struct App {
+    pub routers: Vec<Router>,
+    pub state: Arc<AppState>,
+    pub tls: Option<TlsAcceptor>,
+}
+
+impl App {
+    pub fn from_config(config: &Config) -> Result<App, ConfigError> {
+        let static_cache = Arc::new(StaticCache::new(config.cache.clone()));
+        Self::build(config, Self::repository(&config.api)?, static_cache, Arc::new(Broadcast::new()))
+    }
+
+    /**
+     * # 重新加载
+     * 用新的配置构建，沿用`previous`配置构建的`state`中的订阅列表，原来的WebSocket订阅者继续收到更新。
+     * 存储配置没有变化时沿用原来的角色存储，`[cache]`没有变化时沿用原来的静态文件缓存，
+     * 所以`storage = "memory"`时通过API写入的数据和已经缓存的文件不会因为重新加载而丢失
+     */
+    pub fn reload(config: &Config, previous: &Config, state: &AppState) -> Result<App, ConfigError> {
+        let characters = match config.api.same_storage(&previous.api) {
+            true => state.characters.clone(),
+            false => Self::repository(&config.api)?,
+        };
+        let static_cache = match config.cache == previous.cache {
+            true => state.static_cache.clone(),
+            false => Arc::new(StaticCache::new(config.cache.clone())),
+        };
+        Self::build(config, characters, static_cache, state.updates.clone())
+    }
+
+    fn build(
+        config: &Config,
+        characters: Arc<dyn Repository>,
+        static_cache: Arc<StaticCache>,
+        updates: Arc<Broadcast>,
+    ) -> Result<App, ConfigError> {
+        let state = Arc::new(AppState { updates, ..AppState::new(characters, static_cache.clone()) });
+
+        let tls = config.tls.as_ref().map(TlsAcceptor::from_config).transpose()?;
+
+        let routers = config.listeners.iter()
+            .map(|listener| Self::router(config, listener, &static_cache))
+            .collect::<Result<_, _>>()?;
+        Ok(App { routers, state, tls })
+    }
+
+    /**
+     * 按`api.storage`打开角色数据的存储。使用编译进二进制的数据时没有可写的文件，`json`等同于`memory`
+     */
+    fn repository(api: &ApiConfig) -> Result<Arc<dyn Repository>, ConfigError> {
+        let data_dir = api.data_dir.as_deref();
+        let seed = || WebServiceHandler::load_json(data_dir).map_err(|e| ConfigError::invalid("api.data_dir", e.to_string()));
+        match (api.storage, WebServiceHandler::data_file(data_dir)) {
+            (Storage::Json, Some(file)) => match JsonFile::open(file) {
+                Ok(repository) => Ok(Arc::new(repository)),
+                Err(e) => Err(ConfigError::invalid("api.data_dir", e.to_string())),
+            },
+            (Storage::Json | Storage::Memory, _) => Ok(Arc::new(Memory::new(seed()?))),
+            (Storage::Sqlite, _) => Self::sqlite(api, seed),
+        }
+    }
+
+    /**
+     * 打开SQLite数据库，文件不存在时新建并导入初始数据；导入失败时删掉新建的文件，下次启动重新导入
+     */
+    #[cfg(feature = "sqlite")]
+    fn sqlite<F>(api: &ApiConfig, seed: F) -> Result<Arc<dyn Repository>, ConfigError>
+    where
+        F: FnOnce() -> Result<Vec<OrderStatus>, ConfigError>,
+    {
+        use super::repository::Sqlite;
+        let path = api.database_path();
+        let seed = if path.exists() { None } else { Some(seed()?) };
+        let invalid = |e: super::repository::StorageError| ConfigError::invalid("api.database", format!("{}: {}", path.display(), e));
+        let database = Sqlite::open(&path).map_err(invalid)?;
+        if let Some(items) = seed {
+            if let Err(e) = database.import(&items) {
+                drop(database);
+                let _ = std::fs::remove_file(&path);
+                return Err(invalid(e));
+            }
+        }
+        Ok(Arc::new(database))
+    }
+
+    #[cfg(not(feature = "sqlite"))]
+    fn sqlite<F>(_api: &ApiConfig, _seed: F) -> Result<Arc<dyn Repository>, ConfigError>
+    where
+        F: FnOnce() -> Result<Vec<OrderStatus>, ConfigError>,
+    {
+        Err(ConfigError::invalid("api.storage", "this binary was built without the `sqlite` feature"))
+    }
+
+    /**
+     * 跳转的目标端口：第一个TLS监听器的端口，无法确定(Unix域套接字、systemd)时为443
+     */
+    fn https_port(config: &Config) -> u16 {
+        config.listeners.iter()
+            .filter(|l| l.secure(config.tls.is_some()))
+            .find_map(|l| match ListenAddr::parse(&l.addr) {
+                Ok(ListenAddr::Tcp(addr)) => addr.to_socket_addrs().ok()?.next().map(|a| a.port()),
+                _ => None,
+            })
+            .unwrap_or(443)
+    }
+
+    /**
+     * 一个监听器的路由表，只包含它选择的API和静态挂载
+     */
+    fn router(config: &Config, listener: &ListenerConfig, static_cache: &Arc<StaticCache>) -> Result<Router, ConfigError> {
+        let mut router = Router::new();
+        if config.logging.access_log {
+            router.wrap(Logger);
+        }
+        if listener.redirect_https {
+            router.not_found(HttpsRedirect::new(Self::https_port(config)));
+            return Ok(router);
+        }
+        if listener.compress {
+            router.wrap(Compress::default());
+        }
+        // 问题详情在请求ID之内，才能在响应体中带上它
+        let problems = match listener.api {
+            true => ProblemDetails::new().prefix(&config.api.prefix),
+            false => ProblemDetails::new(),
+        };
+        router
+            .wrap(RequestId::new())
+            .wrap(Timing)
+            .wrap(problems);
+        if listener.api {
+            router.group(&config.api.prefix, |api| {
+                api.wrap(Cors::default())
+                    .mount("/shipping", WebServiceHandler::routes())
+                    .mount("/characters", CharacterHandler::routes())
+                    .get("/cache/stats", WebServiceHandler::cache_stats);
+            });
+        }
+
+        for (i, mount) in config.statics.iter().enumerate() {
+            if !listener.serves(&mount.prefix) {
+                continue;
+            }
+            let options = Arc::new(mount.options());
+            let handler = StaticPageHandler::new(options.clone(), static_cache.clone());
+            let not_found = PageNotFoundHandler::new(options, static_cache.clone());
+            if mount.prefix.trim_end_matches('/').is_empty() {
+                router.get("/*path", handler).not_found(not_found);
+            } else {
+                let mut sub = Router::new();
+                sub.get("/*path", handler).not_found(not_found);
+                router.try_mount(&mount.prefix, sub)
+                    .map_err(|e| ConfigError::invalid(format!("static[{}].prefix", i), e.to_string()))?;
+            }
+        }
+        Ok(router)
+    }
+
+    /**
+     * 拆成每个监听器的路由表和注入了[`AppState`]的扩展表，用于[`Server::replace_all`]
+     *
+     * [`Server::replace_all`]: super::server::Server::replace_all
+     */
+    pub fn into_parts(self) -> Vec<(Router, Extensions)> {
+        let mut extensions = Extensions::default();
+        extensions.insert(self.state);
+        self.routers.into_iter().map(|router| (router, extensions.clone())).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::StaticMount;
+    use http::httprequest::HttpRequst;
+    use http::httpresponse::HttpResponse;
+
+    fn dispatch(parts: &[(Router, Extensions)], raw: &str) -> HttpResponse {
+        let (router, extensions) = &parts[0];
+        let mut req: HttpRequst = raw.to_string().into();
+        req.extensions = extensions.clone();
+        router.dispatch(req)
+    }
+
+    fn send(parts: &[(Router, Extensions)], method: &str, path: &str, body: &str) -> HttpResponse {
+        dispatch(parts, &format!("{} {} HTTP/1.1\r\nContent-Type: application/json\r\n\r\n{}", method, path, body))
+    }
+
+    #[test]
+    fn test_router_wiring() {
+        let mut config = Config::default();
+        config.api.storage = Storage::Memory;
+        config.statics.push(StaticMount { prefix: "/assets".to_string(), ..StaticMount::default() });
+        let parts = App::from_config(&config).unwrap().into_parts();
+        let origin = "Origin: https://example.com\r\n";
+
+        // /api分组经过Cors和ProblemDetails，分组前缀下的404也一样
+        let resp = dispatch(&parts, &format!("GET /api/shipping/characters HTTP/1.1\r\n{}\r\n", origin));
+        assert_eq!((resp.status_code(), resp.header("Access-Control-Allow-Origin")), ("200", Some("*")));
+        let resp = dispatch(&parts, &format!("OPTIONS /api/characters HTTP/1.1\r\n{}Access-Control-Request-Method: PUT\r\n\r\n", origin));
+        assert_eq!(resp.status_code(), "204");
+        assert!(resp.header("Access-Control-Allow-Methods").is_some());
+        let resp = dispatch(&parts, &format!("GET /api/nope HTTP/1.1\r\n{}\r\n", origin));
+        assert_eq!((resp.status_code(), resp.header("Content-Type")), ("404", Some(crate::problem::CONTENT_TYPE)));
+        assert_eq!(resp.header("Access-Control-Allow-Origin"), Some("*"));
+
+        // 静态挂载：根目录和/assets前缀都能找到文件，分组外面的404是错误页，没有CORS头
+        let html = |resp: &HttpResponse| resp.header("Content-Type").is_some_and(|t| t.starts_with("text/html"));
+        let resp = dispatch(&parts, "GET / HTTP/1.1\r\n\r\n");
+        assert!(resp.status_code() == "200" && html(&resp));
+        let resp = dispatch(&parts, "GET /assets/index.html HTTP/1.1\r\n\r\n");
+        assert!(resp.status_code() == "200" && html(&resp));
+        let resp = dispatch(&parts, &format!("GET /nope.html HTTP/1.1\r\n{}\r\n", origin));
+        assert!(resp.status_code() == "404" && html(&resp));
+        assert_eq!(resp.header("Access-Control-Allow-Origin"), None);
+    }
+
+    #[test]
+    fn test_reload_keeps_storage_and_cache() {
+        let mut config = Config::default();
+        config.api.storage = Storage::Memory;
+        let app = App::from_config(&config).unwrap();
+        let state = app.state.clone();
+        let klee = r#"{"name": "Klee", "level": 80, "health": 10287.0, "element": "火", "skills": ["砰砰礼物"]}"#;
+        assert_eq!(send(&app.into_parts(), "POST", "/api/characters", klee).status_code(), "201");
+
+        // 只改了API前缀：沿用内存中的数据和静态文件缓存
+        let mut renamed = config.clone();
+        renamed.api.prefix = "/v2".to_string();
+        let app = App::reload(&renamed, &config, &state).unwrap();
+        assert!(Arc::ptr_eq(&app.state.characters, &state.characters));
+        assert!(Arc::ptr_eq(&app.state.static_cache, &state.static_cache));
+        assert!(Arc::ptr_eq(&app.state.updates, &state.updates));
+        assert_eq!(send(&app.into_parts(), "GET", "/v2/characters/Klee", "").status_code(), "200");
+
+        // 缓存配置变化时换成新的缓存，数据仍然保留
+        let mut resized = renamed.clone();
+        resized.cache.max_bytes += 1;
+        let app = App::reload(&resized, &renamed, &state).unwrap();
+        assert!(!Arc::ptr_eq(&app.state.static_cache, &state.static_cache));
+        assert_eq!(send(&app.into_parts(), "GET", "/v2/characters/Klee", "").status_code(), "200");
+
+        // 存储配置变化时重新打开，内存中写入的数据不再可见
+        let mut seeded = resized.clone();
+        seeded.api.storage = Storage::Json;
+        let app = App::reload(&seeded, &resized, &state).unwrap();
+        assert!(!Arc::ptr_eq(&app.state.characters, &state.characters));
+        assert_eq!(send(&app.into_parts(), "GET", "/v2/characters/Klee", "").status_code(), "404");
+    }
+}
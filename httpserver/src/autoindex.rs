@@ -0,0 +1,291 @@
+use super::embed;
+use super::router::MountPrefix;
+use super::staticfile::StaticOptions;
+use http::httprequest::{percent_encode, HttpRequst};
+use http::httpresponse::{HttpResponse, ResponseBody};
+use http::mime;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+/**
+ * # 目录项
+ * `modified`为UNIX时间戳(秒)，目录的`size`为0，`mime`为`inode/directory`
+ */
+#[derive(Debug, Serialize)]
+pub struct Entry {
+    pub name: String,
+    pub dir: bool,
+    pub size: u64,
+    pub modified: u64,
+    pub mime: String,
+}
+
+/**
+ * 排序字段，对应查询参数`sort=name|size|mtime|type`
+ */
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum SortKey {
+    Name,
+    Size,
+    Modified,
+    Type,
+}
+
+impl From<&str> for SortKey {
+    fn from(s: &str) -> SortKey {
+        match s {
+            "size" => SortKey::Size,
+            "mtime" | "modified" => SortKey::Modified,
+            "type" | "mime" => SortKey::Type,
+            _ => SortKey::Name,
+        }
+    }
+}
+
+impl SortKey {
+    fn as_str(&self) -> &'static str {
+        match self {
+            SortKey::Name => "name",
+            SortKey::Size => "size",
+            SortKey::Modified => "mtime",
+            SortKey::Type => "type",
+        }
+    }
+}
+
+/**
+ * 读取目录，按[`StaticOptions::is_listed`]过滤隐藏文件和备份文件，
+ * 不符合符号链接策略的目录项也不会列出
+ */
+pub fn read_dir(dir: &Path, opts: &StaticOptions) -> io::Result<Vec<Entry>> {
+    if opts.embedded {
+        return Ok(read_embedded_dir(dir, opts));
+    }
+    let mut entries = Vec::new();
+    for item in fs::read_dir(dir)? {
+        let item = item?;
+        let name = match item.file_name().into_string() {
+            Ok(name) => name,
+            Err(_) => continue,
+        };
+        if !opts.is_listed(&name) {
+            continue;
+        }
+        let rel = item.path().strip_prefix(&opts.root).map(|p| p.to_path_buf());
+        if !rel.map(|rel| opts.check_symlinks(&rel.to_string_lossy())).unwrap_or(false) {
+            continue;
+        }
+        let meta = match fs::metadata(item.path()) {
+            Ok(meta) => meta,
+            Err(_) => continue,
+        };
+        let modified = meta.modified().ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let dir = meta.is_dir();
+        let mime = if dir {
+            "inode/directory".to_string()
+        } else {
+            mime::registry().content_type(&name).to_string()
+        };
+        entries.push(Entry {
+            size: if dir { 0 } else { meta.len() },
+            name,
+            dir,
+            modified,
+            mime,
+        });
+    }
+    Ok(entries)
+}
+
+/**
+ * 嵌入资源没有修改时间，`modified`为0
+ */
+fn read_embedded_dir(dir: &Path, opts: &StaticOptions) -> Vec<Entry> {
+    let rel = dir.strip_prefix(&opts.root).unwrap_or(dir).to_string_lossy();
+    embed::read_dir(&rel).into_iter()
+        .filter(|(name, _, _)| opts.is_listed(name))
+        .map(|(name, dir, size)| Entry {
+            mime: if dir {
+                "inode/directory".to_string()
+            } else {
+                mime::registry().content_type(&name).to_string()
+            },
+            name,
+            dir,
+            size,
+            modified: 0,
+        })
+        .collect()
+}
+
+/**
+ * 目录总是排在文件前面，其余按`key`排序
+ */
+pub fn sort(entries: &mut [Entry], key: SortKey, desc: bool) {
+    entries.sort_by(|a, b| {
+        let ord = match key {
+            SortKey::Name => a.name.to_lowercase().cmp(&b.name.to_lowercase()),
+            SortKey::Size => a.size.cmp(&b.size),
+            SortKey::Modified => a.modified.cmp(&b.modified),
+            SortKey::Type => a.mime.cmp(&b.mime),
+        }
+        .then_with(|| a.name.cmp(&b.name));
+        let ord = if desc { ord.reverse() } else { ord };
+        b.dir.cmp(&a.dir).then(ord)
+    });
+}
+
+/**
+ * UNIX时间戳格式化为`YYYY-MM-DD HH:MM`(UTC)
+ */
+fn format_time(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+    // 公历日期换算，见 http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02} {:02}:{:02}", year, month, day, rem / 3600, rem % 3600 / 60)
+}
+
+fn format_size(size: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "K", "M", "G"];
+    let mut size = size as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{}{}", size, UNITS[unit])
+    } else {
+        format!("{:.1}{}", size, UNITS[unit])
+    }
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+/**
+ * 渲染HTML列表，表头可以点击切换排序
+ */
+pub fn render_html(url_path: &str, entries: &[Entry], key: SortKey, desc: bool) -> String {
+    let title = escape_html(url_path);
+    let column = |k: SortKey, label: &str| {
+        let order = if k == key && !desc { "desc" } else { "asc" };
+        format!("<th><a href=\"?sort={}&amp;order={}\">{}</a></th>", k.as_str(), order, label)
+    };
+
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>Index of {0}</title></head>\n\
+         <body>\n<h1>Index of {0}</h1>\n<table>\n<tr>{1}{2}{3}{4}</tr>\n",
+        title,
+        column(SortKey::Name, "Name"),
+        column(SortKey::Size, "Size"),
+        column(SortKey::Modified, "Modified"),
+        column(SortKey::Type, "Type"),
+    );
+    if url_path != "/" {
+        html.push_str("<tr><td><a href=\"../\">../</a></td><td></td><td></td><td></td></tr>\n");
+    }
+    for entry in entries {
+        let slash = if entry.dir { "/" } else { "" };
+        html.push_str(&format!(
+            "<tr><td><a href=\"{}{}\">{}{}</a></td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+            percent_encode(&entry.name),
+            slash,
+            escape_html(&entry.name),
+            slash,
+            if entry.dir { "-".to_string() } else { format_size(entry.size) },
+            format_time(entry.modified),
+            escape_html(&entry.mime),
+        ));
+    }
+    html.push_str("</table>\n</body>\n</html>\n");
+    html
+}
+
+/**
+ * # 目录列表
+ * 根据`Accept`返回HTML或JSON，`sort`和`order`查询参数控制排序
+ ```text
+ GET /artifacts/?sort=size&order=desc
+ Accept: application/json
+ ```
+ */
+pub fn listing(req: &HttpRequst, dir: &Path, opts: &StaticOptions) -> HttpResponse {
+    let mut entries = match read_dir(dir, opts) {
+        Ok(entries) => entries,
+        Err(_) => return HttpResponse::new("404", None, None),
+    };
+    let params = req.query_params();
+    let key: SortKey = params.get("sort").map(|s| s.as_str()).unwrap_or("name").into();
+    let desc = params.get("order").map(|o| o == "desc").unwrap_or(false);
+    sort(&mut entries, key, desc);
+
+    // 挂载在前缀下时标题显示完整路径
+    let base = req.state::<MountPrefix>().map(|m| m.0.as_str()).unwrap_or("");
+    let url_path = format!("{}{}", base, req.resource.path());
+    let wants_json = req.header("Accept")
+        .map(|a| a.contains("application/json"))
+        .unwrap_or(false);
+
+    let mut headers = HashMap::new();
+    let body = if wants_json {
+        headers.insert("Content-Type", "application/json; charset=utf-8");
+        serde_json::to_string_pretty(&entries).unwrap_or_default()
+    } else {
+        headers.insert("Content-Type", "text/html; charset=utf-8");
+        render_html(&url_path, &entries, key, desc)
+    };
+    HttpResponse::new("200", Some(headers), Some(ResponseBody::Text(body)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str, dir: bool, size: u64) -> Entry {
+        Entry {
+            name: name.to_string(),
+            dir,
+            size,
+            modified: 0,
+            mime: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_sort_dirs_first() {
+        let mut entries = vec![entry("b.zip", false, 10), entry("img", true, 0), entry("a.txt", false, 20)];
+        sort(&mut entries, SortKey::Size, true);
+        let names: Vec<&str> = entries.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, vec!["img", "a.txt", "b.zip"]);
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!(format_time(0), "1970-01-01 00:00");
+        assert_eq!(format_time(1700000000), "2023-11-14 22:13");
+        assert_eq!(format_size(512), "512B");
+        assert_eq!(format_size(760562), "742.7K");
+    }
+}
@@ -0,0 +1,237 @@
+use http::httpresponse::ResponseBody;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/**
+ * # 缓存配置
+ * - `max_bytes`: 缓存总字节数上限，读取`STATIC_CACHE_BYTES`，为0时关闭缓存(默认)
+ * - `max_file_bytes`: 单个文件超过这个大小不缓存，读取`STATIC_CACHE_FILE_BYTES`，默认1MiB
+ *
+ * 配置文件中对应`[cache]`，没有写的字段使用环境变量的值
+ */
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheOptions {
+    pub max_bytes: usize,
+    pub max_file_bytes: usize,
+}
+
+impl CacheOptions {
+    pub fn from_env() -> Self {
+        let read = |key: &str, default: usize| {
+            env::var(key).ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default)
+        };
+        CacheOptions {
+            max_bytes: read("STATIC_CACHE_BYTES", 0),
+            max_file_bytes: read("STATIC_CACHE_FILE_BYTES", 1024 * 1024),
+        }
+    }
+}
+
+impl Default for CacheOptions {
+    fn default() -> Self {
+        CacheOptions::from_env()
+    }
+}
+
+/**
+ * 缓存命中情况，用于`/api/cache/stats`
+ */
+#[derive(Debug, Serialize, PartialEq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+    pub bytes: usize,
+    pub capacity: usize,
+}
+
+/**
+ * 文件的修改时间和长度，任意一个变化就认为缓存失效
+ */
+#[derive(Debug, PartialEq, Clone, Copy)]
+struct Stamp {
+    modified: Option<SystemTime>,
+    len: u64,
+}
+
+impl Stamp {
+    fn of(path: &Path) -> Option<Stamp> {
+        let meta = fs::metadata(path).ok()?;
+        Some(Stamp {
+            modified: meta.modified().ok(),
+            len: meta.len(),
+        })
+    }
+}
+
+struct Entry {
+    body: ResponseBody,
+    stamp: Stamp,
+    tick: u64,
+}
+
+#[derive(Default)]
+struct Inner {
+    entries: HashMap<PathBuf, Entry>,
+    // 最近使用顺序，tick越小越久没有使用
+    order: BTreeMap<u64, PathBuf>,
+    bytes: usize,
+    tick: u64,
+}
+
+impl Inner {
+    fn remove(&mut self, path: &Path) {
+        if let Some(entry) = self.entries.remove(path) {
+            self.order.remove(&entry.tick);
+            self.bytes -= entry.body.as_bytes().len();
+        }
+    }
+
+    fn touch(&mut self, path: &Path) {
+        self.tick += 1;
+        let tick = self.tick;
+        if let Some(entry) = self.entries.get_mut(path) {
+            self.order.remove(&entry.tick);
+            entry.tick = tick;
+            self.order.insert(tick, path.to_path_buf());
+        }
+    }
+}
+
+/**
+# StaticCache
+按总字节数做LRU淘汰的静态文件缓存，每次命中都会比较文件的修改时间和长度，
+磁盘上的文件被修改后下一次请求就会重新读取，不需要重启服务
+```rust,ignore
+let body = cache.get_or_load(&full_path, |path| fs::read(path).ok().map(ResponseBody::Binary));
+```
+ */
+pub struct StaticCache {
+    opts: CacheOptions,
+    inner: Mutex<Inner>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl StaticCache {
+    pub fn new(opts: CacheOptions) -> Self {
+        StaticCache {
+            opts,
+            inner: Mutex::new(Inner::default()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.opts.max_bytes > 0
+    }
+
+    /**
+     * 从缓存中取出文件，没有命中或已经过期时调用`load`读取并放入缓存
+     */
+    pub fn get_or_load(
+        &self,
+        path: &Path,
+        load: impl FnOnce(&Path) -> Option<ResponseBody>,
+    ) -> Option<ResponseBody> {
+        if !self.enabled() {
+            return load(path);
+        }
+        let stamp = Stamp::of(path);
+
+        {
+            let mut inner = self.inner.lock().unwrap();
+            let cached = inner.entries.get(path).map(|e| (e.stamp, e.body.clone()));
+            match cached {
+                Some((cached_stamp, body)) if Some(cached_stamp) == stamp => {
+                    inner.touch(path);
+                    self.hits.fetch_add(1, Ordering::Relaxed);
+                    return Some(body);
+                }
+                Some(_) => inner.remove(path),
+                None => (),
+            }
+        }
+
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let body = load(path)?;
+        if let Some(stamp) = stamp {
+            self.insert(path, body.clone(), stamp);
+        }
+        Some(body)
+    }
+
+    fn insert(&self, path: &Path, body: ResponseBody, stamp: Stamp) {
+        let size = body.as_bytes().len();
+        if size > self.opts.max_file_bytes || size > self.opts.max_bytes {
+            return;
+        }
+        let mut inner = self.inner.lock().unwrap();
+        inner.remove(path);
+        while inner.bytes + size > self.opts.max_bytes {
+            let oldest = match inner.order.first_key_value() {
+                Some((_, oldest)) => oldest.clone(),
+                None => break,
+            };
+            inner.remove(&oldest);
+        }
+        inner.tick += 1;
+        let tick = inner.tick;
+        inner.order.insert(tick, path.to_path_buf());
+        inner.bytes += size;
+        inner.entries.insert(path.to_path_buf(), Entry { body, stamp, tick });
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let inner = self.inner.lock().unwrap();
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: inner.entries.len(),
+            bytes: inner.bytes,
+            capacity: self.opts.max_bytes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lru_and_invalidation() {
+        let dir = env::temp_dir().join(format!("httpserver-cache-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        for name in ["a", "b", "c"] {
+            fs::write(dir.join(name), "0123456789").unwrap();
+        }
+        let cache = StaticCache::new(CacheOptions { max_bytes: 25, max_file_bytes: 20 });
+        let read = |path: &Path| fs::read(path).ok().map(ResponseBody::Binary);
+
+        cache.get_or_load(&dir.join("a"), read);
+        cache.get_or_load(&dir.join("b"), read);
+        cache.get_or_load(&dir.join("a"), read);
+        // 放入c时淘汰最久没有使用的b
+        cache.get_or_load(&dir.join("c"), read);
+        assert_eq!(cache.stats(), CacheStats { hits: 1, misses: 3, entries: 2, bytes: 20, capacity: 25 });
+        cache.get_or_load(&dir.join("a"), read);
+        assert_eq!(cache.stats().hits, 2);
+
+        fs::write(dir.join("a"), "changed").unwrap();
+        let body = cache.get_or_load(&dir.join("a"), read).unwrap();
+        assert_eq!(body.as_bytes(), b"changed");
+        assert_eq!(cache.stats().misses, 4);
+        assert_eq!(cache.stats().bytes, 17);
+    }
+}
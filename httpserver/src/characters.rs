@@ -0,0 +1,690 @@
+use super::handler::{Handler, IntoResponse};
+use super::negotiate::{represent, Format};
+use super::problem::Problem;
+use super::repository::StorageError;
+use super::router::{MountPrefix, Router};
+use super::state::AppState;
+use http::httprequest::{percent_encode, HttpRequst};
+use http::httpresponse::{HttpResponse, ResponseBody};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+/**
+ * 等级的范围
+ */
+pub const LEVELS: std::ops::RangeInclusive<i32> = 1..=90;
+
+/**
+ * 合法的元素
+ */
+pub const ELEMENTS: [&str; 7] = ["风", "火", "雷", "水", "草", "冰", "岩"];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OrderStatus {
+    pub(crate) name: String,
+    pub(crate) level: i32,
+    pub(crate) health: f32,
+    pub(crate) element: String,
+    pub(crate) skills: Vec<String>,
+}
+
+impl OrderStatus {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /**
+     * 检查字段的取值，返回所有不合法的字段说明
+     */
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut errors = Vec::new();
+        if self.name.trim().is_empty() || self.name.contains('/') {
+            errors.push("name must be non-empty and must not contain '/'".to_string());
+        }
+        if !LEVELS.contains(&self.level) {
+            errors.push(format!("level must be between {} and {}", LEVELS.start(), LEVELS.end()));
+        }
+        if !self.health.is_finite() || self.health < 0.0 {
+            errors.push("health must be a non-negative number".to_string());
+        }
+        if !ELEMENTS.contains(&self.element.as_str()) {
+            errors.push(format!("element must be one of {}", ELEMENTS.join(", ")));
+        }
+        if errors.is_empty() { Ok(()) } else { Err(errors) }
+    }
+}
+
+/**
+ * 可以用来排序和选择的字段
+ */
+pub const FIELDS: [&str; 5] = ["name", "level", "health", "element", "skills"];
+
+/**
+ * 每页的默认数量和上限
+ */
+const DEFAULT_LIMIT: usize = 20;
+const MAX_LIMIT: usize = 100;
+
+/**
+ * # 分页方式
+ * - `Offset`: 跳过前面的若干条，可以直接跳到任意一页
+ * - `Cursor`: 从上一页最后一条的排序键之后开始，翻页期间有增删也不会重复或遗漏，`None`为第一页
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum Paging {
+    Offset(usize),
+    Cursor(Option<Vec<Value>>),
+}
+
+impl Paging {
+    /**
+     * 查询串中的写法，游标是排序键的JSON的十六进制编码
+     */
+    fn param(&self) -> (&'static str, String) {
+        match self {
+            Paging::Offset(offset) => ("offset", offset.to_string()),
+            Paging::Cursor(None) => ("cursor", String::new()),
+            Paging::Cursor(Some(key)) => {
+                let json = serde_json::to_vec(key).unwrap();
+                ("cursor", json.iter().map(|b| format!("{:02x}", b)).collect())
+            }
+        }
+    }
+}
+
+fn decode_cursor(cursor: &str) -> Option<Vec<Value>> {
+    if !cursor.len().is_multiple_of(2) {
+        return None;
+    }
+    let bytes = (0..cursor.len()).step_by(2)
+        .map(|i| u8::from_str_radix(cursor.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    serde_json::from_slice(&bytes).ok()
+}
+
+/**
+ * 比较两个字段值：数字按大小，字符串按字典序，数组逐个元素比较
+ */
+fn compare(a: &Value, b: &Value) -> Ordering {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => {
+            a.as_f64().partial_cmp(&b.as_f64()).unwrap_or(Ordering::Equal)
+        }
+        (Value::String(a), Value::String(b)) => a.cmp(b),
+        (Value::Array(a), Value::Array(b)) => a.iter().zip(b)
+            .map(|(a, b)| compare(a, b))
+            .find(|o| o.is_ne())
+            .unwrap_or_else(|| a.len().cmp(&b.len())),
+        _ => Ordering::Equal,
+    }
+}
+
+/**
+# 列表查询
+`GET /`的查询参数，不认识的参数会被忽略：
+- `element=火,雷`: 元素是其中之一
+- `level_min=50&level_max=60`: 等级范围，包含两端
+- `skill=斩`: 有技能名包含这段文字
+- `sort=-level,health`: 排序字段，`-`表示降序；最后总是按名字升序，默认只按名字
+- `limit=20`: 每页的数量，最多100
+- `offset=40`或`cursor=`: 分页方式，`cursor`为空时是第一页，之后使用`Link`中`next`的地址
+- `fields=name,level`: 只返回这些字段
+```rust,ignore
+let query = ListQuery::parse(&req.query_params())?;
+let page = query.apply(&state.characters.list()?);
+```
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListQuery {
+    elements: Vec<String>,
+    level_min: Option<i32>,
+    level_max: Option<i32>,
+    skill: Option<String>,
+    /** 排序字段和是否降序 */
+    sort: Vec<(String, bool)>,
+    limit: usize,
+    paging: Paging,
+    fields: Option<Vec<String>>,
+}
+
+/**
+ * 查询的一页结果，`total`为符合条件的总数，`next`等是相邻页的分页参数
+ */
+#[derive(Debug)]
+pub struct Page {
+    pub items: Vec<Value>,
+    pub total: usize,
+    pub first: Paging,
+    pub prev: Option<Paging>,
+    pub next: Option<Paging>,
+    pub last: Option<Paging>,
+}
+
+impl ListQuery {
+    /**
+     * 解析查询参数，字段名或数字不合法时返回错误说明
+     */
+    pub fn parse(params: &HashMap<String, String>) -> Result<ListQuery, String> {
+        let list = |key: &str| -> Vec<String> {
+            params.get(key).map(|v| {
+                v.split(',').map(str::trim).filter(|s| !s.is_empty()).map(String::from).collect()
+            }).unwrap_or_default()
+        };
+        let field = |name: String| -> Result<String, String> {
+            match FIELDS.contains(&name.as_str()) {
+                true => Ok(name),
+                false => Err(format!("unknown field \"{}\", expected one of {}", name, FIELDS.join(", "))),
+            }
+        };
+        let number = |key: &str| -> Result<Option<i64>, String> {
+            params.get(key).filter(|v| !v.is_empty())
+                .map(|v| v.parse::<i64>().map_err(|_| format!("{} must be an integer", key)))
+                .transpose()
+        };
+
+        let mut sort = list("sort").into_iter()
+            .map(|key| match key.strip_prefix('-') {
+                Some(name) => field(name.to_string()).map(|name| (name, true)),
+                None => field(key).map(|name| (name, false)),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if !sort.iter().any(|(name, _)| name == "name") {
+            sort.push(("name".to_string(), false));
+        }
+        let fields = match list("fields") {
+            fields if fields.is_empty() => None,
+            fields => Some(fields.into_iter().map(field).collect::<Result<Vec<_>, _>>()?),
+        };
+        let limit = match number("limit")? {
+            None => DEFAULT_LIMIT,
+            Some(n) if (1..=MAX_LIMIT as i64).contains(&n) => n as usize,
+            Some(_) => return Err(format!("limit must be between 1 and {}", MAX_LIMIT)),
+        };
+        let paging = match (number("offset")?, params.get("cursor")) {
+            (Some(_), Some(_)) => return Err("offset and cursor cannot be used together".to_string()),
+            (Some(offset), None) => Paging::Offset(usize::try_from(offset).map_err(|_| "offset must not be negative".to_string())?),
+            (None, Some(cursor)) if cursor.is_empty() => Paging::Cursor(None),
+            (None, Some(cursor)) => match decode_cursor(cursor) {
+                Some(key) if key.len() == sort.len() => Paging::Cursor(Some(key)),
+                _ => return Err("invalid cursor".to_string()),
+            },
+            (None, None) => Paging::Offset(0),
+        };
+        let level = |key: &str| -> Result<Option<i32>, String> {
+            number(key)?.map(|n| i32::try_from(n).map_err(|_| format!("{} is out of range", key))).transpose()
+        };
+
+        Ok(ListQuery {
+            elements: list("element"),
+            level_min: level("level_min")?,
+            level_max: level("level_max")?,
+            skill: params.get("skill").filter(|s| !s.is_empty()).cloned(),
+            sort,
+            limit,
+            paging,
+            fields,
+        })
+    }
+
+    fn matches(&self, character: &OrderStatus) -> bool {
+        (self.elements.is_empty() || self.elements.contains(&character.element))
+            && self.level_min.is_none_or(|min| character.level >= min)
+            && self.level_max.is_none_or(|max| character.level <= max)
+            && self.skill.as_ref().is_none_or(|skill| character.skills.iter().any(|s| s.contains(skill.as_str())))
+    }
+
+    fn key(&self, item: &Value) -> Vec<Value> {
+        self.sort.iter().map(|(name, _)| item[name].clone()).collect()
+    }
+
+    /**
+     * 按排序字段比较两个排序键，降序的字段结果取反
+     */
+    fn order(&self, a: &[Value], b: &[Value]) -> Ordering {
+        self.sort.iter().zip(a.iter().zip(b))
+            .map(|((_, desc), (a, b))| if *desc { compare(a, b).reverse() } else { compare(a, b) })
+            .find(|o| o.is_ne())
+            .unwrap_or(Ordering::Equal)
+    }
+
+    /**
+     * 过滤、排序，然后取出当前页
+     */
+    pub fn apply(&self, characters: &[OrderStatus]) -> Page {
+        let mut items: Vec<(Vec<Value>, Value)> = characters.iter()
+            .filter(|c| self.matches(c))
+            .map(|c| {
+                let item = serde_json::to_value(c).unwrap();
+                (self.key(&item), item)
+            })
+            .collect();
+        items.sort_by(|a, b| self.order(&a.0, &b.0));
+        let total = items.len();
+
+        let start = match &self.paging {
+            Paging::Offset(offset) => (*offset).min(total),
+            Paging::Cursor(None) => 0,
+            Paging::Cursor(Some(after)) => items.partition_point(|(key, _)| self.order(key, after).is_le()),
+        };
+        let end = (start + self.limit).min(total);
+        let more = end < total;
+        let last_offset = total.saturating_sub(1) / self.limit * self.limit;
+        let (first, prev, next, last) = match &self.paging {
+            Paging::Offset(offset) => (
+                Paging::Offset(0),
+                (*offset > 0).then(|| Paging::Offset(offset.saturating_sub(self.limit).min(last_offset))),
+                more.then_some(Paging::Offset(end)),
+                Some(Paging::Offset(last_offset)),
+            ),
+            Paging::Cursor(_) => (
+                Paging::Cursor(None),
+                None,
+                more.then(|| Paging::Cursor(Some(items[end - 1].0.clone()))),
+                None,
+            ),
+        };
+
+        let items = items.drain(start..end)
+            .map(|(_, item)| match &self.fields {
+                None => item,
+                Some(fields) => fields.iter().map(|f| (f.clone(), item[f].clone())).collect::<serde_json::Map<_, _>>().into(),
+            })
+            .collect();
+        Page { items, total, first, prev, next, last }
+    }
+}
+
+/** `POST`、`PUT`接受的请求体 */
+const JSON: &[&str] = &["application/json"];
+/** `PATCH`的请求体是JSON Merge Patch，也接受普通的JSON */
+const MERGE_PATCH: &[&str] = &["application/merge-patch+json", "application/json"];
+
+/**
+ * 修改类请求的处理器：请求头中的`Content-Type`不是列出的媒体类型时在读取请求体之前回应415
+ */
+struct Json(fn(&HttpRequst) -> Result<HttpResponse, Problem>, &'static [&'static str]);
+
+impl Handler for Json {
+    fn handle(&self, req: &HttpRequst) -> HttpResponse {
+        (self.0)(req).into_response(req)
+    }
+
+    fn check(&self, req: &HttpRequst) -> Option<HttpResponse> {
+        let media_type = req.header("Content-Type").and_then(|v| v.split(';').next()).unwrap_or("").trim();
+        if self.1.iter().any(|accepted| media_type.eq_ignore_ascii_case(accepted)) {
+            return None;
+        }
+        // 检查时还没有经过中间件，由`ProblemDetails`改写成问题详情，才能带上请求ID
+        let mut headers: HashMap<&str, &str> = HashMap::new();
+        headers.insert("Content-Type", "text/plain; charset=utf-8");
+        let text = format!("request body must be {}", self.1.join(" or "));
+        let mut resp = HttpResponse::new("415", Some(headers), Some(ResponseBody::Text(text)));
+        if self.1 == MERGE_PATCH {
+            resp.set_header("Accept-Patch", MERGE_PATCH[0]);
+        }
+        Some(resp)
+    }
+}
+
+/**
+# 角色资源
+`GET /`列出所有角色，`POST /`新建，`GET`、`PUT`、`PATCH`、`DELETE /:name`读取、替换、
+部分修改和删除一个角色。修改成功后把新的列表推送给[`AppState::updates`]的订阅者。
+返回的数据按`Accept`选择格式，见[`Format`]；出错时返回[`Problem`]
+ */
+pub struct CharacterHandler;
+
+impl CharacterHandler {
+    /**
+     * # 角色资源的路由表
+     * 路径都是相对的，由[`App`]挂载到`<api.prefix>/characters`下
+     *
+     * [`App`]: super::app::App
+     */
+    pub fn routes() -> Router {
+        let mut router = Router::new();
+        router
+            .get("/", Self::list)
+            .post("/", Json(Self::create, JSON))
+            .get("/:name", Self::show)
+            .put("/:name", Json(Self::replace, JSON))
+            .patch("/:name", Json(Self::update, MERGE_PATCH))
+            .delete("/:name", Self::delete);
+        router
+    }
+
+    fn state(req: &HttpRequst) -> Result<&AppState, Problem> {
+        req.state::<AppState>().ok_or_else(|| Problem::new("500").detail("application state is not configured"))
+    }
+
+    fn not_found() -> Problem {
+        Problem::from(StorageError::NotFound)
+    }
+
+    /**
+     * 解析请求体：不是合法的JSON时为400，字段缺失、类型不对或取值不合法时为422
+     */
+    fn parse(body: &str) -> Result<OrderStatus, Problem> {
+        Self::from_value(serde_json::from_str(body)?)
+    }
+
+    /**
+     * 不合法的字段逐条列在扩展成员`errors`中
+     */
+    fn from_value(value: Value) -> Result<OrderStatus, Problem> {
+        let character: OrderStatus = serde_json::from_value(value)
+            .map_err(|e| Problem::new("422").detail(e.to_string()))?;
+        character.validate()
+            .map_err(|errors| Problem::new("422").detail("validation failed").member("errors", errors))?;
+        Ok(character)
+    }
+
+    /**
+     * 修改成功后通知订阅者，并用事先协商好的格式返回当前的数据
+     */
+    fn changed(state: &AppState, format: Format, status: &'static str, character: &OrderStatus) -> Result<HttpResponse, Problem> {
+        state.publish_characters();
+        format.respond(status, character)
+    }
+
+    /**
+     * `GET /`：按[`ListQuery`]过滤、排序和分页，`X-Total-Count`为符合条件的总数，
+     * `Link`中是第一页、上一页、下一页和最后一页的地址
+     */
+    pub fn list(req: &HttpRequst) -> Result<HttpResponse, Problem> {
+        let state = Self::state(req)?;
+        let params = req.query_params();
+        let query = ListQuery::parse(&params).map_err(|e| Problem::new("400").detail(e))?;
+        let page = query.apply(&state.characters.list()?);
+
+        let base = req.state::<MountPrefix>().map(|m| m.0.as_str()).unwrap_or("");
+        let link = |paging: &Paging, rel: &str| {
+            let mut params = params.clone();
+            params.remove("offset");
+            params.remove("cursor");
+            let (key, value) = paging.param();
+            params.insert(key.to_string(), value);
+            let mut pairs: Vec<_> = params.iter().collect();
+            pairs.sort();
+            let query: Vec<String> = pairs.iter().map(|(k, v)| format!("{}={}", percent_encode(k), percent_encode(v))).collect();
+            format!("<{}{}?{}>; rel=\"{}\"", base, req.resource.path(), query.join("&"), rel)
+        };
+        let links: Vec<String> = [(Some(&page.first), "first"), (page.prev.as_ref(), "prev"), (page.next.as_ref(), "next"), (page.last.as_ref(), "last")]
+            .into_iter()
+            .filter_map(|(paging, rel)| paging.map(|p| link(p, rel)))
+            .collect();
+
+        let mut resp = represent(req, "200", &page.items)?;
+        resp.set_header("X-Total-Count", &page.total.to_string());
+        resp.set_header("Link", &links.join(", "));
+        Ok(resp)
+    }
+
+    /**
+     * `GET /:name`
+     */
+    pub fn show(req: &HttpRequst) -> Result<HttpResponse, Problem> {
+        let state = Self::state(req)?;
+        let character = state.characters.get(req.param("name").unwrap_or(""))?.ok_or_else(Self::not_found)?;
+        represent(req, "200", &character)
+    }
+
+    /**
+     * `POST /`：新建角色，返回201和指向新角色的`Location`，名字已经存在时返回409
+     */
+    pub fn create(req: &HttpRequst) -> Result<HttpResponse, Problem> {
+        let state = Self::state(req)?;
+        let format = Format::negotiate(req, &Format::ALL)?;
+        let character = Self::parse(&req.msg_body)?;
+        state.characters.insert(character.clone())?;
+        let base = req.state::<MountPrefix>().map(|m| m.0.as_str()).unwrap_or("");
+        let mut resp = Self::changed(state, format, "201", &character)?;
+        resp.set_header("Location", &format!("{}/{}", base, percent_encode(&character.name)));
+        Ok(resp)
+    }
+
+    /**
+     * `PUT /:name`：用请求体替换整个角色，请求体中的名字不同时改名
+     */
+    pub fn replace(req: &HttpRequst) -> Result<HttpResponse, Problem> {
+        let state = Self::state(req)?;
+        let format = Format::negotiate(req, &Format::ALL)?;
+        let character = Self::parse(&req.msg_body)?;
+        state.characters.replace(req.param("name").unwrap_or(""), character.clone())?;
+        Self::changed(state, format, "200", &character)
+    }
+
+    /**
+     * `PATCH /:name`：JSON Merge Patch(RFC 7396)，只修改请求体中出现的字段。
+     * 合并在[`Repository::update`]中进行，同时修改同一个角色的请求不会丢失对方的修改
+     *
+     * [`Repository::update`]: super::repository::Repository::update
+     */
+    pub fn update(req: &HttpRequst) -> Result<HttpResponse, Problem> {
+        let state = Self::state(req)?;
+        let format = Format::negotiate(req, &Format::ALL)?;
+        let Value::Object(patch) = serde_json::from_str::<Value>(&req.msg_body)? else {
+            return Err(Problem::new("422").detail("patch must be a JSON object"));
+        };
+        let character = state.characters.update(req.param("name").unwrap_or(""), Box::new(|current| {
+            let mut merged = serde_json::to_value(&current).unwrap();
+            for (key, value) in patch {
+                match value {
+                    Value::Null => merged.as_object_mut().unwrap().remove(&key),
+                    value => merged.as_object_mut().unwrap().insert(key, value),
+                };
+            }
+            Self::from_value(merged)
+        }))?;
+        Self::changed(state, format, "200", &character)
+    }
+
+    /**
+     * `DELETE /:name`：成功时返回204
+     */
+    pub fn delete(req: &HttpRequst) -> Result<HttpResponse, Problem> {
+        let state = Self::state(req)?;
+        state.characters.remove(req.param("name").unwrap_or(""))?;
+        state.publish_characters();
+        Ok(HttpResponse::new("204", None, None))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::{CacheOptions, StaticCache};
+    use crate::problem::CONTENT_TYPE;
+    use crate::repository::JsonFile;
+    use serde_json::json;
+    use std::fs;
+    use std::env;
+    use std::sync::Arc;
+
+    fn hutao() -> Value {
+        json!({ "name": "胡桃", "level": 90, "health": 15552.0, "element": "火", "skills": ["蝶引来生"] })
+    }
+
+    #[test]
+    fn test_validate() {
+        let character = |patch: Value| {
+            let mut value = hutao();
+            value.as_object_mut().unwrap().extend(patch.as_object().unwrap().clone());
+            serde_json::from_value::<OrderStatus>(value).unwrap().validate()
+        };
+        assert_eq!(character(json!({})), Ok(()));
+        assert_eq!(character(json!({ "level": 0 })).unwrap_err().len(), 1);
+        assert_eq!(character(json!({ "level": 91, "health": -1.0, "element": "光", "name": "a/b" })).unwrap_err().len(), 4);
+    }
+
+    #[test]
+    fn test_crud_persists_atomically() {
+        let dir = env::temp_dir().join(format!("httpserver-characters-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("characters.json");
+        fs::write(&file, "[]").unwrap();
+
+        let characters = JsonFile::open(&file).unwrap();
+        let state = Arc::new(AppState::new(Arc::new(characters), Arc::new(StaticCache::new(CacheOptions::default()))));
+        let mut router = Router::new();
+        router.group("/api", |api| { api.mount("/characters", CharacterHandler::routes()); });
+        let send = |method: &str, path: &str, body: Option<&str>| {
+            let raw = match body {
+                Some(body) => format!("{} {} HTTP/1.1\r\nContent-Type: application/json\r\n\r\n{}", method, path, body),
+                None => format!("{} {} HTTP/1.1\r\n\r\n", method, path),
+            };
+            let mut req: HttpRequst = raw.into();
+            req.extensions.insert(state.clone());
+            router.dispatch(req)
+        };
+        let saved = || serde_json::from_str::<Value>(&fs::read_to_string(&file).unwrap()).unwrap();
+        let encoded = percent_encode("胡桃");
+
+        let resp = send("POST", "/api/characters", Some(&hutao().to_string()));
+        assert_eq!(resp.status_code(), "201");
+        assert_eq!(resp.header("Location"), Some(format!("/api/characters/{}", encoded).as_str()));
+        assert_eq!(saved(), json!([hutao()]));
+        let resp = send("GET", "/api/characters?fields=name&limit=1", None);
+        assert_eq!(resp.header("X-Total-Count"), Some("1"));
+        assert_eq!(resp.header("Link"), Some(r#"</api/characters?fields=name&limit=1&offset=0>; rel="first", </api/characters?fields=name&limit=1&offset=0>; rel="last""#));
+        assert_eq!(send("GET", "/api/characters?sort=x", None).status_code(), "400");
+        assert_eq!(send("POST", "/api/characters", Some(&hutao().to_string())).status_code(), "409");
+        assert_eq!(send("POST", "/api/characters", Some("{")).status_code(), "400");
+        assert_eq!(send("POST", "/api/characters", Some(r#"{"name": "x"}"#)).status_code(), "422");
+        let mut invalid = hutao();
+        invalid["name"] = json!("钟离");
+        invalid["element"] = json!("光");
+        let resp = send("POST", "/api/characters", Some(&invalid.to_string()));
+        assert_eq!((resp.status_code(), resp.header("Content-Type")), ("422", Some(CONTENT_TYPE)));
+        match resp.body() {
+            Some(ResponseBody::Text(body)) => {
+                let problem: Value = serde_json::from_str(body).unwrap();
+                assert_eq!((&problem["instance"], problem["errors"].as_array().unwrap().len()), (&json!("/api/characters"), 1));
+            }
+            body => panic!("unexpected body {:?}", body),
+        }
+        // 不是JSON的请求体在读取之前就被拒绝
+        let mut req: HttpRequst = "POST /api/characters HTTP/1.1\r\nContent-Type: text/plain\r\n\r\n".to_string().into();
+        assert_eq!(router.check(&mut req).map(|resp| resp.status_code().to_string()), Some("415".to_string()));
+
+        // 按Accept选择格式，没有可接受的格式时不做修改
+        let accept = |method: &str, path: &str, accept: &str| {
+            let raw = format!("{} {} HTTP/1.1\r\nContent-Type: application/json\r\nAccept: {}\r\n\r\n{}", method, path, accept, hutao());
+            let mut req: HttpRequst = raw.into();
+            req.extensions.insert(state.clone());
+            router.dispatch(req)
+        };
+        let resp = accept("GET", "/api/characters?fields=name,level", "text/csv;q=0.9, application/json;q=0.5");
+        assert_eq!(resp.header("Content-Type"), Some("text/csv; charset=utf-8"));
+        assert_eq!(resp.body(), Some(&ResponseBody::Text("name,level\n胡桃,90\n".to_string())));
+        assert_eq!(accept("DELETE", "/api/characters/x", "image/png").status_code(), "404");
+        assert_eq!(accept("POST", "/api/characters", "image/png").status_code(), "406");
+        assert_eq!(saved(), json!([hutao()]));
+
+        let path = format!("/api/characters/{}", encoded);
+        assert_eq!(send("GET", &path, None).status_code(), "200");
+        assert_eq!(send("GET", "/api/characters/nobody", None).status_code(), "404");
+        let resp = send("PATCH", &path, Some(r#"{"level": 80, "skills": ["蝶引来生", "安神秘法"]}"#));
+        assert_eq!(resp.status_code(), "200");
+        assert_eq!(saved()[0]["level"], json!(80));
+        assert_eq!(saved()[0]["skills"].as_array().unwrap().len(), 2);
+        assert_eq!(send("PATCH", &path, Some(r#"{"health": -5}"#)).status_code(), "422");
+        assert_eq!(saved()[0]["health"], json!(15552.0));
+        // PATCH的请求体也可以标成merge-patch，其它修改仍然只接受JSON
+        let raw = format!("PATCH {} HTTP/1.1\r\nContent-Type: application/merge-patch+json\r\n\r\n{{\"level\": 81}}", path);
+        let mut req: HttpRequst = raw.into();
+        assert_eq!(router.check(&mut req).map(|resp| resp.status_code().to_string()), None);
+        req.extensions.insert(state.clone());
+        assert_eq!(router.dispatch(req).status_code(), "200");
+        assert_eq!(saved()[0]["level"], json!(81));
+        let mut req: HttpRequst = format!("PATCH {} HTTP/1.1\r\nContent-Type: text/plain\r\n\r\n", path).into();
+        assert_eq!(router.check(&mut req).and_then(|resp| resp.header("Accept-Patch").map(str::to_string)),
+                   Some("application/merge-patch+json".to_string()));
+        let mut req: HttpRequst = "POST /api/characters HTTP/1.1\r\nContent-Type: application/merge-patch+json\r\n\r\n".to_string().into();
+        assert_eq!(router.check(&mut req).map(|resp| resp.status_code().to_string()), Some("415".to_string()));
+
+        let mut renamed = hutao();
+        renamed["name"] = json!("堂主");
+        assert_eq!(send("PUT", &path, Some(&renamed.to_string())).status_code(), "200");
+        assert_eq!(send("PUT", &path, Some(&renamed.to_string())).status_code(), "404");
+        match send("GET", "/api/characters", None).body() {
+            Some(ResponseBody::Text(body)) => assert_eq!(serde_json::from_str::<Value>(body).unwrap(), json!([renamed])),
+            body => panic!("unexpected body {:?}", body),
+        }
+
+        let path = format!("/api/characters/{}", percent_encode("堂主"));
+        assert_eq!(send("DELETE", &path, None).status_code(), "204");
+        assert_eq!(send("DELETE", &path, None).status_code(), "404");
+        assert_eq!(saved(), json!([]));
+        // 只留下数据文件，临时文件都已经改名或删除
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        // 数据文件被删掉后存储不可用，恢复之后重新读取
+        fs::remove_file(&file).unwrap();
+        let resp = send("POST", "/api/characters", Some(&hutao().to_string()));
+        assert_eq!((resp.status_code(), resp.header("Retry-After")), ("503", Some("5")));
+        assert_eq!(send("GET", "/api/characters", None).status_code(), "503");
+        fs::write(&file, json!([hutao()]).to_string()).unwrap();
+        assert_eq!(send("GET", &format!("/api/characters/{}", encoded), None).status_code(), "200");
+
+        // 临时文件无法创建时写回失败，数据不变
+        fs::create_dir(dir.join(format!(".characters.json.{}.tmp", std::process::id()))).unwrap();
+        assert_eq!(send("DELETE", &format!("/api/characters/{}", encoded), None).status_code(), "500");
+        assert_eq!(saved(), json!([hutao()]));
+        assert_eq!(state.characters.list().unwrap().len(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn query(q: &str) -> Result<ListQuery, String> {
+        ListQuery::parse(&http::httprequest::parse_query(q))
+    }
+
+    fn sample() -> Vec<OrderStatus> {
+        [("旅行者", 50, "风", "风涡剑"), ("迪卢克", 60, "火", "炽焰斩"), ("刻晴", 55, "雷", "玉衡斩"),
+         ("胡桃", 90, "火", "蝶引来生"), ("钟离", 90, "岩", "天星"), ("可莉", 40, "火", "蹦蹦炸弹")]
+            .into_iter()
+            .map(|(name, level, element, skill)| OrderStatus {
+                name: name.into(), level, health: level as f32 * 100.0, element: element.into(), skills: vec![skill.into()],
+            })
+            .collect()
+    }
+
+    fn names(page: &Page) -> Vec<&str> {
+        page.items.iter().map(|item| item["name"].as_str().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_list_query() {
+        let items = sample();
+        let page = query("element=火,岩&level_min=50&sort=-level,health&fields=name,level").unwrap().apply(&items);
+        assert_eq!(names(&page), ["胡桃", "钟离", "迪卢克"]);
+        assert_eq!(page.items[0], json!({ "name": "胡桃", "level": 90 }));
+        assert_eq!(page.total, 3);
+        assert_eq!(names(&query("skill=斩&level_max=55").unwrap().apply(&items)), ["刻晴"]);
+
+        // 偏移分页
+        let page = query("sort=level&limit=4&offset=2").unwrap().apply(&items);
+        assert_eq!(names(&page), ["刻晴", "迪卢克", "胡桃", "钟离"]);
+        assert_eq!((page.prev, page.next, page.last), (Some(Paging::Offset(0)), None, Some(Paging::Offset(4))));
+
+        // 游标分页：翻页期间删掉已经看过的数据也不会跳过后面的
+        let mut items = items;
+        let page = query("sort=-level&limit=2&cursor=").unwrap().apply(&items);
+        assert_eq!(names(&page), ["胡桃", "钟离"]);
+        let (_, cursor) = page.next.unwrap().param();
+        items.retain(|c| c.name != "胡桃");
+        let page = query(&format!("sort=-level&limit=2&cursor={}", cursor)).unwrap().apply(&items);
+        assert_eq!(names(&page), ["迪卢克", "刻晴"]);
+        assert_eq!(page.total, 5);
+        assert!(page.next.is_some() && page.prev.is_none());
+
+        assert!(query("sort=power").is_err());
+        assert!(query("fields=name,secret").is_err());
+        assert!(query("limit=0").is_err());
+        assert!(query("offset=1&cursor=").is_err());
+        assert!(query("cursor=zz").is_err());
+        assert!(query("sort=level&cursor=5b5d").is_err());
+    }
+}
@@ -0,0 +1,265 @@
+use super::config::{Config, ConfigError, ListenerConfig, Override};
+use std::path::PathBuf;
+
+pub const USAGE: &str = "\
+usage: httpserver [COMMAND] [OPTIONS]
+
+commands:
+  serve                     start the server (default)
+  check-config              load and validate the configuration, then exit
+  routes                    print the route table, then exit
+
+options:
+  -c, --config FILE         read configuration from a TOML file
+  -b, --bind ADDR           listen on ADDR (host, host:port, unix:PATH or systemd[:N]), repeatable;
+                            keeps the other settings of the single configured listener
+  -p, --port PORT           port for --bind addresses without one, or for the configured
+                            listener when used alone (default: its current port, else 3000)
+      --static PREFIX=DIR   serve DIR under URL PREFIX, repeatable; replaces [[static]]
+      --data DIR            directory containing characters.json
+  -w, --workers N           number of worker threads
+      --log-level LEVEL     off, error, warn, info or debug
+      --tls-cert FILE       PEM certificate chain
+      --tls-key FILE        PEM private key
+      --set KEY=VALUE       override any configuration key, e.g. server.max_body_bytes=65536
+  -h, --help                print this help
+  -V, --version             print the version";
+
+/**
+ * # 子命令
+ * - `Serve`: 启动服务，默认
+ * - `CheckConfig`: 只读取并校验配置
+ * - `Routes`: 打印路由表
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    Serve,
+    CheckConfig,
+    Routes,
+    Help,
+    Version,
+}
+
+/**
+# Cli
+命令行参数，具名选项都转换成对配置的[`Override`]，按出现的顺序在配置文件之后应用，
+所以命令行总是优先于配置文件。`--bind`和`--port`只修改监听地址，见[`Cli::load`]
+```text
+httpserver --bind 0.0.0.0 --port 8080 --static /=public --static /downloads=/srv/files
+httpserver routes --config server.toml
+```
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cli {
+    pub command: Command,
+    pub config: Option<PathBuf>,
+    pub overrides: Vec<Override>,
+    pub binds: Vec<String>,
+    pub port: Option<u16>,
+}
+
+fn usage_error(message: String) -> ConfigError {
+    ConfigError::Usage(format!("{}\n\n{}", message, USAGE))
+}
+
+/**
+ * `PREFIX=DIR`，只写`DIR`时挂载在`/`
+ */
+fn static_mount(spec: &str) -> toml::Value {
+    let (prefix, dir) = match spec.split_once('=') {
+        Some((prefix, dir)) => (prefix, dir),
+        None => ("/", spec),
+    };
+    let mut table = toml::Table::new();
+    table.insert("prefix".to_string(), prefix.into());
+    table.insert("dir".to_string(), dir.into());
+    toml::Value::Table(table)
+}
+
+/**
+ * `--bind`的地址是否不需要再加端口：已经带了端口(IPv6地址需要写成`[::1]`)，
+ * 或者是`unix:PATH`、`systemd[:N]`
+ */
+fn complete(host: &str) -> bool {
+    host.starts_with("unix:") || host.starts_with("systemd") || match host.rfind(']') {
+        Some(end) => host[end..].contains(':'),
+        None => host.contains(':'),
+    }
+}
+
+/**
+ * TCP地址拆成(主机, 端口)，Unix域套接字和systemd套接字返回`None`
+ */
+fn split_port(addr: &str) -> Option<(&str, u16)> {
+    if addr.starts_with("unix:") || addr.starts_with("systemd") {
+        return None;
+    }
+    let (host, port) = addr.rsplit_once(':')?;
+    Some((host, port.parse().ok()?))
+}
+
+impl Cli {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Cli, ConfigError> {
+        let mut cli = Cli { command: Command::Serve, config: None, overrides: Vec::new(), binds: Vec::new(), port: None };
+        let mut statics = Vec::new();
+        let mut args = args.into_iter().peekable();
+
+        if let Some(first) = args.peek() {
+            let command = match first.as_str() {
+                "serve" => Some(Command::Serve),
+                "check-config" => Some(Command::CheckConfig),
+                "routes" => Some(Command::Routes),
+                _ => None,
+            };
+            if let Some(command) = command {
+                cli.command = command;
+                args.next();
+            }
+        }
+
+        while let Some(arg) = args.next() {
+            // 同时支持`--name value`和`--name=value`
+            let (name, inline) = match arg.split_once('=') {
+                Some((name, value)) if name.starts_with("--") => (name.to_string(), Some(value.to_string())),
+                _ => (arg.clone(), None),
+            };
+            let mut value = || match inline.clone().or_else(|| args.next()) {
+                Some(value) => Ok(value),
+                None => Err(usage_error(format!("{} requires a value", name))),
+            };
+            match name.as_str() {
+                "-c" | "--config" => cli.config = Some(PathBuf::from(value()?)),
+                "-b" | "--bind" => cli.binds.push(value()?),
+                "-p" | "--port" => {
+                    let raw = value()?;
+                    cli.port = Some(raw.parse().map_err(|_| usage_error(format!("invalid port `{}`", raw)))?);
+                }
+                "--static" => statics.push(static_mount(&value()?)),
+                "--data" => cli.overrides.push(Override::new("api.data_dir", value()?)),
+                "-w" | "--workers" => {
+                    let raw = value()?;
+                    let workers: i64 = raw.parse().map_err(|_| usage_error(format!("invalid worker count `{}`", raw)))?;
+                    cli.overrides.push(Override::new("server.workers", workers));
+                }
+                "--log-level" => cli.overrides.push(Override::new("logging.level", value()?)),
+                "--tls-cert" => cli.overrides.push(Override::new("tls.cert", value()?)),
+                "--tls-key" => cli.overrides.push(Override::new("tls.key", value()?)),
+                "--set" => cli.overrides.push(Override::parse(&value()?)?),
+                "-h" | "--help" => cli.command = Command::Help,
+                "-V" | "--version" => cli.command = Command::Version,
+                _ => return Err(usage_error(format!("unknown argument `{}`", arg))),
+            }
+        }
+
+        if !statics.is_empty() {
+            cli.overrides.push(Override::new("static", toml::Value::Array(statics)));
+        }
+        Ok(cli)
+    }
+
+    /**
+     * 读取配置文件并应用命令行覆盖
+     */
+    pub fn load(&self) -> Result<Config, ConfigError> {
+        let mut config = Config::load(self.config.as_deref(), &self.overrides)?;
+        if !self.binds.is_empty() || self.port.is_some() {
+            self.apply_binds(&mut config)?;
+            config.validate()?;
+        }
+        Ok(config)
+    }
+
+    /**
+     * # 监听地址
+     * 每个`--bind`地址使用配置中唯一的监听器的其它设置(`tls`、`api`、`static`、`redirect_https`等)，
+     * 没有端口时使用`--port`，再没有时沿用原来的端口；只有`--port`时只修改那个监听器的端口。
+     * 配置了多个监听器时不知道应当修改哪一个，返回错误，请用`--set listeners.N.addr=ADDR`
+     */
+    fn apply_binds(&self, config: &mut Config) -> Result<(), ConfigError> {
+        if config.listeners.len() > 1 {
+            return Err(ConfigError::invalid("listeners", format!(
+                "--bind and --port cannot be used with {} configured listeners, use --set listeners.N.addr=ADDR instead",
+                config.listeners.len(),
+            )));
+        }
+        let template = config.listeners.pop().unwrap_or_default();
+        let current = split_port(&template.addr);
+        let port = self.port.or(current.map(|(_, port)| port)).unwrap_or(3000);
+        let hosts = match (&self.binds[..], current) {
+            ([], Some((host, _))) => vec![host.to_string()],
+            ([], None) => {
+                return Err(ConfigError::invalid("listeners[0].addr", format!("--port does not apply to \"{}\"", template.addr)));
+            }
+            (binds, _) => binds.to_vec(),
+        };
+        config.listeners = hosts.into_iter()
+            .map(|host| {
+                let addr = if complete(&host) { host } else { format!("{}:{}", host, port) };
+                ListenerConfig { addr, ..template.clone() }
+            })
+            .collect();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Cli, ConfigError> {
+        Cli::parse(args.split_whitespace().map(String::from))
+    }
+
+    #[test]
+    fn test_parse() {
+        let cli = parse("routes -c server.toml --bind 0.0.0.0 -b [::1] -b 127.0.0.1:9000 -b unix:/tmp/a.sock --port=8080 \
+                         --static /=public --static /dl=/srv/files -w 8 --log-level debug").unwrap();
+        assert_eq!(cli.command, Command::Routes);
+        assert_eq!(cli.config, Some(PathBuf::from("server.toml")));
+
+        let value = |key: &str| cli.overrides.iter().find(|o| o.key == key).map(|o| o.value.to_string());
+        assert_eq!(value("server.workers"), Some("8".into()));
+        assert_eq!(value("logging.level"), Some("\"debug\"".into()));
+        assert_eq!(value("listeners"), None);
+        assert_eq!(cli.binds, vec!["0.0.0.0", "[::1]", "127.0.0.1:9000", "unix:/tmp/a.sock"]);
+        assert_eq!(cli.port, Some(8080));
+        assert_eq!(
+            value("static"),
+            Some(r#"[{ dir = "public", prefix = "/" }, { dir = "/srv/files", prefix = "/dl" }]"#.into())
+        );
+
+        assert_eq!(parse("").unwrap().command, Command::Serve);
+        assert!(matches!(parse("--port nope"), Err(ConfigError::Usage(_))));
+        assert!(matches!(parse("--frobnicate"), Err(ConfigError::Usage(_))));
+        assert!(matches!(parse("--data"), Err(ConfigError::Usage(_))));
+    }
+
+    #[test]
+    fn test_binds_keep_listener_settings() {
+        let listener = ListenerConfig {
+            addr: "127.0.0.1:8080".to_string(),
+            statics: Some(Vec::new()),
+            api: false,
+            redirect_https: true,
+            ..ListenerConfig::default()
+        };
+        let bind = |args: &str, listeners: Vec<ListenerConfig>| {
+            let mut config = Config { listeners, ..Config::default() };
+            parse(args).unwrap().apply_binds(&mut config).map(|_| config.listeners)
+        };
+
+        let listeners = bind("-b 0.0.0.0 -b [::1]:9000 -b unix:/tmp/a.sock", vec![listener.clone()]).unwrap();
+        let addrs: Vec<&str> = listeners.iter().map(|l| l.addr.as_str()).collect();
+        assert_eq!(addrs, ["0.0.0.0:8080", "[::1]:9000", "unix:/tmp/a.sock"]);
+        assert!(listeners.iter().all(|l| !l.api && l.redirect_https && l.statics == Some(Vec::new())));
+
+        let listeners = bind("--port 9090", vec![listener.clone()]).unwrap();
+        assert_eq!(listeners, vec![ListenerConfig { addr: "127.0.0.1:9090".to_string(), ..listener.clone() }]);
+        assert_eq!(bind("-p 80", vec![ListenerConfig::new("[::]:8080")]).unwrap()[0].addr, "[::]:80");
+        assert!(bind("-p 80", vec![ListenerConfig::new("unix:/tmp/a.sock")]).is_err());
+
+        // 有多个监听器时不知道应当修改哪一个
+        let e = bind("-b 0.0.0.0", vec![listener.clone(), ListenerConfig::new("unix:/tmp/a.sock")]).unwrap_err();
+        assert!(matches!(e, ConfigError::Invalid { .. }), "{:?}", e);
+    }
+}
@@ -0,0 +1,699 @@
+use super::cache::CacheOptions;
+use super::embed;
+use super::listener::ListenAddr;
+use super::log::LogLevel;
+use super::repository;
+use super::staticfile::{StaticOptions, SymlinkPolicy};
+use super::tls;
+use serde::Deserialize;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/**
+ * # 配置错误
+ * - `Read`: 配置文件读取失败
+ * - `Parse`: TOML语法错误、未知的键或者类型不对，信息中带有行号和键名
+ * - `Invalid`: 通过了解析但取值不合法，`key`为出错的键，例如`static[1].dir`
+ * - `Usage`: 命令行参数错误
+ */
+#[derive(Debug, PartialEq)]
+pub enum ConfigError {
+    Read(PathBuf, String),
+    Parse(String, String),
+    Invalid { key: String, message: String },
+    Usage(String),
+}
+
+impl ConfigError {
+    pub fn invalid(key: impl Into<String>, message: impl Into<String>) -> Self {
+        ConfigError::Invalid { key: key.into(), message: message.into() }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Read(path, e) => write!(f, "cannot read config file {}: {}", path.display(), e),
+            ConfigError::Parse(origin, e) => write!(f, "invalid configuration in {}: {}", origin, e.trim_end()),
+            ConfigError::Invalid { key, message } => write!(f, "invalid configuration `{}`: {}", key, message),
+            ConfigError::Usage(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+/**
+ * # 服务器参数
+ * - `workers`: 处理请求的线程数
+ * - `read_timeout_secs`/`write_timeout_secs`: 读写超时，`0`表示不限制
+ * - `keep_alive_secs`: 长连接等待下一个请求的时间，`0`表示每个请求之后都关闭连接
+ * - `max_header_bytes`: 请求行和请求头的最大字节数，超过返回431
+ * - `max_body_bytes`: 请求体的最大字节数，超过返回413
+ * - `max_sessions`: 同时进行的WebSocket和事件流会话数，每个会话占用一个线程，超过后新的会话返回503
+ */
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub workers: usize,
+    pub read_timeout_secs: u64,
+    pub write_timeout_secs: u64,
+    pub keep_alive_secs: u64,
+    pub max_header_bytes: usize,
+    pub max_body_bytes: usize,
+    pub max_sessions: usize,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            workers: 4,
+            read_timeout_secs: 30,
+            write_timeout_secs: 30,
+            keep_alive_secs: 5,
+            max_header_bytes: 16 * 1024,
+            max_body_bytes: 1024 * 1024,
+            max_sessions: 256,
+        }
+    }
+}
+
+/**
+ * # 监听器
+ * `addr`的写法见[`ListenAddr`]：`0.0.0.0:80`、`[::]:80`、`unix:/run/httpserver.sock`、`systemd:0`。
+ * 每个监听器有自己的路由表，`static`列出这个监听器提供的静态挂载前缀(不写时提供全部)，
+ * `api = false`时不提供API。
+ * `tls`不写时跟随是否配置了`[tls]`；`redirect_https = true`的明文监听器把所有请求跳转到HTTPS；
+ * `compress = false`时不对响应做gzip压缩，见[`Compress`]
+ *
+ * [`Compress`]: super::middleware::Compress
+ */
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ListenerConfig {
+    pub addr: String,
+    #[serde(rename = "static")]
+    pub statics: Option<Vec<String>>,
+    pub api: bool,
+    pub tls: Option<bool>,
+    pub redirect_https: bool,
+    pub compress: bool,
+}
+
+impl ListenerConfig {
+    pub fn new(addr: impl Into<String>) -> Self {
+        ListenerConfig { addr: addr.into(), ..ListenerConfig::default() }
+    }
+
+    /**
+     * 这个监听器是否使用TLS，`tls_configured`为是否有`[tls]`配置
+     */
+    pub fn secure(&self, tls_configured: bool) -> bool {
+        self.tls.unwrap_or(tls_configured)
+    }
+
+    /**
+     * 这个监听器是否提供前缀为`prefix`的静态挂载
+     */
+    pub fn serves(&self, prefix: &str) -> bool {
+        match &self.statics {
+            Some(prefixes) => prefixes.iter().any(|p| p.trim_end_matches('/') == prefix.trim_end_matches('/')),
+            None => true,
+        }
+    }
+}
+
+impl Default for ListenerConfig {
+    fn default() -> Self {
+        ListenerConfig {
+            addr: "localhost:3000".to_string(),
+            statics: None,
+            api: true,
+            tls: None,
+            redirect_https: false,
+            compress: true,
+        }
+    }
+}
+
+/**
+ * # 静态目录挂载
+ * 把URL前缀`prefix`映射到目录`dir`，其余字段与[`StaticOptions`]一一对应，
+ * 没有写的字段沿用对应环境变量的值。`dir`没有写时使用`PUBLIC_PATH`
+ */
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct StaticMount {
+    pub prefix: String,
+    pub dir: Option<PathBuf>,
+    pub embedded: bool,
+    pub index: Vec<String>,
+    pub html_fallback: bool,
+    pub autoindex: bool,
+    pub show_hidden: bool,
+    pub follow_symlinks: SymlinkPolicy,
+    pub deny_hidden: bool,
+    pub deny_backup: bool,
+    pub precompressed: bool,
+    pub deny_status: u16,
+}
+
+impl Default for StaticMount {
+    fn default() -> Self {
+        let options = StaticOptions::from_env();
+        StaticMount {
+            prefix: "/".to_string(),
+            dir: None,
+            embedded: false,
+            index: options.index,
+            html_fallback: options.html_fallback,
+            autoindex: options.autoindex,
+            show_hidden: options.show_hidden,
+            follow_symlinks: options.symlinks,
+            deny_hidden: options.deny_hidden,
+            deny_backup: options.deny_backup,
+            precompressed: options.precompressed,
+            deny_status: if options.deny_status == "403" { 403 } else { 404 },
+        }
+    }
+}
+
+impl StaticMount {
+    pub fn options(&self) -> StaticOptions {
+        let defaults = StaticOptions::from_env();
+        let (root, embedded) = match &self.dir {
+            Some(dir) => (dir.clone(), self.embedded),
+            None => (defaults.root, defaults.embedded || self.embedded),
+        };
+        StaticOptions {
+            root,
+            embedded,
+            index: self.index.clone(),
+            html_fallback: self.html_fallback,
+            autoindex: self.autoindex,
+            show_hidden: self.show_hidden,
+            symlinks: self.follow_symlinks,
+            deny_hidden: self.deny_hidden,
+            deny_backup: self.deny_backup,
+            precompressed: self.precompressed,
+            deny_status: if self.deny_status == 403 { "403" } else { "404" },
+        }
+    }
+}
+
+/**
+ * # 角色数据的存储
+ * 配置文件中写作`json`/`memory`/`sqlite`，默认`json`
+ * - `Json`: 读写`characters.json`，文件被手工修改后自动重新读取
+ * - `Memory`: 从`characters.json`读取初始数据，修改只保存在内存中
+ * - `Sqlite`: SQLite数据库文件，新建时导入`characters.json`，需要`sqlite`特性
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Storage {
+    #[default]
+    Json,
+    Memory,
+    Sqlite,
+}
+
+/**
+ * # API
+ * - `prefix`: API路由的前缀，角色数据在`<prefix>/shipping`下，可以修改的角色资源在`<prefix>/characters`下
+ * - `data_dir`: `characters.json`所在目录，默认读取`DATA_PATH`
+ * - `storage`: 见[`Storage`]
+ * - `database`: SQLite数据库文件，默认为`data_dir`下的`characters.db`
+ */
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ApiConfig {
+    pub prefix: String,
+    pub data_dir: Option<PathBuf>,
+    pub storage: Storage,
+    pub database: Option<PathBuf>,
+}
+
+impl Default for ApiConfig {
+    fn default() -> Self {
+        ApiConfig {
+            prefix: "/api".to_string(),
+            data_dir: env::var_os("DATA_PATH").map(PathBuf::from),
+            storage: Storage::Json,
+            database: None,
+        }
+    }
+}
+
+impl ApiConfig {
+    /**
+     * 两份配置使用同一份角色数据：`storage`、`data_dir`和`database`都相同
+     */
+    pub fn same_storage(&self, other: &ApiConfig) -> bool {
+        self.storage == other.storage && self.data_dir == other.data_dir && self.database == other.database
+    }
+
+    pub fn database_path(&self) -> PathBuf {
+        match (&self.database, &self.data_dir) {
+            (Some(database), _) => database.clone(),
+            (None, Some(dir)) => dir.join("characters.db"),
+            (None, None) => PathBuf::from(format!("{}/data/characters.db", env!("CARGO_MANIFEST_DIR"))),
+        }
+    }
+}
+
+/**
+ * # 日志
+ * - `level`: 见[`LogLevel`]
+ * - `access_log`: 是否为每个请求输出一行访问日志
+ */
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    pub level: LogLevel,
+    pub access_log: bool,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        LoggingConfig { level: LogLevel::Info, access_log: true }
+    }
+}
+
+/**
+ * # TLS
+ * `cert`/`key`为PEM格式的证书链和私钥，客户端没有发送SNI或者没有匹配的`sni`条目时使用。
+ * `sni`按主机名选择证书，`names`支持`*.example.com`形式的通配符；
+ * `alpn`为按优先级排列的应用层协议
+ */
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    #[serde(default)]
+    pub sni: Vec<SniCert>,
+    #[serde(default = "TlsConfig::default_alpn")]
+    pub alpn: Vec<String>,
+}
+
+impl TlsConfig {
+    fn default_alpn() -> Vec<String> {
+        vec!["h2".to_string(), "http/1.1".to_string()]
+    }
+}
+
+/**
+ * 一组主机名使用的证书
+ */
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct SniCert {
+    pub names: Vec<String>,
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+/**
+# Config
+服务器的完整配置，从TOML文件读取，没有写的部分使用默认值，
+默认值沿用原来的环境变量(`PUBLIC_PATH`、`DATA_PATH`、`STATIC_CACHE_BYTES`等)
+```toml
+[server]
+workers = 8
+max_body_bytes = 65536
+
+[[listeners]]
+addr = "[::]:443"
+
+[[listeners]]
+addr = "[::]:80"
+tls = false
+redirect_https = true
+
+[[listeners]]
+addr = "unix:/run/httpserver/admin.sock"
+tls = false
+static = []
+
+[[static]]
+prefix = "/"
+dir = "public"
+
+[[static]]
+prefix = "/downloads"
+dir = "/srv/files"
+autoindex = true
+
+[cache]
+max_bytes = 67108864
+
+[api]
+data_dir = "data"
+storage = "sqlite"
+
+[logging]
+level = "debug"
+
+[tls]
+cert = "/etc/httpserver/cert.pem"
+key = "/etc/httpserver/key.pem"
+```
+ */
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub listeners: Vec<ListenerConfig>,
+    #[serde(rename = "static")]
+    pub statics: Vec<StaticMount>,
+    pub cache: CacheOptions,
+    pub api: ApiConfig,
+    pub logging: LoggingConfig,
+    pub tls: Option<TlsConfig>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            server: ServerConfig::default(),
+            listeners: vec![ListenerConfig::default()],
+            statics: vec![StaticMount::default()],
+            cache: CacheOptions::default(),
+            api: ApiConfig::default(),
+            logging: LoggingConfig::default(),
+            tls: None,
+        }
+    }
+}
+
+/**
+ * 把命令行中的值按TOML解析，`8`、`true`、`["a", "b"]`保持类型，其余当作字符串
+ */
+fn parse_value(raw: &str) -> toml::Value {
+    toml::from_str::<toml::Table>(&format!("v = {}", raw))
+        .ok()
+        .and_then(|mut table| table.remove("v"))
+        .unwrap_or_else(|| toml::Value::String(raw.to_string()))
+}
+
+/**
+ * 按`a.b.0.c`设置值，数字段表示数组下标，下标等于数组长度时追加一个新表
+ */
+fn set_path(value: &mut toml::Value, segs: &[&str], new: toml::Value, key: &str) -> Result<(), ConfigError> {
+    let (seg, rest) = match segs.split_first() {
+        None => {
+            *value = new;
+            return Ok(());
+        }
+        Some(split) => split,
+    };
+    // 缺少的中间层按下一段是否为数字创建数组或表
+    let empty = || match rest.first() {
+        Some(next) if next.parse::<usize>().is_ok() => toml::Value::Array(Vec::new()),
+        _ => toml::Value::Table(toml::Table::new()),
+    };
+    let child = match value {
+        toml::Value::Table(table) => table.entry(seg.to_string()).or_insert_with(empty),
+        toml::Value::Array(array) => {
+            let i: usize = seg.parse().map_err(|_| ConfigError::invalid(key, format!("`{}` is not an array index", seg)))?;
+            if i == array.len() {
+                array.push(empty());
+            }
+            array.get_mut(i).ok_or_else(|| ConfigError::invalid(key, format!("index {} is out of range", i)))?
+        }
+        _ => return Err(ConfigError::invalid(key, format!("cannot set `{}` on a value that is not a table", seg))),
+    };
+    set_path(child, rest, new, key)
+}
+
+/**
+ * # 配置覆盖
+ * 命令行对配置中某个键的覆盖，`key`为点分隔的路径，例如`server.workers`、`static.0.dir`
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Override {
+    pub key: String,
+    pub value: toml::Value,
+}
+
+impl Override {
+    pub fn new(key: &str, value: impl Into<toml::Value>) -> Self {
+        Override { key: key.to_string(), value: value.into() }
+    }
+
+    /**
+     * 解析`--set`的`KEY=VALUE`，值按TOML解析，解析失败时当作字符串
+     */
+    pub fn parse(assignment: &str) -> Result<Override, ConfigError> {
+        let (key, raw) = assignment.split_once('=')
+            .ok_or_else(|| ConfigError::Usage(format!("expected KEY=VALUE, got `{}`", assignment)))?;
+        Ok(Override::new(key.trim(), parse_value(raw.trim())))
+    }
+}
+
+/**
+ * 在解析后的表上应用一条覆盖
+ */
+pub fn apply_override(table: &mut toml::Table, item: &Override) -> Result<(), ConfigError> {
+    let key = item.key.as_str();
+    let segs: Vec<&str> = key.split('.').collect();
+    if segs.iter().any(|s| s.is_empty()) {
+        return Err(ConfigError::Usage(format!("invalid configuration key `{}`", key)));
+    }
+    let mut root = toml::Value::Table(std::mem::take(table));
+    let result = set_path(&mut root, &segs, item.value.clone(), key);
+    if let toml::Value::Table(t) = root {
+        *table = t;
+    }
+    result
+}
+
+impl Config {
+    /**
+     * # 读取配置
+     * 读取`path`(没有时全部使用默认值)，依次应用`overrides`，再做校验
+     */
+    pub fn load(path: Option<&Path>, overrides: &[Override]) -> Result<Config, ConfigError> {
+        let (origin, text) = match path {
+            Some(path) => {
+                let text = fs::read_to_string(path).map_err(|e| ConfigError::Read(path.to_path_buf(), e.to_string()))?;
+                (path.display().to_string(), text)
+            }
+            None => ("defaults".to_string(), String::new()),
+        };
+
+        let config: Config = if overrides.is_empty() {
+            // 直接从原文解析，错误信息中的行号对应配置文件
+            toml::from_str(&text).map_err(|e| ConfigError::Parse(origin, e.to_string()))?
+        } else {
+            let mut table: toml::Table = toml::from_str(&text).map_err(|e| ConfigError::Parse(origin.clone(), e.to_string()))?;
+            for item in overrides {
+                apply_override(&mut table, item)?;
+            }
+            table.try_into().map_err(|e: toml::de::Error| {
+                ConfigError::Parse(format!("{} with command-line overrides", origin), e.to_string())
+            })?
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    /**
+     * # 校验
+     * 检查解析阶段发现不了的问题：地址能否解析、目录是否存在、前缀是否重复等，
+     * 返回第一个出错的键
+     */
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let server = &self.server;
+        if server.workers == 0 {
+            return Err(ConfigError::invalid("server.workers", "must be at least 1"));
+        }
+        if server.max_header_bytes < 256 {
+            return Err(ConfigError::invalid("server.max_header_bytes", "must be at least 256"));
+        }
+
+        if self.listeners.is_empty() {
+            return Err(ConfigError::invalid("listeners", "at least one listener is required"));
+        }
+        // 比较解析后的地址，`systemd`和`systemd:0`是同一个套接字
+        let mut addrs: Vec<ListenAddr> = Vec::new();
+        for (i, listener) in self.listeners.iter().enumerate() {
+            let key = format!("listeners[{}].addr", i);
+            let addr = ListenAddr::parse(&listener.addr).map_err(|e| ConfigError::invalid(&key, e))?;
+            if addrs.contains(&addr) {
+                return Err(ConfigError::invalid(key, format!("\"{}\" is listed more than once", listener.addr)));
+            }
+            addrs.push(addr);
+        }
+        for (i, (listener, addr)) in self.listeners.iter().zip(&addrs).enumerate() {
+            let key = |field: &str| format!("listeners[{}].{}", i, field);
+            addr.check().map_err(|e| ConfigError::invalid(key("addr"), e))?;
+            if listener.tls == Some(true) && self.tls.is_none() {
+                return Err(ConfigError::invalid(key("tls"), "requires a [tls] section with a certificate"));
+            }
+            if listener.redirect_https {
+                if listener.secure(self.tls.is_some()) {
+                    return Err(ConfigError::invalid(key("redirect_https"), "only plaintext listeners can redirect to HTTPS"));
+                }
+                if !self.listeners.iter().any(|l| l.secure(self.tls.is_some())) {
+                    return Err(ConfigError::invalid(key("redirect_https"), "there is no TLS listener to redirect to"));
+                }
+            }
+            for prefix in listener.statics.iter().flatten() {
+                if !self.statics.iter().any(|m| m.prefix.trim_end_matches('/') == prefix.trim_end_matches('/')) {
+                    return Err(ConfigError::invalid(key("static"), format!("\"{}\" is not a configured static prefix", prefix)));
+                }
+            }
+        }
+
+        for (i, mount) in self.statics.iter().enumerate() {
+            let key = |field: &str| format!("static[{}].{}", i, field);
+            let prefix = &mount.prefix;
+            let dynamic = prefix.split('/').any(|s| s.starts_with(':') || s.starts_with('*'));
+            if !prefix.starts_with('/') || dynamic {
+                return Err(ConfigError::invalid(key("prefix"), format!("\"{}\" must start with '/' and cannot contain parameters", prefix)));
+            }
+            let normalized = prefix.trim_end_matches('/');
+            if self.statics[..i].iter().any(|m| m.prefix.trim_end_matches('/') == normalized) {
+                return Err(ConfigError::invalid(key("prefix"), format!("\"{}\" is mounted more than once", prefix)));
+            }
+            let api = self.api.prefix.trim_end_matches('/');
+            if !normalized.is_empty() && (api == normalized || api.starts_with(&format!("{}/", normalized))) {
+                return Err(ConfigError::invalid(key("prefix"), format!("\"{}\" would hide the API under \"{}\"", prefix, self.api.prefix)));
+            }
+            if ![403, 404].contains(&mount.deny_status) {
+                return Err(ConfigError::invalid(key("deny_status"), "must be 403 or 404"));
+            }
+            let options = mount.options();
+            if options.embedded {
+                if !embed::available() {
+                    return Err(ConfigError::invalid(key("embedded"), "this binary was built without the `embed-assets` feature"));
+                }
+            } else if !options.root.is_dir() {
+                return Err(ConfigError::invalid(key("dir"), format!("{} is not a directory", options.root.display())));
+            }
+        }
+
+        if !self.api.prefix.starts_with('/') {
+            return Err(ConfigError::invalid("api.prefix", format!("\"{}\" must start with '/'", self.api.prefix)));
+        }
+        if let Some(dir) = &self.api.data_dir {
+            if !dir.join("characters.json").is_file() {
+                return Err(ConfigError::invalid("api.data_dir", format!("{} does not contain characters.json", dir.display())));
+            }
+        }
+        if self.api.storage == Storage::Sqlite {
+            if !repository::sqlite_available() {
+                return Err(ConfigError::invalid("api.storage", "this binary was built without the `sqlite` feature"));
+            }
+            let database = self.api.database_path();
+            let dir = database.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+            if !dir.is_dir() {
+                return Err(ConfigError::invalid("api.database", format!("{} is not a directory", dir.display())));
+            }
+        }
+
+        if let Some(tls) = &self.tls {
+            if !tls::available() {
+                return Err(ConfigError::invalid("tls", "this binary was built without the `tls` feature"));
+            }
+            let mut files = vec![("tls.cert".to_string(), &tls.cert), ("tls.key".to_string(), &tls.key)];
+            for (i, sni) in tls.sni.iter().enumerate() {
+                if sni.names.is_empty() {
+                    return Err(ConfigError::invalid(format!("tls.sni[{}].names", i), "at least one host name is required"));
+                }
+                files.push((format!("tls.sni[{}].cert", i), &sni.cert));
+                files.push((format!("tls.sni[{}].key", i), &sni.key));
+            }
+            for (field, path) in files {
+                if !path.is_file() {
+                    return Err(ConfigError::invalid(field, format!("{} does not exist", path.display())));
+                }
+            }
+            if tls.alpn.is_empty() {
+                return Err(ConfigError::invalid("tls.alpn", "at least one protocol is required"));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_config(name: &str, contents: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("httpserver-config-{}-{}.toml", name, std::process::id()));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_load_with_overrides() {
+        let public = format!("{}/public", env!("CARGO_MANIFEST_DIR"));
+        let path = write_config("ok", &format!(r#"
+[server]
+workers = 2
+
+[[listeners]]
+addr = "127.0.0.1:0"
+
+[[static]]
+prefix = "/"
+dir = "{}"
+
+[logging]
+level = "warn"
+"#, public));
+        let overrides: Vec<Override> = [
+            "server.workers=8".to_string(),
+            "static.0.autoindex=true".to_string(),
+            format!("static.1.dir={}", public),
+            "static.1.prefix=/files".to_string(),
+        ].iter().map(|s| Override::parse(s).unwrap()).collect();
+        let config = Config::load(Some(&path), &overrides).unwrap();
+        assert_eq!(config.server.workers, 8);
+        assert_eq!(config.server.max_body_bytes, 1024 * 1024);
+        assert_eq!(config.listeners, vec![ListenerConfig::new("127.0.0.1:0")]);
+        assert_eq!(config.statics.len(), 2);
+        assert!(config.statics[0].autoindex);
+        assert_eq!(config.statics[1].prefix, "/files");
+        assert_eq!(config.logging.level, LogLevel::Warn);
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_errors_point_at_key() {
+        let path = write_config("typo", "[server]\nworker = 2\n");
+        let e = Config::load(Some(&path), &[]).unwrap_err().to_string();
+        assert!(e.contains("line 2"), "{}", e);
+        assert!(e.contains("worker"), "{}", e);
+        let _ = fs::remove_file(path);
+
+        let e = Config::load(None, &[Override::parse("server.workers=0").unwrap()]).unwrap_err();
+        assert_eq!(e, ConfigError::invalid("server.workers", "must be at least 1"));
+
+        let e = Config::load(None, &[Override::parse("static.0.dir=/nonexistent/httpserver").unwrap()]).unwrap_err();
+        assert!(matches!(e, ConfigError::Invalid { ref key, .. } if key == "static[0].dir"), "{}", e);
+
+        let e = Config::load(None, &[Override::parse("static.0.prefix=/api").unwrap()]).unwrap_err();
+        assert!(matches!(e, ConfigError::Invalid { ref key, .. } if key == "static[0].prefix"), "{}", e);
+
+        let e = Config::load(None, &[Override::parse(r#"listeners.0.static=["/nope"]"#).unwrap()]).unwrap_err();
+        assert!(matches!(e, ConfigError::Invalid { ref key, .. } if key == "listeners[0].static"), "{}", e);
+
+        let e = Config::load(None, &[Override::parse("listeners.0.addr=unix:").unwrap()]).unwrap_err();
+        assert!(matches!(e, ConfigError::Invalid { ref key, .. } if key == "listeners[0].addr"), "{}", e);
+
+        // `systemd`和`systemd:0`是同一个套接字，不能接管两次
+        let e = Config::load(None, &[Override::parse(r#"listeners=[{addr="systemd"}, {addr="systemd:0"}]"#).unwrap()]).unwrap_err();
+        assert!(matches!(e, ConfigError::Invalid { ref key, ref message } if key == "listeners[1].addr" && message.contains("more than once")), "{}", e);
+
+        let e = Config::load(None, &[Override::parse("logging.level=loud").unwrap()]).unwrap_err().to_string();
+        assert!(e.contains("loud"), "{}", e);
+
+        let e = Config::load(None, &[Override::parse("api.storage=sqlite").unwrap(), Override::parse("api.database=/nonexistent/httpserver/c.db").unwrap()]).unwrap_err();
+        let key = if repository::sqlite_available() { "api.database" } else { "api.storage" };
+        assert!(matches!(e, ConfigError::Invalid { key: ref k, .. } if k == key), "{}", e);
+    }
+}
@@ -0,0 +1,197 @@
+use http::httprequest::HttpRequst;
+use http::httpresponse::{HttpResponse, ResponseBody};
+use http::mime;
+use super::staticfile::accepts_encoding;
+use std::collections::HashMap;
+
+/**
+ * # 嵌入资源
+ * 编译期写入二进制的文件，`path`为相对于`public/`(或`data/`)的路径，
+ * `etag`在编译时根据文件内容计算，`br`/`gzip`为可选的预压缩版本
+ */
+#[derive(Debug)]
+pub struct Asset {
+    pub path: &'static str,
+    pub data: &'static [u8],
+    pub etag: &'static str,
+    pub br: Option<&'static [u8]>,
+    pub gzip: Option<&'static [u8]>,
+}
+
+#[cfg(feature = "embed-assets")]
+include!(concat!(env!("OUT_DIR"), "/assets.rs"));
+
+#[cfg(not(feature = "embed-assets"))]
+static ASSETS: &[Asset] = &[];
+
+#[cfg(not(feature = "embed-assets"))]
+static DATA: &[Asset] = &[];
+
+/**
+ * 是否编译了`embed-assets`特性
+ */
+pub fn available() -> bool {
+    cfg!(feature = "embed-assets")
+}
+
+fn find(table: &'static [Asset], rel: &str) -> Option<&'static Asset> {
+    table.binary_search_by(|asset| asset.path.cmp(rel))
+        .ok()
+        .map(|i| &table[i])
+}
+
+/**
+ * 按相对路径查找`public/`中的文件
+ */
+pub fn get(rel: &str) -> Option<&'static Asset> {
+    find(ASSETS, rel)
+}
+
+/**
+ * 按相对路径查找`data/`中的文件
+ */
+pub fn data(rel: &str) -> Option<&'static Asset> {
+    find(DATA, rel)
+}
+
+/**
+ * 嵌入的文件中是否有以`rel/`开头的路径，空路径表示根目录
+ */
+pub fn is_dir(rel: &str) -> bool {
+    rel.is_empty() || ASSETS.iter().any(|asset| {
+        asset.path.strip_prefix(rel).map(|rest| rest.starts_with('/')).unwrap_or(false)
+    })
+}
+
+/**
+ * 列出目录`rel`下的直接子项，返回(名字, 是否目录, 大小)
+ */
+pub fn read_dir(rel: &str) -> Vec<(String, bool, u64)> {
+    let prefix = if rel.is_empty() { String::new() } else { format!("{}/", rel) };
+    let mut entries: Vec<(String, bool, u64)> = Vec::new();
+    for asset in ASSETS {
+        let rest = match asset.path.strip_prefix(&prefix) {
+            Some(rest) => rest,
+            None => continue,
+        };
+        match rest.split_once('/') {
+            Some((dir, _)) => {
+                if !entries.iter().any(|(name, is_dir, _)| *is_dir && name == dir) {
+                    entries.push((dir.to_string(), true, 0));
+                }
+            }
+            None => entries.push((rest.to_string(), false, asset.data.len() as u64)),
+        }
+    }
+    entries
+}
+
+/**
+ * 嵌入文件的内容，文本类型转成`ResponseBody::Text`
+ */
+pub fn body(asset: &Asset) -> ResponseBody {
+    if mime::registry().is_text(asset.path) {
+        if let Ok(txt) = std::str::from_utf8(asset.data) {
+            return ResponseBody::Text(txt.to_string());
+        }
+    }
+    ResponseBody::Binary(asset.data.to_vec())
+}
+
+/**
+ * # 返回嵌入文件
+ * 带上编译时计算的`ETag`，`If-None-Match`匹配时返回304；
+ * 客户端支持时优先返回嵌入的br/gzip版本
+ */
+pub fn respond(req: &HttpRequst, asset: &Asset) -> HttpResponse {
+    let mut headers = HashMap::new();
+    headers.insert("ETag", asset.etag);
+    headers.insert("Vary", "Accept-Encoding");
+
+    // 弱比较：经过`Compress`压缩的响应带的是`W/`开头的弱ETag
+    let not_modified = req.header("If-None-Match")
+        .map(|tags| tags.split(',').map(|tag| tag.trim()).any(|tag| tag.trim_start_matches("W/") == asset.etag || tag == "*"))
+        .unwrap_or(false);
+    if not_modified {
+        return HttpResponse::new("304", Some(headers), None);
+    }
+
+    headers.insert("Content-Type", mime::registry().content_type_for(asset.path, asset.data));
+    let accept_encoding = req.header("Accept-Encoding").unwrap_or("");
+    let compressed = [("br", asset.br), ("gzip", asset.gzip)].into_iter()
+        .find_map(|(coding, data)| data.filter(|_| accepts_encoding(accept_encoding, coding)).map(|d| (coding, d)));
+    match compressed {
+        Some((coding, data)) => {
+            headers.insert("Content-Encoding", coding);
+            HttpResponse::new("200", Some(headers), Some(ResponseBody::Binary(data.to_vec())))
+        }
+        None => HttpResponse::new("200", Some(headers), Some(body(asset))),
+    }
+}
+
+#[cfg(all(test, feature = "embed-assets"))]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn fnv1a(data: &[u8]) -> u64 {
+        data.iter().fold(0xcbf29ce484222325, |hash, b| (hash ^ *b as u64).wrapping_mul(0x100000001b3))
+    }
+
+    fn request(headers: &str) -> HttpRequst {
+        format!("GET /index.html HTTP/1.1\r\n{}\r\n", headers).into()
+    }
+
+    #[test]
+    fn test_lookup_and_listing() {
+        let asset = get("index.html").expect("public/index.html is embedded");
+        let on_disk = fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/public/index.html")).unwrap();
+        assert_eq!(asset.data, on_disk.as_slice());
+        assert_eq!(asset.etag, format!("\"{:016x}\"", fnv1a(&on_disk)));
+        assert!(get("missing.html").is_none());
+        assert!(get("../Cargo.toml").is_none());
+        assert!(data("characters.json").is_some());
+        assert!(get("characters.json").is_none());
+
+        assert!(is_dir(""));
+        assert!(!is_dir("index.html"));
+        assert!(!is_dir("missing"));
+        let entries = read_dir("");
+        assert!(entries.contains(&("index.html".to_string(), false, on_disk.len() as u64)));
+        assert!(read_dir("missing").is_empty());
+    }
+
+    #[test]
+    fn test_respond_etag() {
+        let asset = get("index.html").unwrap();
+        let resp = respond(&request(""), asset);
+        assert_eq!(resp.status_code(), "200");
+        assert_eq!(resp.header("ETag"), Some(asset.etag));
+        assert_eq!(resp.header("Content-Type"), Some("text/html; charset=utf-8"));
+
+        let resp = respond(&request(&format!("If-None-Match: \"other\", {}\r\n", asset.etag)), asset);
+        assert_eq!(resp.status_code(), "304");
+        assert!(resp.body().is_none());
+        assert_eq!(respond(&request("If-None-Match: \"other\"\r\n"), asset).status_code(), "200");
+        let weak = respond(&request(&format!("If-None-Match: W/{}\r\n", asset.etag)), asset);
+        assert_eq!(weak.status_code(), "304");
+    }
+
+    #[test]
+    fn test_respond_precompressed() {
+        static ASSET: Asset = Asset {
+            path: "app.js",
+            data: b"console.log(1)",
+            etag: "\"0000000000000001\"",
+            br: Some(b"br-bytes"),
+            gzip: Some(b"gz-bytes"),
+        };
+        let encoded = |accept: &str| {
+            let resp = respond(&request(&format!("Accept-Encoding: {}\r\n", accept)), &ASSET);
+            (resp.header("Content-Encoding").map(str::to_string), resp.body().cloned())
+        };
+        assert_eq!(encoded("gzip, br"), (Some("br".into()), Some(ResponseBody::Binary(b"br-bytes".to_vec()))));
+        assert_eq!(encoded("gzip, br;q=0"), (Some("gzip".into()), Some(ResponseBody::Binary(b"gz-bytes".to_vec()))));
+        assert_eq!(encoded("identity"), (None, Some(ResponseBody::Text("console.log(1)".into()))));
+    }
+}
@@ -0,0 +1,1350 @@
+use super::config::ServerConfig;
+use super::listener;
+use super::router::{Router, Vetted};
+use super::server::{self, Sessions};
+use super::sse::{Session, Streaming};
+use http::hpack::{self, Decoder, HpackError};
+use http::httprequest::{Extensions, HttpRequst, Method, Resource, Version};
+use http::httpresponse::HttpResponse;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io::{self, Read, Write};
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender, TryRecvError};
+use std::sync::Arc;
+use std::thread::{self, Scope};
+use std::time::Duration;
+
+/**
+ * 客户端连接序言，h2c(prior knowledge)通过它识别HTTP/2连接
+ */
+pub const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+const FRAME_HEADER_LEN: usize = 9;
+/** 我们接受的最大帧，即SETTINGS_MAX_FRAME_SIZE的默认值 */
+const MAX_FRAME_SIZE: usize = 16_384;
+const DEFAULT_WINDOW: i64 = 65_535;
+const MAX_WINDOW: i64 = (1 << 31) - 1;
+const MAX_CONCURRENT_STREAMS: usize = 100;
+/** 同一个连接上最多同时运行的处理器，更多收完的请求排队等待 */
+const MAX_DISPATCHING: usize = 8;
+/** 事件流会话最多排队的写入次数，排满后会话的写入阻塞，直到连接线程取走数据 */
+const EVENT_QUEUE: usize = 16;
+/** 事件流每个流最多缓存的待发送数据，超过后不再从会话取数据，等对端放开窗口 */
+const MAX_EVENT_PENDING: usize = 64 * 1024;
+/** 有事件流时等待对端数据的间隔，到时检查事件流有没有新数据 */
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+/**
+ * 有处理器在运行时等待的间隔。处理器通常很快完成，刚分发请求后先等它这么久，
+ * 之后用它作为读超时轮询对端数据，间隔太长会拖慢响应
+ */
+const DISPATCH_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+// 帧类型
+const DATA: u8 = 0x0;
+const HEADERS: u8 = 0x1;
+const PRIORITY: u8 = 0x2;
+const RST_STREAM: u8 = 0x3;
+const SETTINGS: u8 = 0x4;
+const PUSH_PROMISE: u8 = 0x5;
+const PING: u8 = 0x6;
+const GOAWAY: u8 = 0x7;
+const WINDOW_UPDATE: u8 = 0x8;
+const CONTINUATION: u8 = 0x9;
+
+// 标志位
+const END_STREAM: u8 = 0x1;
+const ACK: u8 = 0x1;
+const END_HEADERS: u8 = 0x4;
+const PADDED: u8 = 0x8;
+const PRIORITY_FLAG: u8 = 0x20;
+
+// SETTINGS参数
+const SETTINGS_ENABLE_PUSH: u16 = 0x2;
+const SETTINGS_MAX_CONCURRENT_STREAMS: u16 = 0x3;
+const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+const SETTINGS_MAX_FRAME_SIZE: u16 = 0x5;
+const SETTINGS_MAX_HEADER_LIST_SIZE: u16 = 0x6;
+
+// 错误码
+const NO_ERROR: u32 = 0x0;
+const PROTOCOL_ERROR: u32 = 0x1;
+const FLOW_CONTROL_ERROR: u32 = 0x3;
+const STREAM_CLOSED: u32 = 0x5;
+const FRAME_SIZE_ERROR: u32 = 0x6;
+const REFUSED_STREAM: u32 = 0x7;
+const COMPRESSION_ERROR: u32 = 0x9;
+const ENHANCE_YOUR_CALM: u32 = 0xb;
+
+/**
+ * HTTP/1.1中逐跳的头部，在HTTP/2中不允许出现
+ */
+const CONNECTION_HEADERS: &[&str] = &["connection", "keep-alive", "proxy-connection", "transfer-encoding", "upgrade"];
+
+/**
+ * HTTP/2连接使用的传输层，除了读写还需要调整读超时，以便在等待对端数据时转发事件流
+ */
+pub trait Transport: Read + Write {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+impl Transport for listener::Connection {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        listener::Connection::set_read_timeout(self, timeout)
+    }
+}
+
+/**
+ * 事件流会话的写入端，数据交给连接所在的线程作为DATA帧发送。
+ * 队列是有界的，对端不读取时写入阻塞而不是无限缓存；流关闭后写入失败
+ */
+struct DataSink(SyncSender<Vec<u8>>);
+
+impl Write for DataSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.send(buf.to_vec()).map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/**
+ * 连接无法继续时的原因：I/O错误直接关闭，协议错误先发送GOAWAY
+ */
+#[derive(Debug)]
+enum H2Error {
+    Io(io::Error),
+    Connection(u32, &'static str),
+}
+
+impl From<io::Error> for H2Error {
+    fn from(e: io::Error) -> Self {
+        H2Error::Io(e)
+    }
+}
+
+struct Frame {
+    kind: u8,
+    flags: u8,
+    stream: u32,
+    payload: Vec<u8>,
+}
+
+/**
+ * 一个请求/响应流，收完请求(END_STREAM)后分发，响应数据按流量控制窗口分批发送
+ */
+struct Stream {
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+    recv_closed: bool,
+    responded: bool,
+    send_window: i64,
+    /** 对端还可以发送的请求体，我们没有通告SETTINGS_INITIAL_WINDOW_SIZE，从默认窗口开始 */
+    recv_window: i64,
+    pending: Vec<u8>,
+    /** 事件流响应的数据来源，会话结束(发送端关闭)后为`None` */
+    events: Option<Receiver<Vec<u8>>>,
+    /** 请求头已经在回应`100`前通过了检查 */
+    vetted: bool,
+}
+
+/**
+ * 正在接收的头部块，HEADERS没有END_HEADERS时后面必须紧跟同一个流的CONTINUATION
+ */
+struct HeaderBlock {
+    stream: u32,
+    block: Vec<u8>,
+    end_stream: bool,
+}
+
+/**
+ * 处理器线程的结果，交回连接线程发送
+ */
+struct Dispatched {
+    id: u32,
+    head: bool,
+    resp: HttpResponse,
+    /** 事件流响应的会话，由连接线程在占到会话名额后启动 */
+    session: Option<Session>,
+}
+
+struct Connection<'a, 'e, S> {
+    io: &'a mut S,
+    buf: Vec<u8>,
+    out: Vec<u8>,
+    options: &'a ServerConfig,
+    sessions: &'a Arc<Sessions>,
+    router: &'a Router,
+    extensions: &'a Extensions,
+    scope: &'a Scope<'a, 'e>,
+    done: (Sender<Dispatched>, Receiver<Dispatched>),
+    /** 请求已经收完、等待空闲处理器的流 */
+    queued: VecDeque<u32>,
+    dispatching: usize,
+    /** 上次取结果之后分发过新的请求 */
+    dispatched: bool,
+    decoder: Decoder,
+    streams: BTreeMap<u32, Stream>,
+    last_stream: u32,
+    /** 我们重置过的流，只保留最近的一些；对端在收到RST_STREAM之前发出的帧直接忽略 */
+    reset_streams: VecDeque<u32>,
+    send_window: i64,
+    recv_window: i64,
+    initial_window: i64,
+    max_frame: usize,
+    continuation: Option<HeaderBlock>,
+    goaway: bool,
+    timeout: Option<Duration>,
+}
+
+/**
+# 处理一个HTTP/2连接
+`initial`为已经从连接中读出的数据(h2c时至少包含连接序言)。
+每个流收完请求后转换成[`HttpRequst`]交给`router`，所以处理器不需要关心协议版本。
+处理器在单独的线程中运行，慢的处理器不会阻塞同一个连接上的其他流；每个连接最多同时运行
+[`MAX_DISPATCHING`]个，其余的排队。响应数据按各个流的窗口交错发送，
+没有打开的流时和HTTP/1.1长连接一样最多等待`keep_alive_secs`
+```rust,ignore
+h2::serve(&mut stream, Vec::new(), &router, &extensions, &options, &sessions);
+```
+ */
+pub fn serve<S: Transport>(
+    io: &mut S,
+    initial: Vec<u8>,
+    router: &Router,
+    extensions: &Extensions,
+    options: &ServerConfig,
+    sessions: &Arc<Sessions>,
+) {
+    // 连接结束时等待还在运行的处理器，它们借用了`router`
+    thread::scope(|scope| {
+        let mut conn = Connection {
+            io,
+            buf: initial,
+            out: Vec::new(),
+            options,
+            sessions,
+            router,
+            extensions,
+            scope,
+            done: mpsc::channel(),
+            queued: VecDeque::new(),
+            dispatching: 0,
+            dispatched: false,
+            decoder: Decoder::new(4096),
+            streams: BTreeMap::new(),
+            last_stream: 0,
+            reset_streams: VecDeque::new(),
+            send_window: DEFAULT_WINDOW,
+            recv_window: DEFAULT_WINDOW,
+            initial_window: DEFAULT_WINDOW,
+            max_frame: MAX_FRAME_SIZE,
+            continuation: None,
+            goaway: false,
+            // 调用方已经按read_timeout_secs设置了读超时
+            timeout: timeout(options.read_timeout_secs),
+        };
+        match conn.run() {
+            Ok(()) => (),
+            Err(H2Error::Connection(code, reason)) => {
+                crate::debug!("HTTP/2 connection error {:#x}: {}", code, reason);
+                let _ = conn.send_goaway(code);
+            }
+            Err(H2Error::Io(e)) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+                let _ = conn.send_goaway(NO_ERROR);
+            }
+            Err(H2Error::Io(e)) => crate::debug!("HTTP/2 connection closed: {}", e),
+        }
+    });
+}
+
+fn timeout(secs: u64) -> Option<Duration> {
+    (secs > 0).then(|| Duration::from_secs(secs))
+}
+
+impl<S: Transport> Connection<'_, '_, S> {
+    fn run(&mut self) -> Result<(), H2Error> {
+        self.fill(PREFACE.len())?;
+        if !self.buf.starts_with(PREFACE) {
+            return Err(H2Error::Connection(PROTOCOL_ERROR, "invalid connection preface"));
+        }
+        self.buf.drain(..PREFACE.len());
+
+        let mut settings = Vec::new();
+        for (id, value) in [
+            (SETTINGS_MAX_CONCURRENT_STREAMS, MAX_CONCURRENT_STREAMS as u32),
+            (SETTINGS_MAX_HEADER_LIST_SIZE, self.options.max_header_bytes as u32),
+        ] {
+            settings.extend_from_slice(&id.to_be_bytes());
+            settings.extend_from_slice(&value.to_be_bytes());
+        }
+        self.write_frame(SETTINGS, 0, 0, &settings)?;
+        self.flush()?;
+
+        loop {
+            self.collect()?;
+            self.pump_events()?;
+            self.flush_pending()?;
+            if self.goaway && self.streams.values().all(|s| s.responded && s.pending.is_empty() && s.events.is_none()) {
+                return Ok(());
+            }
+            // 处理完请求后没有打开的流：按keep_alive_secs等待下一个请求，为0时立即关闭
+            let idle = self.last_stream > 0 && self.streams.is_empty() && self.buf.is_empty();
+            if idle && self.options.keep_alive_secs == 0 {
+                return Ok(self.send_goaway(NO_ERROR)?);
+            }
+            // 有处理器在运行或者有事件流时用较短的读超时轮询，超时只是回去检查它们，不关闭连接
+            let polling = if self.dispatching > 0 {
+                Some(DISPATCH_POLL_INTERVAL)
+            } else if self.streams.values().any(|s| s.events.is_some()) {
+                Some(POLL_INTERVAL)
+            } else {
+                None
+            };
+            let timeout = match polling {
+                Some(interval) => Some(interval),
+                None if idle => timeout(self.options.keep_alive_secs),
+                None => timeout(self.options.read_timeout_secs),
+            };
+            if timeout != self.timeout {
+                self.io.set_read_timeout(timeout)?;
+                self.timeout = timeout;
+            }
+            let frame = match self.read_frame() {
+                Err(H2Error::Io(e)) if polling.is_some() && matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => continue,
+                frame => frame?,
+            };
+            self.handle(frame)?;
+        }
+    }
+
+    /**
+     * 读到缓冲区中至少有`n`个字节
+     */
+    fn fill(&mut self, n: usize) -> Result<(), H2Error> {
+        let mut chunk = [0; 16 * 1024];
+        while self.buf.len() < n {
+            match self.io.read(&mut chunk)? {
+                0 => return Err(H2Error::Io(io::ErrorKind::UnexpectedEof.into())),
+                read => self.buf.extend_from_slice(&chunk[..read]),
+            }
+        }
+        Ok(())
+    }
+
+    fn read_frame(&mut self) -> Result<Frame, H2Error> {
+        self.fill(FRAME_HEADER_LEN)?;
+        let len = u32::from_be_bytes([0, self.buf[0], self.buf[1], self.buf[2]]) as usize;
+        if len > MAX_FRAME_SIZE {
+            return Err(H2Error::Connection(FRAME_SIZE_ERROR, "frame exceeds SETTINGS_MAX_FRAME_SIZE"));
+        }
+        self.fill(FRAME_HEADER_LEN + len)?;
+        let kind = self.buf[3];
+        let flags = self.buf[4];
+        let stream = u32::from_be_bytes([self.buf[5], self.buf[6], self.buf[7], self.buf[8]]) & 0x7fff_ffff;
+        let payload = self.buf[FRAME_HEADER_LEN..FRAME_HEADER_LEN + len].to_vec();
+        self.buf.drain(..FRAME_HEADER_LEN + len);
+        Ok(Frame { kind, flags, stream, payload })
+    }
+
+    /**
+     * 帧先放进发送缓冲区，由[`Connection::flush`]一次写出，
+     * 避免响应头和数据分成几个小的TCP段时被Nagle算法和延迟确认拖慢
+     */
+    fn write_frame(&mut self, kind: u8, flags: u8, stream: u32, payload: &[u8]) -> io::Result<()> {
+        let len = (payload.len() as u32).to_be_bytes();
+        self.out.extend_from_slice(&len[1..]);
+        self.out.push(kind);
+        self.out.push(flags);
+        self.out.extend_from_slice(&stream.to_be_bytes());
+        self.out.extend_from_slice(payload);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.out.is_empty() {
+            self.io.write_all(&self.out)?;
+            self.out.clear();
+        }
+        self.io.flush()
+    }
+
+    fn send_goaway(&mut self, code: u32) -> io::Result<()> {
+        let mut payload = self.last_stream.to_be_bytes().to_vec();
+        payload.extend_from_slice(&code.to_be_bytes());
+        self.write_frame(GOAWAY, 0, 0, &payload)?;
+        self.flush()
+    }
+
+    fn reset(&mut self, stream: u32, code: u32) -> io::Result<()> {
+        self.streams.remove(&stream);
+        if self.reset_streams.len() >= MAX_CONCURRENT_STREAMS {
+            self.reset_streams.pop_front();
+        }
+        self.reset_streams.push_back(stream);
+        self.write_frame(RST_STREAM, 0, stream, &code.to_be_bytes())
+    }
+
+    fn handle(&mut self, frame: Frame) -> Result<(), H2Error> {
+        if let Some(block) = &self.continuation {
+            if frame.kind != CONTINUATION || frame.stream != block.stream {
+                return Err(H2Error::Connection(PROTOCOL_ERROR, "expected CONTINUATION"));
+            }
+        }
+        match frame.kind {
+            DATA => self.on_data(frame),
+            HEADERS => self.on_headers(frame),
+            CONTINUATION => self.on_continuation(frame),
+            PRIORITY => {
+                if frame.stream == 0 || frame.payload.len() != 5 {
+                    return Err(H2Error::Connection(PROTOCOL_ERROR, "invalid PRIORITY"));
+                }
+                Ok(())
+            }
+            RST_STREAM => {
+                if frame.stream == 0 || frame.stream > self.last_stream {
+                    return Err(H2Error::Connection(PROTOCOL_ERROR, "RST_STREAM on an idle stream"));
+                }
+                if frame.payload.len() != 4 {
+                    return Err(H2Error::Connection(FRAME_SIZE_ERROR, "invalid RST_STREAM"));
+                }
+                self.streams.remove(&frame.stream);
+                Ok(())
+            }
+            SETTINGS => self.on_settings(frame),
+            PUSH_PROMISE => Err(H2Error::Connection(PROTOCOL_ERROR, "clients cannot push")),
+            PING => {
+                if frame.stream != 0 {
+                    return Err(H2Error::Connection(PROTOCOL_ERROR, "PING on a stream"));
+                }
+                if frame.payload.len() != 8 {
+                    return Err(H2Error::Connection(FRAME_SIZE_ERROR, "invalid PING"));
+                }
+                if frame.flags & ACK == 0 {
+                    self.write_frame(PING, ACK, 0, &frame.payload)?;
+                    self.flush()?;
+                }
+                Ok(())
+            }
+            GOAWAY => {
+                self.goaway = true;
+                Ok(())
+            }
+            WINDOW_UPDATE => self.on_window_update(frame),
+            // 未知类型的帧必须忽略
+            _ => Ok(()),
+        }
+    }
+
+    /**
+     * 去掉PADDED标志带来的填充
+     */
+    fn strip_padding(frame: &Frame, start: usize) -> Result<&[u8], H2Error> {
+        let payload = &frame.payload;
+        if frame.flags & PADDED == 0 {
+            return payload.get(start..).ok_or(H2Error::Connection(FRAME_SIZE_ERROR, "frame is too short"));
+        }
+        let pad = *payload.first().ok_or(H2Error::Connection(FRAME_SIZE_ERROR, "frame is too short"))? as usize;
+        if 1 + start + pad > payload.len() {
+            return Err(H2Error::Connection(PROTOCOL_ERROR, "padding exceeds the frame"));
+        }
+        Ok(&payload[1 + start..payload.len() - pad])
+    }
+
+    fn on_data(&mut self, frame: Frame) -> Result<(), H2Error> {
+        if frame.stream == 0 || frame.stream > self.last_stream {
+            return Err(H2Error::Connection(PROTOCOL_ERROR, "DATA on an idle stream"));
+        }
+        let data = Self::strip_padding(&frame, 0)?.to_vec();
+        // 整个帧(包括填充)都占用流量控制窗口，关闭了的流上的数据也一样
+        let len = frame.payload.len() as i64;
+        if len > self.recv_window {
+            return Err(H2Error::Connection(FLOW_CONTROL_ERROR, "DATA exceeds the connection window"));
+        }
+        // 收到的数据立即取走，请求体大小由max_body_bytes限制；连接窗口用掉一半后一次归还
+        self.recv_window -= len;
+        if self.recv_window <= DEFAULT_WINDOW / 2 {
+            self.write_frame(WINDOW_UPDATE, 0, 0, &((DEFAULT_WINDOW - self.recv_window) as u32).to_be_bytes())?;
+            self.recv_window = DEFAULT_WINDOW;
+        }
+        let id = frame.stream;
+        let max_body = self.options.max_body_bytes;
+        let Some(stream) = self.streams.get_mut(&id) else {
+            if self.reset_streams.contains(&id) {
+                return Ok(());
+            }
+            return Ok(self.reset(id, STREAM_CLOSED)?);
+        };
+        // 对端已经用END_STREAM结束了请求
+        if stream.recv_closed {
+            return Ok(self.reset(id, STREAM_CLOSED)?);
+        }
+        if len > stream.recv_window {
+            return Ok(self.reset(id, FLOW_CONTROL_ERROR)?);
+        }
+        stream.recv_window -= len;
+        let end = frame.flags & END_STREAM != 0;
+        stream.recv_closed = end;
+        // 已经提前回应的流，剩下的请求体丢弃，也不再归还流窗口
+        if stream.responded {
+            if end {
+                self.streams.remove(&id);
+            }
+            return Ok(());
+        }
+        if stream.body.len() + data.len() > max_body {
+            return self.respond_status(id, "413");
+        }
+        stream.body.extend_from_slice(&data);
+        if end {
+            self.respond(id)?;
+        } else if stream.recv_window <= DEFAULT_WINDOW / 2 {
+            let increment = DEFAULT_WINDOW - stream.recv_window;
+            stream.recv_window = DEFAULT_WINDOW;
+            self.write_frame(WINDOW_UPDATE, 0, id, &(increment as u32).to_be_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn on_headers(&mut self, frame: Frame) -> Result<(), H2Error> {
+        if frame.stream == 0 || frame.stream.is_multiple_of(2) {
+            return Err(H2Error::Connection(PROTOCOL_ERROR, "invalid stream identifier for HEADERS"));
+        }
+        let start = if frame.flags & PRIORITY_FLAG != 0 { 5 } else { 0 };
+        let block = HeaderBlock {
+            stream: frame.stream,
+            block: Self::strip_padding(&frame, start)?.to_vec(),
+            end_stream: frame.flags & END_STREAM != 0,
+        };
+        if frame.flags & END_HEADERS != 0 {
+            self.on_header_block(block)
+        } else {
+            self.continuation = Some(block);
+            Ok(())
+        }
+    }
+
+    fn on_continuation(&mut self, frame: Frame) -> Result<(), H2Error> {
+        let mut block = self.continuation.take()
+            .ok_or(H2Error::Connection(PROTOCOL_ERROR, "unexpected CONTINUATION"))?;
+        block.block.extend_from_slice(&frame.payload);
+        // 压缩后的头部不会比解压后的上限大太多，防止无休止的CONTINUATION
+        if block.block.len() > self.options.max_header_bytes + MAX_FRAME_SIZE {
+            return Err(H2Error::Connection(ENHANCE_YOUR_CALM, "header block is too large"));
+        }
+        if frame.flags & END_HEADERS != 0 {
+            self.on_header_block(block)
+        } else {
+            self.continuation = Some(block);
+            Ok(())
+        }
+    }
+
+    fn on_header_block(&mut self, block: HeaderBlock) -> Result<(), H2Error> {
+        // 即使流会被拒绝也要解码，否则动态表会和对端不同步
+        let decoded = match self.decoder.decode(&block.block, self.options.max_header_bytes) {
+            Err(e) if e != HpackError::HeaderListTooLarge => {
+                return Err(H2Error::Connection(COMPRESSION_ERROR, "cannot decode header block"));
+            }
+            decoded => decoded,
+        };
+        let id = block.stream;
+
+        if let Some(stream) = self.streams.get_mut(&id) {
+            // 请求体之后的trailer，只用来结束请求
+            if stream.recv_closed || !block.end_stream {
+                return Ok(self.reset(id, PROTOCOL_ERROR)?);
+            }
+            stream.recv_closed = true;
+            if !stream.responded {
+                self.respond(id)?;
+            }
+            return Ok(());
+        }
+        if id <= self.last_stream {
+            if self.reset_streams.contains(&id) {
+                return Ok(());
+            }
+            return Err(H2Error::Connection(STREAM_CLOSED, "HEADERS on a closed stream"));
+        }
+        self.last_stream = id;
+
+        let (headers, too_large) = match decoded {
+            Ok(headers) => (headers, false),
+            Err(_) => (Vec::new(), true),
+        };
+        if self.goaway || self.streams.len() >= MAX_CONCURRENT_STREAMS {
+            return Ok(self.reset(id, REFUSED_STREAM)?);
+        }
+        self.streams.insert(id, Stream {
+            headers,
+            body: Vec::new(),
+            recv_closed: block.end_stream,
+            responded: false,
+            send_window: self.initial_window,
+            recv_window: DEFAULT_WINDOW,
+            pending: Vec::new(),
+            vetted: false,
+            events: None,
+        });
+        if too_large {
+            self.respond_status(id, "431")
+        } else if block.end_stream {
+            self.respond(id)
+        } else {
+            self.expect(id)
+        }
+    }
+
+    /**
+     * 请求带`expect: 100-continue`时先让路由表检查请求头，通过后发送`:status 100`，
+     * 拒绝时直接回应，后面的请求体被丢弃；其它期望回应417
+     */
+    fn expect(&mut self, id: u32) -> Result<(), H2Error> {
+        let Some(stream) = self.streams.get(&id) else {
+            return Ok(());
+        };
+        let Some((_, expect)) = stream.headers.iter().find(|(name, _)| name == "expect") else {
+            return Ok(());
+        };
+        if !expect.trim().eq_ignore_ascii_case("100-continue") {
+            return self.respond_status(id, "417");
+        }
+        // 格式不对的请求等收完之后在`respond`中重置
+        let Ok(mut req) = to_request(stream.headers.clone(), Vec::new()) else {
+            return Ok(());
+        };
+        req.extensions = self.extensions.clone();
+        match self.router.check(&mut req) {
+            None => {
+                if let Some(stream) = self.streams.get_mut(&id) {
+                    stream.vetted = true;
+                }
+                let mut block = Vec::new();
+                hpack::encode([(":status", "100")], &mut block);
+                Ok(self.write_frame(HEADERS, END_HEADERS, id, &block)?)
+            }
+            Some(resp) => self.send_response(id, &resp, req.method == Method::Head, None),
+        }
+    }
+
+    fn on_settings(&mut self, frame: Frame) -> Result<(), H2Error> {
+        if frame.stream != 0 {
+            return Err(H2Error::Connection(PROTOCOL_ERROR, "SETTINGS on a stream"));
+        }
+        if frame.flags & ACK != 0 {
+            if !frame.payload.is_empty() {
+                return Err(H2Error::Connection(FRAME_SIZE_ERROR, "SETTINGS ACK with a payload"));
+            }
+            return Ok(());
+        }
+        if !frame.payload.len().is_multiple_of(6) {
+            return Err(H2Error::Connection(FRAME_SIZE_ERROR, "invalid SETTINGS length"));
+        }
+        for setting in frame.payload.chunks(6) {
+            let id = u16::from_be_bytes([setting[0], setting[1]]);
+            let value = u32::from_be_bytes([setting[2], setting[3], setting[4], setting[5]]);
+            match id {
+                SETTINGS_ENABLE_PUSH if value > 1 => {
+                    return Err(H2Error::Connection(PROTOCOL_ERROR, "invalid SETTINGS_ENABLE_PUSH"));
+                }
+                SETTINGS_INITIAL_WINDOW_SIZE => {
+                    let value = value as i64;
+                    if value > MAX_WINDOW {
+                        return Err(H2Error::Connection(FLOW_CONTROL_ERROR, "invalid SETTINGS_INITIAL_WINDOW_SIZE"));
+                    }
+                    // 新的初始窗口对所有已经打开的流生效
+                    let delta = value - self.initial_window;
+                    for stream in self.streams.values_mut() {
+                        stream.send_window += delta;
+                        if stream.send_window > MAX_WINDOW {
+                            return Err(H2Error::Connection(FLOW_CONTROL_ERROR, "stream window overflow"));
+                        }
+                    }
+                    self.initial_window = value;
+                }
+                SETTINGS_MAX_FRAME_SIZE => {
+                    if !(16_384..=16_777_215).contains(&value) {
+                        return Err(H2Error::Connection(PROTOCOL_ERROR, "invalid SETTINGS_MAX_FRAME_SIZE"));
+                    }
+                    self.max_frame = value as usize;
+                }
+                _ => (),
+            }
+        }
+        self.write_frame(SETTINGS, ACK, 0, &[])?;
+        self.flush()?;
+        Ok(())
+    }
+
+    fn on_window_update(&mut self, frame: Frame) -> Result<(), H2Error> {
+        if frame.payload.len() != 4 {
+            return Err(H2Error::Connection(FRAME_SIZE_ERROR, "invalid WINDOW_UPDATE"));
+        }
+        let increment = (u32::from_be_bytes([frame.payload[0], frame.payload[1], frame.payload[2], frame.payload[3]]) & 0x7fff_ffff) as i64;
+        if frame.stream == 0 {
+            if increment == 0 {
+                return Err(H2Error::Connection(PROTOCOL_ERROR, "zero WINDOW_UPDATE"));
+            }
+            self.send_window += increment;
+            if self.send_window > MAX_WINDOW {
+                return Err(H2Error::Connection(FLOW_CONTROL_ERROR, "connection window overflow"));
+            }
+            return Ok(());
+        }
+        if frame.stream > self.last_stream {
+            return Err(H2Error::Connection(PROTOCOL_ERROR, "WINDOW_UPDATE on an idle stream"));
+        }
+        let Some(stream) = self.streams.get_mut(&frame.stream) else {
+            return Ok(());
+        };
+        stream.send_window += increment;
+        if increment == 0 {
+            self.reset(frame.stream, PROTOCOL_ERROR)?;
+        } else if stream.send_window > MAX_WINDOW {
+            self.reset(frame.stream, FLOW_CONTROL_ERROR)?;
+        }
+        Ok(())
+    }
+
+    /**
+     * 请求已经收完，有空闲的处理器时立即分发，否则排队
+     */
+    fn respond(&mut self, id: u32) -> Result<(), H2Error> {
+        if self.dispatching >= MAX_DISPATCHING {
+            self.queued.push_back(id);
+            return Ok(());
+        }
+        self.dispatch(id)
+    }
+
+    /**
+     * 在单独的线程中交给路由表处理，结果由[`Connection::collect`]取回发送
+     */
+    fn dispatch(&mut self, id: u32) -> Result<(), H2Error> {
+        let Some(stream) = self.streams.get_mut(&id) else {
+            return Ok(());
+        };
+        let headers = std::mem::take(&mut stream.headers);
+        let body = std::mem::take(&mut stream.body);
+        let vetted = stream.vetted;
+        let mut req = match to_request(headers, body) {
+            Ok(req) => req,
+            Err(reason) => {
+                crate::debug!("Malformed HTTP/2 request on stream {}: {}", id, reason);
+                return Ok(self.reset(id, PROTOCOL_ERROR)?);
+            }
+        };
+        req.extensions = self.extensions.clone();
+        if vetted {
+            req.extensions.insert(Arc::new(Vetted));
+        }
+        let streaming = Arc::new(Streaming::default());
+        req.extensions.insert(streaming.clone());
+        let head = req.method == Method::Head;
+        let router = self.router;
+        let done = self.done.0.clone();
+        self.dispatching += 1;
+        self.dispatched = true;
+        self.scope.spawn(move || {
+            let resp = router.dispatch(req);
+            let session = if resp.status_code() == "200" && !head { streaming.take() } else { None };
+            let _ = done.send(Dispatched { id, head, resp, session });
+        });
+        Ok(())
+    }
+
+    /**
+     * 发送已经完成的处理器的响应，空出的位置交给排队的流
+     */
+    fn collect(&mut self) -> Result<(), H2Error> {
+        let mut wait = std::mem::take(&mut self.dispatched).then_some(DISPATCH_POLL_INTERVAL);
+        loop {
+            let done = match wait.take() {
+                Some(timeout) => self.done.1.recv_timeout(timeout).ok(),
+                None => self.done.1.try_recv().ok(),
+            };
+            let Some(Dispatched { id, head, resp, session }) = done else {
+                break;
+            };
+            self.dispatching -= 1;
+            // 处理期间对端已经重置了这个流
+            if !self.streams.contains_key(&id) {
+                continue;
+            }
+            // 事件流：会话在单独的线程中运行，写入的数据由连接线程作为这个流的DATA帧发送；
+            // 和HTTP/1.x共用会话数的限制，已满时回应503
+            let Some(session) = session else {
+                self.send_response(id, &resp, head, None)?;
+                continue;
+            };
+            let Some(guard) = self.sessions.enter(self.options.max_sessions) else {
+                self.send_response(id, &server::too_many_sessions(), false, None)?;
+                continue;
+            };
+            let (sender, events) = mpsc::sync_channel(EVENT_QUEUE);
+            self.send_response(id, &resp, false, Some(events))?;
+            thread::spawn(move || {
+                let _guard = guard;
+                session(Box::new(DataSink(sender)))
+            });
+        }
+        while self.dispatching < MAX_DISPATCHING {
+            let Some(id) = self.queued.pop_front() else {
+                break;
+            };
+            self.dispatch(id)?;
+        }
+        Ok(())
+    }
+
+    /**
+     * 不经过路由表直接回应一个错误状态，例如请求体或请求头过大
+     */
+    fn respond_status(&mut self, id: u32, code: &'static str) -> Result<(), H2Error> {
+        let text = format!("{} {}", code, http::httpresponse::status_text(code));
+        let resp = HttpResponse::new(code, None, Some(http::httpresponse::ResponseBody::Text(text)));
+        self.send_response(id, &resp, false, None)
+    }
+
+    /**
+     * 发送响应头和body；`events`不为空时是事件流，不发送`content-length`，数据由[`Connection::pump_events`]转发
+     */
+    fn send_response(&mut self, id: u32, resp: &HttpResponse, head: bool, events: Option<Receiver<Vec<u8>>>) -> Result<(), H2Error> {
+        let body = resp.body().map(|b| b.as_bytes().to_vec()).unwrap_or_default();
+        let status = resp.status_code();
+        let mut fields = vec![(":status".to_string(), status.to_string())];
+        for (name, value) in resp.header_fields() {
+            let name = name.to_ascii_lowercase();
+            if !CONNECTION_HEADERS.contains(&name.as_str()) && name != "content-length" {
+                fields.push((name, value.to_string()));
+            }
+        }
+        if status != "204" && status != "304" && events.is_none() {
+            fields.push(("content-length".to_string(), body.len().to_string()));
+        }
+        let mut block = Vec::new();
+        hpack::encode(fields.iter().map(|(n, v)| (n.as_str(), v.as_str())), &mut block);
+
+        let end_stream = head || (body.is_empty() && events.is_none());
+        let mut chunks = block.chunks(self.max_frame).peekable();
+        let mut kind = HEADERS;
+        while let Some(chunk) = chunks.next() {
+            let mut flags = if kind == HEADERS && end_stream { END_STREAM } else { 0 };
+            if chunks.peek().is_none() {
+                flags |= END_HEADERS;
+            }
+            self.write_frame(kind, flags, id, chunk)?;
+            kind = CONTINUATION;
+        }
+        if block.is_empty() {
+            self.write_frame(HEADERS, END_HEADERS | if end_stream { END_STREAM } else { 0 }, id, &[])?;
+        }
+
+        if let Some(stream) = self.streams.get_mut(&id) {
+            stream.responded = true;
+            if !end_stream {
+                stream.pending = body;
+                stream.events = events;
+            }
+        }
+        if end_stream {
+            self.finish(id)?;
+        }
+        Ok(())
+    }
+
+    /**
+     * 响应发送完毕；请求还没有收完(提前回应的错误)时用RST_STREAM(NO_ERROR)让客户端停止发送
+     */
+    fn finish(&mut self, id: u32) -> io::Result<()> {
+        match self.streams.remove(&id) {
+            Some(stream) if !stream.recv_closed => self.reset(id, NO_ERROR),
+            _ => Ok(()),
+        }
+    }
+
+    /**
+     * 把事件流会话写入的数据放进各自的待发送数据，会话结束且数据发完的流以空的DATA帧结束。
+     * 待发送数据超过[`MAX_EVENT_PENDING`]时先不取，会话的写入因此阻塞
+     */
+    fn pump_events(&mut self) -> io::Result<()> {
+        let mut ended = Vec::new();
+        for (&id, stream) in self.streams.iter_mut() {
+            let Some(events) = &stream.events else {
+                continue;
+            };
+            let closed = loop {
+                if stream.pending.len() >= MAX_EVENT_PENDING {
+                    break false;
+                }
+                match events.try_recv() {
+                    Ok(data) => stream.pending.extend_from_slice(&data),
+                    Err(TryRecvError::Empty) => break false,
+                    Err(TryRecvError::Disconnected) => break true,
+                }
+            };
+            if closed {
+                stream.events = None;
+                if stream.pending.is_empty() {
+                    ended.push(id);
+                }
+            }
+        }
+        for id in ended {
+            self.write_frame(DATA, END_STREAM, id, &[])?;
+            self.finish(id)?;
+        }
+        Ok(())
+    }
+
+    /**
+     * 在连接和各个流的窗口允许的范围内发送等待中的响应数据，按流编号轮流发送
+     */
+    fn flush_pending(&mut self) -> io::Result<()> {
+        loop {
+            let mut progress = false;
+            let ids: Vec<u32> = self.streams.iter()
+                .filter(|(_, s)| !s.pending.is_empty())
+                .map(|(&id, _)| id)
+                .collect();
+            for id in ids {
+                let stream = &self.streams[&id];
+                let len = (self.send_window.min(stream.send_window).max(0) as usize)
+                    .min(self.max_frame)
+                    .min(stream.pending.len());
+                if len == 0 {
+                    continue;
+                }
+                let stream = self.streams.get_mut(&id).unwrap();
+                let chunk: Vec<u8> = stream.pending.drain(..len).collect();
+                stream.send_window -= len as i64;
+                let done = stream.pending.is_empty() && stream.events.is_none();
+                self.send_window -= len as i64;
+                self.write_frame(DATA, if done { END_STREAM } else { 0 }, id, &chunk)?;
+                if done {
+                    self.finish(id)?;
+                }
+                progress = true;
+            }
+            if !progress {
+                break;
+            }
+        }
+        self.flush()
+    }
+}
+
+/**
+ * # 转换成HttpRequst
+ * 伪头部`:method`、`:path`必须有，`:authority`没有`host`时作为`Host`，
+ * 伪头部必须在普通头部之前，名字必须是小写，不允许逐跳头部；
+ * 重复的头部用`, `合并(`cookie`用`; `)
+ */
+fn to_request(fields: Vec<(String, String)>, body: Vec<u8>) -> Result<HttpRequst, &'static str> {
+    let mut method = None;
+    let mut path = None;
+    let mut authority = None;
+    let mut headers: HashMap<String, String> = HashMap::new();
+    for (name, value) in fields {
+        if let Some(pseudo) = name.strip_prefix(':') {
+            if !headers.is_empty() {
+                return Err("pseudo-header after regular header");
+            }
+            let slot = match pseudo {
+                "method" => &mut method,
+                "path" => &mut path,
+                "authority" => &mut authority,
+                "scheme" => continue,
+                _ => return Err("unknown pseudo-header"),
+            };
+            if slot.replace(value).is_some() {
+                return Err("duplicate pseudo-header");
+            }
+            continue;
+        }
+        if name.bytes().any(|b| b.is_ascii_uppercase()) {
+            return Err("uppercase header name");
+        }
+        if CONNECTION_HEADERS.contains(&name.as_str()) || (name == "te" && value != "trailers") {
+            return Err("connection-specific header");
+        }
+        let separator = if name == "cookie" { "; " } else { ", " };
+        headers.entry(name)
+            .and_modify(|v| {
+                v.push_str(separator);
+                v.push_str(&value);
+            })
+            .or_insert(value);
+    }
+    let method = method.ok_or("missing :method")?;
+    let path = path.filter(|p| !p.is_empty()).ok_or("missing :path")?;
+    if let Some(authority) = authority {
+        headers.entry("host".to_string()).or_insert(authority);
+    }
+    Ok(HttpRequst {
+        method: method.as_str().into(),
+        version: Version::V2_0,
+        resource: Resource::Path(path),
+        headers,
+        msg_body: String::from_utf8_lossy(&body).into_owned(),
+        params: HashMap::new(),
+        extensions: Extensions::default(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sse;
+    use http::httpresponse::ResponseBody;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /** `/flood`会话成功写入的字节数 */
+    static FLOODED: AtomicUsize = AtomicUsize::new(0);
+    /** 正在运行和同时运行最多的`/slow`处理器 */
+    static RUNNING: AtomicUsize = AtomicUsize::new(0);
+    static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+    /**
+     * 内存中的双向连接：读取预先准备好的客户端数据，记录服务端写出的数据
+     */
+    struct Pipe {
+        input: io::Cursor<Vec<u8>>,
+        output: Vec<u8>,
+        /** 输入读完后还要返回多少次读超时，模拟对端保持连接但不发送数据 */
+        idle: usize,
+    }
+
+    impl Read for Pipe {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.input.read(buf)?;
+            if n == 0 && self.idle > 0 {
+                self.idle -= 1;
+                thread::sleep(Duration::from_millis(1));
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+            Ok(n)
+        }
+    }
+
+    impl Transport for Pipe {
+        fn set_read_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Write for Pipe {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn frame(kind: u8, flags: u8, stream: u32, payload: &[u8]) -> Vec<u8> {
+        let mut out = (payload.len() as u32).to_be_bytes()[1..].to_vec();
+        out.extend_from_slice(&[kind, flags]);
+        out.extend_from_slice(&stream.to_be_bytes());
+        out.extend_from_slice(payload);
+        out
+    }
+
+    fn request(stream: u32, method: &str, path: &str, end_stream: bool) -> Vec<u8> {
+        let mut block = Vec::new();
+        hpack::encode([(":method", method), (":scheme", "http"), (":path", path), (":authority", "localhost")], &mut block);
+        frame(HEADERS, END_HEADERS | if end_stream { END_STREAM } else { 0 }, stream, &block)
+    }
+
+    fn parse(mut out: &[u8]) -> Vec<Frame> {
+        let mut frames = Vec::new();
+        while out.len() >= FRAME_HEADER_LEN {
+            let len = u32::from_be_bytes([0, out[0], out[1], out[2]]) as usize;
+            let stream = u32::from_be_bytes([out[5], out[6], out[7], out[8]]);
+            frames.push(Frame { kind: out[3], flags: out[4], stream, payload: out[9..9 + len].to_vec() });
+            out = &out[9 + len..];
+        }
+        frames
+    }
+
+    fn run(input: Vec<u8>, options: &ServerConfig) -> Vec<Frame> {
+        let mut router = Router::new();
+        router.get("/hello", |_: &HttpRequst| {
+            HttpResponse::new("200", None, Some(ResponseBody::Text("hello".into())))
+        });
+        router.post("/echo", |req: &HttpRequst| {
+            let body = format!("{} {}", req.header("host").unwrap_or(""), req.msg_body);
+            HttpResponse::new("200", None, Some(ResponseBody::Text(body)))
+        });
+        router.get("/big", |_: &HttpRequst| {
+            HttpResponse::new("200", None, Some(ResponseBody::Binary(vec![b'x'; 100_000])))
+        });
+        router.get("/slow", |_: &HttpRequst| {
+            let running = RUNNING.fetch_add(1, Ordering::SeqCst) + 1;
+            PEAK.fetch_max(running, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(100));
+            RUNNING.fetch_sub(1, Ordering::SeqCst);
+            HttpResponse::new("200", None, Some(ResponseBody::Text("slow".into())))
+        });
+        router.get("/events", |req: &HttpRequst| {
+            sse::stream(req, |mut events| {
+                for i in 0..3 {
+                    events.send(&sse::Event::new(i.to_string())).unwrap();
+                }
+            })
+        });
+        router.get("/flood", |req: &HttpRequst| {
+            sse::stream(req, |mut events| {
+                let event = sse::Event::new("x".repeat(1000));
+                for _ in 0..1000 {
+                    if events.send(&event).is_err() {
+                        break;
+                    }
+                    FLOODED.fetch_add(1006, Ordering::SeqCst);
+                }
+            })
+        });
+        let mut pipe = Pipe { input: io::Cursor::new(input), output: Vec::new(), idle: 1000 };
+        serve(&mut pipe, Vec::new(), &router, &Extensions::default(), options, &Arc::default());
+        parse(&pipe.output)
+    }
+
+    fn headers_of(frames: &[Frame], stream: u32) -> Vec<(String, String)> {
+        let frame = frames.iter().find(|f| f.kind == HEADERS && f.stream == stream).unwrap();
+        Decoder::new(4096).decode(&frame.payload, 1 << 20).unwrap()
+    }
+
+    fn data_of(frames: &[Frame], stream: u32) -> Vec<u8> {
+        frames.iter().filter(|f| f.kind == DATA && f.stream == stream).flat_map(|f| f.payload.clone()).collect()
+    }
+
+    #[test]
+    fn test_multiplexed_requests() {
+        let mut input = PREFACE.to_vec();
+        input.extend(frame(SETTINGS, 0, 0, &[]));
+        input.extend(request(1, "GET", "/hello", true));
+        input.extend(request(3, "POST", "/echo", false));
+        input.extend(request(5, "HEAD", "/hello", true));
+        input.extend(frame(DATA, 0, 3, b"ab"));
+        input.extend(frame(DATA, END_STREAM, 3, b"c"));
+        input.extend(frame(PING, 0, 0, b"12345678"));
+        input.extend(frame(GOAWAY, 0, 0, &[0; 8]));
+        let frames = run(input, &ServerConfig::default());
+
+        assert_eq!(frames[0].kind, SETTINGS);
+        assert!(frames.iter().any(|f| f.kind == SETTINGS && f.flags == ACK));
+        assert_eq!(headers_of(&frames, 1)[0], (":status".to_string(), "200".to_string()));
+        assert!(headers_of(&frames, 1).contains(&("content-length".to_string(), "5".to_string())));
+        assert_eq!(data_of(&frames, 1), b"hello");
+        assert_eq!(data_of(&frames, 3), b"localhost abc");
+        // HEAD只有头部，END_STREAM在HEADERS上
+        assert!(frames.iter().any(|f| f.kind == HEADERS && f.stream == 5 && f.flags & END_STREAM != 0));
+        assert!(data_of(&frames, 5).is_empty());
+        assert!(frames.iter().any(|f| f.kind == PING && f.flags == ACK && f.payload == b"12345678"));
+    }
+
+    #[test]
+    fn test_concurrent_dispatch() {
+        // 慢的处理器不阻塞后面的流
+        let mut input = PREFACE.to_vec();
+        input.extend(request(1, "GET", "/slow", true));
+        input.extend(request(3, "GET", "/hello", true));
+        let frames = run(input, &ServerConfig::default());
+        let first = frames.iter().find(|f| f.kind == HEADERS).unwrap();
+        assert_eq!(first.stream, 3);
+        assert_eq!(data_of(&frames, 1), b"slow");
+
+        // 超过MAX_DISPATCHING的请求排队，全部都会得到回应
+        let mut input = PREFACE.to_vec();
+        let ids: Vec<u32> = (0..MAX_DISPATCHING as u32 + 2).map(|i| 1 + 2 * i).collect();
+        for &id in &ids {
+            input.extend(request(id, "GET", "/slow", true));
+        }
+        let frames = run(input, &ServerConfig::default());
+        assert!(ids.iter().all(|&id| data_of(&frames, id) == b"slow"));
+        assert!(PEAK.load(Ordering::SeqCst) <= MAX_DISPATCHING);
+    }
+
+    #[test]
+    fn test_keep_alive() {
+        // keep_alive_secs为0时处理完请求、没有打开的流就关闭连接
+        let mut input = PREFACE.to_vec();
+        input.extend(request(1, "GET", "/hello", true));
+        let frames = run(input, &ServerConfig { keep_alive_secs: 0, ..ServerConfig::default() });
+        assert_eq!(data_of(&frames, 1), b"hello");
+        let last = frames.last().unwrap();
+        assert_eq!(last.kind, GOAWAY);
+        assert_eq!(last.payload, [1u32.to_be_bytes(), NO_ERROR.to_be_bytes()].concat());
+    }
+
+    #[test]
+    fn test_flow_control() {
+        // 客户端初始窗口为10字节，之后逐步放开
+        let mut input = PREFACE.to_vec();
+        let mut settings = SETTINGS_INITIAL_WINDOW_SIZE.to_be_bytes().to_vec();
+        settings.extend_from_slice(&10u32.to_be_bytes());
+        input.extend(frame(SETTINGS, 0, 0, &settings));
+        input.extend(request(1, "GET", "/big", true));
+        input.extend(frame(WINDOW_UPDATE, 0, 1, &50_000u32.to_be_bytes()));
+        input.extend(frame(WINDOW_UPDATE, 0, 0, &40_000u32.to_be_bytes()));
+        let frames = run(input, &ServerConfig::default());
+
+        let sent: Vec<usize> = frames.iter().filter(|f| f.kind == DATA).map(|f| f.payload.len()).collect();
+        assert!(sent.iter().all(|&len| len <= MAX_FRAME_SIZE));
+        // 连接窗口65535+40000，流窗口10+50000，取较小的
+        assert_eq!(sent.iter().sum::<usize>(), 50_010);
+        assert!(frames.iter().all(|f| f.kind != DATA || f.flags & END_STREAM == 0));
+    }
+
+    #[test]
+    fn test_closed_streams() {
+        // 请求已经结束的流上的DATA是流错误STREAM_CLOSED
+        let mut input = PREFACE.to_vec();
+        input.extend(request(1, "GET", "/hello", true));
+        input.extend(frame(DATA, 0, 1, b"late"));
+        let frames = run(input, &ServerConfig::default());
+        assert!(frames.iter().any(|f| f.kind == RST_STREAM && f.stream == 1 && f.payload == STREAM_CLOSED.to_be_bytes()));
+        assert!(frames.iter().all(|f| f.kind != GOAWAY || f.payload[4..] == NO_ERROR.to_be_bytes()));
+
+        // 我们重置过的流上还在路上的数据直接忽略
+        let options = ServerConfig { max_body_bytes: 2, ..ServerConfig::default() };
+        let mut input = PREFACE.to_vec();
+        input.extend(request(1, "POST", "/echo", false));
+        input.extend(frame(DATA, 0, 1, b"abc"));
+        input.extend(frame(DATA, 0, 1, b"def"));
+        input.extend(frame(DATA, END_STREAM, 1, b"ghi"));
+        let frames = run(input, &options);
+        let resets: Vec<&Frame> = frames.iter().filter(|f| f.kind == RST_STREAM).collect();
+        assert_eq!(resets.len(), 1);
+        assert_eq!(resets[0].payload, NO_ERROR.to_be_bytes());
+    }
+
+    #[test]
+    fn test_receive_window() {
+        // 对端窗口为0，提前回应的417发不出去，流一直打开；
+        // 对端不等WINDOW_UPDATE继续发送请求体，超过默认的流窗口后重置这个流
+        let mut input = PREFACE.to_vec();
+        let mut settings = SETTINGS_INITIAL_WINDOW_SIZE.to_be_bytes().to_vec();
+        settings.extend_from_slice(&0u32.to_be_bytes());
+        input.extend(frame(SETTINGS, 0, 0, &settings));
+        let mut block = Vec::new();
+        hpack::encode([(":method", "POST"), (":scheme", "http"), (":path", "/echo"), ("expect", "later")], &mut block);
+        input.extend(frame(HEADERS, END_HEADERS, 1, &block));
+        for _ in 0..5 {
+            input.extend(frame(DATA, 0, 1, &[0; MAX_FRAME_SIZE]));
+        }
+        let frames = run(input, &ServerConfig::default());
+        assert_eq!(headers_of(&frames, 1)[0].1, "417");
+        assert!(data_of(&frames, 1).is_empty());
+        assert!(frames.iter().any(|f| f.kind == RST_STREAM && f.stream == 1 && f.payload == FLOW_CONTROL_ERROR.to_be_bytes()));
+        // 连接窗口照常归还，不是连接错误
+        assert!(frames.iter().any(|f| f.kind == WINDOW_UPDATE && f.stream == 0));
+        assert!(frames.iter().all(|f| f.kind != GOAWAY || f.payload[4..] == NO_ERROR.to_be_bytes()));
+    }
+
+    #[test]
+    fn test_event_stream() {
+        let mut input = PREFACE.to_vec();
+        input.extend(request(1, "GET", "/events", true));
+        input.extend(request(3, "GET", "/hello", true));
+        let frames = run(input, &ServerConfig::default());
+
+        let headers = headers_of(&frames, 1);
+        assert!(headers.contains(&("content-type".into(), "text/event-stream; charset=utf-8".into())));
+        assert!(!headers.iter().any(|(name, _)| name == "content-length"));
+        // 事件流打开期间其他流照常处理
+        assert_eq!(data_of(&frames, 3), b"hello");
+        assert_eq!(data_of(&frames, 1), b"data: 0\n\ndata: 1\n\ndata: 2\n\n");
+        let last = frames.iter().rfind(|f| f.stream == 1).unwrap();
+        assert_eq!((last.kind, last.flags & END_STREAM), (DATA, END_STREAM));
+    }
+
+    #[test]
+    fn test_event_stream_session_limit() {
+        let mut input = PREFACE.to_vec();
+        input.extend(request(1, "GET", "/events", true));
+        let frames = run(input, &ServerConfig { max_sessions: 0, ..ServerConfig::default() });
+        assert_eq!(headers_of(&frames, 1)[0], (":status".to_string(), "503".to_string()));
+        assert!(headers_of(&frames, 1).contains(&("retry-after".to_string(), "5".to_string())));
+    }
+
+    #[test]
+    fn test_event_backpressure() {
+        // 对端窗口只有10字节，会话的写入应当在缓存了有限的数据后阻塞，连接关闭后失败
+        let mut input = PREFACE.to_vec();
+        let mut settings = SETTINGS_INITIAL_WINDOW_SIZE.to_be_bytes().to_vec();
+        settings.extend_from_slice(&10u32.to_be_bytes());
+        input.extend(frame(SETTINGS, 0, 0, &settings));
+        input.extend(request(1, "GET", "/flood", true));
+        let frames = run(input, &ServerConfig::default());
+
+        assert_eq!(data_of(&frames, 1).len(), 10);
+        thread::sleep(Duration::from_millis(50));
+        let flooded = FLOODED.load(Ordering::SeqCst);
+        assert!(flooded <= MAX_EVENT_PENDING + (EVENT_QUEUE + 2) * 1006, "{}", flooded);
+    }
+
+    #[test]
+    fn test_expect_continue() {
+        let expect = |stream: u32, method: &str, path: &str, value: &str| {
+            let mut block = Vec::new();
+            hpack::encode([(":method", method), (":scheme", "http"), (":path", path), ("expect", value)], &mut block);
+            frame(HEADERS, END_HEADERS, stream, &block)
+        };
+        let mut input = PREFACE.to_vec();
+        input.extend(expect(1, "POST", "/echo", "100-continue"));
+        input.extend(frame(DATA, END_STREAM, 1, b"abc"));
+        input.extend(expect(3, "POST", "/hello", "100-continue"));
+        input.extend(expect(5, "POST", "/echo", "later"));
+        let frames = run(input, &ServerConfig::default());
+
+        let status = |stream: u32| -> Vec<String> {
+            frames.iter()
+                .filter(|f| f.kind == HEADERS && f.stream == stream)
+                .map(|f| Decoder::new(4096).decode(&f.payload, 1 << 20).unwrap()[0].1.clone())
+                .collect()
+        };
+        assert_eq!(status(1), ["100", "200"]);
+        assert_eq!(data_of(&frames, 1), b" abc");
+        // 路由拒绝或者不认识的期望：不发送100，直接回应并让客户端停止发送请求体
+        assert_eq!(status(3), ["405"]);
+        assert_eq!(status(5), ["417"]);
+        assert!(frames.iter().any(|f| f.kind == RST_STREAM && f.stream == 3 && f.payload == NO_ERROR.to_be_bytes()));
+    }
+
+    #[test]
+    fn test_errors() {
+        // 请求体超过上限时提前回应413并重置流
+        let options = ServerConfig { max_body_bytes: 2, ..ServerConfig::default() };
+        let mut input = PREFACE.to_vec();
+        input.extend(request(1, "POST", "/echo", false));
+        input.extend(frame(DATA, 0, 1, b"abc"));
+        let frames = run(input, &options);
+        assert_eq!(headers_of(&frames, 1)[0].1, "413");
+        assert!(frames.iter().any(|f| f.kind == RST_STREAM && f.stream == 1 && f.payload == NO_ERROR.to_be_bytes()));
+
+        // 偶数流编号是连接错误
+        let mut input = PREFACE.to_vec();
+        input.extend(request(2, "GET", "/hello", true));
+        let frames = run(input, &ServerConfig::default());
+        let goaway = frames.iter().find(|f| f.kind == GOAWAY).unwrap();
+        assert_eq!(&goaway.payload[4..], PROTOCOL_ERROR.to_be_bytes());
+
+        // 缺少:path的请求只重置这个流
+        let mut block = Vec::new();
+        hpack::encode([(":method", "GET")], &mut block);
+        let mut input = PREFACE.to_vec();
+        input.extend(frame(HEADERS, END_HEADERS | END_STREAM, 1, &block));
+        input.extend(request(3, "GET", "/hello", true));
+        let frames = run(input, &ServerConfig::default());
+        assert!(frames.iter().any(|f| f.kind == RST_STREAM && f.stream == 1 && f.payload == PROTOCOL_ERROR.to_be_bytes()));
+        assert_eq!(data_of(&frames, 3), b"hello");
+
+        // 错误的连接序言
+        let frames = run(b"GET / HTTP/1.1\r\n\r\n0123456789".to_vec(), &ServerConfig::default());
+        assert!(frames.iter().any(|f| f.kind == GOAWAY));
+    }
+}
@@ -1,182 +1,453 @@
 use http::{httprequest::HttpRequst, httpresponse::HttpResponse, httpresponse::ResponseBody};
-use serde::{Deserialize, Serialize};
+use http::mime::{self, MimeRegistry};
+use super::autoindex;
+use super::cache::StaticCache;
+use super::characters::OrderStatus;
+use super::embed;
+use super::negotiate::represent;
+use super::problem::Problem;
+use super::repository::StorageError;
+use super::router::{MountPrefix, Router};
+use super::state::AppState;
+use super::staticfile::{self, Resolved, StaticOptions};
+use super::sse::{self, Event};
+use super::websocket::{self, Limits, Message};
 use std::collections::HashMap;
-// use std::default;
 use std::env;
 use std::fs;
-// use std::path;
-use std::path::PathBuf;
-// use std::hash::Hash;
+use std::path::{Path, PathBuf};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::Arc;
+use std::time::Duration;
 
-
-
-pub trait Handler {
-    fn handle(req: &HttpRequst) -> HttpResponse;
+/**
+# Handler
+请求处理的统一接口，处理器通过`&self`持有自己的配置或缓存，可以作为
+`Box<dyn Handler>`放进路由表。签名匹配的函数和闭包也自动实现了`Handler`，返回值见[`IntoResponse`]，
+服务启动时注入的共享状态可以通过[`HttpRequst::state`]取得
+```rust,ignore
+router.get("/health", |_req: &HttpRequst| {
+    HttpResponse::new("200", None, Some(ResponseBody::Text("ok".into())))
+});
+```
+ */
+pub trait Handler: Send + Sync {
+    fn handle(&self, req: &HttpRequst) -> HttpResponse;
 
     /**
-     * # 文件加载
-     * 根据不同的文件类型选择不同的Body
-     * 对于文本文件返回`ResponseBody::Text(String)`
-     * 对于图片等文件返回`ResponseBody::Binary(Vec[u8])`
+     * 在读取请求体之前只根据请求头检查请求，例如鉴权、`Content-Length`和`Content-Type`，
+     * 返回`Some`时用这个响应拒绝请求。带`Expect: 100-continue`的请求只有通过检查才会
+     * 收到`100 Continue`，被拒绝时请求体不会被读取。
+     * 此时`msg_body`为空，路径参数已经填好，但`resource`还是挂载前的完整路径；默认接受所有请求
      */
-    fn load_file(file_name: &str) -> Option<ResponseBody> {
-        let default_path = format!("{}/public", env!("CARGO_MANIFEST_DIR"));
-        let public_path = env::var("PUBLIC_PATH").unwrap_or(default_path);
-        let full_path = format!("{}/{}", public_path, file_name);
-
-        let contents : Option<ResponseBody> = 
-        if let Some(ext) = PathBuf::from(full_path.clone()).extension().and_then(|ext| ext.to_str()) {
-            match ext {  
-                "html" | "css" | "js" |"xml" |"json" |"txt" => {
-                    match fs::read_to_string(full_path) {
-                        Ok(txt) => Some(ResponseBody::Text(txt)),
-                        _ => None,
-                    }
-                },
-                _ => {
-                    match fs::read(full_path) {
-                        Ok(data) => Some(ResponseBody::Binary(data)),
-                        _ => None,
-                  }
-                },
+    fn check(&self, _req: &HttpRequst) -> Option<HttpResponse> {
+        None
+    }
+}
+
+/**
+ * # 处理器的返回值
+ * 可以直接返回`HttpResponse`，也可以返回`Result`并用`?`传播错误，
+ * 错误会转换成[`Problem`]响应
+ */
+pub trait IntoResponse {
+    fn into_response(self, req: &HttpRequst) -> HttpResponse;
+}
+
+impl IntoResponse for HttpResponse {
+    fn into_response(self, _req: &HttpRequst) -> HttpResponse {
+        self
+    }
+}
+
+impl<E: Into<Problem>> IntoResponse for Result<HttpResponse, E> {
+    fn into_response(self, req: &HttpRequst) -> HttpResponse {
+        self.unwrap_or_else(|e| e.into().respond(req))
+    }
+}
+
+impl<F, R> Handler for F
+where
+    F: Fn(&HttpRequst) -> R + Send + Sync,
+    R: IntoResponse,
+{
+    fn handle(&self, req: &HttpRequst) -> HttpResponse {
+        self(req).into_response(req)
+    }
+}
+
+/**
+ * # 文件加载
+ * 根据不同的文件类型选择不同的Body
+ * 对于文本文件返回`ResponseBody::Text(String)`
+ * 对于图片等文件返回`ResponseBody::Binary(Vec[u8])`
+ * 
+ * 开启`STATIC_CACHE_BYTES`后小文件会放在内存缓存中，见[`StaticCache`]
+ */
+pub fn load_file(options: &StaticOptions, cache: &StaticCache, file_name: &str) -> Option<ResponseBody> {
+    if options.embedded {
+        return embed::get(file_name).map(embed::body);
+    }
+    let full_path = options.root.join(file_name);
+    let is_text = mime::registry().is_text(file_name);
+
+    cache.get_or_load(&full_path, |full_path| {
+        if is_text {
+            match fs::read_to_string(full_path) {
+                Ok(txt) => Some(ResponseBody::Text(txt)),
+                _ => None,
+            }
+        } else {
+            match fs::read(full_path) {
+                Ok(data) => Some(ResponseBody::Binary(data)),
+                _ => None,
             }
-        } else {None};
-        contents
+        }
+    })
+}
+
+/**
+ * # 初始化MIME注册表
+ * - `MIME_TYPES`: mime.types格式的文件路径，用来覆盖或补充默认扩展名表
+ * - `MIME_SNIFF`: 设为`1`/`true`时，未知扩展名的文件根据内容猜测类型
+ */
+pub fn init_mime_registry() {
+    let mut registry = MimeRegistry::default();
+    if let Ok(path) = env::var("MIME_TYPES") {
+        match fs::read_to_string(&path) {
+            Ok(contents) => {
+                let n = registry.load_mime_types(&contents);
+                crate::info!("Loaded {} mime types from {}", n, path);
+            }
+            Err(e) => crate::warn!("Failed to read MIME_TYPES {}: {}", path, e),
+        }
+    }
+    if let Ok(sniff) = env::var("MIME_SNIFF") {
+        registry.set_sniff(matches!(sniff.as_str(), "1" | "true" | "yes"));
     }
+    let _ = mime::set_registry(registry);
+}
+
+/**
+ * 返回`404.html`页面
+ */
+pub struct PageNotFoundHandler {
+    options: Arc<StaticOptions>,
+    cache: Arc<StaticCache>,
+}
+
+/**
+ * 静态文件处理器，持有静态文件配置和文件缓存
+ */
+pub struct StaticPageHandler {
+    options: Arc<StaticOptions>,
+    cache: Arc<StaticCache>,
+    not_found: PageNotFoundHandler,
 }
 
-pub struct StaticPageHandler;
-pub struct PageNotFoundHandler;
 pub struct  WebServiceHandler;
 
 
-#[derive(Serialize, Deserialize)]
-pub struct OrderStatus {
-    name: String,
-    level: i32,
-    health: f32,
-    element: String,  
-    skills: Vec<String>,
+impl PageNotFoundHandler {
+    pub fn new(options: Arc<StaticOptions>, cache: Arc<StaticCache>) -> Self {
+        PageNotFoundHandler { options, cache }
+    }
+
+    /**
+     * 使用指定状态码返回`404.html`，用于403等同样需要错误页的情况
+     */
+    pub fn page(&self, status_code: &'static str) -> HttpResponse {
+        HttpResponse::new(status_code, None, load_file(&self.options, &self.cache, "404.html"))
+    }
 }
 
 impl Handler for PageNotFoundHandler {
-    fn handle(_req: &HttpRequst) -> HttpResponse {
-        HttpResponse::new("404", None, Self::load_file("404.html"))
+    fn handle(&self, _req: &HttpRequst) -> HttpResponse {
+        self.page("404")
     }
 }
 
 
+impl StaticPageHandler {
+    pub fn new(options: Arc<StaticOptions>, cache: Arc<StaticCache>) -> Self {
+        let not_found = PageNotFoundHandler::new(options.clone(), cache.clone());
+        StaticPageHandler {
+            options,
+            cache,
+            not_found,
+        }
+    }
+}
+
 impl Handler for StaticPageHandler {
     /**
      * # 静态页面处理
-     * 接受[`Router`]放过来的静态页面路由路径，找到相应文件，读取并返回
+     * 接受[`Router`]中通配符路由放过来的静态页面路径，映射到`PUBLIC_PATH`下任意深度的文件，读取并返回。
+     * 目录路径返回其中的`index.html`，不以`/`结尾的目录路径会被301重定向到带`/`的地址，
+     * 开启`AUTOINDEX`后没有`index.html`的目录返回文件列表
      * 
      * # Example
-     ```rust
-     match &req.resource {
-        httprequest::Resource::Path(s) => {
-            println!("Path: \"{}\"", s);
-            let route: Vec<&str> = s.split("/").collect();
-            match route[1] {
-                "api" => WebServiceHandler::handle(&req),
-
-                _ => StaticPageHandler::handle(&req)
-            }
-        }
-    },
+     ```text
+     /                        -> public/index.html
+     /img/characters/hutao.jpg -> public/img/characters/hutao.jpg
+     /docs                    -> 301 Location: /docs/
+     /docs/                   -> public/docs/index.html
+     /characters              -> public/characters.html (HTML_FALLBACK)
      ```
      */
-    fn handle(req: &HttpRequst) -> HttpResponse {
-        let http::httprequest::Resource::Path(s) = &req.resource;
-        let route: Vec<&str> = s.split("/").collect();
-        let mut file_name = match route[1] {
-            "" => "index".to_string(),
-            file_name => file_name.to_string(),
+    fn handle(&self, req: &HttpRequst) -> HttpResponse {
+        let options = self.options.as_ref();
+        let file_name = match staticfile::resolve(&req.resource, options) {
+            Resolved::File(file_name) => file_name,
+            Resolved::Directory(dir) => {
+                return autoindex::listing(req, &options.root.join(dir), options);
+            }
+            Resolved::Denied => return self.not_found.page(options.deny_status),
+            Resolved::Redirect(location) => {
+                // 挂载在前缀下时，Location需要带上被去掉的前缀
+                let base = req.state::<MountPrefix>().map(|m| m.0.as_str()).unwrap_or("");
+                let mut resp = HttpResponse::new("301", None, None);
+                resp.set_header("Location", &format!("{}{}", base, location));
+                return resp;
+            }
+            Resolved::NotFound => return self.not_found.page("404"),
         };
 
-        let mut headers = HashMap::new();
-        let mut content_type = "text/html";
-
+        if options.embedded {
+            return match embed::get(&file_name) {
+                Some(asset) => embed::respond(req, asset),
+                None => self.not_found.page("404"),
+            };
+        }
 
-        let binding = PathBuf::from(file_name.clone());
-        if binding.extension().is_none() {
-            file_name.push_str(".html");
+        // 客户端支持时优先返回预压缩文件，Content-Type仍然按原文件确定
+        let accept_encoding = req.header("Accept-Encoding").unwrap_or("");
+        if let Some((compressed, encoding)) = staticfile::precompressed(&file_name, accept_encoding, options) {
+            if let Some(body) = load_file(options, &self.cache, &compressed) {
+                let mut headers = HashMap::new();
+                headers.insert("Content-Type", mime::registry().content_type(&file_name));
+                headers.insert("Content-Encoding", encoding);
+                headers.insert("Vary", "Accept-Encoding");
+                return HttpResponse::new("200", Some(headers), Some(body));
+            }
         }
-        // 尝试从文件名获取扩展名，并设置适当的Content-Type  
-        else if let Some(ext) = binding.extension().and_then(|ext| ext.to_str()) {  
-            match ext {  
-                "html" => content_type = "text/html",
-                "css" => content_type = "text/css",  
-                "js" => content_type = "text/javascript",  
-                "xml" => content_type = "text/xml",
-                "json" => content_type = "text/json",
-                "txt" => content_type = "text/plain",  
-                "gif" => content_type = "image/gif",  
-                "png" => content_type = "image/png",  
-                "jpg" | "jpeg" => content_type = "image/jpeg",  
-                "zip" => content_type = "application/zip",
-                "mp3" => content_type = "application/map3", 
-                "mp4" => content_type = "application/map4", 
-                _ => {  
-                    // 如果不支持的扩展名，则重定向到nosupport页面  
-                    file_name = "nosupport.html".to_string();  
-                    content_type = "text/html";  
-                }  
-            }  
-        }  
-        
-        headers.insert("Content-Type", content_type);  
-
-        match Self::load_file(&file_name) {
-            None => HttpResponse::new("404", None, Self::load_file("404.html")),
-            Some(body) => HttpResponse::new("200", Some(headers), Some(body)),
+
+        match load_file(options, &self.cache, &file_name) {
+            None => self.not_found.page("404"),
+            Some(body) => {
+                // 根据扩展名设置Content-Type，未知扩展名按注册表配置嗅探或当作二进制流
+                let content_type = mime::registry().content_type_for(&file_name, body.as_bytes());
+                let mut headers = HashMap::new();
+                headers.insert("Content-Type", content_type);
+                if options.precompressed {
+                    headers.insert("Vary", "Accept-Encoding");
+                }
+                HttpResponse::new("200", Some(headers), Some(body))
+            }
         }
     }
 }
 
 
 
+/**
+ * 事件流没有数据时发送心跳的间隔
+ */
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+
 impl  WebServiceHandler {
-    fn load_json() -> Vec<OrderStatus> {
-        
-        let default_path = format!("{}/data", env!("CARGO_MANIFEST_DIR"));
-        let data_path = env::var("DATA_PATH").unwrap_or(default_path);
-        let full_path = format!("{}/{}", data_path, "characters.json");
-        // println!("full_path:{}", full_path);
-        let json_contents = fs::read_to_string(full_path);
-        let orders: Vec<OrderStatus> =
-            serde_json::from_str(json_contents
-                .unwrap()
-                .as_str()
-            )
-            .unwrap();
-        orders
+    /**
+     * 角色数据文件`data_dir/characters.json`的位置，开启`embed-assets`并且没有配置`data_dir`时
+     * 使用编译进二进制的版本，返回`None`
+     */
+    pub fn data_file(data_dir: Option<&Path>) -> Option<PathBuf> {
+        if data_dir.is_none() && embed::data("characters.json").is_some() {
+            return None;
+        }
+        let default_path = PathBuf::from(format!("{}/data", env!("CARGO_MANIFEST_DIR")));
+        Some(data_dir.unwrap_or(&default_path).join("characters.json"))
+    }
+
+    /**
+     * 读取[`WebServiceHandler::data_file`]中的角色数据，用作内存和SQLite存储的初始数据；
+     * 文件缺失或格式错误时返回[`StorageError::Unavailable`]
+     */
+    pub fn load_json(data_dir: Option<&Path>) -> Result<Vec<OrderStatus>, StorageError> {
+        let Some(full_path) = Self::data_file(data_dir) else {
+            let asset = embed::data("characters.json")
+                .ok_or_else(|| StorageError::Unavailable("embedded characters.json is missing".to_string()))?;
+            return serde_json::from_slice(asset.data)
+                .map_err(|e| StorageError::Unavailable(format!("embedded characters.json: {}", e)));
+        };
+        let unavailable = |e: &dyn std::fmt::Display| StorageError::Unavailable(format!("{}: {}", full_path.display(), e));
+        let json_contents = fs::read_to_string(&full_path).map_err(|e| unavailable(&e))?;
+        serde_json::from_str(&json_contents).map_err(|e| unavailable(&e))
     }
      
 }
 
+impl WebServiceHandler {
+    fn json(body: String) -> HttpResponse {
+        let mut headers: HashMap<&str, &str> = HashMap::new();
+        headers.insert("Content-Type", "application/json; charset=utf-8");
+        HttpResponse::new("200", Some(headers), Some(ResponseBody::Text(body)))
+    }
+
+    /**
+     * 没有注入[`AppState`]时无法处理API请求
+     */
+    fn missing_state() -> Problem {
+        Problem::new("500").detail("application state is not configured")
+    }
+
+    /**
+     * # 角色API的路由表
+     * 路径都是相对的，由`main`挂载到`/api/shipping`下
+     */
+    pub fn routes() -> Router {
+        let mut router = Router::new();
+        router.get("/characters", Self::characters);
+        router.get("/characters/live", Self::characters_live);
+        router.get("/characters/events", Self::characters_events);
+        router
+    }
+
+    /**
+     * `GET /characters`：返回当前的角色数据，修改见[`CharacterHandler`]
+     *
+     * [`CharacterHandler`]: super::characters::CharacterHandler
+     */
+    pub fn characters(req: &HttpRequst) -> Result<HttpResponse, Problem> {
+        let Some(state) = req.state::<AppState>() else {
+            return Err(Self::missing_state());
+        };
+        represent(req, "200", &state.characters.list()?)
+    }
+
+    /**
+     * `GET /characters/live`：WebSocket，连接后先发送一次当前的角色数据，
+     * 之后每次数据变化(例如重新加载)都会收到新的完整列表；读取不到数据时不升级
+     */
+    pub fn characters_live(req: &HttpRequst) -> Result<HttpResponse, Problem> {
+        let Some(state) = req.extensions.get_arc::<AppState>() else {
+            return Err(Self::missing_state());
+        };
+        let snapshot = state.characters_json()?;
+        Ok(websocket::upgrade(req, Limits::default(), move |mut ws| {
+            state.updates.subscribe(&ws);
+            // 订阅之后再读一次，避免错过升级期间的修改
+            let snapshot = state.characters_json().unwrap_or(snapshot);
+            if ws.send(Message::Text(snapshot)).is_err() {
+                return;
+            }
+            // 客户端发来的消息不需要处理，只是保持连接直到对端关闭
+            while let Ok(Some(_)) = ws.recv() {}
+        }))
+    }
+
+    /**
+     * `GET /characters/events`：事件流版本的`/characters/live`，事件类型为`characters`，
+     * 编号是数据内容的摘要，重连时`Last-Event-ID`与当前数据相同就不再重复发送；
+     * 没有变化时每隔[`HEARTBEAT_INTERVAL`]发送一次心跳
+     */
+    pub fn characters_events(req: &HttpRequst) -> Result<HttpResponse, Problem> {
+        let Some(state) = req.extensions.get_arc::<AppState>() else {
+            return Err(Self::missing_state());
+        };
+        let snapshot = state.characters_json()?;
+        Ok(sse::stream(req, move |mut events| {
+            let updates = state.updates.receiver();
+            let digest = |json: &str| {
+                let mut hasher = DefaultHasher::new();
+                json.hash(&mut hasher);
+                format!("{:016x}", hasher.finish())
+            };
+            let event = |json: String| Event::new(json.clone()).event("characters").id(&digest(&json));
+            let snapshot = state.characters_json().unwrap_or(snapshot);
+            if events.last_event_id() != Some(digest(&snapshot).as_str()) && events.send(&event(snapshot)).is_err() {
+                return;
+            }
+            loop {
+                let sent = match updates.recv_timeout(HEARTBEAT_INTERVAL) {
+                    Ok(Message::Text(json)) => events.send(&event(json)),
+                    Ok(Message::Binary(_)) => Ok(()),
+                    Err(RecvTimeoutError::Timeout) => events.heartbeat(),
+                    Err(RecvTimeoutError::Disconnected) => return,
+                };
+                if sent.is_err() {
+                    return;
+                }
+            }
+        }))
+    }
+
+    /**
+     * `GET /api/cache/stats`：静态文件缓存的命中情况
+     */
+    pub fn cache_stats(req: &HttpRequst) -> Result<HttpResponse, Problem> {
+        let state = req.state::<AppState>().ok_or_else(Self::missing_state)?;
+        Ok(Self::json(serde_json::to_string_pretty(&state.static_cache.stats()).unwrap()))
+    }
+}
+
 impl Handler for WebServiceHandler {
     /**
      * # 网页服务处理
-     * 根据路由路径，对一些api进行响应
-     * 目前的实现就是读取json并返回
+     * 根据相对于挂载点的路径，对一些api进行响应，路由表中挂载的是
+     * [`WebServiceHandler::routes`]
      * 
      * # Example
      * [character](http://localhost:3000/api/shipping/characters)
      */
-    fn handle(req: &HttpRequst) -> HttpResponse {
-        let http::httprequest::Resource::Path(s) = &req.resource;
-        let route: Vec<&str> = s.split("/").collect();
-
-        match route[2] {
-            "shipping" if route.len() > 2 && route[3] == "characters" => {
-                let body = Some(serde_json::to_string_pretty(&Self::load_json()).unwrap());
-                println!("{}", body.as_ref().unwrap());
-                let mut headers: HashMap<&str, &str> = HashMap::new();
-                headers.insert("Content-Type", "application/json");
-                HttpResponse::new("200", Some(headers), Some(ResponseBody::Text(body.unwrap())))
+    fn handle(&self, req: &HttpRequst) -> HttpResponse {
+        match req.resource.path().trim_end_matches('/') {
+            "/characters" => Self::characters(req).into_response(req),
+            _ => Problem::new("404").respond(req),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::problem::CONTENT_TYPE;
+
+    #[test]
+    fn test_closure_handlers() {
+        let text = |_: &HttpRequst| HttpResponse::new("200", None, Some(ResponseBody::Text("ok".into())));
+        let fallible = |req: &HttpRequst| -> Result<HttpResponse, Problem> {
+            match req.header("X-Fail") {
+                Some(_) => Err(Problem::new("409").detail("conflict")),
+                None => Ok(HttpResponse::new("204", None, None)),
             }
-            _ => HttpResponse::new("404", None, Self::load_file("404.html"))
+        };
+        let handlers: Vec<Box<dyn Handler>> = vec![Box::new(text), Box::new(fallible)];
+        let req: HttpRequst = "GET / HTTP/1.1\r\n\r\n".to_string().into();
+        assert_eq!(handlers[0].handle(&req).status_code(), "200");
+        assert!(handlers.iter().all(|handler| handler.check(&req).is_none()));
+        assert_eq!(handlers[1].handle(&req).status_code(), "204");
+
+        // Err经过IntoResponse变成问题详情
+        let failing: HttpRequst = "GET / HTTP/1.1\r\nX-Fail: 1\r\n\r\n".to_string().into();
+        let resp = handlers[1].handle(&failing);
+        assert_eq!((resp.status_code(), resp.header("Content-Type")), ("409", Some(CONTENT_TYPE)));
+    }
+
+    #[test]
+    fn test_load_json() {
+        assert!(!WebServiceHandler::load_json(None).unwrap().is_empty());
+        let missing = env::temp_dir().join(format!("httpserver-no-data-{}", std::process::id()));
+        match WebServiceHandler::load_json(Some(&missing)) {
+            Err(StorageError::Unavailable(reason)) => assert!(reason.contains("characters.json"), "{}", reason),
+            other => panic!("unexpected {:?}", other.map(|items| items.len())),
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_web_service_not_found() {
+        let req: HttpRequst = "GET /unknown HTTP/1.1\r\n\r\n".to_string().into();
+        let resp = WebServiceHandler.handle(&req);
+        assert_eq!((resp.status_code(), resp.header("Content-Type")), ("404", Some(CONTENT_TYPE)));
+    }
+}
@@ -0,0 +1,23 @@
+pub mod app;
+pub mod autoindex;
+pub mod cache;
+pub mod characters;
+pub mod cli;
+pub mod config;
+pub mod embed;
+pub mod h2;
+pub mod handler;
+pub mod listener;
+pub mod log;
+pub mod middleware;
+pub mod negotiate;
+pub mod problem;
+pub mod reload;
+pub mod repository;
+pub mod router;
+pub mod server;
+pub mod sse;
+pub mod state;
+pub mod staticfile;
+pub mod tls;
+pub mod websocket;
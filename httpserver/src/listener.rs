@@ -0,0 +1,345 @@
+use std::env;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+
+/**
+ * systemd传入的第一个文件描述符，见sd_listen_fds(3)
+ */
+#[cfg(unix)]
+const SD_LISTEN_FDS_START: i32 = 3;
+
+/**
+ * # 监听地址
+ * - `Tcp`: `host:port`，IPv6地址写成`[::1]:3000`
+ * - `Unix`: `unix:/run/httpserver.sock`，Unix域套接字
+ * - `Systemd`: `systemd`或`systemd:N`，使用systemd socket activation传入的第N个(从0开始)套接字
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum ListenAddr {
+    Tcp(String),
+    Unix(PathBuf),
+    Systemd(usize),
+}
+
+impl ListenAddr {
+    pub fn parse(addr: &str) -> Result<ListenAddr, String> {
+        if let Some(path) = addr.strip_prefix("unix:") {
+            if path.is_empty() {
+                return Err("unix socket path is empty".to_string());
+            }
+            if cfg!(not(unix)) {
+                return Err("unix sockets are not supported on this platform".to_string());
+            }
+            return Ok(ListenAddr::Unix(PathBuf::from(path)));
+        }
+        if addr == "systemd" {
+            return Ok(ListenAddr::Systemd(0));
+        }
+        if let Some(index) = addr.strip_prefix("systemd:") {
+            return index.parse()
+                .map(ListenAddr::Systemd)
+                .map_err(|_| format!("invalid systemd socket index \"{}\"", index));
+        }
+        match addr.to_socket_addrs() {
+            Ok(_) => Ok(ListenAddr::Tcp(addr.to_string())),
+            Err(e) => Err(format!("cannot resolve \"{}\": {}", addr, e)),
+        }
+    }
+
+    /**
+     * 检查地址现在能否使用：systemd套接字是否已经传入
+     */
+    pub fn check(&self) -> Result<(), String> {
+        match self {
+            ListenAddr::Systemd(index) => {
+                let count = systemd_fds()?;
+                if *index >= count {
+                    return Err(format!("systemd passed {} socket(s), index {} is out of range", count, index));
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ListenAddr::Tcp(addr) => write!(f, "{}", addr),
+            ListenAddr::Unix(path) => write!(f, "unix:{}", path.display()),
+            ListenAddr::Systemd(index) => write!(f, "systemd:{}", index),
+        }
+    }
+}
+
+/**
+ * systemd通过`LISTEN_PID`/`LISTEN_FDS`传入的套接字数量。
+ * 第一次调用时读取后就删除这些环境变量，子进程不会误以为套接字是传给它的
+ */
+pub fn systemd_fds() -> Result<usize, String> {
+    static FDS: OnceLock<Result<usize, String>> = OnceLock::new();
+    FDS.get_or_init(|| {
+        let fds = read_systemd_env();
+        for key in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+            env::remove_var(key);
+        }
+        fds
+    })
+    .clone()
+}
+
+fn read_systemd_env() -> Result<usize, String> {
+    let pid = env::var("LISTEN_PID").map_err(|_| "no sockets were passed by systemd (LISTEN_PID is not set)".to_string())?;
+    if pid.trim() != std::process::id().to_string() {
+        return Err(format!("LISTEN_PID {} does not match this process", pid));
+    }
+    env::var("LISTEN_FDS").ok()
+        .and_then(|n| n.trim().parse().ok())
+        .ok_or_else(|| "LISTEN_FDS is not set or invalid".to_string())
+}
+
+/**
+ * 接管systemd传入的第`index`个套接字：每个套接字只能接管一次，
+ * 必须是正在监听的流式套接字，接管后设置`FD_CLOEXEC`
+ */
+#[cfg(unix)]
+fn inherit(index: usize) -> io::Result<Listener> {
+    use std::os::unix::io::FromRawFd;
+    use std::sync::Mutex;
+    static CLAIMED: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+
+    ListenAddr::Systemd(index).check().map_err(io::Error::other)?;
+    let mut claimed = CLAIMED.lock().unwrap();
+    if claimed.contains(&index) {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("systemd socket {} is already in use", index)));
+    }
+    let fd = SD_LISTEN_FDS_START + index as i32;
+    let family = socket_family(fd)?;
+    claimed.push(index);
+    // SAFETY: fd是systemd传给本进程的套接字，上面检查过类型，并且只会被接管一次
+    unsafe {
+        libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+        match family {
+            libc::AF_UNIX => Ok(Listener::Unix(UnixListener::from_raw_fd(fd))),
+            _ => Ok(Listener::Tcp(TcpListener::from_raw_fd(fd))),
+        }
+    }
+}
+
+/**
+ * 检查`fd`是正在监听的流式套接字，返回它的地址族(`AF_INET`、`AF_INET6`或`AF_UNIX`)
+ */
+#[cfg(unix)]
+fn socket_family(fd: i32) -> io::Result<i32> {
+    use std::mem;
+    let invalid = |what: &str| io::Error::new(io::ErrorKind::InvalidInput, format!("file descriptor {} {}", fd, what));
+    let check = |ret: libc::c_int| if ret == 0 { Ok(()) } else { Err(io::Error::last_os_error()) };
+    let option = |name: libc::c_int| -> io::Result<libc::c_int> {
+        let mut value: libc::c_int = 0;
+        let mut len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+        // SAFETY: value和len指向足够大的本地变量
+        check(unsafe { libc::getsockopt(fd, libc::SOL_SOCKET, name, &mut value as *mut _ as *mut libc::c_void, &mut len) })?;
+        Ok(value)
+    };
+
+    // SAFETY: 只读取fd的状态，不接管它
+    let mut stat: libc::stat = unsafe { mem::zeroed() };
+    check(unsafe { libc::fstat(fd, &mut stat) })?;
+    if stat.st_mode & libc::S_IFMT != libc::S_IFSOCK {
+        return Err(invalid("is not a socket"));
+    }
+    if option(libc::SO_TYPE)? != libc::SOCK_STREAM {
+        return Err(invalid("is not a stream socket"));
+    }
+    if option(libc::SO_ACCEPTCONN)? == 0 {
+        return Err(invalid("is not listening"));
+    }
+    let mut addr: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let mut len = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+    check(unsafe { libc::getsockname(fd, &mut addr as *mut _ as *mut libc::sockaddr, &mut len) })?;
+    match addr.ss_family as libc::c_int {
+        family @ (libc::AF_INET | libc::AF_INET6 | libc::AF_UNIX) => Ok(family),
+        family => Err(invalid(&format!("has unsupported address family {}", family))),
+    }
+}
+
+/**
+ * 已经绑定的监听套接字
+ */
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+impl Listener {
+    pub fn bind(addr: &ListenAddr) -> io::Result<Listener> {
+        match addr {
+            ListenAddr::Tcp(addr) => TcpListener::bind(addr).map(Listener::Tcp),
+            #[cfg(unix)]
+            ListenAddr::Unix(path) => {
+                use std::os::unix::fs::FileTypeExt;
+                // 上次退出时留下的套接字文件会导致绑定失败，仍有进程在监听时保留
+                let stale = std::fs::metadata(path).map(|m| m.file_type().is_socket()).unwrap_or(false)
+                    && UnixStream::connect(path).is_err();
+                if stale {
+                    std::fs::remove_file(path)?;
+                }
+                UnixListener::bind(path).map(Listener::Unix)
+            }
+            #[cfg(unix)]
+            ListenAddr::Systemd(index) => inherit(*index),
+            #[cfg(not(unix))]
+            _ => Err(io::Error::new(io::ErrorKind::Unsupported, "only TCP listeners are supported on this platform")),
+        }
+    }
+
+    pub fn accept(&self) -> io::Result<Connection> {
+        match self {
+            Listener::Tcp(listener) => listener.accept().map(|(stream, _)| Connection::Tcp(stream)),
+            #[cfg(unix)]
+            Listener::Unix(listener) => listener.accept().map(|(stream, _)| Connection::Unix(stream)),
+        }
+    }
+}
+
+/**
+ * 一个客户端连接，TCP、Unix域套接字和TLS使用同样的处理流程
+ */
+pub enum Connection {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+    #[cfg(feature = "tls")]
+    Tls(Box<rustls::StreamOwned<rustls::ServerConnection, Connection>>),
+}
+
+impl Connection {
+    /**
+     * TLS握手时协商的应用层协议，例如`h2`
+     */
+    pub fn alpn_protocol(&self) -> Option<&[u8]> {
+        match self {
+            #[cfg(feature = "tls")]
+            Connection::Tls(stream) => stream.conn.alpn_protocol(),
+            _ => None,
+        }
+    }
+
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Connection::Tcp(stream) => stream.set_read_timeout(timeout),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.set_read_timeout(timeout),
+            #[cfg(feature = "tls")]
+            Connection::Tls(stream) => stream.sock.set_read_timeout(timeout),
+        }
+    }
+
+    pub fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Connection::Tcp(stream) => stream.set_write_timeout(timeout),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.set_write_timeout(timeout),
+            #[cfg(feature = "tls")]
+            Connection::Tls(stream) => stream.sock.set_write_timeout(timeout),
+        }
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Connection::Tcp(stream) => stream.read(buf),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.read(buf),
+            #[cfg(feature = "tls")]
+            Connection::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Connection::Tcp(stream) => stream.write(buf),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.write(buf),
+            #[cfg(feature = "tls")]
+            Connection::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Connection::Tcp(stream) => stream.flush(),
+            #[cfg(unix)]
+            Connection::Unix(stream) => stream.flush(),
+            #[cfg(feature = "tls")]
+            Connection::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(ListenAddr::parse("[::1]:3000"), Ok(ListenAddr::Tcp("[::1]:3000".into())));
+        assert_eq!(ListenAddr::parse("systemd"), Ok(ListenAddr::Systemd(0)));
+        assert_eq!(ListenAddr::parse("systemd:2"), Ok(ListenAddr::Systemd(2)));
+        assert!(ListenAddr::parse("systemd:x").is_err());
+        assert!(ListenAddr::parse("localhost").is_err());
+        assert!(ListenAddr::parse("unix:").is_err());
+        assert_eq!(ListenAddr::parse("unix:/tmp/a.sock").unwrap().to_string(), "unix:/tmp/a.sock");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unix_listener() {
+        let path = env::temp_dir().join(format!("httpserver-listener-{}.sock", std::process::id()));
+        let addr = ListenAddr::Unix(path.clone());
+        // 留下的套接字文件没有进程监听时会被替换
+        drop(Listener::bind(&addr).unwrap());
+        let listener = Listener::bind(&addr).unwrap();
+
+        let mut client = UnixStream::connect(&path).unwrap();
+        client.write_all(b"ping").unwrap();
+        let mut conn = listener.accept().unwrap();
+        let mut buf = [0; 4];
+        conn.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ping");
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_socket_family() {
+        use std::os::unix::io::AsRawFd;
+        let tcp = TcpListener::bind("127.0.0.1:0").unwrap();
+        assert_eq!(socket_family(tcp.as_raw_fd()).unwrap(), libc::AF_INET);
+        let path = env::temp_dir().join(format!("httpserver-family-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let unix = UnixListener::bind(&path).unwrap();
+        assert_eq!(socket_family(unix.as_raw_fd()).unwrap(), libc::AF_UNIX);
+        let _ = std::fs::remove_file(&path);
+
+        // 连接、普通文件和UDP套接字都不能当作监听套接字接管
+        let stream = TcpStream::connect(tcp.local_addr().unwrap()).unwrap();
+        assert!(socket_family(stream.as_raw_fd()).is_err());
+        let file = std::fs::File::open(env!("CARGO_MANIFEST_DIR")).unwrap();
+        assert!(socket_family(file.as_raw_fd()).is_err());
+        let udp = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        assert!(socket_family(udp.as_raw_fd()).is_err());
+    }
+}
@@ -1,10 +1,111 @@
-use server::Server;
+use httpserver::app::App;
+use httpserver::cli::{self, Cli, Command};
+use httpserver::config::{Config, ConfigError};
+use httpserver::handler;
+use httpserver::log;
+use httpserver::router::Router;
+use httpserver::server::Server;
+use httpserver::state::AppState;
+use http::httprequest::Extensions;
+use std::env;
+use std::process;
+use std::sync::{Arc, Mutex};
 
-mod server;
-mod router;
-mod handler;
+/**
+ * 输出错误并以状态码2退出，用于命令行和配置错误
+ */
+fn fail(e: ConfigError) -> ! {
+    eprintln!("{}", e);
+    process::exit(2);
+}
+
+/**
+ * # 重新加载
+ * 重新读取并校验配置、构建新的路由表，全部成功后才会替换正在使用的配置，
+ * 并把新读取的角色数据推送给WebSocket订阅者。`current`是正在使用的配置和状态，
+ * 存储配置和`[cache]`没有变化时沿用其中的角色数据和静态文件缓存，见[`App::reload`]。
+ * 监听地址、`[server]`和`[tls]`的修改需要重启才能生效
+ */
+fn rebuild(initial: &Config, cli: &Cli, current: &Mutex<(Config, Arc<AppState>)>) -> Result<Vec<(Router, Extensions)>, String> {
+    let config = cli.load().map_err(|e| e.to_string())?;
+    let mut current = current.lock().unwrap();
+    let app = App::reload(&config, &current.0, &current.1).map_err(|e| e.to_string())?;
+    if config.listeners != initial.listeners || config.server != initial.server || config.tls != initial.tls {
+        httpserver::warn!("Changes to listeners, [server] and [tls] take effect after a restart");
+    }
+    log::set_level(config.logging.level);
+    app.state.publish_characters();
+    *current = (config, app.state.clone());
+    Ok(app.into_parts())
+}
+
+/**
+ * `routes`子命令：每行一条路由，方法、模式和处理器
+ */
+fn print_routes(router: &Router) {
+    let routes = router.routes();
+    let width = routes.iter().map(|r| r.pattern.len()).max().unwrap_or(0);
+    for route in routes {
+        println!("{:<7} {:<width$}  {}", route.method.as_str(), route.pattern, route.handler, width = width);
+    }
+}
 
 fn main() {
-    let server = Server::new("localhost:3000");
-    server.run();
+    let cli = Cli::parse(env::args().skip(1)).unwrap_or_else(|e| fail(e));
+    match cli.command {
+        Command::Help => {
+            println!("{}", cli::USAGE);
+            return;
+        }
+        Command::Version => {
+            println!("httpserver {}", env!("CARGO_PKG_VERSION"));
+            return;
+        }
+        _ => (),
+    }
+
+    let config = cli.load().unwrap_or_else(|e| fail(e));
+    log::set_level(config.logging.level);
+    handler::init_mime_registry();
+    let app = App::from_config(&config).unwrap_or_else(|e| fail(e));
+
+    match cli.command {
+        Command::CheckConfig => {
+            println!("configuration ok: {} listener(s), {} static mount(s), {} route(s)",
+                config.listeners.len(), config.statics.len(), app.routers.iter().map(|r| r.routes().len()).sum::<usize>());
+            return;
+        }
+        Command::Routes => {
+            for (i, (listener, router)) in config.listeners.iter().zip(&app.routers).enumerate() {
+                if app.routers.len() > 1 {
+                    println!("{}listener {}", if i > 0 { "\n" } else { "" }, listener.addr);
+                }
+                print_routes(router);
+            }
+            return;
+        }
+        _ => (),
+    }
+
+    let listeners = config.listeners.clone();
+    let current = Mutex::new((config.clone(), app.state.clone()));
+    let watch = cli.config.clone();
+    let mut routers = app.routers.into_iter();
+    let mut server = Server::new(&listeners[0].addr, routers.next().unwrap());
+    for (i, listener) in listeners.iter().enumerate() {
+        if i > 0 {
+            server = server.listen_with(&listener.addr, routers.next().unwrap());
+        }
+        if let Some(tls) = app.tls.as_ref().filter(|_| listener.secure(config.tls.is_some())) {
+            server = server.with_tls(tls.clone());
+        }
+    }
+    let server = server
+        .with_options(config.server.clone())
+        .with_state(app.state)
+        .with_reload(watch, Box::new(move || rebuild(&config, &cli, &current)));
+    if let Err(e) = server.run() {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
 }
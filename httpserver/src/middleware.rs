@@ -0,0 +1,450 @@
+use super::handler::Handler;
+use super::staticfile::accepts_encoding;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use http::httprequest::{HttpRequst, Method};
+use http::httpresponse::{HttpResponse, ResponseBody};
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/**
+# Middleware
+包在处理器外面的一层逻辑，按洋葱模型执行：先注册的在外层。
+- 调用`next.run(req)`之前可以检查或修改请求
+- 不调用`next.run`直接返回响应即可短路，例如鉴权失败
+- `next.run`返回之后可以修改响应，例如加上响应头
+```rust,ignore
+router.wrap(|req: &mut HttpRequst, next: Next| {
+    let mut resp = next.run(req);
+    resp.set_header("X-Powered-By", "rust-httpserver");
+    resp
+});
+```
+ */
+pub trait Middleware: Send + Sync {
+    fn handle(&self, req: &mut HttpRequst, next: Next<'_>) -> HttpResponse;
+
+    /**
+     * 在读取请求体之前检查请求头，见[`Handler::check`]。
+     * 返回的响应会经过外层的中间件；默认接受所有请求
+     */
+    fn check(&self, _req: &HttpRequst) -> Option<HttpResponse> {
+        None
+    }
+}
+
+impl<F> Middleware for F
+where
+    F: Fn(&mut HttpRequst, Next<'_>) -> HttpResponse + Send + Sync,
+{
+    fn handle(&self, req: &mut HttpRequst, next: Next<'_>) -> HttpResponse {
+        self(req, next)
+    }
+}
+
+/**
+ * 中间件栈，克隆时只增加引用计数
+ */
+pub type Stack = Vec<Arc<dyn Middleware>>;
+
+/**
+ * 剩余的中间件和最终的处理器
+ */
+pub struct Next<'a> {
+    chain: &'a [Arc<dyn Middleware>],
+    handler: &'a dyn Handler,
+}
+
+impl<'a> Next<'a> {
+    pub fn new(chain: &'a [Arc<dyn Middleware>], handler: &'a dyn Handler) -> Self {
+        Next { chain, handler }
+    }
+
+    /**
+     * 执行下一层中间件，全部执行完后调用处理器
+     */
+    pub fn run(self, req: &mut HttpRequst) -> HttpResponse {
+        match self.chain.split_first() {
+            Some((first, rest)) => first.handle(req, Next::new(rest, self.handler)),
+            None => self.handler.handle(req),
+        }
+    }
+}
+
+/**
+ * # 访问日志
+ * 打印方法、路径、状态码和耗时
+ */
+pub struct Logger;
+
+impl Middleware for Logger {
+    fn handle(&self, req: &mut HttpRequst, next: Next<'_>) -> HttpResponse {
+        let method = req.method.as_str();
+        let http::httprequest::Resource::Path(path) = &req.resource;
+        let path = path.clone();
+        let start = Instant::now();
+        let resp = next.run(req);
+        crate::info!("{} \"{}\" {} {:.1?}", method, path, resp.status_code(), start.elapsed());
+        resp
+    }
+}
+
+/**
+ * # 请求ID
+ * 沿用客户端传来的`X-Request-Id`，没有时生成一个，写回请求头供后面的处理器使用，
+ * 并在响应头中返回
+ */
+#[derive(Default)]
+pub struct RequestId {
+    counter: AtomicU64,
+}
+
+impl RequestId {
+    pub fn new() -> Self {
+        RequestId::default()
+    }
+
+    fn generate(&self) -> String {
+        let seq = self.counter.fetch_add(1, Ordering::Relaxed);
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_micros())
+            .unwrap_or(0);
+        format!("{:x}-{:x}-{:x}", now, std::process::id(), seq)
+    }
+}
+
+impl Middleware for RequestId {
+    fn handle(&self, req: &mut HttpRequst, next: Next<'_>) -> HttpResponse {
+        let id = match req.header("X-Request-Id") {
+            Some(id) if !id.is_empty() && id.len() <= 128 => id.to_string(),
+            _ => self.generate(),
+        };
+        req.headers.retain(|k, _| !k.trim().eq_ignore_ascii_case("X-Request-Id"));
+        req.headers.insert("X-Request-Id".to_string(), id.clone());
+        let mut resp = next.run(req);
+        resp.set_header("X-Request-Id", &id);
+        resp
+    }
+}
+
+/**
+ * # 处理耗时
+ * 在响应头中加上`Server-Timing: app;dur=<毫秒>`
+ */
+pub struct Timing;
+
+impl Middleware for Timing {
+    fn handle(&self, req: &mut HttpRequst, next: Next<'_>) -> HttpResponse {
+        let start = Instant::now();
+        let mut resp = next.run(req);
+        let ms = start.elapsed().as_secs_f64() * 1000.0;
+        resp.set_header("Server-Timing", &format!("app;dur={:.3}", ms));
+        resp
+    }
+}
+
+/**
+ * # 跨域
+ * `allow_origins`为空时允许任意来源。预检请求(`OPTIONS`带`Access-Control-Request-Method`)
+ * 直接返回204，不再交给处理器
+ */
+pub struct Cors {
+    pub allow_origins: Vec<String>,
+    pub allow_methods: String,
+    pub allow_headers: String,
+    pub max_age: u32,
+}
+
+impl Default for Cors {
+    fn default() -> Self {
+        Cors {
+            allow_origins: Vec::new(),
+            allow_methods: "GET, POST, PUT, PATCH, DELETE, OPTIONS".to_string(),
+            allow_headers: "Content-Type, Authorization, X-Request-Id".to_string(),
+            max_age: 600,
+        }
+    }
+}
+
+impl Cors {
+    fn allowed_origin(&self, origin: &str) -> Option<String> {
+        if self.allow_origins.is_empty() {
+            Some("*".to_string())
+        } else if self.allow_origins.iter().any(|o| o == origin) {
+            Some(origin.to_string())
+        } else {
+            None
+        }
+    }
+}
+
+impl Middleware for Cors {
+    fn handle(&self, req: &mut HttpRequst, next: Next<'_>) -> HttpResponse {
+        let origin = match req.header("Origin") {
+            Some(origin) => origin.to_string(),
+            None => return next.run(req),
+        };
+        let allowed = self.allowed_origin(&origin);
+        let preflight = req.method == Method::Options
+            && req.header("Access-Control-Request-Method").is_some();
+
+        let mut resp = if preflight {
+            let mut resp = HttpResponse::new("204", None, None);
+            if allowed.is_some() {
+                resp.set_header("Access-Control-Allow-Methods", &self.allow_methods);
+                resp.set_header("Access-Control-Allow-Headers", &self.allow_headers);
+                resp.set_header("Access-Control-Max-Age", &self.max_age.to_string());
+            }
+            resp
+        } else {
+            next.run(req)
+        };
+        if let Some(allowed) = allowed {
+            resp.set_header("Access-Control-Allow-Origin", &allowed);
+            if allowed != "*" {
+                resp.set_header("Vary", "Origin");
+            }
+        }
+        resp
+    }
+}
+
+/**
+ * # 压缩
+ * 客户端的`Accept-Encoding`接受gzip时压缩文本类的响应体(HTML、CSS、JS、JSON、XML、SVG等)，
+ * 加上`Content-Encoding: gzip`和`Vary: Accept-Encoding`，强`ETag`改成弱`ETag`。
+ * 小于`min_size`字节、已经带`Content-Encoding`(例如预压缩的静态文件)、
+ * 部分内容(206)和事件流的响应保持不变
+ */
+pub struct Compress {
+    pub min_size: usize,
+}
+
+impl Default for Compress {
+    fn default() -> Self {
+        Compress { min_size: 1024 }
+    }
+}
+
+/**
+ * 值得压缩的媒体类型，图片、音视频和压缩包本身已经压缩过
+ */
+fn compressible(content_type: &str) -> bool {
+    let essence = content_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+    match essence.split_once('/') {
+        Some(("text", "event-stream")) => false,
+        Some(("text", _)) => true,
+        Some((_, sub)) => {
+            matches!(sub, "json" | "javascript" | "xml" | "yaml" | "wasm")
+                || sub.ends_with("+json")
+                || sub.ends_with("+xml")
+        }
+        None => false,
+    }
+}
+
+fn gzip(data: &[u8]) -> Option<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::with_capacity(data.len() / 2), Compression::default());
+    encoder.write_all(data).ok()?;
+    encoder.finish().ok()
+}
+
+impl Middleware for Compress {
+    fn handle(&self, req: &mut HttpRequst, next: Next<'_>) -> HttpResponse {
+        let accepted = req.header("Accept-Encoding").is_some_and(|ae| accepts_encoding(ae, "gzip"));
+        let mut resp = next.run(req);
+        if matches!(resp.status_code(), "204" | "206" | "304") || resp.header("Content-Encoding").is_some() {
+            return resp;
+        }
+        if !resp.header("Content-Type").is_some_and(compressible) {
+            return resp;
+        }
+        let size = resp.body().map_or(0, |body| body.as_bytes().len());
+        if size < self.min_size.max(1) {
+            return resp;
+        }
+        // 结果随`Accept-Encoding`变化，不压缩时同样需要告诉缓存
+        let vary = match resp.header("Vary") {
+            Some(vary) if vary.split(',').any(|v| v.trim().eq_ignore_ascii_case("Accept-Encoding")) => None,
+            Some(vary) => Some(format!("{}, Accept-Encoding", vary)),
+            None => Some("Accept-Encoding".to_string()),
+        };
+        if let Some(vary) = vary {
+            resp.set_header("Vary", &vary);
+        }
+        if !accepted {
+            return resp;
+        }
+        let Some(compressed) = resp.body().and_then(|body| gzip(body.as_bytes())) else {
+            return resp;
+        };
+        if let Some(etag) = resp.header("ETag").filter(|etag| !etag.starts_with("W/")).map(str::to_string) {
+            resp.set_header("ETag", &format!("W/{}", etag));
+        }
+        resp.set_header("Content-Encoding", "gzip");
+        resp.set_body(Some(ResponseBody::Binary(compressed)));
+        resp
+    }
+}
+
+/**
+ * # Bearer鉴权
+ * `Authorization: Bearer <token>`不匹配时直接返回401；
+ * 在读取请求体之前就检查，带`Expect: 100-continue`的请求被拒绝时客户端不会发送请求体
+ */
+pub struct BearerAuth {
+    pub token: String,
+}
+
+/**
+ * 比较所用的时间只和长度有关，不会因为前面有多少字节相同而不同，避免逐字节猜出令牌
+ */
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+impl BearerAuth {
+    fn unauthorized(&self, req: &HttpRequst) -> Option<HttpResponse> {
+        let authorized = req.header("Authorization")
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .is_some_and(|token| constant_time_eq(token.trim().as_bytes(), self.token.as_bytes()));
+        if authorized {
+            return None;
+        }
+        let mut resp = HttpResponse::new("401", None, Some(ResponseBody::Text("401 Unauthorized".into())));
+        resp.set_header("WWW-Authenticate", "Bearer");
+        Some(resp)
+    }
+}
+
+impl Middleware for BearerAuth {
+    fn handle(&self, req: &mut HttpRequst, next: Next<'_>) -> HttpResponse {
+        match self.unauthorized(req) {
+            Some(resp) => resp,
+            None => next.run(req),
+        }
+    }
+
+    fn check(&self, req: &HttpRequst) -> Option<HttpResponse> {
+        self.unauthorized(req)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ok(req: &HttpRequst) -> HttpResponse {
+        let id = req.header("X-Request-Id").unwrap_or("none").to_string();
+        HttpResponse::new("200", None, Some(ResponseBody::Text(id)))
+    }
+
+    fn request(raw: &str) -> HttpRequst {
+        raw.to_string().into()
+    }
+
+    #[test]
+    fn test_chain_order() {
+        let order = |tag: &'static str| {
+            Arc::new(move |req: &mut HttpRequst, next: Next<'_>| {
+                req.msg_body.push_str(tag);
+                let mut resp = next.run(req);
+                let seen = resp.header("X-Order").unwrap_or("").to_string();
+                resp.set_header("X-Order", &format!("{}{}", seen, tag));
+                resp
+            }) as Arc<dyn Middleware>
+        };
+        let chain: Stack = vec![order("a"), order("b")];
+        let handler = |req: &HttpRequst| HttpResponse::new("200", None, Some(ResponseBody::Text(req.msg_body.clone())));
+
+        let mut req = request("GET / HTTP/1.1\r\n\r\n");
+        let resp = Next::new(&chain, &handler).run(&mut req);
+        assert_eq!(resp.body(), Some(&ResponseBody::Text("ab".into())));
+        assert_eq!(resp.header("X-Order"), Some("ba"));
+    }
+
+    #[test]
+    fn test_request_id_and_auth() {
+        let chain: Stack = vec![Arc::new(RequestId::new()), Arc::new(BearerAuth { token: "secret".into() })];
+
+        let mut req = request("GET / HTTP/1.1\r\nX-Request-Id: abc\r\n\r\n");
+        let resp = Next::new(&chain, &ok).run(&mut req);
+        assert_eq!(resp.status_code(), "401");
+        assert_eq!(resp.header("X-Request-Id"), Some("abc"));
+
+        let mut req = request("GET / HTTP/1.1\r\nAuthorization: Bearer secret\r\n\r\n");
+        let resp = Next::new(&chain, &ok).run(&mut req);
+        assert_eq!(resp.status_code(), "200");
+        let id = resp.header("X-Request-Id").unwrap().to_string();
+        assert_eq!(resp.body(), Some(&ResponseBody::Text(id)));
+
+        // 读取请求体之前就用同样的规则检查
+        let auth = BearerAuth { token: "secret".into() };
+        assert_eq!(auth.check(&request("GET / HTTP/1.1\r\nAuthorization: Bearer secreT\r\n\r\n")).unwrap().status_code(), "401");
+        assert_eq!(auth.check(&request("GET / HTTP/1.1\r\nAuthorization: Bearer secrets\r\n\r\n")).unwrap().status_code(), "401");
+        assert!(auth.check(&request("GET / HTTP/1.1\r\nAuthorization: Bearer secret\r\n\r\n")).is_none());
+        assert!(constant_time_eq(b"", b"") && !constant_time_eq(b"a", b"") && !constant_time_eq(b"ab", b"ac"));
+    }
+
+    #[test]
+    fn test_compress() {
+        let page = "<p>hutao</p>".repeat(200);
+        let handler = |req: &HttpRequst| {
+            let mut resp = HttpResponse::new("200", None, Some(ResponseBody::Text(req.msg_body.clone())));
+            resp.set_header("ETag", "\"abc\"");
+            resp
+        };
+        let chain: Stack = vec![Arc::new(Compress::default())];
+        let send = |accept: &str, body: &str| {
+            let mut req = request(&format!("GET / HTTP/1.1\r\nAccept-Encoding: {}\r\n\r\n{}", accept, body));
+            Next::new(&chain, &handler).run(&mut req)
+        };
+
+        let resp = send("gzip, deflate", &page);
+        assert_eq!(resp.header("Content-Encoding"), Some("gzip"));
+        assert_eq!(resp.header("Vary"), Some("Accept-Encoding"));
+        assert_eq!(resp.header("ETag"), Some("W/\"abc\""));
+        let mut decoded = String::new();
+        let compressed = resp.body().unwrap().as_bytes();
+        assert!(compressed.len() < page.len());
+        std::io::Read::read_to_string(&mut flate2::read::GzDecoder::new(compressed), &mut decoded).unwrap();
+        assert_eq!(decoded, page);
+
+        // 不接受gzip、响应太小时原样返回
+        let resp = send("gzip;q=0, br", &page);
+        assert_eq!((resp.header("Content-Encoding"), resp.header("Vary")), (None, Some("Accept-Encoding")));
+        assert_eq!(resp.body(), Some(&ResponseBody::Text(page.clone())));
+        assert_eq!(send("gzip", "tiny").header("Content-Encoding"), None);
+
+        // 已经压缩过的和图片不再压缩
+        let jpeg = |_: &HttpRequst| {
+            let mut headers = std::collections::HashMap::new();
+            headers.insert("Content-Type", "image/jpeg");
+            HttpResponse::new("200", Some(headers), Some(ResponseBody::Binary(vec![0; 4096])))
+        };
+        let mut req = request("GET / HTTP/1.1\r\nAccept-Encoding: gzip\r\n\r\n");
+        assert_eq!(Next::new(&chain, &jpeg).run(&mut req).header("Content-Encoding"), None);
+        assert!(compressible("application/problem+json"));
+        assert!(!compressible("text/event-stream"));
+    }
+
+    #[test]
+    fn test_cors_preflight() {
+        let chain: Stack = vec![Arc::new(Cors {
+            allow_origins: vec!["https://example.com".into()],
+            ..Cors::default()
+        })];
+        let mut req = request("OPTIONS /api HTTP/1.1\r\nOrigin: https://example.com\r\nAccess-Control-Request-Method: PUT\r\n\r\n");
+        let resp = Next::new(&chain, &ok).run(&mut req);
+        assert_eq!(resp.status_code(), "204");
+        assert_eq!(resp.header("Access-Control-Allow-Origin"), Some("https://example.com"));
+
+        let mut req = request("GET /api HTTP/1.1\r\nOrigin: https://evil.com\r\n\r\n");
+        let resp = Next::new(&chain, &ok).run(&mut req);
+        assert_eq!(resp.status_code(), "200");
+        assert_eq!(resp.header("Access-Control-Allow-Origin"), None);
+    }
+}
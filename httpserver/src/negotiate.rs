@@ -0,0 +1,301 @@
+use super::problem::Problem;
+use http::httprequest::HttpRequst;
+use http::httpresponse::{HttpResponse, ResponseBody};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+
+/**
+ * `Accept`中的一项：小写的`type/subtype`、除`q`以外的参数和权重
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct MediaRange {
+    pub media_type: String,
+    pub params: Vec<(String, String)>,
+    pub q: f32,
+}
+
+impl MediaRange {
+    /**
+     * 解析`type/subtype; name=value`，`q`不合法时按0处理
+     */
+    pub fn parse(s: &str) -> Option<MediaRange> {
+        let mut parts = s.split(';');
+        let media_type = parts.next()?.trim().to_ascii_lowercase();
+        let (main, sub) = media_type.split_once('/')?;
+        if main.is_empty() || sub.is_empty() || (main == "*" && sub != "*") {
+            return None;
+        }
+        let mut range = MediaRange { media_type, params: Vec::new(), q: 1.0 };
+        for param in parts {
+            let Some((name, value)) = param.split_once('=') else { continue };
+            let name = name.trim().to_ascii_lowercase();
+            let value = value.trim().trim_matches('"').to_ascii_lowercase();
+            if name == "q" {
+                range.q = value.parse::<f32>().ok().filter(|q| (0.0..=1.0).contains(q)).unwrap_or(0.0);
+            } else {
+                range.params.push((name, value));
+            }
+        }
+        Some(range)
+    }
+
+    /**
+     * 匹配`offer`时的具体程度，不匹配时为`None`：完整类型优先于只写了主类型的范围，
+     * 后者又优先于任意类型；参数越多越具体。范围中的参数都要在`offer`中出现
+     */
+    fn precedence(&self, offer: &MediaRange) -> Option<(u8, usize)> {
+        let (main, sub) = self.media_type.split_once('/')?;
+        let level = match (main, sub) {
+            ("*", "*") => 0,
+            (main, "*") if offer.media_type.split('/').next() == Some(main) => 1,
+            _ if self.media_type == offer.media_type => 2,
+            _ => return None,
+        };
+        self.params.iter().all(|p| offer.params.contains(p)).then_some((level, self.params.len()))
+    }
+}
+
+/**
+ * 解析`Accept`请求头，忽略不合法的项
+ */
+pub fn parse_accept(accept: &str) -> Vec<MediaRange> {
+    accept.split(',').filter_map(MediaRange::parse).collect()
+}
+
+/**
+# 内容协商
+按RFC 9110从`offered`中选出客户端最想要的一个：每个候选使用最具体的匹配项的`q`，
+`q`最高的胜出，相同时按`offered`的顺序。没有`Accept`时返回第一个候选；
+所有候选都不可接受时返回`None`，通常回应406
+```rust
+use httpserver::negotiate::negotiate;
+
+let offered = ["application/json", "text/csv"];
+assert_eq!(negotiate(None, &offered), Some("application/json"));
+assert_eq!(negotiate(Some("text/csv;q=0.9, application/json;q=0.5"), &offered), Some("text/csv"));
+assert_eq!(negotiate(Some("application/json;q=0, text/csv"), &offered), Some("text/csv"));
+assert_eq!(negotiate(Some("image/png"), &offered), None);
+```
+ */
+pub fn negotiate<'a>(accept: Option<&str>, offered: &[&'a str]) -> Option<&'a str> {
+    let ranges = match accept.map(parse_accept) {
+        Some(ranges) if !ranges.is_empty() => ranges,
+        _ => return offered.first().copied(),
+    };
+    let mut best: Option<(&str, f32)> = None;
+    for &offer in offered {
+        let Some(parsed) = MediaRange::parse(offer) else { continue };
+        let q = ranges.iter()
+            .filter_map(|range| range.precedence(&parsed).map(|p| (p, range.q)))
+            .max_by_key(|(p, _)| *p)
+            .map_or(0.0, |(_, q)| q);
+        if q > 0.0 && best.is_none_or(|(_, best)| q > best) {
+            best = Some((offer, q));
+        }
+    }
+    best.map(|(offer, _)| offer)
+}
+
+/**
+ * # 表示格式
+ * 同一份数据可以返回的格式，`Accept`中可以使用的媒体类型见[`Format::media_types`]
+ * - `Json`: 缩进的JSON，默认
+ * - `CompactJson`: 不缩进的JSON，`application/json; format=compact`
+ * - `Csv`: 对象数组的每个对象一行，第一行是列名，数组字段用`;`连接
+ * - `Yaml`
+ * - `MessagePack`
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    CompactJson,
+    Csv,
+    Yaml,
+    MessagePack,
+}
+
+impl Format {
+    pub const ALL: [Format; 5] = [Format::Json, Format::CompactJson, Format::Csv, Format::Yaml, Format::MessagePack];
+
+    /**
+     * 在`Accept`中对应这个格式的媒体类型，第一个是正式的名字
+     */
+    pub fn media_types(self) -> &'static [&'static str] {
+        match self {
+            Format::Json => &["application/json"],
+            Format::CompactJson => &["application/json; format=compact"],
+            Format::Csv => &["text/csv"],
+            Format::Yaml => &["application/yaml", "application/x-yaml", "text/yaml"],
+            Format::MessagePack => &["application/msgpack", "application/x-msgpack", "application/vnd.msgpack"],
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Json | Format::CompactJson => "application/json; charset=utf-8",
+            Format::Csv => "text/csv; charset=utf-8",
+            Format::Yaml => "application/yaml; charset=utf-8",
+            Format::MessagePack => "application/msgpack",
+        }
+    }
+
+    /**
+     * 按`Accept`从`formats`中选择，都不可接受时返回406，`acceptable`中列出可以使用的媒体类型
+     */
+    pub fn negotiate(req: &HttpRequst, formats: &[Format]) -> Result<Format, Problem> {
+        let offered: Vec<&str> = formats.iter().flat_map(|f| f.media_types().iter().copied()).collect();
+        let chosen = negotiate(req.header("Accept"), &offered).and_then(|media_type| {
+            formats.iter().copied().find(|f| f.media_types().contains(&media_type))
+        });
+        chosen.ok_or_else(|| {
+            let acceptable: Vec<&str> = formats.iter().map(|f| f.media_types()[0]).collect();
+            Problem::new("406")
+                .detail(format!("no acceptable representation, available: {}", acceptable.join(", ")))
+                .member("acceptable", acceptable)
+        })
+    }
+
+    /**
+     * 用这个格式输出`value`并带上`Vary: Accept`。修改数据的处理器应当先协商，
+     * 成功修改之后再调用，避免修改了却只能回应406
+     */
+    pub fn respond<T: Serialize>(self, status: &'static str, value: &T) -> Result<HttpResponse, Problem> {
+        let value = serde_json::to_value(value).map_err(|e| Problem::new("500").detail(e.to_string()))?;
+        let body = self.render(&value).map_err(|e| Problem::new("500").detail(e))?;
+        let mut headers: HashMap<&str, &str> = HashMap::new();
+        headers.insert("Content-Type", self.content_type());
+        headers.insert("Vary", "Accept");
+        Ok(HttpResponse::new(status, Some(headers), Some(body)))
+    }
+
+    pub fn render(self, value: &Value) -> Result<ResponseBody, String> {
+        match self {
+            Format::Json => Ok(ResponseBody::Text(serde_json::to_string_pretty(value).map_err(|e| e.to_string())?)),
+            Format::CompactJson => Ok(ResponseBody::Text(serde_json::to_string(value).map_err(|e| e.to_string())?)),
+            Format::Csv => to_csv(value).map(ResponseBody::Text),
+            Format::Yaml => Ok(ResponseBody::Text(serde_yaml_ng::to_string(value).map_err(|e| e.to_string())?)),
+            Format::MessagePack => Ok(ResponseBody::Binary(rmp_serde::to_vec_named(value).map_err(|e| e.to_string())?)),
+        }
+    }
+}
+
+/**
+ * CSV的一格：字符串原样，数组用`;`连接，`null`为空，其它为JSON文本
+ */
+fn cell(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        Value::Array(items) => items.iter().map(cell).collect::<Vec<_>>().join(";"),
+        other => other.to_string(),
+    }
+}
+
+/**
+ * 对象或对象数组转换成CSV，列为对象中出现过的字段，按第一次出现的顺序
+ */
+fn to_csv(value: &Value) -> Result<String, String> {
+    let rows: Vec<&serde_json::Map<String, Value>> = match value {
+        Value::Object(row) => vec![row],
+        Value::Array(items) => items.iter()
+            .map(|item| item.as_object().ok_or("CSV rows must be objects"))
+            .collect::<Result<_, _>>()?,
+        _ => return Err("CSV needs an object or an array of objects".to_string()),
+    };
+    let mut columns: Vec<&str> = Vec::new();
+    for key in rows.iter().flat_map(|row| row.keys()) {
+        if !columns.contains(&key.as_str()) {
+            columns.push(key);
+        }
+    }
+    let mut out = csv::Writer::from_writer(Vec::new());
+    out.write_record(&columns).map_err(|e| e.to_string())?;
+    for row in rows {
+        out.write_record(columns.iter().map(|c| row.get(*c).map(cell).unwrap_or_default()))
+            .map_err(|e| e.to_string())?;
+    }
+    String::from_utf8(out.into_inner().map_err(|e| e.to_string())?).map_err(|e| e.to_string())
+}
+
+/**
+# 按`Accept`返回数据
+在[`Format::ALL`]中协商，用选中的格式输出`value`，并带上`Vary: Accept`；
+没有可接受的格式时返回406的[`Problem`]
+```rust,ignore
+fn show(req: &HttpRequst) -> Result<HttpResponse, Problem> {
+    let character = ...;
+    represent(req, "200", &character)
+}
+```
+ */
+pub fn represent<T: Serialize>(req: &HttpRequst, status: &'static str, value: &T) -> Result<HttpResponse, Problem> {
+    represent_as(req, status, value, &Format::ALL)
+}
+
+/**
+ * 与[`represent`]相同，只在`formats`中协商，第一个是没有`Accept`时的默认格式
+ */
+pub fn represent_as<T: Serialize>(req: &HttpRequst, status: &'static str, value: &T, formats: &[Format]) -> Result<HttpResponse, Problem> {
+    Format::negotiate(req, formats)?.respond(status, value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_negotiate() {
+        let offered = ["application/json", "application/json; format=compact", "text/csv", "application/yaml"];
+        let pick = |accept: &str| negotiate(Some(accept), &offered);
+        assert_eq!(pick(""), Some("application/json"));
+        assert_eq!(pick("*/*"), Some("application/json"));
+        assert_eq!(pick("application/json; format=compact"), Some("application/json; format=compact"));
+        assert_eq!(pick("application/json;q=0.5, text/csv;q=0.8"), Some("text/csv"));
+        // 更具体的项覆盖宽泛的项：text/csv不可接受，其它text/*可以
+        assert_eq!(pick("text/*, text/csv;q=0, application/*;q=0.1"), Some("application/json"));
+        assert_eq!(pick("application/yaml, */*;q=0.1"), Some("application/yaml"));
+        assert_eq!(pick("image/png, text/html;q=0.9"), None);
+        assert_eq!(pick("application/json;q=0, text/csv"), Some("text/csv"));
+        assert_eq!(pick("bogus, */*;q=x"), None);
+    }
+
+    fn request(accept: &str) -> HttpRequst {
+        format!("GET / HTTP/1.1\r\nAccept: {}\r\n\r\n", accept).into()
+    }
+
+    fn text(resp: &HttpResponse) -> &str {
+        match resp.body() {
+            Some(ResponseBody::Text(text)) => text,
+            body => panic!("unexpected body {:?}", body),
+        }
+    }
+
+    #[test]
+    fn test_represent() {
+        let value = json!([
+            { "name": "胡桃", "level": 90, "skills": ["蝶引来生", "安神秘法"] },
+            { "name": "Keqing, \"Yuheng\"", "level": 55, "skills": [] },
+        ]);
+        let resp = represent(&request("text/csv"), "200", &value).unwrap();
+        assert_eq!((resp.header("Content-Type"), resp.header("Vary")), (Some("text/csv; charset=utf-8"), Some("Accept")));
+        assert_eq!(text(&resp), "name,level,skills\n胡桃,90,蝶引来生;安神秘法\n\"Keqing, \"\"Yuheng\"\"\",55,\n");
+
+        let resp = represent(&request("application/json;format=compact"), "200", &value).unwrap();
+        assert_eq!(text(&resp), value.to_string());
+        let resp = represent(&request("application/x-yaml"), "200", &value).unwrap();
+        assert_eq!(serde_yaml_ng::from_str::<Value>(text(&resp)).unwrap(), value);
+        let resp = represent(&request("application/msgpack"), "201", &value).unwrap();
+        match resp.body() {
+            Some(ResponseBody::Binary(bytes)) => assert_eq!(rmp_serde::from_slice::<Value>(bytes).unwrap(), value),
+            body => panic!("unexpected body {:?}", body),
+        }
+        assert_eq!(resp.status_code(), "201");
+
+        let problem = represent(&request("image/png"), "200", &value).unwrap_err();
+        assert_eq!(problem.status(), "406");
+        let csv_only = represent_as(&request("application/json"), "200", &value, &[Format::Csv]);
+        assert_eq!(csv_only.unwrap_err().status(), "406");
+    }
+}
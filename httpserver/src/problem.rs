@@ -0,0 +1,281 @@
+use super::middleware::{Middleware, Next};
+use super::negotiate::negotiate;
+use super::repository::StorageError;
+use super::router::MountPrefix;
+use http::httprequest::HttpRequst;
+use http::httpresponse::{status_text, HttpResponse, ResponseBody};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::panic::{self, AssertUnwindSafe};
+
+/**
+ * 问题详情的媒体类型
+ */
+pub const CONTENT_TYPE: &str = "application/problem+json";
+
+/**
+# Problem
+RFC 9457的问题详情，API处理器出错时返回的统一错误。
+处理器可以返回`Result<HttpResponse, Problem>`并用`?`传播错误，
+错误在返回时自动转换成`application/problem+json`响应，并带上`instance`和`request_id`
+```rust,ignore
+fn show(req: &HttpRequst) -> Result<HttpResponse, Problem> {
+    let character = state.characters.get(name)?.ok_or_else(|| Problem::new("404").detail("character not found"))?;
+    ...
+}
+```
+```json
+{"type": "about:blank", "status": 404, "title": "Not Found", "detail": "character not found",
+ "instance": "/api/characters/nobody", "request_id": "65e2...-1f-0"}
+```
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Problem {
+    status: &'static str,
+    title: Option<String>,
+    detail: Option<String>,
+    members: Vec<(String, Value)>,
+    headers: Vec<(String, String)>,
+}
+
+impl Problem {
+    pub fn new(status: &'static str) -> Self {
+        Problem { status, title: None, detail: None, members: Vec::new(), headers: Vec::new() }
+    }
+
+    pub fn status(&self) -> &'static str {
+        self.status
+    }
+
+    /**
+     * 问题类型的简短说明，默认为状态码的原因短语
+     */
+    pub fn title(mut self, title: impl Into<String>) -> Self {
+        self.title = Some(title.into());
+        self
+    }
+
+    /**
+     * 这一次出错的具体说明
+     */
+    pub fn detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    /**
+     * 扩展成员，例如校验失败时逐条列出的`errors`；不能覆盖标准成员
+     */
+    pub fn member(mut self, name: &str, value: impl Into<Value>) -> Self {
+        self.members.push((name.to_string(), value.into()));
+        self
+    }
+
+    /**
+     * 随响应一起发送的响应头，例如`Retry-After`、`Allow`
+     */
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /**
+     * 响应体：`instance`为请求的完整路径，`request_id`来自[`RequestId`]写回的请求头
+     *
+     * [`RequestId`]: super::middleware::RequestId
+     */
+    pub fn body(&self, req: &HttpRequst) -> Value {
+        let base = req.state::<MountPrefix>().map(|m| m.0.as_str()).unwrap_or("");
+        let mut body = Map::new();
+        body.insert("type".into(), json!("about:blank"));
+        body.insert("status".into(), json!(self.status.parse::<u16>().unwrap_or(500)));
+        body.insert("title".into(), json!(self.title.as_deref().unwrap_or(status_text(self.status))));
+        if let Some(detail) = &self.detail {
+            body.insert("detail".into(), json!(detail));
+        }
+        body.insert("instance".into(), json!(format!("{}{}", base, req.resource.path())));
+        if let Some(id) = req.header("X-Request-Id") {
+            body.insert("request_id".into(), json!(id));
+        }
+        for (name, value) in &self.members {
+            body.entry(name.clone()).or_insert_with(|| value.clone());
+        }
+        Value::Object(body)
+    }
+
+    pub fn respond(&self, req: &HttpRequst) -> HttpResponse {
+        let mut headers: HashMap<&str, &str> = HashMap::new();
+        headers.insert("Content-Type", CONTENT_TYPE);
+        let body = serde_json::to_string_pretty(&self.body(req)).unwrap();
+        let mut resp = HttpResponse::new(self.status, Some(headers), Some(ResponseBody::Text(body)));
+        for (name, value) in &self.headers {
+            resp.set_header(name, value);
+        }
+        resp
+    }
+}
+
+/**
+ * 存储错误：404、409原样说明；不可用时为503并带上`Retry-After`，读写失败为500，
+ * 这两种情况的详细原因只写进日志
+ */
+impl From<StorageError> for Problem {
+    fn from(e: StorageError) -> Self {
+        match e {
+            StorageError::NotFound | StorageError::Conflict => Problem::new(e.status()).detail(e.to_string()),
+            StorageError::Unavailable(_) => {
+                crate::warn!("{}", e);
+                Problem::new("503").detail("character storage is unavailable").header("Retry-After", "5")
+            }
+            StorageError::Failed(_) => {
+                crate::error!("{}", e);
+                Problem::new("500").detail("character storage failed")
+            }
+        }
+    }
+}
+
+/**
+ * 请求体不是合法的JSON
+ */
+impl From<serde_json::Error> for Problem {
+    fn from(e: serde_json::Error) -> Self {
+        Problem::new("400").detail(format!("invalid JSON: {}", e))
+    }
+}
+
+/**
+ * 客户端是否更想要JSON：按`Accept`协商，JSON比HTML更合适；没有`Accept`或相同时选HTML
+ */
+fn wants_json(req: &HttpRequst) -> bool {
+    let offered = ["text/html", CONTENT_TYPE, "application/json"];
+    negotiate(req.header("Accept"), &offered).is_some_and(|media_type| media_type != "text/html")
+}
+
+/**
+# 问题详情中间件
+把内层返回的错误响应(4xx、5xx，例如404、405、415)改写成[`Problem`]，保留原来的响应头，
+`text/plain`的响应体作为`detail`；处理器panic时返回500而不是断开连接。
+请求路径在[`ProblemDetails::prefix`]之下，或者`Accept`更想要JSON时才改写，
+其它请求保持原来的错误页面。已经是`application/problem+json`的响应不变
+```rust,ignore
+router.wrap(RequestId::new()).wrap(ProblemDetails::new().prefix("/api"));
+```
+ */
+#[derive(Debug, Clone, Default)]
+pub struct ProblemDetails {
+    prefixes: Vec<String>,
+}
+
+impl ProblemDetails {
+    pub fn new() -> Self {
+        ProblemDetails::default()
+    }
+
+    /**
+     * 这个前缀下的请求总是使用问题详情，`/`表示所有请求
+     */
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self.prefixes.push(prefix.trim_end_matches('/').to_string());
+        self
+    }
+
+    fn selected(&self, req: &HttpRequst) -> bool {
+        let path = req.resource.path();
+        self.prefixes.iter().any(|prefix| {
+            path.strip_prefix(prefix.as_str()).is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        }) || wants_json(req)
+    }
+}
+
+impl Middleware for ProblemDetails {
+    fn handle(&self, req: &mut HttpRequst, next: Next<'_>) -> HttpResponse {
+        // 先记下来：panic时内层的挂载还没有恢复原来的路径
+        let selected = self.selected(req);
+        let resp = match panic::catch_unwind(AssertUnwindSafe(|| next.run(req))) {
+            Ok(resp) => resp,
+            Err(_) => {
+                crate::error!("handler panicked on {} \"{}\"", req.method.as_str(), req.resource.path());
+                HttpResponse::new("500", None, Some(ResponseBody::Text("500 Internal Server Error".into())))
+            }
+        };
+        let status = resp.status_code();
+        let is_problem = resp.header("Content-Type").is_some_and(|t| t.starts_with(CONTENT_TYPE));
+        if !status.starts_with(['4', '5']) || is_problem || !selected {
+            return resp;
+        }
+
+        let mut problem = Problem::new(status);
+        if let (Some(ResponseBody::Text(text)), Some(true)) = (resp.body(), resp.header("Content-Type").map(|t| t.starts_with("text/plain"))) {
+            problem = problem.detail(text.trim());
+        }
+        for (name, value) in resp.header_fields() {
+            if !name.eq_ignore_ascii_case("Content-Type") {
+                problem = problem.header(name, value);
+            }
+        }
+        problem.respond(req)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middleware::RequestId;
+    use crate::router::Router;
+
+    fn body(resp: &HttpResponse) -> Value {
+        match resp.body() {
+            Some(ResponseBody::Text(text)) => serde_json::from_str(text).unwrap(),
+            body => panic!("unexpected body {:?}", body),
+        }
+    }
+
+    #[test]
+    fn test_problem_details() {
+        let mut api = Router::new();
+        api.get("/items/:id", |req: &HttpRequst| -> Result<HttpResponse, Problem> {
+            match req.param("id") {
+                Some("1") => Ok(HttpResponse::new("200", None, None)),
+                Some("boom") => panic!("boom"),
+                _ => Err(Problem::new("404").detail("no such item").member("errors", json!(["id"]))),
+            }
+        });
+        api.get("/plain", |_req: &HttpRequst| {
+            let mut headers = HashMap::new();
+            headers.insert("Content-Type", "text/plain");
+            HttpResponse::new("503", Some(headers), Some(ResponseBody::Text("try later\n".into())))
+        });
+        let mut router = Router::new();
+        router.wrap(RequestId::new()).wrap(ProblemDetails::new().prefix("/api/"));
+        router.mount("/api", api);
+        let send = |line: &str| router.dispatch(format!("{} HTTP/1.1\r\nX-Request-Id: r1\r\n\r\n", line).into());
+
+        let resp = send("GET /api/items/2");
+        assert_eq!((resp.status_code(), resp.header("Content-Type")), ("404", Some(CONTENT_TYPE)));
+        assert_eq!(body(&resp), json!({
+            "type": "about:blank", "status": 404, "title": "Not Found", "detail": "no such item",
+            "instance": "/api/items/2", "request_id": "r1", "errors": ["id"],
+        }));
+        assert_eq!(send("GET /api/items/1").status_code(), "200");
+
+        // 路由表自己的404、405和纯文本错误同样改写，响应头保留
+        assert_eq!(body(&send("GET /api/nothing"))["instance"], json!("/api/nothing"));
+        let resp = send("POST /api/items/1");
+        assert_eq!((resp.status_code(), resp.header("Allow")), ("405", Some("GET, HEAD")));
+        assert_eq!(body(&resp)["title"], json!("Method Not Allowed"));
+        assert_eq!(body(&send("GET /api/plain"))["detail"], json!("try later"));
+
+        // panic变成500，连接不会因此断开
+        let resp = send("GET /api/items/boom");
+        assert_eq!(body(&resp)["status"], json!(500));
+        assert_eq!(resp.header("X-Request-Id"), Some("r1"));
+
+        // API之外只有想要JSON的客户端才会收到问题详情
+        assert_eq!(send("GET /other").header("Content-Type"), Some("text/html"));
+        let json: HttpResponse = router.dispatch("GET /other HTTP/1.1\r\nAccept: application/json\r\n\r\n".to_string().into());
+        assert_eq!(json.header("Content-Type"), Some(CONTENT_TYPE));
+        let html = router.dispatch("GET /other HTTP/1.1\r\nAccept: text/html,application/json\r\n\r\n".to_string().into());
+        assert_eq!(html.header("Content-Type"), Some("text/html"));
+    }
+}
@@ -0,0 +1,97 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+/**
+ * 检查配置文件和信号的间隔
+ */
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/**
+ * 文件的修改时间和长度，任意一个变化就认为文件被修改
+ */
+type Stamp = Option<(Option<SystemTime>, u64)>;
+
+fn stamp(path: &Option<PathBuf>) -> Stamp {
+    let meta = fs::metadata(path.as_ref()?).ok()?;
+    Some((meta.modified().ok(), meta.len()))
+}
+
+/**
+# Watcher
+等待重新加载配置的时机：收到`SIGHUP`(仅unix)或者配置文件被修改。
+文件变化通过定时比较修改时间和长度发现，不依赖平台的文件通知接口
+```rust,ignore
+let mut watcher = Watcher::new(Some(config_path))?;
+loop {
+    watcher.wait();
+    reload();
+}
+```
+ */
+pub struct Watcher {
+    path: Option<PathBuf>,
+    stamp: Stamp,
+    hangup: Arc<AtomicBool>,
+}
+
+impl Watcher {
+    pub fn new(path: Option<PathBuf>) -> io::Result<Self> {
+        let hangup = Arc::new(AtomicBool::new(false));
+        #[cfg(unix)]
+        signal_hook::flag::register(signal_hook::consts::SIGHUP, hangup.clone())?;
+        Ok(Watcher {
+            stamp: stamp(&path),
+            path,
+            hangup,
+        })
+    }
+
+    /**
+     * 自上次检查以来是否收到了`SIGHUP`或者文件发生了变化
+     */
+    pub fn poll(&mut self) -> bool {
+        let current = stamp(&self.path);
+        let changed = current != self.stamp;
+        self.stamp = current;
+        self.hangup.swap(false, Ordering::Relaxed) || changed
+    }
+
+    /**
+     * 阻塞直到需要重新加载
+     */
+    pub fn wait(&mut self) {
+        while !self.poll() {
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_poll_detects_changes() {
+        let path = env::temp_dir().join(format!("httpserver-reload-{}.toml", std::process::id()));
+        fs::write(&path, "[server]\n").unwrap();
+        let mut watcher = Watcher::new(Some(path.clone())).unwrap();
+        assert!(!watcher.poll());
+
+        fs::write(&path, "[server]\nworkers = 2\n").unwrap();
+        assert!(watcher.poll());
+        assert!(!watcher.poll());
+
+        watcher.hangup.store(true, Ordering::Relaxed);
+        assert!(watcher.poll());
+        assert!(!watcher.poll());
+
+        fs::remove_file(&path).unwrap();
+        assert!(watcher.poll());
+    }
+}
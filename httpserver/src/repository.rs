@@ -0,0 +1,527 @@
+use super::characters::OrderStatus;
+use super::problem::Problem;
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::time::SystemTime;
+
+/**
+ * 是否编译了`sqlite`特性
+ */
+pub fn sqlite_available() -> bool {
+    cfg!(feature = "sqlite")
+}
+
+/**
+ * # 存储错误
+ * - `NotFound`: 没有这个名字的角色，对应404
+ * - `Conflict`: 名字已经被其它角色使用，对应409
+ * - `Unavailable`: 存储暂时不可用，例如数据文件缺失或格式错误、数据库被锁，对应503
+ * - `Failed`: 读写失败，对应500
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum StorageError {
+    NotFound,
+    Conflict,
+    Unavailable(String),
+    Failed(String),
+}
+
+impl StorageError {
+    pub fn status(&self) -> &'static str {
+        match self {
+            StorageError::NotFound => "404",
+            StorageError::Conflict => "409",
+            StorageError::Unavailable(_) => "503",
+            StorageError::Failed(_) => "500",
+        }
+    }
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StorageError::NotFound => write!(f, "character not found"),
+            StorageError::Conflict => write!(f, "a character with this name already exists"),
+            StorageError::Unavailable(reason) => write!(f, "storage is unavailable: {}", reason),
+            StorageError::Failed(reason) => write!(f, "storage failed: {}", reason),
+        }
+    }
+}
+
+/**
+# Repository
+角色数据的存储，处理器通过`&dyn Repository`读写，不关心数据放在哪里。
+修改要么完整生效，要么返回错误并保持原来的数据
+```rust,ignore
+let repo: Box<dyn Repository> = Box::new(JsonFile::open("data/characters.json")?);
+repo.insert(character)?;
+let all = repo.list()?;
+```
+ */
+pub trait Repository: Send + Sync {
+    /**
+     * 所有角色，按加入的顺序
+     */
+    fn list(&self) -> Result<Vec<OrderStatus>, StorageError>;
+
+    fn get(&self, name: &str) -> Result<Option<OrderStatus>, StorageError> {
+        Ok(self.list()?.into_iter().find(|c| c.name == name))
+    }
+
+    /**
+     * 新增一个角色，名字已经存在时返回`Conflict`
+     */
+    fn insert(&self, character: OrderStatus) -> Result<(), StorageError>;
+
+    /**
+     * 替换名为`name`的角色，新数据的名字不同时相当于改名
+     */
+    fn replace(&self, name: &str, character: OrderStatus) -> Result<(), StorageError>;
+
+    /**
+     * 读取名为`name`的角色，交给`change`算出新数据后替换，返回替换后的角色。
+     * 读和写之间不会插入其它修改，所以并发的部分修改不会互相覆盖；`change`返回错误时不做修改
+     */
+    fn update(&self, name: &str, change: Change<'_>) -> Result<OrderStatus, Problem>;
+
+    fn remove(&self, name: &str) -> Result<(), StorageError>;
+}
+
+/**
+ * [`Repository::update`]中根据当前数据算出新数据的函数，例如合并PATCH请求体并校验
+ */
+pub type Change<'a> = Box<dyn FnOnce(OrderStatus) -> Result<OrderStatus, Problem> + 'a>;
+
+fn insert_into(items: &mut Vec<OrderStatus>, character: OrderStatus) -> Result<(), StorageError> {
+    if items.iter().any(|c| c.name == character.name) {
+        return Err(StorageError::Conflict);
+    }
+    items.push(character);
+    Ok(())
+}
+
+fn replace_in(items: &mut [OrderStatus], name: &str, character: OrderStatus) -> Result<(), StorageError> {
+    let index = items.iter().position(|c| c.name == name).ok_or(StorageError::NotFound)?;
+    if character.name != name && items.iter().any(|c| c.name == character.name) {
+        return Err(StorageError::Conflict);
+    }
+    items[index] = character;
+    Ok(())
+}
+
+fn update_in(items: &mut [OrderStatus], name: &str, change: Change<'_>) -> Result<OrderStatus, Problem> {
+    let current = items.iter().find(|c| c.name == name).cloned().ok_or(StorageError::NotFound)?;
+    let character = change(current)?;
+    replace_in(items, name, character.clone())?;
+    Ok(character)
+}
+
+fn remove_from(items: &mut Vec<OrderStatus>, name: &str) -> Result<(), StorageError> {
+    let index = items.iter().position(|c| c.name == name).ok_or(StorageError::NotFound)?;
+    items.remove(index);
+    Ok(())
+}
+
+/**
+ * # 内存存储
+ * 用于测试和编译进二进制的数据，重启后修改丢失
+ */
+#[derive(Default)]
+pub struct Memory {
+    items: RwLock<Vec<OrderStatus>>,
+}
+
+impl Memory {
+    pub fn new(items: Vec<OrderStatus>) -> Self {
+        Memory { items: RwLock::new(items) }
+    }
+}
+
+impl Repository for Memory {
+    fn list(&self) -> Result<Vec<OrderStatus>, StorageError> {
+        Ok(self.items.read().unwrap().clone())
+    }
+
+    fn insert(&self, character: OrderStatus) -> Result<(), StorageError> {
+        insert_into(&mut self.items.write().unwrap(), character)
+    }
+
+    fn replace(&self, name: &str, character: OrderStatus) -> Result<(), StorageError> {
+        replace_in(&mut self.items.write().unwrap(), name, character)
+    }
+
+    fn update(&self, name: &str, change: Change<'_>) -> Result<OrderStatus, Problem> {
+        update_in(&mut self.items.write().unwrap(), name, change)
+    }
+
+    fn remove(&self, name: &str) -> Result<(), StorageError> {
+        remove_from(&mut self.items.write().unwrap(), name)
+    }
+}
+
+/**
+ * JSON文件的内容和读取时的修改时间
+ */
+struct Cached {
+    items: Vec<OrderStatus>,
+    modified: Option<SystemTime>,
+}
+
+/**
+# JSON文件存储
+读取一次后缓存在内存中，文件的修改时间变化(例如手工编辑)时重新读取；
+文件缺失或格式错误时返回`Unavailable`，不会用缓存覆盖它。
+修改先写入同一目录下的临时文件再改名覆盖，成功后才更新缓存，中途失败也不会留下写了一半的文件
+ */
+pub struct JsonFile {
+    path: PathBuf,
+    cache: RwLock<Cached>,
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+fn read(path: &Path) -> Result<Cached, StorageError> {
+    let modified = modified(path);
+    let contents = fs::read_to_string(path)
+        .map_err(|e| StorageError::Unavailable(format!("{}: {}", path.display(), e)))?;
+    let items = serde_json::from_str(&contents)
+        .map_err(|e| StorageError::Unavailable(format!("{}: {}", path.display(), e)))?;
+    Ok(Cached { items, modified })
+}
+
+/**
+ * 原子地写入`path`：写入同一目录下的临时文件并同步到磁盘，然后改名覆盖
+ */
+fn save(path: &Path, items: &[OrderStatus]) -> io::Result<()> {
+    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("characters.json");
+    let tmp = path.with_file_name(format!(".{}.{}.tmp", name, std::process::id()));
+    let result = (|| {
+        let mut out = fs::File::create(&tmp)?;
+        out.write_all(serde_json::to_string_pretty(items).unwrap().as_bytes())?;
+        out.write_all(b"\n")?;
+        out.sync_all()?;
+        fs::rename(&tmp, path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result
+}
+
+impl JsonFile {
+    /**
+     * 打开并读取数据文件，文件缺失或格式错误时返回错误
+     */
+    pub fn open(path: impl Into<PathBuf>) -> Result<JsonFile, StorageError> {
+        let path = path.into();
+        let cache = RwLock::new(read(&path)?);
+        Ok(JsonFile { path, cache })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /**
+     * 重新读取数据文件，失败时保留原来的缓存
+     */
+    pub fn reload(&self) -> Result<(), StorageError> {
+        *self.cache.write().unwrap() = read(&self.path)?;
+        Ok(())
+    }
+
+    /**
+     * 文件在外部被修改过时先重新读取，再在副本上修改并写回
+     */
+    fn modify<F, T, E>(&self, change: F) -> Result<T, E>
+    where
+        F: FnOnce(&mut Vec<OrderStatus>) -> Result<T, E>,
+        E: From<StorageError>,
+    {
+        let mut cache = self.cache.write().unwrap();
+        if modified(&self.path) != cache.modified {
+            *cache = read(&self.path)?;
+        }
+        let mut updated = cache.items.clone();
+        let result = change(&mut updated)?;
+        save(&self.path, &updated)
+            .map_err(|e| StorageError::Failed(format!("cannot save {}: {}", self.path.display(), e)))?;
+        *cache = Cached { items: updated, modified: modified(&self.path) };
+        Ok(result)
+    }
+}
+
+impl Repository for JsonFile {
+    fn list(&self) -> Result<Vec<OrderStatus>, StorageError> {
+        {
+            let cache = self.cache.read().unwrap();
+            if modified(&self.path) == cache.modified {
+                return Ok(cache.items.clone());
+            }
+        }
+        self.reload()?;
+        Ok(self.cache.read().unwrap().items.clone())
+    }
+
+    fn insert(&self, character: OrderStatus) -> Result<(), StorageError> {
+        self.modify(|items| insert_into(items, character))
+    }
+
+    fn replace(&self, name: &str, character: OrderStatus) -> Result<(), StorageError> {
+        self.modify(|items| replace_in(items, name, character))
+    }
+
+    fn update(&self, name: &str, change: Change<'_>) -> Result<OrderStatus, Problem> {
+        self.modify(|items| update_in(items, name, change))
+    }
+
+    fn remove(&self, name: &str) -> Result<(), StorageError> {
+        self.modify(|items| remove_from(items, name))
+    }
+}
+
+#[cfg(feature = "sqlite")]
+pub use self::sqlite::Sqlite;
+
+#[cfg(feature = "sqlite")]
+mod sqlite {
+    use super::{Change, Repository, StorageError};
+    use crate::characters::OrderStatus;
+    use crate::problem::Problem;
+    use rusqlite::{params, Connection, ErrorCode, OptionalExtension, Row, TransactionBehavior};
+    use std::path::Path;
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    /**
+     * 数据库被其它连接锁住时等待的时间，超过后返回`Unavailable`
+     */
+    const BUSY_TIMEOUT: Duration = Duration::from_secs(2);
+
+    const INSERT: &str = "INSERT INTO characters (name, level, health, element, skills) VALUES (?1, ?2, ?3, ?4, ?5)";
+    const SELECT_ONE: &str = "SELECT name, level, health, element, skills FROM characters WHERE name = ?1";
+
+    /**
+    # SQLite存储
+    嵌入式的SQLite数据库文件，表`characters`不存在时自动创建，技能列表以JSON文本保存。
+    同一个进程中的请求共用一个连接
+     */
+    pub struct Sqlite {
+        conn: Mutex<Connection>,
+    }
+
+    fn storage_error(e: rusqlite::Error) -> StorageError {
+        match e.sqlite_error_code() {
+            Some(ErrorCode::ConstraintViolation) => StorageError::Conflict,
+            Some(ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked | ErrorCode::CannotOpen | ErrorCode::ReadOnly) => {
+                StorageError::Unavailable(e.to_string())
+            }
+            _ => StorageError::Failed(e.to_string()),
+        }
+    }
+
+    /**
+     * 替换一行，`conn`也可以是一个事务
+     */
+    fn update_row(conn: &Connection, name: &str, c: &OrderStatus) -> Result<(), StorageError> {
+        let skills = serde_json::to_string(&c.skills).unwrap();
+        let changed = conn
+            .execute(
+                "UPDATE characters SET name = ?1, level = ?2, health = ?3, element = ?4, skills = ?5 WHERE name = ?6",
+                params![c.name, c.level, c.health as f64, c.element, skills, name],
+            )
+            .map_err(storage_error)?;
+        if changed == 0 { Err(StorageError::NotFound) } else { Ok(()) }
+    }
+
+    fn character(row: &Row) -> rusqlite::Result<OrderStatus> {
+        let skills: String = row.get(4)?;
+        Ok(OrderStatus {
+            name: row.get(0)?,
+            level: row.get(1)?,
+            health: row.get::<_, f64>(2)? as f32,
+            element: row.get(3)?,
+            skills: serde_json::from_str(&skills).unwrap_or_default(),
+        })
+    }
+
+    impl Sqlite {
+        pub fn open(path: impl AsRef<Path>) -> Result<Sqlite, StorageError> {
+            let conn = Connection::open(path).map_err(storage_error)?;
+            Self::init(conn)
+        }
+
+        pub fn open_in_memory() -> Result<Sqlite, StorageError> {
+            Self::init(Connection::open_in_memory().map_err(storage_error)?)
+        }
+
+        fn init(conn: Connection) -> Result<Sqlite, StorageError> {
+            conn.busy_timeout(BUSY_TIMEOUT).map_err(storage_error)?;
+            conn.execute_batch(
+                "CREATE TABLE IF NOT EXISTS characters (
+                    name TEXT PRIMARY KEY NOT NULL,
+                    level INTEGER NOT NULL,
+                    health REAL NOT NULL,
+                    element TEXT NOT NULL,
+                    skills TEXT NOT NULL
+                )",
+            ).map_err(storage_error)?;
+            Ok(Sqlite { conn: Mutex::new(conn) })
+        }
+    }
+
+    impl Sqlite {
+        /**
+         * 在一个事务中导入多个角色，任何一个失败都不会留下部分数据
+         */
+        pub fn import(&self, items: &[OrderStatus]) -> Result<(), StorageError> {
+            let mut conn = self.conn.lock().unwrap();
+            let tx = conn.transaction().map_err(storage_error)?;
+            for c in items {
+                tx.execute(INSERT, params![c.name, c.level, c.health as f64, c.element, serde_json::to_string(&c.skills).unwrap()])
+                    .map_err(storage_error)?;
+            }
+            tx.commit().map_err(storage_error)
+        }
+    }
+
+    impl Repository for Sqlite {
+        fn list(&self) -> Result<Vec<OrderStatus>, StorageError> {
+            let conn = self.conn.lock().unwrap();
+            let mut stmt = conn.prepare_cached("SELECT name, level, health, element, skills FROM characters ORDER BY rowid")
+                .map_err(storage_error)?;
+            let rows = stmt.query_map([], character).map_err(storage_error)?;
+            rows.collect::<Result<_, _>>().map_err(storage_error)
+        }
+
+        fn get(&self, name: &str) -> Result<Option<OrderStatus>, StorageError> {
+            let conn = self.conn.lock().unwrap();
+            conn.query_row(SELECT_ONE, [name], character)
+                .optional()
+                .map_err(storage_error)
+        }
+
+        fn insert(&self, c: OrderStatus) -> Result<(), StorageError> {
+            let skills = serde_json::to_string(&c.skills).unwrap();
+            self.conn.lock().unwrap()
+                .execute(INSERT, params![c.name, c.level, c.health as f64, c.element, skills])
+                .map(|_| ())
+                .map_err(storage_error)
+        }
+
+        fn replace(&self, name: &str, c: OrderStatus) -> Result<(), StorageError> {
+            update_row(&self.conn.lock().unwrap(), name, &c)
+        }
+
+        fn update(&self, name: &str, change: Change<'_>) -> Result<OrderStatus, Problem> {
+            let mut conn = self.conn.lock().unwrap();
+            // 立即取得写锁，其它连接不能在读和写之间修改这一行
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate).map_err(storage_error)?;
+            let current = tx.query_row(SELECT_ONE, [name], character)
+                .optional()
+                .map_err(storage_error)?
+                .ok_or(StorageError::NotFound)?;
+            let updated = change(current)?;
+            update_row(&tx, name, &updated)?;
+            tx.commit().map_err(storage_error)?;
+            Ok(updated)
+        }
+
+        fn remove(&self, name: &str) -> Result<(), StorageError> {
+            let changed = self.conn.lock().unwrap()
+                .execute("DELETE FROM characters WHERE name = ?1", [name])
+                .map_err(storage_error)?;
+            if changed == 0 { Err(StorageError::NotFound) } else { Ok(()) }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::env;
+
+    fn character(name: &str, level: i32) -> OrderStatus {
+        serde_json::from_value(json!({ "name": name, "level": level, "health": 1000.0, "element": "火", "skills": ["a", "b"] })).unwrap()
+    }
+
+    /**
+     * 每种存储都要满足的行为
+     */
+    fn exercise(repo: &dyn Repository) {
+        repo.insert(character("胡桃", 90)).unwrap();
+        repo.insert(character("可莉", 40)).unwrap();
+        assert_eq!(repo.insert(character("胡桃", 1)), Err(StorageError::Conflict));
+        assert_eq!(repo.get("胡桃").unwrap(), Some(character("胡桃", 90)));
+        assert_eq!(repo.get("钟离").unwrap(), None);
+
+        repo.replace("胡桃", character("堂主", 80)).unwrap();
+        assert_eq!(repo.replace("胡桃", character("堂主", 80)), Err(StorageError::NotFound));
+        assert_eq!(repo.replace("可莉", character("堂主", 1)), Err(StorageError::Conflict));
+        assert_eq!(repo.list().unwrap(), vec![character("堂主", 80), character("可莉", 40)]);
+
+        // 读-改-写在同一次调用中完成，`change`出错或者改名冲突时不做修改
+        let levelled = repo.update("堂主", Box::new(|c| Ok(OrderStatus { level: c.level + 1, ..c }))).unwrap();
+        assert_eq!(levelled, character("堂主", 81));
+        let refused = repo.update("堂主", Box::new(|_| Err(Problem::new("422"))));
+        assert_eq!(refused.unwrap_err().status(), "422");
+        let renamed = repo.update("堂主", Box::new(|c| Ok(OrderStatus { name: "可莉".into(), ..c })));
+        assert_eq!(renamed.unwrap_err().status(), "409");
+        assert_eq!(repo.update("钟离", Box::new(Ok)).unwrap_err().status(), "404");
+        assert_eq!(repo.list().unwrap(), vec![character("堂主", 81), character("可莉", 40)]);
+
+        repo.remove("堂主").unwrap();
+        assert_eq!(repo.remove("堂主"), Err(StorageError::NotFound));
+        assert_eq!(repo.list().unwrap(), vec![character("可莉", 40)]);
+    }
+
+    #[test]
+    fn test_memory() {
+        exercise(&Memory::default());
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_sqlite() {
+        exercise(&Sqlite::open_in_memory().unwrap());
+        let path = env::temp_dir().join(format!("httpserver-repo-{}.db", std::process::id()));
+        let _ = fs::remove_file(&path);
+        Sqlite::open(&path).unwrap().insert(character("胡桃", 90)).unwrap();
+        assert_eq!(Sqlite::open(&path).unwrap().list().unwrap(), vec![character("胡桃", 90)]);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_json_file() {
+        let dir = env::temp_dir().join(format!("httpserver-repo-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("characters.json");
+        assert!(matches!(JsonFile::open(&path), Err(StorageError::Unavailable(_))));
+
+        fs::write(&path, "[]").unwrap();
+        let repo = JsonFile::open(&path).unwrap();
+        exercise(&repo);
+        let saved: Vec<OrderStatus> = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(saved, vec![character("可莉", 40)]);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        // 手工编辑后重新读取；改坏之后返回503而不是覆盖它
+        let edited = serde_json::to_string(&vec![character("钟离", 90)]).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        fs::write(&path, edited).unwrap();
+        assert_eq!(repo.list().unwrap(), vec![character("钟离", 90)]);
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        fs::write(&path, "[{").unwrap();
+        assert!(matches!(repo.list(), Err(StorageError::Unavailable(_))));
+        assert!(matches!(repo.insert(character("胡桃", 90)), Err(StorageError::Unavailable(_))));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "[{");
+        assert_eq!(repo.get("钟离").map_err(|e| e.status()), Err("503"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,36 +1,1000 @@
-use super::handler::{Handler, PageNotFoundHandler, StaticPageHandler, WebServiceHandler,};
-use http::{httprequest, httprequest::HttpRequst};
+use super::handler::Handler;
+use super::middleware::{Middleware, Next, Stack};
+use http::httprequest::{percent_decode, HttpRequst, Method, Resource};
+use http::httpresponse::{HttpResponse, ResponseBody};
+use std::collections::HashMap;
+use std::fmt;
 use std::io::prelude::*;
+use std::sync::Arc;
 
 /**
- * 我想应该可以通过读取一些配置文件来达到路由设置的目的，现在先简单硬编码路由
+ * 路由表中保存的处理器
  */
-pub struct Router;
+pub type BoxedHandler = Box<dyn Handler + Send + Sync>;
 
+/**
+ * 路由表中的一条路由：处理器、处理器的类型名和注册时所在分组的中间件
+ */
+struct Route {
+    handler: BoxedHandler,
+    name: &'static str,
+    middleware: Stack,
+}
+
+impl Route {
+    fn new<H: Handler + 'static>(handler: H, middleware: Stack) -> Self {
+        Route { handler: Box::new(handler), name: std::any::type_name::<H>(), middleware }
+    }
+}
+
+/**
+ * # 路由信息
+ * [`Router::routes`]列出的一条路由，`handler`为处理器的类型名，用于打印路由表
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct RouteInfo {
+    pub method: Method,
+    pub pattern: String,
+    pub handler: &'static str,
+}
+
+/**
+ * # 路由注册错误
+ * - `InvalidPattern`: 模式不以`/`开头，或者参数、通配符没有名字，或者通配符不在最后
+ * - `ParamConflict`: 同一位置已经注册了名字不同的参数，例如`/:id`和`/:name`
+ * - `Duplicate`: 同一个方法和模式注册了两次
+ */
+#[derive(Debug, PartialEq)]
+pub enum RouteError {
+    InvalidPattern(String),
+    ParamConflict(String),
+    Duplicate(String),
+}
+
+impl fmt::Display for RouteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RouteError::InvalidPattern(p) => write!(f, "invalid route pattern \"{}\"", p),
+            RouteError::ParamConflict(p) => write!(f, "route \"{}\" conflicts with an existing parameter name", p),
+            RouteError::Duplicate(p) => write!(f, "route \"{}\" is already registered", p),
+        }
+    }
+}
+
+/**
+ * 前缀树节点，每个节点对应路径中的一段。
+ * 查找时按 静态段 > `:param` > `*wildcard` 的顺序匹配，匹配失败会回溯
+ */
+#[derive(Default)]
+struct Node {
+    statics: HashMap<String, Node>,
+    param: Option<(String, Box<Node>)>,
+    wildcard: Option<(String, HashMap<Method, Route>)>,
+    handlers: HashMap<Method, Route>,
+}
+
+/**
+ * # 路由匹配结果
+ * - `Found`: 处理函数、路由所在分组的中间件和路径参数
+ * - `MethodNotAllowed`: 路径存在但方法不匹配，附带允许的方法和路径所在分组的中间件
+ * - `NotFound`: 没有匹配的路径，附带路径所在分组的中间件
+ *
+ * 路径所在分组是前缀匹配路径的最深的分组，例如跨域预检请求的405也要经过分组中的[`Cors`]
+ *
+ * [`Cors`]: super::middleware::Cors
+ */
+pub enum Match<'r> {
+    Found(&'r dyn Handler, &'r [Arc<dyn Middleware>], HashMap<String, String>),
+    MethodNotAllowed(Vec<Method>, &'r [Arc<dyn Middleware>]),
+    NotFound(&'r [Arc<dyn Middleware>]),
+}
+
+fn pick(handlers: &HashMap<Method, Route>, method: Method) -> Option<&Route> {
+    handlers.get(&method).or_else(|| {
+        // HEAD没有单独注册时使用GET的处理器
+        if method == Method::Head {
+            handlers.get(&Method::Get)
+        } else {
+            None
+        }
+    })
+}
+
+/**
+ * 路径存在但方法不匹配时的处理器，同样会经过全局中间件
+ */
+struct MethodNotAllowed(Vec<Method>);
+
+impl Handler for MethodNotAllowed {
+    fn handle(&self, _req: &HttpRequst) -> HttpResponse {
+        let allowed: Vec<&str> = self.0.iter().map(|m| m.as_str()).collect();
+        let mut resp = HttpResponse::new("405", None, None);
+        resp.set_header("Allow", &allowed.join(", "));
+        resp
+    }
+}
+
+/**
+ * 请求头检查没有通过时的处理器，把拒绝的响应交给外层的中间件
+ */
+struct Reject(HttpResponse);
+
+impl Handler for Reject {
+    fn handle(&self, _req: &HttpRequst) -> HttpResponse {
+        self.0.clone()
+    }
+}
+
+/**
+ * 分发时找到的处理器
+ */
+enum Target<'r> {
+    Handler(&'r dyn Handler, HashMap<String, String>),
+    NotFound(&'r dyn Handler),
+    NotAllowed(MethodNotAllowed),
+}
+
+/**
+ * 依次用中间件和处理器检查请求头，被第`i`层拒绝时响应只经过它外面的`i`层中间件
+ */
+fn vet(chain: &Stack, handler: &dyn Handler, req: &mut HttpRequst) -> Option<HttpResponse> {
+    for (i, middleware) in chain.iter().enumerate() {
+        if let Some(resp) = middleware.check(req) {
+            return Some(Next::new(&chain[..i], &Reject(resp)).run(req));
+        }
+    }
+    handler.check(req).map(|resp| Next::new(chain, &Reject(resp)).run(req))
+}
+
+/**
+ * 请求头已经通过了[`Router::check`]：服务端回应`100 Continue`前检查过请求头时，
+ * 读完请求体后把它放进请求的扩展表，[`Router::dispatch`]不再重复调用中间件和处理器的`check`
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Vetted;
+
+/**
+ * # 挂载前缀
+ * 请求进入挂载的子路由时，路径中的前缀会被去掉，去掉的部分记录在这里，
+ * 嵌套挂载时是所有前缀拼起来的结果。需要生成绝对路径(例如重定向)的处理器可以读取它
+ * ```rust,ignore
+ * let base = req.state::<MountPrefix>().map(|m| m.0.as_str()).unwrap_or("");
+ * ```
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct MountPrefix(pub String);
+
+/**
+ * 挂载边界上的中间件：进入子路由前去掉前缀，返回后恢复原来的路径
+ */
+struct StripPrefix(String);
+
+impl Middleware for StripPrefix {
+    fn handle(&self, req: &mut HttpRequst, next: Next<'_>) -> HttpResponse {
+        let Resource::Path(full) = &req.resource;
+        // 请求的正好是挂载点时相对路径为空，静态目录据此重定向到带`/`的地址
+        let relative = full.get(self.0.len()..).unwrap_or("").to_string();
+        let base = match req.state::<MountPrefix>() {
+            Some(MountPrefix(base)) => format!("{}{}", base, self.0),
+            None => self.0.clone(),
+        };
+
+        let original = std::mem::replace(&mut req.resource, Resource::Path(relative));
+        let extensions = req.extensions.clone();
+        req.extensions.insert(Arc::new(MountPrefix(base)));
+        let resp = next.run(req);
+        req.resource = original;
+        req.extensions = extensions;
+        resp
+    }
+}
+
+/**
+ * 挂载在某个前缀下的子路由，`middleware`是挂载时所在分组的中间件
+ */
+struct Mount {
+    prefix: String,
+    router: Router,
+    middleware: Stack,
+}
+
+impl Mount {
+    /**
+     * `path`在前缀下时返回去掉前缀后的路径
+     */
+    fn strip<'p>(&self, path: &'p str) -> Option<&'p str> {
+        let rest = path.strip_prefix(&self.prefix)?;
+        match rest {
+            "" => Some(""),
+            _ if rest.starts_with('/') => Some(rest),
+            _ => None,
+        }
+    }
+}
+
+/**
+ * 把分组前缀和路由模式拼在一起，`/api` + `/` 得到 `/api`
+ */
+fn join_prefix(prefix: &str, pattern: &str) -> String {
+    let prefix = prefix.trim_end_matches('/');
+    match pattern.trim_start_matches('/') {
+        "" if !prefix.is_empty() => prefix.to_string(),
+        rest => format!("{}/{}", prefix, rest),
+    }
+}
+
+impl Node {
+    /**
+     * 按注册时的写法列出这个节点下的所有路由
+     */
+    fn collect(&self, path: &str, out: &mut Vec<RouteInfo>) {
+        let info = |pattern: String, handlers: &HashMap<Method, Route>, out: &mut Vec<RouteInfo>| {
+            for (method, route) in handlers {
+                out.push(RouteInfo { method: *method, pattern: pattern.clone(), handler: route.name });
+            }
+        };
+        info(if path.is_empty() { "/".to_string() } else { path.to_string() }, &self.handlers, out);
+        for (seg, child) in &self.statics {
+            child.collect(&format!("{}/{}", path, seg), out);
+        }
+        if let Some((name, child)) = &self.param {
+            child.collect(&format!("{}/:{}", path, name), out);
+        }
+        if let Some((name, handlers)) = &self.wildcard {
+            info(format!("{}/*{}", path, name), handlers, out);
+        }
+    }
+
+    /**
+     * 查找`segs`对应的处理器，前`floor`段之内的通配符不参与匹配，
+     * 这样分组外的通配符就不会吞掉分组前缀下的路径
+     */
+    fn find<'n>(
+        &'n self,
+        segs: &[String],
+        floor: usize,
+        params: &mut Vec<(String, String)>,
+    ) -> Option<&'n HashMap<Method, Route>> {
+        let wildcard = if floor == 0 { self.wildcard.as_ref() } else { None };
+        let (seg, rest) = match segs.split_first() {
+            None => {
+                if !self.handlers.is_empty() {
+                    return Some(&self.handlers);
+                }
+                return wildcard.map(|(name, handlers)| {
+                    params.push((name.clone(), String::new()));
+                    handlers
+                });
+            }
+            Some(split) => split,
+        };
+
+        if let Some(child) = self.statics.get(seg) {
+            if let Some(found) = child.find(rest, floor.saturating_sub(1), params) {
+                return Some(found);
+            }
+        }
+        if let Some((name, child)) = &self.param {
+            params.push((name.clone(), seg.clone()));
+            if let Some(found) = child.find(rest, floor.saturating_sub(1), params) {
+                return Some(found);
+            }
+            params.pop();
+        }
+        wildcard.map(|(name, handlers)| {
+            params.push((name.clone(), segs.join("/")));
+            handlers
+        })
+    }
+}
+
+/**
+# Router
+基于前缀树的路由表，查找耗时只和路径深度有关，与路由数量无关
+- 静态段：`/api/cache/stats`
+- 参数：`/api/characters/:name`，匹配到的值放在[`HttpRequst::params`]中
+- 通配符：`*path`，匹配剩余的所有段(可以为空)，只能是最后一段，例如`/static`下的`*path`匹配`/static/css/site.css`
+
+同一路径上静态段优先于参数，参数优先于通配符。分组外注册的通配符不匹配分组前缀下的路径，
+这些路径找不到路由时得到经过分组中间件的404。
+
+用[`Router::wrap`]添加的中间件包在所有请求外面(包括404和405)，
+用[`Router::group`]添加的分组中间件只作用于分组内的路由(包括分组前缀下的404和405)，
+并在全局中间件之后执行。
+
+用[`Router::mount`]可以把另一个`Router`整个挂载到前缀下，子路由看到的是去掉前缀后的路径，
+并使用自己的中间件和404处理器。挂载优先于本路由表中的路由。
+```rust,ignore
+let mut shipping = Router::new();
+shipping.get("/characters", WebServiceHandler::characters);
+
+let mut router = Router::new();
+router
+    .wrap(Logger)
+    .group("/api", |api| {
+        api.wrap(Cors::default())
+            .mount("/shipping", shipping)
+            .get("/cache/stats", WebServiceHandler::cache_stats);
+    })
+    // 没有匹配的路径交给静态文件处理器，找不到文件时它自己返回404页面
+    .not_found(StaticPageHandler::new(options, cache));
+```
+ */
+pub struct Router {
+    root: Node,
+    not_found: BoxedHandler,
+    middleware: Stack,
+    mounts: Vec<Mount>,
+    groups: Vec<(Vec<String>, Stack)>,
+}
+
+impl Default for Router {
+    fn default() -> Self {
+        Router::new()
+    }
+}
 
 impl Router {
+    pub fn new() -> Self {
+        Router {
+            root: Node::default(),
+            not_found: Box::new(|_req: &HttpRequst| {
+                HttpResponse::new("404", None, Some(ResponseBody::Text("404 Not Found".into())))
+            }),
+            middleware: Vec::new(),
+            mounts: Vec::new(),
+            groups: Vec::new(),
+        }
+    }
+
     /**
-     * Router: 对不同的请求进行不同的相应
+     * # 注册路由
+     * 模式不合法或者与已有路由冲突时返回[`RouteError`]
      */
-    pub fn route(req: HttpRequst, stream :&mut impl Write) -> () {
-        let resp =
-        match req.method {
-            httprequest::Method::Get => 
-                match &req.resource {
-                    httprequest::Resource::Path(s) => {
-                        println!("Path: \"{}\"", s);
-                        let route: Vec<&str> = s.split("/").collect();
-                        match route[1] {
-                            "api" => WebServiceHandler::handle(&req),
-
-                            _ => StaticPageHandler::handle(&req)
-                        }
-                    }
-                },
-            _ => PageNotFoundHandler::handle(&req),
+    pub fn add<H: Handler + 'static>(&mut self, method: Method, pattern: &str, handler: H) -> Result<(), RouteError> {
+        self.add_route(method, pattern, Route::new(handler, Vec::new()))
+    }
+
+    fn add_route(&mut self, method: Method, pattern: &str, route: Route) -> Result<(), RouteError> {
+        let invalid = || RouteError::InvalidPattern(pattern.to_string());
+        if !pattern.starts_with('/') {
+            return Err(invalid());
+        }
+        let segs: Vec<&str> = pattern.split('/').filter(|s| !s.is_empty()).collect();
+
+        let mut node = &mut self.root;
+        for (i, seg) in segs.iter().enumerate() {
+            if let Some(name) = seg.strip_prefix('*') {
+                if name.is_empty() || i != segs.len() - 1 {
+                    return Err(invalid());
+                }
+                let (existing, handlers) = node.wildcard
+                    .get_or_insert_with(|| (name.to_string(), HashMap::new()));
+                if existing != name {
+                    return Err(RouteError::ParamConflict(pattern.to_string()));
+                }
+                if handlers.insert(method, route).is_some() {
+                    return Err(RouteError::Duplicate(pattern.to_string()));
+                }
+                return Ok(());
+            } else if let Some(name) = seg.strip_prefix(':') {
+                if name.is_empty() {
+                    return Err(invalid());
+                }
+                let (existing, child) = node.param
+                    .get_or_insert_with(|| (name.to_string(), Box::default()));
+                if existing != name {
+                    return Err(RouteError::ParamConflict(pattern.to_string()));
+                }
+                node = child;
+            } else {
+                node = node.statics.entry(seg.to_string()).or_default();
+            }
+        }
+
+        if node.handlers.insert(method, route).is_some() {
+            return Err(RouteError::Duplicate(pattern.to_string()));
+        }
+        Ok(())
+    }
+
+    fn register(&mut self, method: Method, pattern: &str, handler: impl Handler + 'static) -> &mut Self {
+        if let Err(e) = self.add(method, pattern, handler) {
+            panic!("{}", e);
+        }
+        self
+    }
+
+    /**
+     * 注册GET路由，模式冲突时panic，运行时构建的路由表请使用[`Router::add`]
+     */
+    pub fn get(&mut self, pattern: &str, handler: impl Handler + 'static) -> &mut Self {
+        self.register(Method::Get, pattern, handler)
+    }
+
+    pub fn post(&mut self, pattern: &str, handler: impl Handler + 'static) -> &mut Self {
+        self.register(Method::Post, pattern, handler)
+    }
+
+    pub fn put(&mut self, pattern: &str, handler: impl Handler + 'static) -> &mut Self {
+        self.register(Method::Put, pattern, handler)
+    }
+
+    pub fn patch(&mut self, pattern: &str, handler: impl Handler + 'static) -> &mut Self {
+        self.register(Method::Patch, pattern, handler)
+    }
+
+    pub fn delete(&mut self, pattern: &str, handler: impl Handler + 'static) -> &mut Self {
+        self.register(Method::Delete, pattern, handler)
+    }
+
+    /**
+     * 没有匹配的路由时使用的处理器，默认返回纯文本的404，
+     * 通常设置为[`PageNotFoundHandler`](super::handler::PageNotFoundHandler)
+     */
+    pub fn not_found(&mut self, handler: impl Handler + 'static) -> &mut Self {
+        self.not_found = Box::new(handler);
+        self
+    }
+
+    /**
+     * 添加全局中间件，先添加的在外层
+     */
+    pub fn wrap(&mut self, middleware: impl Middleware + 'static) -> &mut Self {
+        self.middleware.push(Arc::new(middleware));
+        self
+    }
+
+    /**
+     * # 挂载子路由
+     * 前缀必须以`/`开头且不能是`/`本身，不能包含参数或通配符，同一前缀只能挂载一次
+     */
+    pub fn try_mount(&mut self, prefix: &str, router: Router) -> Result<(), RouteError> {
+        self.add_mount(prefix, router, Vec::new())
+    }
+
+    fn add_mount(&mut self, prefix: &str, router: Router, middleware: Stack) -> Result<(), RouteError> {
+        let normalized = prefix.trim_end_matches('/');
+        let dynamic = normalized.split('/').any(|s| s.starts_with(':') || s.starts_with('*'));
+        if !normalized.starts_with('/') || dynamic {
+            return Err(RouteError::InvalidPattern(prefix.to_string()));
+        }
+        if self.mounts.iter().any(|m| m.prefix == normalized) {
+            return Err(RouteError::Duplicate(prefix.to_string()));
+        }
+        self.mounts.push(Mount { prefix: normalized.to_string(), router, middleware });
+        // 前缀长的先匹配，`/api/shipping`优先于`/api`
+        self.mounts.sort_by_key(|m| std::cmp::Reverse(m.prefix.len()));
+        Ok(())
+    }
+
+    /**
+     * 挂载子路由，前缀不合法或重复时panic，见[`Router::try_mount`]
+     */
+    pub fn mount(&mut self, prefix: &str, router: Router) -> &mut Self {
+        if let Err(e) = self.try_mount(prefix, router) {
+            panic!("{}", e);
+        }
+        self
+    }
+
+    /**
+     * # 路由分组
+     * 在`build`中注册的路由都带上`prefix`前缀，并经过分组的中间件
+     */
+    pub fn group(&mut self, prefix: &str, build: impl FnOnce(&mut Group)) -> &mut Self {
+        let mut group = Group {
+            router: self,
+            prefix: prefix.to_string(),
+            middleware: Vec::new(),
         };
+        build(&mut group);
+        group.finish();
+        self
+    }
+
+    /**
+     * # 路由表
+     * 列出所有路由(包括挂载的子路由，模式带上挂载前缀)，按模式和方法排序
+     */
+    pub fn routes(&self) -> Vec<RouteInfo> {
+        let mut out = Vec::new();
+        self.root.collect("", &mut out);
+        for mount in &self.mounts {
+            for route in mount.router.routes() {
+                let pattern = join_prefix(&mount.prefix, &route.pattern);
+                out.push(RouteInfo { pattern, ..route });
+            }
+        }
+        out.sort_by(|a, b| (&a.pattern, a.method.as_str()).cmp(&(&b.pattern, b.method.as_str())));
+        out
+    }
+
+    /**
+     * 按方法和路径查找路由
+     */
+    pub fn lookup(&self, method: Method, path: &str) -> Match<'_> {
+        let segs: Option<Vec<String>> = path.split('/')
+            .filter(|s| !s.is_empty())
+            .map(percent_decode)
+            .collect();
+        let segs = match segs {
+            Some(segs) => segs,
+            None => return Match::NotFound(&[]),
+        };
+
+        let floor = self.group_prefix(&segs).map_or(0, |(prefix, _)| prefix.len());
+        let mut params = Vec::new();
+        match self.root.find(&segs, floor, &mut params) {
+            None => Match::NotFound(self.group_middleware(&segs)),
+            Some(handlers) => match pick(handlers, method) {
+                Some(route) => Match::Found(route.handler.as_ref(), &route.middleware, params.into_iter().collect()),
+                None => {
+                    let mut allowed: Vec<Method> = handlers.keys().copied().collect();
+                    if allowed.contains(&Method::Get) && !allowed.contains(&Method::Head) {
+                        allowed.push(Method::Head);
+                    }
+                    allowed.sort_by_key(|m| m.as_str());
+                    Match::MethodNotAllowed(allowed, self.group_middleware(&segs))
+                }
+            },
+        }
+    }
+
+    fn group_middleware(&self, segs: &[String]) -> &[Arc<dyn Middleware>] {
+        self.group_prefix(segs).map_or(&[], |(_, middleware)| middleware.as_slice())
+    }
+
+    /**
+     * 前缀匹配`segs`的最深的分组，前缀相同时取后注册的
+     */
+    fn group_prefix(&self, segs: &[String]) -> Option<&(Vec<String>, Stack)> {
+        self.groups.iter()
+            .filter(|(prefix, _)| segs.starts_with(prefix))
+            .max_by_key(|(prefix, _)| prefix.len())
+    }
+
+    /**
+     * 沿着挂载关系找到最终的处理器，并把经过的每一层中间件按顺序放入`chain`
+     */
+    fn resolve<'r>(&'r self, method: Method, path: &str, chain: &mut Stack) -> Target<'r> {
+        chain.extend(self.middleware.iter().cloned());
+        for mount in &self.mounts {
+            if let Some(relative) = mount.strip(path) {
+                chain.extend(mount.middleware.iter().cloned());
+                chain.push(Arc::new(StripPrefix(mount.prefix.clone())));
+                return mount.router.resolve(method, relative, chain);
+            }
+        }
+        match self.lookup(method, path) {
+            Match::Found(handler, middleware, params) => {
+                chain.extend(middleware.iter().cloned());
+                Target::Handler(handler, params)
+            }
+            Match::MethodNotAllowed(allowed, middleware) => {
+                chain.extend(middleware.iter().cloned());
+                Target::NotAllowed(MethodNotAllowed(allowed))
+            }
+            Match::NotFound(middleware) => {
+                chain.extend(middleware.iter().cloned());
+                Target::NotFound(self.not_found.as_ref())
+            }
+        }
+    }
 
+    /**
+     * # 检查请求头
+     * 在读取请求体之前决定是否接受请求：路由不存在或方法不匹配时直接得到404/405，
+     * 否则依次调用中间件和处理器的`check`。返回`Some`时是应当发给客户端的响应，
+     * 已经经过了相应的中间件
+     */
+    pub fn check(&self, req: &mut HttpRequst) -> Option<HttpResponse> {
+        let mut chain = Stack::new();
+        let path = req.resource.path().to_string();
+        match self.resolve(req.method, &path, &mut chain) {
+            Target::Handler(handler, params) => {
+                req.params = params;
+                vet(&chain, handler, req)
+            }
+            Target::NotFound(handler) => Some(Next::new(&chain, handler).run(req)),
+            Target::NotAllowed(handler) => Some(Next::new(&chain, &handler).run(req)),
+        }
+    }
+
+    /**
+     * # 分发请求
+     * 找到处理器后依次经过全局中间件、分组中间件，最后交给处理器。
+     * 进入挂载的子路由时，还会经过子路由自己的中间件。
+     * 请求头没有通过[`Router::check`]时不会调用处理器；带有[`Vetted`]标记时不再检查
+     */
+    pub fn dispatch(&self, mut req: HttpRequst) -> HttpResponse {
+        let mut chain = Stack::new();
+        let path = req.resource.path().to_string();
+        match self.resolve(req.method, &path, &mut chain) {
+            Target::Handler(handler, params) => {
+                req.params = params;
+                let rejected = match req.state::<Vetted>() {
+                    Some(_) => None,
+                    None => vet(&chain, handler, &mut req),
+                };
+                match rejected {
+                    None => Next::new(&chain, handler).run(&mut req),
+                    Some(resp) => resp,
+                }
+            }
+            Target::NotFound(handler) => Next::new(&chain, handler).run(&mut req),
+            Target::NotAllowed(handler) => Next::new(&chain, &handler).run(&mut req),
+        }
+    }
 
+    /**
+     * Router: 对不同的请求进行不同的相应
+     */
+    pub fn route(&self, req: HttpRequst, stream :&mut impl Write) {
+        let resp = self.dispatch(req);
         let _ = resp.send_response(stream);
     }
-}
\ No newline at end of file
+}
+
+/**
+ * # 路由分组
+ * 由[`Router::group`]创建，分组中间件在注册时复制到每条路由上，
+ * 所以要先调用[`Group::wrap`]再注册路由
+ */
+pub struct Group<'r> {
+    router: &'r mut Router,
+    prefix: String,
+    middleware: Stack,
+}
+
+impl Group<'_> {
+    /**
+     * 记下分组最终的中间件，分组内的404和405也要经过它们
+     */
+    fn finish(self) {
+        let segs = self.prefix.split('/').filter(|s| !s.is_empty()).map(str::to_string).collect();
+        self.router.groups.push((segs, self.middleware));
+    }
+
+    /**
+     * 添加分组中间件，只影响之后注册的路由
+     */
+    pub fn wrap(&mut self, middleware: impl Middleware + 'static) -> &mut Self {
+        self.middleware.push(Arc::new(middleware));
+        self
+    }
+
+    pub fn add<H: Handler + 'static>(&mut self, method: Method, pattern: &str, handler: H) -> Result<(), RouteError> {
+        let route = Route::new(handler, self.middleware.clone());
+        self.router.add_route(method, &join_prefix(&self.prefix, pattern), route)
+    }
+
+    fn register(&mut self, method: Method, pattern: &str, handler: impl Handler + 'static) -> &mut Self {
+        if let Err(e) = self.add(method, pattern, handler) {
+            panic!("{}", e);
+        }
+        self
+    }
+
+    pub fn get(&mut self, pattern: &str, handler: impl Handler + 'static) -> &mut Self {
+        self.register(Method::Get, pattern, handler)
+    }
+
+    pub fn post(&mut self, pattern: &str, handler: impl Handler + 'static) -> &mut Self {
+        self.register(Method::Post, pattern, handler)
+    }
+
+    pub fn put(&mut self, pattern: &str, handler: impl Handler + 'static) -> &mut Self {
+        self.register(Method::Put, pattern, handler)
+    }
+
+    pub fn patch(&mut self, pattern: &str, handler: impl Handler + 'static) -> &mut Self {
+        self.register(Method::Patch, pattern, handler)
+    }
+
+    pub fn delete(&mut self, pattern: &str, handler: impl Handler + 'static) -> &mut Self {
+        self.register(Method::Delete, pattern, handler)
+    }
+
+    /**
+     * 在分组前缀下挂载子路由，请求会先经过分组中间件，再经过子路由自己的中间件
+     */
+    pub fn mount(&mut self, prefix: &str, router: Router) -> &mut Self {
+        let prefix = join_prefix(&self.prefix, prefix);
+        if let Err(e) = self.router.add_mount(&prefix, router, self.middleware.clone()) {
+            panic!("{}", e);
+        }
+        self
+    }
+
+    /**
+     * 嵌套分组，继承当前分组的前缀和中间件
+     */
+    pub fn group(&mut self, prefix: &str, build: impl FnOnce(&mut Group)) -> &mut Self {
+        let mut group = Group {
+            prefix: join_prefix(&self.prefix, prefix),
+            middleware: self.middleware.clone(),
+            router: &mut *self.router,
+        };
+        build(&mut group);
+        group.finish();
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middleware::Cors;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn named(name: &str) -> HttpResponse {
+        HttpResponse::new("200", None, Some(ResponseBody::Text(name.to_string())))
+    }
+    fn list(_: &HttpRequst) -> HttpResponse { named("list") }
+    fn show(_: &HttpRequst) -> HttpResponse { named("show") }
+
+    /**
+     * 返回匹配到的处理器的响应内容和路径参数
+     */
+    fn found(router: &Router, method: Method, path: &str) -> Option<(String, HashMap<String, String>)> {
+        let req: HttpRequst = format!("{} {} HTTP/1.1\r\n\r\n", method.as_str(), path).into();
+        match router.lookup(method, path) {
+            Match::Found(handler, _, params) => match handler.handle(&req).body() {
+                Some(ResponseBody::Text(name)) => Some((name.clone(), params)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_precedence_and_params() {
+        let mut router = Router::new();
+        router
+            .get("/api/characters", list)
+            .get("/api/characters/:name", show)
+            .get("/api/characters/me", |_: &HttpRequst| named("me"))
+            .get("/*path", |_: &HttpRequst| named("files"));
+
+        let (name, params) = found(&router, Method::Get, "/api/characters/%E8%83%A1%E6%A1%83").unwrap();
+        assert_eq!(name, "show");
+        assert_eq!(params.get("name").map(|s| s.as_str()), Some("胡桃"));
+
+        let (name, _) = found(&router, Method::Get, "/api/characters/me").unwrap();
+        assert_eq!(name, "me");
+
+        // 参数分支匹配不上时回溯到通配符
+        let (name, params) = found(&router, Method::Get, "/api/characters/me/extra").unwrap();
+        assert_eq!(name, "files");
+        assert_eq!(params.get("path").map(|s| s.as_str()), Some("api/characters/me/extra"));
+
+        let (name, params) = found(&router, Method::Head, "/").unwrap();
+        assert_eq!(name, "files");
+        assert_eq!(params.get("path").map(|s| s.as_str()), Some(""));
+    }
+
+    #[test]
+    fn test_method_not_allowed_and_errors() {
+        let mut router = Router::new();
+        router.get("/api/characters", list).post("/api/characters", show);
+        match router.lookup(Method::Delete, "/api/characters") {
+            Match::MethodNotAllowed(allowed, _) => assert_eq!(allowed, vec![Method::Get, Method::Head, Method::Post]),
+            _ => panic!("expected 405"),
+        }
+        assert!(matches!(router.lookup(Method::Get, "/nope"), Match::NotFound(_)));
+
+        assert_eq!(router.add(Method::Get, "/api/characters", list), Err(RouteError::Duplicate("/api/characters".into())));
+        router.get("/api/characters/:name", show);
+        assert_eq!(router.add(Method::Get, "/api/characters/:id", show), Err(RouteError::ParamConflict("/api/characters/:id".into())));
+        assert_eq!(router.add(Method::Get, "/static/*path/x", list), Err(RouteError::InvalidPattern("/static/*path/x".into())));
+        assert_eq!(router.add(Method::Get, "api", list), Err(RouteError::InvalidPattern("api".into())));
+    }
+
+    #[test]
+    fn test_group_middleware() {
+        let tag = |tag: &'static str| {
+            move |req: &mut HttpRequst, next: Next<'_>| {
+                let mut resp = next.run(req);
+                let seen = resp.header("X-Chain").unwrap_or("").to_string();
+                resp.set_header("X-Chain", &format!("{}{}", tag, seen));
+                resp
+            }
+        };
+        let mut router = Router::new();
+        router
+            .wrap(tag("global"))
+            .group("/api/", |api| {
+                api.wrap(tag(">api"))
+                    .get("/", list)
+                    .group("/characters", |chars| {
+                        chars.wrap(tag(">chars")).get("/:name", show);
+                    });
+            })
+            .get("/index.html", list);
+
+        let send = |path: &str| {
+            let req: HttpRequst = format!("GET {} HTTP/1.1\r\n\r\n", path).into();
+            router.dispatch(req)
+        };
+        assert_eq!(send("/api").header("X-Chain"), Some("global>api"));
+        let resp = send("/api/characters/hutao");
+        assert_eq!(resp.header("X-Chain"), Some("global>api>chars"));
+        assert_eq!(resp.body(), Some(&ResponseBody::Text("show".into())));
+        assert_eq!(send("/index.html").header("X-Chain"), Some("global"));
+        assert_eq!(send("/missing").status_code(), "404");
+        assert_eq!(send("/missing").header("X-Chain"), Some("global"));
+
+        // 分组内的404和405经过最深的分组的中间件
+        let resp = send("/api/characters/hutao/extra");
+        assert_eq!((resp.status_code(), resp.header("X-Chain")), ("404", Some("global>api>chars")));
+        assert_eq!(send("/api/nothing").header("X-Chain"), Some("global>api"));
+        let req: HttpRequst = "DELETE /api/characters/hutao HTTP/1.1\r\n\r\n".to_string().into();
+        let resp = router.dispatch(req);
+        assert_eq!((resp.status_code(), resp.header("X-Chain")), ("405", Some("global>api>chars")));
+
+        // 分组外的通配符不吞掉分组前缀下的路径
+        router.get("/*path", list);
+        let send = |path: &str| {
+            let req: HttpRequst = format!("GET {} HTTP/1.1\r\n\r\n", path).into();
+            router.dispatch(req)
+        };
+        assert_eq!(send("/css/site.css").status_code(), "200");
+        let resp = send("/api/nothing");
+        assert_eq!((resp.status_code(), resp.header("X-Chain")), ("404", Some("global>api")));
+        assert_eq!(send("/api").status_code(), "200");
+    }
+
+    #[test]
+    fn test_group_preflight() {
+        let mut router = Router::new();
+        router
+            .group("/api", |api| {
+                api.wrap(Cors::default()).get("/cache/stats", list);
+            })
+            .get("/*path", list);
+        let preflight = |path: &str| {
+            let req: HttpRequst = format!(
+                "OPTIONS {} HTTP/1.1\r\nOrigin: https://example.com\r\nAccess-Control-Request-Method: GET\r\n\r\n",
+                path,
+            ).into();
+            router.dispatch(req)
+        };
+        let resp = preflight("/api/cache/stats");
+        assert_eq!(resp.status_code(), "204");
+        assert_eq!(resp.header("Access-Control-Allow-Origin"), Some("*"));
+        assert_eq!(resp.header("Access-Control-Allow-Methods"), Some(Cors::default().allow_methods.as_str()));
+        // 分组之外的路由不受影响
+        assert_eq!(preflight("/index.html").status_code(), "405");
+    }
+
+    #[test]
+    fn test_mount() {
+        let echo = |req: &HttpRequst| {
+            let base = req.state::<MountPrefix>().map(|m| m.0.clone()).unwrap_or_default();
+            let body = format!("{}|{}|{}", base, req.resource.path(), req.param("name").unwrap_or(""));
+            HttpResponse::new("200", None, Some(ResponseBody::Text(body)))
+        };
+        let mut inner = Router::new();
+        inner.get("/characters/:name", echo);
+        let mut shipping = Router::new();
+        shipping
+            .wrap(|req: &mut HttpRequst, next: Next<'_>| {
+                let mut resp = next.run(req);
+                resp.set_header("X-Shipping", "yes");
+                resp
+            })
+            .get("/", echo)
+            .mount("/v1", inner)
+            .not_found(|_: &HttpRequst| HttpResponse::new("404", None, Some(ResponseBody::Text("shipping".into()))));
+
+        let mut router = Router::new();
+        router
+            .wrap(|req: &mut HttpRequst, next: Next<'_>| {
+                // 外层中间件看到的始终是完整路径
+                let path = req.resource.path().to_string();
+                let mut resp = next.run(req);
+                resp.set_header("X-Path", &path);
+                resp
+            })
+            .group("/api", |api| { api.mount("/shipping/", shipping); })
+            .get("/*path", echo);
+
+        let send = |path: &str| {
+            let req: HttpRequst = format!("GET {} HTTP/1.1\r\n\r\n", path).into();
+            router.dispatch(req)
+        };
+        let text = |resp: &HttpResponse| match resp.body() {
+            Some(ResponseBody::Text(t)) => t.clone(),
+            _ => String::new(),
+        };
+
+        let resp = send("/api/shipping/v1/characters/hutao?x=1");
+        assert_eq!(text(&resp), "/api/shipping/v1|/characters/hutao|hutao");
+        assert_eq!(resp.header("X-Path"), Some("/api/shipping/v1/characters/hutao"));
+        assert_eq!(resp.header("X-Shipping"), Some("yes"));
+        assert_eq!(text(&send("/api/shipping")), "/api/shipping||");
+        assert_eq!(text(&send("/api/shipping/")), "/api/shipping|/|");
+        assert_eq!(text(&send("/api/shipping/nope")), "shipping");
+        // 只有完整的段才算匹配前缀，分组前缀下没有路由时是分组的404，而不是根上的通配符
+        let resp = send("/api/shippingx");
+        assert_eq!((resp.status_code(), text(&resp).as_str()), ("404", "404 Not Found"));
+        assert_eq!(text(&send("/shippingx")), "|/shippingx|");
+
+        let routes: Vec<(Method, String)> = router.routes().into_iter().map(|r| (r.method, r.pattern)).collect();
+        assert_eq!(routes, vec![
+            (Method::Get, "/*path".to_string()),
+            (Method::Get, "/api/shipping".to_string()),
+            (Method::Get, "/api/shipping/v1/characters/:name".to_string()),
+        ]);
+
+        assert_eq!(router.try_mount("/api/:id", Router::new()), Err(RouteError::InvalidPattern("/api/:id".into())));
+        assert_eq!(router.try_mount("/", Router::new()), Err(RouteError::InvalidPattern("/".into())));
+    }
+
+    #[test]
+    fn test_check_headers() {
+        /** 只接受JSON的上传处理器 */
+        struct Upload;
+        impl Handler for Upload {
+            fn handle(&self, req: &HttpRequst) -> HttpResponse {
+                named(&req.msg_body)
+            }
+            fn check(&self, req: &HttpRequst) -> Option<HttpResponse> {
+                match req.header("Content-Type") {
+                    Some("application/json") => None,
+                    _ => Some(HttpResponse::new("415", None, None)),
+                }
+            }
+        }
+        /** 没有`Authorization`时拒绝，拒绝的响应仍然经过外层中间件；记下检查的次数 */
+        struct Auth(Arc<AtomicUsize>);
+        impl Middleware for Auth {
+            fn handle(&self, req: &mut HttpRequst, next: Next<'_>) -> HttpResponse {
+                next.run(req)
+            }
+            fn check(&self, req: &HttpRequst) -> Option<HttpResponse> {
+                self.0.fetch_add(1, Ordering::SeqCst);
+                req.header("Authorization").is_none().then(|| HttpResponse::new("401", None, None))
+            }
+        }
+        let checks = Arc::new(AtomicUsize::new(0));
+        let auth = Auth(checks.clone());
+        let mut router = Router::new();
+        router
+            .wrap(|req: &mut HttpRequst, next: Next<'_>| {
+                let mut resp = next.run(req);
+                resp.set_header("X-Outer", "yes");
+                resp
+            })
+            .group("/upload", |upload| {
+                upload.wrap(auth).post("/:name", Upload);
+            });
+
+        let check = |head: &str| {
+            let mut req: HttpRequst = head.to_string().into();
+            router.check(&mut req).map(|resp| (resp.status_code().to_string(), resp.header("X-Outer").is_some()))
+        };
+        let json = "Content-Type: application/json\r\n";
+        assert_eq!(check(&format!("POST /upload/a HTTP/1.1\r\nAuthorization: x\r\n{}\r\n", json)), None);
+        assert_eq!(check(&format!("POST /upload/a HTTP/1.1\r\n{}\r\n", json)), Some(("401".into(), true)));
+        assert_eq!(check("POST /upload/a HTTP/1.1\r\nAuthorization: x\r\n\r\n"), Some(("415".into(), true)));
+        assert_eq!(check("PUT /upload/a HTTP/1.1\r\n\r\n"), Some(("405".into(), true)));
+        assert_eq!(check("POST /nope HTTP/1.1\r\n\r\n"), Some(("404".into(), true)));
+
+        // 分发时同样先检查请求头
+        let req: HttpRequst = "POST /upload/a HTTP/1.1\r\nAuthorization: x\r\n\r\nbody".to_string().into();
+        assert_eq!(router.dispatch(req).status_code(), "415");
+        let req: HttpRequst = format!("POST /upload/a HTTP/1.1\r\nAuthorization: x\r\n{}\r\nbody", json).into();
+        assert_eq!(router.dispatch(req).body(), Some(&ResponseBody::Text("body".into())));
+
+        // 回应100之前已经检查过的请求分发时不再检查
+        let head = format!("POST /upload/a HTTP/1.1\r\nAuthorization: x\r\n{}\r\n", json);
+        checks.store(0, Ordering::SeqCst);
+        assert_eq!(check(&head), None);
+        let mut req: HttpRequst = format!("{}body", head).into();
+        req.extensions.insert(Arc::new(Vetted));
+        assert_eq!(router.dispatch(req).body(), Some(&ResponseBody::Text("body".into())));
+        assert_eq!(checks.load(Ordering::SeqCst), 1);
+    }
+}
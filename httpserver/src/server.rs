@@ -1,49 +1,857 @@
-use super::router::Router;
-use http::httprequest::HttpRequst;
-use std::io::prelude::*;
-use std::net::TcpListener;
+use super::config::ServerConfig;
+use super::h2;
+use super::listener::{Connection, ListenAddr, Listener};
+use super::reload::Watcher;
+use super::tls::TlsAcceptor;
+use super::websocket::{Upgrade, WebSocket};
+use super::router::{Router, Vetted};
+use super::sse::Streaming;
+use http::httprequest::{Extensions, HttpRequst, Method, Version};
+use http::httpresponse::{HttpResponse, ResponseBody};
+use std::io::{self, prelude::*};
+use std::path::PathBuf;
 use std::str;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex, RwLock};
+use std::thread;
+use std::time::Duration;
 
-pub struct Server<'a> {
-    socket_addr: &'a str,
+/**
+ * 路由表和共享状态，重新加载时整体替换。
+ * 每个请求(HTTP/2为每个连接)开始时取一份`Arc`，替换后已经在处理的请求仍然使用旧的配置
+ */
+struct Site {
+    router: Router,
+    extensions: Extensions,
 }
 
-impl<'a> Server<'a> {
+/**
+ * 重新构建每个站点的路由表和共享状态，顺序与创建站点的顺序相同，失败时返回错误信息
+ */
+pub type Rebuild = Box<dyn Fn() -> Result<Vec<(Router, Extensions)>, String> + Send + Sync>;
+
+/**
+ * 一个监听地址、它使用的站点，以及是否使用TLS
+ */
+struct Binding {
+    addr: String,
+    site: usize,
+    tls: Option<TlsAcceptor>,
+}
+
+pub struct Server {
+    bindings: Vec<Binding>,
+    sites: Vec<RwLock<Arc<Site>>>,
+    options: ServerConfig,
+    reload: Option<(Option<PathBuf>, Rebuild)>,
+    sessions: Arc<Sessions>,
+}
+
+/**
+ * 升级后的会话(WebSocket、事件流)计数。每个会话在单独的线程中运行，
+ * 不限制时客户端可以打开任意多的连接耗尽线程，达到`max_sessions`后新的会话返回503
+ */
+#[derive(Debug, Default)]
+pub struct Sessions {
+    active: AtomicUsize,
+}
+
+/**
+ * 占用一个会话名额，会话线程结束时释放
+ */
+pub struct SessionGuard(Arc<Sessions>);
+
+impl Sessions {
+    pub fn enter(self: &Arc<Self>, max: usize) -> Option<SessionGuard> {
+        self.active
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| (n < max).then_some(n + 1))
+            .ok()
+            .map(|_| SessionGuard(self.clone()))
+    }
+
+    pub fn active(&self) -> usize {
+        self.active.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        self.0.active.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/**
+ * 会话数已满时代替101或事件流的响应，之后关闭连接
+ */
+pub(crate) fn too_many_sessions() -> HttpResponse {
+    let text = "503 Service Unavailable: too many open sessions".to_string();
+    let mut resp = HttpResponse::new("503", None, Some(ResponseBody::Text(text)));
+    resp.set_header("Retry-After", "5");
+    resp.set_header("Connection", "close");
+    resp
+}
+
+/**
+ * 读到的请求：HTTP/1.x请求头在`buf`的前`head`个字节，后面是`body`字节的请求体(可能还没有读完)，
+ * 或者以HTTP/2连接序言开头的数据(h2c prior knowledge)
+ */
+enum Incoming {
+    Http1 { head: usize, body: usize },
+    H2(Vec<u8>),
+}
+
+/**
+ * 读取请求失败时的结果：对端已经关闭时不再回应，其余情况返回对应的状态码
+ */
+enum ReadError {
+    Closed,
+    Status(&'static str),
+}
+
+fn find_header_end(buf: &[u8]) -> Option<usize> {
+    buf.windows(4).position(|w| w == b"\r\n\r\n").map(|pos| pos + 4)
+}
+
+/**
+ * 从请求头中找出`Content-Length`，格式不对、出现多次或带了多个值时返回400。
+ * 不支持分块等传输编码，带`Transfer-Encoding`的请求返回501，
+ * 否则请求体会被当成下一个请求，前面有代理时可以借此走私请求
+ */
+fn content_length(head: &[u8]) -> Result<usize, ReadError> {
+    let head = String::from_utf8_lossy(head);
+    let mut length = None;
+    for line in head.split("\r\n").skip(1) {
+        if let Some((key, value)) = line.split_once(':') {
+            let key = key.trim();
+            if key.eq_ignore_ascii_case("Transfer-Encoding") {
+                return Err(ReadError::Status("501"));
+            }
+            if key.eq_ignore_ascii_case("Content-Length") {
+                let value = value.trim();
+                if length.is_some() || value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+                    return Err(ReadError::Status("400"));
+                }
+                length = Some(value.parse().map_err(|_| ReadError::Status("400"))?);
+            }
+        }
+    }
+    Ok(length.unwrap_or(0))
+}
+
+/**
+ * 检查请求行中的协议版本：主版本不是1时返回505，请求行格式不对时返回400
+ */
+fn check_version(head: &[u8]) -> Result<(), ReadError> {
+    let line = head.split(|&b| b == b'\r').next().unwrap_or_default();
+    let line = String::from_utf8_lossy(line);
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let version = match words[..] {
+        [_, _, version] => version.strip_prefix("HTTP/").ok_or(ReadError::Status("400"))?,
+        _ => return Err(ReadError::Status("400")),
+    };
+    let digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    match version.split_once('.') {
+        Some(("1", minor)) if digits(minor) => Ok(()),
+        Some((major, minor)) if digits(major) && digits(minor) => Err(ReadError::Status("505")),
+        None if digits(version) && version != "1" => Err(ReadError::Status("505")),
+        _ => Err(ReadError::Status("400")),
+    }
+}
+
+/**
+ * 按请求的版本决定是否保持连接：HTTP/1.1默认保持，除非`Connection: close`；
+ * HTTP/1.0只有明确带`Connection: keep-alive`时才保持
+ */
+fn keep_alive(req: &HttpRequst) -> bool {
+    let has = |token: &str| req.header("Connection")
+        .is_some_and(|v| v.split(',').any(|t| t.trim().eq_ignore_ascii_case(token)));
+    match req.version {
+        Version::V1_0 => has("keep-alive"),
+        _ => !has("close"),
+    }
+}
+
+/**
+ * 从连接中再读一些数据追加到`buf`
+ */
+fn fill(stream: &mut impl Read, buf: &mut Vec<u8>) -> Result<(), ReadError> {
+    let mut chunk = [0; 4096];
+    match stream.read(&mut chunk) {
+        Ok(0) if buf.is_empty() => Err(ReadError::Closed),
+        Ok(0) => Err(ReadError::Status("400")),
+        Ok(n) => {
+            buf.extend_from_slice(&chunk[..n]);
+            Ok(())
+        }
+        Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+            Err(ReadError::Status("408"))
+        }
+        Err(_) => Err(ReadError::Closed),
+    }
+}
+
+/**
+ * # 读取请求头
+ * 读到请求头结束(`\r\n\r\n`)并检查`Content-Length`，请求体由[`read_body`]读取，
+ * 这样可以先根据请求头决定是否回应`100 Continue`。
+ * 请求头超过`max_header_bytes`返回431，请求体超过`max_body_bytes`返回413，读超时返回408，
+ * 不支持的协议版本返回505，带`Transfer-Encoding`返回501。
+ * `buf`中是上一个请求之后已经读到的数据(流水线请求)。
+ * 以HTTP/2连接序言开头时返回已经读到的全部数据，交给[`h2::serve`]处理
+ */
+fn read_head(stream: &mut impl Read, buf: &mut Vec<u8>, options: &ServerConfig) -> Result<Incoming, ReadError> {
+    let header_end = loop {
+        if let Some(end) = find_header_end(buf) {
+            break end;
+        }
+        if buf.len() > options.max_header_bytes {
+            return Err(ReadError::Status("431"));
+        }
+        fill(stream, buf)?;
+    };
+    if header_end > options.max_header_bytes {
+        return Err(ReadError::Status("431"));
+    }
+    // 序言的前18个字节`PRI * HTTP/2.0\r\n\r\n`看起来像一个没有头部的请求
+    if buf.starts_with(&h2::PREFACE[..header_end.min(h2::PREFACE.len())]) {
+        while buf.len() < h2::PREFACE.len() {
+            fill(stream, buf)?;
+        }
+        if buf.starts_with(h2::PREFACE) {
+            return Ok(Incoming::H2(std::mem::take(buf)));
+        }
+        return Err(ReadError::Status("400"));
+    }
+    check_version(&buf[..header_end])?;
+
+    let body_len = content_length(&buf[..header_end])?;
+    if body_len > options.max_body_bytes {
+        return Err(ReadError::Status("413"));
+    }
+    Ok(Incoming::Http1 { head: header_end, body: body_len })
+}
+
+/**
+ * 读完[`read_head`]之后的请求体，返回完整的请求，本次请求之后多读的数据留在`buf`中
+ */
+fn read_body(stream: &mut impl Read, buf: &mut Vec<u8>, head: usize, body: usize) -> Result<Vec<u8>, ReadError> {
+    while buf.len() < head + body {
+        fill(stream, buf)?;
+    }
+    Ok(buf.drain(..head + body).collect())
+}
+
+/**
+ * 不经过路由表回应一个错误状态，然后关闭连接
+ */
+fn send_status(stream: &mut impl Write, code: &'static str) {
+    let text = format!("{} {}", code, http::httpresponse::status_text(code));
+    let mut resp = HttpResponse::new(code, None, Some(ResponseBody::Text(text)));
+    resp.set_header("Connection", "close");
+    let _ = resp.send_response(stream);
+}
+
+impl Server {
     /**
-     * 接受一个socket地址，返回一个Server
-     * 
+     * 接受一个监听地址和路由表，返回一个Server，地址的写法见[`ListenAddr`]
+     *
      ## Example
-     ```rust
-     let server = Server::new("localhost:3000");
+     ```rust,ignore
+     let mut router = Router::new();
+     router.get("/api/shipping/characters", WebServiceHandler::characters);
+     let server = Server::new("localhost:3000", router);
      ```
      */
-    pub fn new(socket_addr: &'a str) -> Self {
+    pub fn new(socket_addr: &str, router: Router) -> Self {
         Server {
-            socket_addr
+            bindings: Vec::new(),
+            sites: Vec::new(),
+            options: ServerConfig::default(),
+            reload: None,
+            sessions: Arc::default(),
         }
+        .listen_with(socket_addr, router)
     }
-    
 
     /**
-     * 启动server，开始监听`socket_addr`并处理请求
+     * 再监听一个地址，与[`Server::new`]的地址共用同一个路由表
+     */
+    pub fn listen(mut self, socket_addr: &str) -> Self {
+        self.bindings.push(Binding { addr: socket_addr.to_string(), site: 0, tls: None });
+        self
+    }
+
+    /**
+     * 再监听一个地址并使用单独的路由表，所有地址共用线程池和[`Server::with_state`]注入的状态
      ## Example
-     ```rust
-     server.run();
+     ```rust,ignore
+     let server = Server::new("[::]:80", public).listen_with("unix:/run/httpserver/admin.sock", admin);
      ```
      */
-    pub fn run(&self) {
-        let connection_listener = TcpListener::bind(self.socket_addr).unwrap();
-        println!("Running on {}", self.socket_addr);
+    pub fn listen_with(mut self, socket_addr: &str, router: Router) -> Self {
+        let extensions = self.sites.first()
+            .map(|site| site.read().unwrap().extensions.clone())
+            .unwrap_or_default();
+        self.sites.push(RwLock::new(Arc::new(Site { router, extensions })));
+        self.bindings.push(Binding { addr: socket_addr.to_string(), site: self.sites.len() - 1, tls: None });
+        self
+    }
 
+    /**
+     * 最近添加的监听地址改为接受TLS连接，握手在工作线程中完成
+     ## Example
+     ```rust,ignore
+     let server = Server::new("[::]:443", router).with_tls(acceptor).listen_with("[::]:80", redirect);
+     ```
+     */
+    pub fn with_tls(mut self, acceptor: TlsAcceptor) -> Self {
+        if let Some(binding) = self.bindings.last_mut() {
+            binding.tls = Some(acceptor);
+        }
+        self
+    }
 
-        for stream in connection_listener.incoming() {
-            let mut stream = stream.unwrap();
-            println!("Connection established");
+    /**
+     * 设置线程数、超时和大小限制，见[`ServerConfig`]
+     */
+    pub fn with_options(mut self, options: ServerConfig) -> Self {
+        self.options = options;
+        self
+    }
 
-            let mut read_buffer = [0; 1024*16];
-            stream.read(&mut read_buffer).unwrap();
-            let req: HttpRequst = String::from_utf8(read_buffer.to_vec()).unwrap().into();
-            Router::route(req, &mut stream);
+    /**
+     * 注入共享状态，每个请求都可以通过`req.state::<T>()`取得，
+     * 可以多次调用注入不同类型的状态
+     ## Example
+     ```rust,ignore
+     let server = Server::new("localhost:3000", router).with_state(Arc::new(AppState::new(characters, cache)));
+     ```
+     */
+    pub fn with_state<T: Send + Sync + 'static>(mut self, state: Arc<T>) -> Self {
+        for site in &mut self.sites {
+            let site = Arc::get_mut(site.get_mut().unwrap()).expect("with_state must be called before run");
+            site.extensions.insert(state.clone());
         }
+        self
     }
-}
\ No newline at end of file
+
+    /**
+     * # 热重载
+     * 收到`SIGHUP`或者`watch`文件被修改时调用`rebuild`，成功后原子地替换路由表和共享状态，
+     * 失败时保留原来的配置并输出错误。已经在处理的连接不受影响
+     ## Example
+     ```rust,ignore
+     let server = server.with_reload(Some(config_path.clone()), Box::new(move || {
+         let config = Config::load(Some(&config_path), &[]).map_err(|e| e.to_string())?;
+         let app = App::from_config(&config).map_err(|e| e.to_string())?;
+         Ok(app.into_parts())
+     }));
+     ```
+     */
+    pub fn with_reload(mut self, watch: Option<PathBuf>, rebuild: Rebuild) -> Self {
+        self.reload = Some((watch, rebuild));
+        self
+    }
+
+    /**
+     * 替换[`Server::new`]的路由表和共享状态，之后建立的连接使用新的配置
+     */
+    pub fn replace(&self, router: Router, extensions: Extensions) {
+        *self.sites[0].write().unwrap() = Arc::new(Site { router, extensions });
+    }
+
+    /**
+     * 按创建的顺序替换所有站点，数量不一致时(增删了监听器)不做任何替换
+     */
+    pub fn replace_all(&self, parts: Vec<(Router, Extensions)>) -> Result<(), String> {
+        if parts.len() != self.sites.len() {
+            return Err(format!("expected {} router(s) but got {}; adding or removing listeners requires a restart", self.sites.len(), parts.len()));
+        }
+        for (site, (router, extensions)) in self.sites.iter().zip(parts) {
+            *site.write().unwrap() = Arc::new(Site { router, extensions });
+        }
+        Ok(())
+    }
+
+    /**
+     * 立即执行一次重新加载，没有设置[`Server::with_reload`]时什么也不做
+     */
+    pub fn reload(&self) -> Result<(), String> {
+        if let Some((_, rebuild)) = &self.reload {
+            self.replace_all(rebuild()?)?;
+        }
+        Ok(())
+    }
+
+    fn site(&self, index: usize) -> Arc<Site> {
+        self.sites[index].read().unwrap().clone()
+    }
+
+    /**
+     * 启动server，开始监听所有地址并处理请求，
+     * 每个地址一个接受连接的线程，连接交给`workers`个工作线程处理。
+     * 任意地址无效或绑定失败时返回错误
+     ## Example
+     ```rust,ignore
+     server.run().unwrap();
+     ```
+     */
+    pub fn run(&self) -> io::Result<()> {
+        let mut listeners = Vec::new();
+        for binding in &self.bindings {
+            let error = |e: io::Error| io::Error::new(e.kind(), format!("cannot listen on {}: {}", binding.addr, e));
+            let addr = ListenAddr::parse(&binding.addr)
+                .map_err(|e| error(io::Error::new(io::ErrorKind::InvalidInput, e)))?;
+            let listener = Listener::bind(&addr).map_err(error)?;
+            let scheme = if binding.tls.is_some() { "https" } else { "http" };
+            crate::info!("Running on {} ({})", addr, scheme);
+            listeners.push(listener);
+        }
+
+        let (sender, receiver) = mpsc::channel::<(Connection, usize)>();
+        let receiver = Mutex::new(receiver);
+        thread::scope(|scope| {
+            for _ in 0..self.options.workers.max(1) {
+                scope.spawn(|| loop {
+                    let (stream, binding) = match receiver.lock().unwrap().recv() {
+                        Ok(conn) => conn,
+                        Err(_) => break,
+                    };
+                    self.handle_connection(stream, &self.bindings[binding]);
+                });
+            }
+            for (binding, listener) in listeners.into_iter().enumerate() {
+                let sender = sender.clone();
+                scope.spawn(move || loop {
+                    match listener.accept() {
+                        Ok(stream) => {
+                            if sender.send((stream, binding)).is_err() {
+                                break;
+                            }
+                        }
+                        Err(e) => {
+                            // 文件描述符耗尽等错误通常会持续一段时间，稍后再试
+                            crate::warn!("Failed to accept connection: {}", e);
+                            thread::sleep(Duration::from_millis(100));
+                        }
+                    }
+                });
+            }
+            drop(sender);
+
+            if let Some((watch, _)) = &self.reload {
+                let watcher = Watcher::new(watch.clone());
+                scope.spawn(move || {
+                    let mut watcher = match watcher {
+                        Ok(watcher) => watcher,
+                        Err(e) => {
+                            crate::error!("Cannot watch for configuration changes: {}", e);
+                            return;
+                        }
+                    };
+                    loop {
+                        watcher.wait();
+                        match self.reload() {
+                            Ok(()) => crate::info!("Configuration reloaded"),
+                            Err(e) => crate::error!("Reload failed, keeping previous configuration: {}", e),
+                        }
+                    }
+                });
+            }
+        });
+        Ok(())
+    }
+
+    fn handle_connection(&self, mut stream: Connection, binding: &Binding) {
+        crate::debug!("Connection established");
+        let timeout = |secs: u64| if secs == 0 { None } else { Some(Duration::from_secs(secs)) };
+        let _ = stream.set_read_timeout(timeout(self.options.read_timeout_secs));
+        let _ = stream.set_write_timeout(timeout(self.options.write_timeout_secs));
+        if let Some(tls) = &binding.tls {
+            stream = match tls.accept(stream) {
+                Ok(stream) => stream,
+                Err(e) => {
+                    crate::debug!("TLS handshake failed: {}", e);
+                    return;
+                }
+            };
+        }
+
+        if stream.alpn_protocol() == Some(b"h2") {
+            let site = self.site(binding.site);
+            h2::serve(&mut stream, Vec::new(), &site.router, &site.extensions, &self.options, &self.sessions);
+            return;
+        }
+        let mut buf = Vec::new();
+        for served in 0.. {
+            // 长连接上用`keep_alive_secs`等待下一个请求，收到数据后恢复正常的读超时
+            if served > 0 && buf.is_empty() {
+                let _ = stream.set_read_timeout(timeout(self.options.keep_alive_secs));
+                let mut chunk = [0; 4096];
+                match stream.read(&mut chunk) {
+                    Ok(n) if n > 0 => buf.extend_from_slice(&chunk[..n]),
+                    _ => return,
+                }
+                let _ = stream.set_read_timeout(timeout(self.options.read_timeout_secs));
+            }
+            let (head_len, body_len) = match read_head(&mut stream, &mut buf, &self.options) {
+                Ok(Incoming::Http1 { head, body }) => (head, body),
+                Ok(Incoming::H2(initial)) if served == 0 => {
+                    let site = self.site(binding.site);
+                    h2::serve(&mut stream, initial, &site.router, &site.extensions, &self.options, &self.sessions);
+                    return;
+                }
+                Ok(Incoming::H2(_)) => return,
+                Err(ReadError::Closed) => return,
+                Err(ReadError::Status(code)) => return send_status(&mut stream, code),
+            };
+            let site = self.site(binding.site);
+            let parse = |raw: &[u8]| {
+                let mut req: HttpRequst = String::from_utf8_lossy(raw).into_owned().into();
+                // HTTP/1.2这样更高的次版本号按1.1处理
+                if req.version == Version::Uninitialized {
+                    req.version = Version::V1_1;
+                }
+                req.extensions = site.extensions.clone();
+                req
+            };
+
+            // 客户端在等待`100 Continue`：路由和处理器先检查请求头，拒绝时不读取请求体直接回应并关闭连接
+            let head = parse(&buf[..head_len]);
+            let mut vetted = false;
+            if let Some(expect) = head.header("Expect").filter(|_| head.version == Version::V1_1) {
+                let is_head = head.method == Method::Head;
+                let rejected = if expect.eq_ignore_ascii_case("100-continue") {
+                    let mut head = head;
+                    site.router.check(&mut head)
+                } else {
+                    let text = "417 Expectation Failed".to_string();
+                    Some(HttpResponse::new("417", None, Some(ResponseBody::Text(text))))
+                };
+                if let Some(mut resp) = rejected {
+                    resp.set_version(Version::V1_1.as_str());
+                    resp.set_header("Connection", "close");
+                    let _ = if is_head { resp.send_head(&mut stream) } else { resp.send_response(&mut stream) };
+                    let _ = stream.flush();
+                    return;
+                }
+                vetted = true;
+                // 请求体已经跟着请求头到达时不需要再回应
+                if buf.len() < head_len + body_len {
+                    let _ = stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n");
+                    let _ = stream.flush();
+                }
+            }
+            let raw = match read_body(&mut stream, &mut buf, head_len, body_len) {
+                Ok(raw) => raw,
+                Err(ReadError::Closed) => return,
+                Err(ReadError::Status(code)) => return send_status(&mut stream, code),
+            };
+            let mut req = parse(&raw);
+            if vetted {
+                req.extensions.insert(Arc::new(Vetted));
+            }
+            let upgrade = Arc::new(Upgrade::default());
+            let streaming = Arc::new(Streaming::default());
+            req.extensions.insert(upgrade.clone());
+            req.extensions.insert(streaming.clone());
+            let (version, head) = (req.version, req.method == Method::Head);
+            let keep = self.options.keep_alive_secs > 0 && keep_alive(&req);
+
+            let mut resp = site.router.dispatch(req);
+            // 处理器接受了WebSocket握手：发出101后连接交给会话，会话在单独的线程中运行，不占用工作线程；
+            // 会话数已满时改为回应503
+            if resp.status_code() == "101" {
+                if let Some((limits, session)) = upgrade.take() {
+                    match self.sessions.enter(self.options.max_sessions) {
+                        Some(guard) => {
+                            let _ = resp.send_response(&mut stream);
+                            let _ = stream.flush();
+                            let _ = stream.set_read_timeout(None);
+                            thread::spawn(move || {
+                                let _guard = guard;
+                                session(WebSocket::new(stream, buf, limits))
+                            });
+                            return;
+                        }
+                        None => resp = too_many_sessions(),
+                    }
+                }
+            }
+            // 事件流：响应头之后的body由会话持续写入，没有分块编码所以以关闭连接结束
+            if resp.status_code() == "200" && !head {
+                if let Some(session) = streaming.take() {
+                    match self.sessions.enter(self.options.max_sessions) {
+                        Some(guard) => {
+                            resp.set_version(version.as_str());
+                            resp.set_header("Connection", "close");
+                            if resp.send_stream_head(&mut stream).and_then(|_| stream.flush()).is_ok() {
+                                thread::spawn(move || {
+                                    let _guard = guard;
+                                    session(Box::new(stream))
+                                });
+                            }
+                            return;
+                        }
+                        None => resp = too_many_sessions(),
+                    }
+                }
+            }
+            let keep = keep && !resp.header("Connection").is_some_and(|v| v.eq_ignore_ascii_case("close"));
+            // 响应的版本和请求一致；从不使用分块编码，所以HTTP/1.0客户端也能按Content-Length读取
+            resp.set_version(version.as_str());
+            if !keep {
+                resp.set_header("Connection", "close");
+            } else if version == Version::V1_0 {
+                resp.set_header("Connection", "keep-alive");
+            }
+            // HEAD的响应不能带body，否则长连接上的下一个请求会读错位置
+            let _ = if head { resp.send_head(&mut stream) } else { resp.send_response(&mut stream) };
+            let _ = stream.flush();
+            if !keep {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_request(stream: &mut impl Read, buf: &mut Vec<u8>, options: &ServerConfig) -> Result<Vec<u8>, ReadError> {
+        match read_head(stream, buf, options)? {
+            Incoming::Http1 { head, body } => read_body(stream, buf, head, body),
+            Incoming::H2(_) => Err(ReadError::Status("h2")),
+        }
+    }
+
+    fn read(raw: &[u8], options: &ServerConfig) -> Result<Vec<u8>, &'static str> {
+        match read_request(&mut &raw[..], &mut Vec::new(), options) {
+            Ok(raw) => Ok(raw),
+            Err(ReadError::Closed) => Err("closed"),
+            Err(ReadError::Status(code)) => Err(code),
+        }
+    }
+
+    #[test]
+    fn test_read_request_limits() {
+        let options = ServerConfig { max_header_bytes: 256, max_body_bytes: 8, ..ServerConfig::default() };
+        let raw = b"POST /api HTTP/1.1\r\nContent-Length: 5\r\n\r\nhelloEXTRA";
+        assert_eq!(read(raw, &options).unwrap(), b"POST /api HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello");
+
+        assert_eq!(read(b"POST /api HTTP/1.1\r\nContent-Length: 9\r\n\r\n", &options), Err("413"));
+        assert_eq!(read(b"POST /api HTTP/1.1\r\nContent-Length: x\r\n\r\n", &options), Err("400"));
+        assert_eq!(read(b"POST /api HTTP/1.1\r\nContent-Length: 5\r\n\r\nhel", &options), Err("400"));
+        let long = format!("GET / HTTP/1.1\r\nX-Long: {}\r\n\r\n", "a".repeat(300));
+        assert_eq!(read(long.as_bytes(), &options), Err("431"));
+        assert_eq!(read(b"", &options), Err("closed"));
+
+        assert_eq!(read(b"GET / HTTP/1.0\r\n\r\n", &options).unwrap(), b"GET / HTTP/1.0\r\n\r\n");
+        assert_eq!(read(b"GET / HTTP/2.0\r\n\r\n", &options), Err("505"));
+        assert_eq!(read(b"GET / HTTP/3\r\n\r\n", &options), Err("505"));
+        assert_eq!(read(b"GET / HTTX/1.1\r\n\r\n", &options), Err("400"));
+        assert_eq!(read(b"GET /\r\n\r\n", &options), Err("400"));
+
+        assert_eq!(read(h2::PREFACE, &options), Err("h2"));
+        assert_eq!(read(b"PRI * HTTP/2.0\r\n\r\nXX\r\n\r\n", &options), Err("400"));
+    }
+
+    #[test]
+    fn test_read_request_framing() {
+        let options = ServerConfig::default();
+        let chunked = b"POST /api HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\n\r\n";
+        assert_eq!(read(chunked, &options), Err("501"));
+        assert_eq!(read(b"POST /api HTTP/1.1\r\nContent-Length: 5\r\ntransfer-encoding: identity\r\n\r\nhello", &options), Err("501"));
+
+        assert_eq!(read(b"POST /api HTTP/1.1\r\nContent-Length: 5\r\nContent-Length: 6\r\n\r\nhello!", &options), Err("400"));
+        assert_eq!(read(b"POST /api HTTP/1.1\r\nContent-Length: 5\r\ncontent-length: 5\r\n\r\nhello", &options), Err("400"));
+        assert_eq!(read(b"POST /api HTTP/1.1\r\nContent-Length: 5, 5\r\n\r\nhello", &options), Err("400"));
+        assert_eq!(read(b"POST /api HTTP/1.1\r\nContent-Length: +5\r\n\r\nhello", &options), Err("400"));
+        assert_eq!(read(b"POST /api HTTP/1.1\r\nContent-Length:\r\n\r\n", &options), Err("400"));
+    }
+
+    #[test]
+    fn test_pipelined_requests_and_keep_alive() {
+        let options = ServerConfig::default();
+        let mut stream = &b"GET /a HTTP/1.0\r\nConnection: Keep-Alive\r\n\r\nGET /b HTTP/1.1\r\n\r\n"[..];
+        let mut buf = Vec::new();
+        let mut next = || match read_request(&mut stream, &mut buf, &options) {
+            Ok(raw) => HttpRequst::from(String::from_utf8(raw).unwrap()),
+            _ => panic!("expected a request"),
+        };
+        let (first, second) = (next(), next());
+        assert_eq!((first.resource.path(), first.version), ("/a", Version::V1_0));
+        assert_eq!((second.resource.path(), second.version), ("/b", Version::V1_1));
+        assert!(keep_alive(&first) && keep_alive(&second));
+
+        let req = |raw: &str| HttpRequst::from(raw.to_string());
+        assert!(!keep_alive(&req("GET / HTTP/1.0\r\n\r\n")));
+        assert!(!keep_alive(&req("GET / HTTP/1.1\r\nConnection: close\r\n\r\n")));
+    }
+
+    #[test]
+    fn test_reload_keeps_old_site_for_inflight() {
+        let page = |name: &'static str| {
+            move |_: &HttpRequst| HttpResponse::new("200", None, Some(ResponseBody::Text(name.into())))
+        };
+        let mut router = Router::new();
+        router.get("/", page("old"));
+        let version = Arc::new(Mutex::new(0));
+        let counter = version.clone();
+        let server = Server::new("127.0.0.1:0", router).with_reload(None, Box::new(move || {
+            let mut n = counter.lock().unwrap();
+            *n += 1;
+            if *n > 1 {
+                return Err("broken config".to_string());
+            }
+            let mut router = Router::new();
+            router.get("/", page("new"));
+            Ok(vec![(router, Extensions::default())])
+        }));
+        let body = |site: &Site| {
+            let req: HttpRequst = "GET / HTTP/1.1\r\n\r\n".to_string().into();
+            site.router.dispatch(req).body().cloned()
+        };
+
+        let inflight = server.site(0);
+        server.reload().unwrap();
+        assert_eq!(body(&inflight), Some(ResponseBody::Text("old".into())));
+        assert_eq!(body(&server.site(0)), Some(ResponseBody::Text("new".into())));
+
+        assert_eq!(server.reload(), Err("broken config".to_string()));
+        assert_eq!(body(&server.site(0)), Some(ResponseBody::Text("new".into())));
+        assert!(server.replace_all(Vec::new()).is_err());
+        assert_eq!(body(&server.site(0)), Some(ResponseBody::Text("new".into())));
+    }
+
+    #[test]
+    fn test_expect_continue() {
+        struct Upload;
+        impl crate::handler::Handler for Upload {
+            fn handle(&self, req: &HttpRequst) -> HttpResponse {
+                HttpResponse::new("200", None, Some(ResponseBody::Text(req.msg_body.clone())))
+            }
+            fn check(&self, req: &HttpRequst) -> Option<HttpResponse> {
+                match req.header("Content-Type") {
+                    Some("text/plain") => None,
+                    _ => Some(HttpResponse::new("415", None, None)),
+                }
+            }
+        }
+        let mut router = Router::new();
+        router.post("/upload", Upload);
+        router.group("/admin", |admin| {
+            admin.wrap(crate::middleware::BearerAuth { token: "secret".into() }).post("/upload", Upload);
+        });
+        let server = Arc::new(Server::new("127.0.0.1:0", router));
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                server.handle_connection(Connection::Tcp(stream), &server.bindings[0]);
+            }
+        });
+        let connect = || {
+            let client = std::net::TcpStream::connect(addr).unwrap();
+            client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            client
+        };
+        let read_until = |client: &mut std::net::TcpStream, end: &str| {
+            let mut out = Vec::new();
+            let mut byte = [0; 1];
+            while !out.ends_with(end.as_bytes()) && client.read(&mut byte).unwrap() == 1 {
+                out.push(byte[0]);
+            }
+            String::from_utf8(out).unwrap()
+        };
+
+        // 请求头通过检查后才收到100，然后再发送请求体
+        let mut client = connect();
+        client.write_all(b"POST /upload HTTP/1.1\r\nContent-Type: text/plain\r\nExpect: 100-continue\r\nContent-Length: 5\r\n\r\n").unwrap();
+        assert_eq!(read_until(&mut client, "\r\n\r\n"), "HTTP/1.1 100 Continue\r\n\r\n");
+        client.write_all(b"hello").unwrap();
+        assert!(read_until(&mut client, "hello").starts_with("HTTP/1.1 200 OK\r\n"));
+
+        // 被拒绝时不等待请求体，直接回应并关闭连接
+        client.write_all(b"POST /upload HTTP/1.1\r\nExpect: 100-continue\r\nContent-Length: 5\r\n\r\n").unwrap();
+        let mut resp = String::new();
+        client.read_to_string(&mut resp).unwrap();
+        assert!(resp.starts_with("HTTP/1.1 415 ") && resp.contains("Connection:close"), "{}", resp);
+
+        let mut client = connect();
+        client.write_all(b"POST /upload HTTP/1.1\r\nExpect: 200-ok\r\nContent-Length: 5\r\n\r\n").unwrap();
+        let mut resp = String::new();
+        client.read_to_string(&mut resp).unwrap();
+        assert!(resp.starts_with("HTTP/1.1 417 "), "{}", resp);
+
+        // 令牌不对：直接回应401，不发送100
+        let mut client = connect();
+        client.write_all(b"POST /admin/upload HTTP/1.1\r\nAuthorization: Bearer guess\r\nContent-Type: text/plain\r\n\
+                           Expect: 100-continue\r\nContent-Length: 5\r\n\r\n").unwrap();
+        let mut resp = String::new();
+        client.read_to_string(&mut resp).unwrap();
+        assert!(resp.starts_with("HTTP/1.1 401 ") && !resp.contains("100 Continue"), "{}", resp);
+
+        let mut client = connect();
+        client.write_all(b"POST /admin/upload HTTP/1.1\r\nAuthorization: Bearer secret\r\nContent-Type: text/plain\r\n\
+                           Expect: 100-continue\r\nContent-Length: 5\r\n\r\n").unwrap();
+        assert_eq!(read_until(&mut client, "\r\n\r\n"), "HTTP/1.1 100 Continue\r\n\r\n");
+        client.write_all(b"hello").unwrap();
+        assert!(read_until(&mut client, "hello").starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+
+    #[test]
+    fn test_session_limit() {
+        let mut router = Router::new();
+        router.get("/live", |req: &HttpRequst| {
+            crate::websocket::upgrade(req, Default::default(), |mut ws| while let Ok(Some(_)) = ws.recv() {})
+        });
+        router.get("/events", |req: &HttpRequst| crate::sse::stream(req, |_| ()));
+        let options = ServerConfig { max_sessions: 1, ..ServerConfig::default() };
+        let server = Arc::new(Server::new("127.0.0.1:0", router).with_options(options));
+        let sessions = server.sessions.clone();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let server = server.clone();
+                thread::spawn(move || server.handle_connection(Connection::Tcp(stream), &server.bindings[0]));
+            }
+        });
+        let open = || {
+            let mut client = std::net::TcpStream::connect(addr).unwrap();
+            client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            client.write_all(b"GET /live HTTP/1.1\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+                                Sec-WebSocket-Version: 13\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n").unwrap();
+            let mut head = [0; 12];
+            client.read_exact(&mut head).unwrap();
+            (client, String::from_utf8_lossy(&head).into_owned())
+        };
+
+        let (first, status) = open();
+        assert_eq!(status, "HTTP/1.1 101");
+        // 名额已满：不再启动会话线程
+        let (mut second, status) = open();
+        assert_eq!(status, "HTTP/1.1 503");
+        let mut resp = String::new();
+        second.read_to_string(&mut resp).unwrap();
+        assert!(resp.contains("Retry-After:5"), "{}", resp);
+        // 事件流和WebSocket共用名额
+        let mut events = std::net::TcpStream::connect(addr).unwrap();
+        events.write_all(b"GET /events HTTP/1.1\r\n\r\n").unwrap();
+        let mut resp = String::new();
+        events.read_to_string(&mut resp).unwrap();
+        assert!(resp.starts_with("HTTP/1.1 503 "), "{}", resp);
+
+        // 第一个会话结束后释放名额
+        drop(first);
+        for _ in 0..200 {
+            if sessions.active() == 0 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(open().1, "HTTP/1.1 101");
+    }
+}
@@ -0,0 +1,227 @@
+use http::httprequest::HttpRequst;
+use http::httpresponse::{HttpResponse, ResponseBody};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Write};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/**
+# Event
+事件流中的一条事件，`data`中的换行会拆成多行`data:`，
+`event`和`id`中的换行会被去掉
+```rust
+use httpserver::sse::Event;
+use std::time::Duration;
+
+let event = Event::new("line 1\nline 2").event("progress").id("42").retry(Duration::from_secs(3));
+assert_eq!(event.to_string(), "event: progress\nid: 42\nretry: 3000\ndata: line 1\ndata: line 2\n\n");
+```
+ */
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Event {
+    event: Option<String>,
+    id: Option<String>,
+    data: String,
+    retry: Option<Duration>,
+}
+
+impl Event {
+    pub fn new(data: impl Into<String>) -> Self {
+        Event { data: data.into(), ..Event::default() }
+    }
+
+    /**
+     * 事件类型，浏览器中用`addEventListener(type, ...)`接收，没有设置时为`message`
+     */
+    pub fn event(mut self, event: &str) -> Self {
+        self.event = Some(single_line(event));
+        self
+    }
+
+    /**
+     * 事件编号，断线重连时浏览器在`Last-Event-ID`中带上最后收到的编号
+     */
+    pub fn id(mut self, id: &str) -> Self {
+        self.id = Some(single_line(id).replace('\0', ""));
+        self
+    }
+
+    /**
+     * 建议浏览器断线后等待多久重连
+     */
+    pub fn retry(mut self, retry: Duration) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+}
+
+fn single_line(s: &str) -> String {
+    s.chars().filter(|&c| c != '\r' && c != '\n').collect()
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(event) = &self.event {
+            writeln!(f, "event: {}", event)?;
+        }
+        if let Some(id) = &self.id {
+            writeln!(f, "id: {}", id)?;
+        }
+        if let Some(retry) = self.retry {
+            writeln!(f, "retry: {}", retry.as_millis())?;
+        }
+        // `\r\n`、`\r`和`\n`都是换行
+        for line in self.data.split("\r\n").flat_map(|l| l.split(['\r', '\n'])) {
+            writeln!(f, "data: {}", line)?;
+        }
+        writeln!(f)
+    }
+}
+
+/**
+# EventStream
+已经发出响应头的事件流，在单独的线程中运行，返回即结束响应。
+写入失败说明客户端已经断开，会话应当结束
+```rust,ignore
+stream(req, |mut events| {
+    for percent in (0..=100).step_by(10) {
+        if events.send(&Event::new(percent.to_string()).event("progress")).is_err() {
+            return;
+        }
+        thread::sleep(Duration::from_millis(200));
+    }
+})
+```
+ */
+pub struct EventStream {
+    sink: Box<dyn Write + Send>,
+    last_event_id: Option<String>,
+}
+
+impl EventStream {
+    pub fn new(sink: Box<dyn Write + Send>, last_event_id: Option<String>) -> Self {
+        EventStream { sink, last_event_id }
+    }
+
+    /**
+     * 重连时请求头`Last-Event-ID`中的编号，用来从断开的位置继续发送
+     */
+    pub fn last_event_id(&self) -> Option<&str> {
+        self.last_event_id.as_deref()
+    }
+
+    pub fn send(&mut self, event: &Event) -> io::Result<()> {
+        self.sink.write_all(event.to_string().as_bytes())?;
+        self.sink.flush()
+    }
+
+    /**
+     * 发送一行注释，浏览器会忽略它，用来防止代理和负载均衡因为空闲断开连接，也能及时发现客户端已经断开
+     */
+    pub fn heartbeat(&mut self) -> io::Result<()> {
+        self.sink.write_all(b": heartbeat\n\n")?;
+        self.sink.flush()
+    }
+}
+
+/**
+ * 开始发送事件流的会话，参数是响应体的写入端
+ */
+pub type Session = Box<dyn FnOnce(Box<dyn Write + Send>) + Send>;
+
+/**
+ * # 事件流槽
+ * 服务端在分发请求前放进请求的扩展表，[`stream`]把会话存进来；
+ * 响应是200时服务端发出响应头，然后把响应体交给会话
+ */
+#[derive(Default)]
+pub struct Streaming {
+    session: Mutex<Option<Session>>,
+}
+
+impl Streaming {
+    pub fn take(&self) -> Option<Session> {
+        self.session.lock().unwrap().take()
+    }
+}
+
+/**
+ * # 返回`text/event-stream`响应
+ * 响应头发出后`session`在单独的线程中运行，连接在它返回之前一直保持打开。
+ * HTTP/1.x的响应体以关闭连接结束，HTTP/2上作为一个流的DATA帧发送
+ ```rust,ignore
+ router.get("/events", |req: &HttpRequst| {
+     stream(req, |mut events| {
+         let _ = events.send(&Event::new("hello"));
+     })
+ });
+ ```
+ */
+pub fn stream<F>(req: &HttpRequst, session: F) -> HttpResponse
+where
+    F: FnOnce(EventStream) + Send + 'static,
+{
+    let Some(slot) = req.state::<Streaming>() else {
+        let text = "event streams are not supported on this connection".to_string();
+        return HttpResponse::new("501", None, Some(ResponseBody::Text(text)));
+    };
+    let last_event_id = req.header("Last-Event-ID").filter(|id| !id.is_empty()).map(String::from);
+    *slot.session.lock().unwrap() = Some(Box::new(move |sink| session(EventStream::new(sink, last_event_id))));
+
+    let mut headers = HashMap::new();
+    headers.insert("Content-Type", "text/event-stream; charset=utf-8");
+    headers.insert("Cache-Control", "no-cache");
+    HttpResponse::new("200", Some(headers), None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /**
+     * 可以在会话结束后查看写入内容的`Write`
+     */
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_event_format() {
+        assert_eq!(Event::new("").to_string(), "data: \n\n");
+        assert_eq!(Event::new("a\r\nb\rc").to_string(), "data: a\ndata: b\ndata: c\n\n");
+        assert_eq!(Event::new("x").event("a\nb").id("1\r\n2").to_string(), "event: ab\nid: 12\ndata: x\n\n");
+    }
+
+    #[test]
+    fn test_stream_resumes_from_last_event_id() {
+        let mut req: HttpRequst = "GET /events HTTP/1.1\r\nLast-Event-ID: 7\r\n\r\n".to_string().into();
+        let slot = Arc::new(Streaming::default());
+        req.extensions.insert(slot.clone());
+        let resp = stream(&req, |mut events| {
+            let next: u32 = events.last_event_id().and_then(|id| id.parse::<u32>().ok()).map_or(0, |id| id + 1);
+            events.send(&Event::new("resumed").id(&next.to_string())).unwrap();
+            events.heartbeat().unwrap();
+        });
+        assert_eq!(resp.status_code(), "200");
+        assert_eq!(resp.header("Content-Type"), Some("text/event-stream; charset=utf-8"));
+
+        let out = Shared::default();
+        slot.take().unwrap()(Box::new(out.clone()));
+        assert_eq!(String::from_utf8(out.0.lock().unwrap().clone()).unwrap(), "id: 8\ndata: resumed\n\n: heartbeat\n\n");
+
+        let plain: HttpRequst = "GET /events HTTP/1.1\r\n\r\n".to_string().into();
+        assert_eq!(stream(&plain, |_| ()).status_code(), "501");
+    }
+}
@@ -0,0 +1,85 @@
+use super::cache::StaticCache;
+use super::repository::{Repository, StorageError};
+use super::websocket::{Broadcast, Message};
+use std::sync::Arc;
+
+/**
+# AppState
+服务启动时构造一次、所有请求共享的数据，通过[`Server::with_state`]注入，
+处理器中用`req.state::<AppState>()`取得
+```rust,ignore
+let characters = JsonFile::open("data/characters.json")?;
+let state = Arc::new(AppState::new(Arc::new(characters), static_cache.clone()));
+let server = Server::new("localhost:3000", router).with_state(state);
+```
+
+[`Server::with_state`]: super::server::Server::with_state
+ */
+pub struct AppState {
+    /** 角色数据的存储，`[api]`的存储配置没有变化时重新加载配置也沿用同一个 */
+    pub characters: Arc<dyn Repository>,
+    pub static_cache: Arc<StaticCache>,
+    /** 订阅角色数据变化的WebSocket连接，重新加载配置时沿用原来的订阅 */
+    pub updates: Arc<Broadcast>,
+}
+
+impl AppState {
+    /**
+     * 角色数据的存储由[`App`]按`[api]`配置选择，缓存和静态文件处理器共用
+     *
+     * [`App`]: super::app::App
+     */
+    pub fn new(characters: Arc<dyn Repository>, static_cache: Arc<StaticCache>) -> Self {
+        AppState {
+            characters,
+            static_cache,
+            updates: Arc::new(Broadcast::new()),
+        }
+    }
+
+    /**
+     * 当前所有角色的JSON
+     */
+    pub fn characters_json(&self) -> Result<String, StorageError> {
+        Ok(serde_json::to_string(&self.characters.list()?).unwrap())
+    }
+
+    /**
+     * 把当前的角色数据发送给所有订阅者，返回收到的连接数；读取失败时不发送
+     */
+    pub fn publish_characters(&self) -> usize {
+        match self.characters_json() {
+            Ok(json) => self.updates.send(Message::Text(json)),
+            Err(e) => {
+                crate::warn!("cannot publish characters: {}", e);
+                0
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::CacheOptions;
+    use crate::repository::Memory;
+    use http::httprequest::{Extensions, HttpRequst};
+
+    #[test]
+    fn test_state_lookup() {
+        let state = Arc::new(AppState::new(Arc::new(Memory::new(Vec::new())), Arc::new(StaticCache::new(CacheOptions::default()))));
+        let mut req: HttpRequst = "GET / HTTP/1.1\r\n\r\n".to_string().into();
+        assert!(req.state::<AppState>().is_none());
+
+        let mut extensions = Extensions::default();
+        extensions.insert(state.clone());
+        req.extensions = extensions.clone();
+        assert!(std::ptr::eq(req.state::<AppState>().unwrap(), state.as_ref()));
+        assert!(Arc::ptr_eq(&req.extensions.get_arc::<AppState>().unwrap(), &state));
+        // 按类型查找，没有注入的类型取不到
+        assert!(req.state::<StaticCache>().is_none());
+
+        assert_eq!(state.characters_json().unwrap(), "[]");
+        assert_eq!(state.publish_characters(), 0);
+    }
+}
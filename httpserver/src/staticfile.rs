@@ -0,0 +1,449 @@
+use super::embed;
+use http::httprequest::{percent_decode, percent_encode, Resource};
+use serde::Deserialize;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/**
+ * # 静态文件配置
+ * - `root`: 静态文件根目录，读取`PUBLIC_PATH`，默认为`<crate>/public`
+ * - `embedded`: 从编译进二进制的资源表读取文件，在开启`embed-assets`特性并且
+ *   没有设置`PUBLIC_PATH`时启用，见[`embed`]
+ * - `index`: 目录的默认页，读取`STATIC_INDEX`(逗号分隔)，默认`index.html`
+ * - `html_fallback`: 没有扩展名的路径找不到时尝试追加`.html`，
+ *   读取`HTML_FALLBACK`(`0`/`false`关闭)，默认开启
+ * - `autoindex`: 目录没有默认页时生成文件列表，读取`AUTOINDEX`，默认关闭
+ * - `show_hidden`: 文件列表中包含以`.`开头的文件，读取`AUTOINDEX_HIDDEN`，默认关闭
+ * - `symlinks`: 符号链接策略，读取`FOLLOW_SYMLINKS`，见[`SymlinkPolicy`]
+ * - `deny_hidden`: 拒绝访问路径中任意一段以`.`开头的文件(`.well-known`除外)，
+ *   例如`.env`、`.git/config`，读取`DENY_HIDDEN`，默认开启
+ * - `deny_backup`: 拒绝访问编辑器备份文件，如`*~`、`*.swp`、`*.bak`，读取`DENY_BACKUP`，默认开启
+ * - `precompressed`: 客户端支持时优先返回预压缩的`<file>.br`/`<file>.gz`，
+ *   读取`STATIC_PRECOMPRESSED`，默认关闭
+ * - `deny_status`: 被拒绝时返回的状态码，`403`或`404`，读取`DENY_STATUS`，默认`404`，
+ *   不暴露文件是否存在
+ */
+#[derive(Debug, Clone)]
+pub struct StaticOptions {
+    pub root: PathBuf,
+    pub embedded: bool,
+    pub index: Vec<String>,
+    pub html_fallback: bool,
+    pub autoindex: bool,
+    pub show_hidden: bool,
+    pub symlinks: SymlinkPolicy,
+    pub deny_hidden: bool,
+    pub deny_backup: bool,
+    pub precompressed: bool,
+    pub deny_status: &'static str,
+}
+
+/**
+ * # 符号链接策略
+ * - `Never`: 路径上出现任何符号链接都拒绝
+ * - `WithinRoot`: 允许符号链接，但解析后的真实路径必须仍在根目录内(默认)
+ * - `Always`: 不做检查，跟随所有符号链接
+ *
+ * 配置文件中写作`never`/`within-root`/`always`
+ */
+#[derive(Debug, PartialEq, Clone, Copy, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SymlinkPolicy {
+    Never,
+    WithinRoot,
+    Always,
+}
+
+impl From<&str> for SymlinkPolicy {
+    fn from(s: &str) -> SymlinkPolicy {
+        match s {
+            "0" | "false" | "no" | "never" => SymlinkPolicy::Never,
+            "1" | "true" | "yes" | "always" => SymlinkPolicy::Always,
+            _ => SymlinkPolicy::WithinRoot,
+        }
+    }
+}
+
+fn env_flag(key: &str, default: bool) -> bool {
+    env::var(key)
+        .map(|v| !matches!(v.as_str(), "" | "0" | "false" | "no" | "off"))
+        .unwrap_or(default)
+}
+
+impl StaticOptions {
+    pub fn from_env() -> Self {
+        let default_path = format!("{}/public", env!("CARGO_MANIFEST_DIR"));
+        let public_path = env::var("PUBLIC_PATH");
+        let embedded = embed::available() && public_path.is_err();
+        let root = PathBuf::from(public_path.unwrap_or(default_path));
+        let index = match env::var("STATIC_INDEX") {
+            Ok(s) => s.split(',')
+                .map(|f| f.trim().to_string())
+                .filter(|f| !f.is_empty())
+                .collect(),
+            Err(_) => vec!["index.html".to_string()],
+        };
+
+        StaticOptions {
+            root,
+            embedded,
+            index,
+            html_fallback: env_flag("HTML_FALLBACK", true),
+            autoindex: env_flag("AUTOINDEX", false),
+            show_hidden: env_flag("AUTOINDEX_HIDDEN", false),
+            symlinks: env::var("FOLLOW_SYMLINKS")
+                .map(|v| SymlinkPolicy::from(v.as_str()))
+                .unwrap_or(SymlinkPolicy::WithinRoot),
+            deny_hidden: env_flag("DENY_HIDDEN", true),
+            deny_backup: env_flag("DENY_BACKUP", true),
+            precompressed: env_flag("STATIC_PRECOMPRESSED", false),
+            deny_status: match env::var("DENY_STATUS").as_deref() {
+                Ok("403") => "403",
+                _ => "404",
+            },
+        }
+    }
+
+    /**
+     * 根目录下的相对路径是否为目录
+     */
+    pub fn is_dir(&self, rel: &str) -> bool {
+        if self.embedded {
+            embed::is_dir(rel)
+        } else {
+            self.root.join(rel).is_dir()
+        }
+    }
+
+    /**
+     * 根目录下的相对路径是否为文件
+     */
+    pub fn is_file(&self, rel: &str) -> bool {
+        if self.embedded {
+            embed::get(rel).is_some()
+        } else {
+            self.root.join(rel).is_file()
+        }
+    }
+
+    /**
+     * 文件名是否被隐藏文件或备份文件规则拒绝
+     */
+    pub fn is_denied_name(&self, name: &str) -> bool {
+        let hidden = name.starts_with('.') && name != ".well-known";
+        let backup = name.ends_with('~')
+            || (name.starts_with('#') && name.ends_with('#'))
+            || [".swp", ".swo", ".swx", ".bak", ".orig", ".tmp"]
+                .iter()
+                .any(|ext| name.ends_with(ext));
+        (self.deny_hidden && hidden) || (self.deny_backup && backup)
+    }
+
+    /**
+     * 文件名是否出现在目录列表中
+     */
+    pub fn is_listed(&self, name: &str) -> bool {
+        let hidden = name.starts_with('.');
+        !self.is_denied_name(name) && (self.show_hidden || !hidden)
+    }
+
+    /**
+     * # 符号链接检查
+     * 按照[`SymlinkPolicy`]检查根目录下的相对路径`rel`，通过时返回true
+     */
+    pub fn check_symlinks(&self, rel: &str) -> bool {
+        if self.embedded {
+            return true;
+        }
+        match self.symlinks {
+            SymlinkPolicy::Always => true,
+            SymlinkPolicy::Never => {
+                let mut path = self.root.clone();
+                rel.split('/').filter(|seg| !seg.is_empty()).all(|seg| {
+                    path.push(seg);
+                    fs::symlink_metadata(&path)
+                        .map(|meta| !meta.file_type().is_symlink())
+                        .unwrap_or(false)
+                })
+            }
+            SymlinkPolicy::WithinRoot => {
+                match (self.root.canonicalize(), self.root.join(rel).canonicalize()) {
+                    (Ok(root), Ok(path)) => path.starts_with(root),
+                    _ => false,
+                }
+            }
+        }
+    }
+}
+
+/**
+ * # 路径解析结果
+ * - `File`: 相对于根目录的文件路径，用`/`分隔
+ * - `Directory`: 没有默认页的目录(仅在`autoindex`开启时)，相对于根目录的路径
+ * - `Redirect`: 请求的是目录但没有以`/`结尾，值为`Location`
+ * - `Denied`: 被安全策略拒绝，返回`deny_status`
+ * - `NotFound`: 不存在或者路径非法
+ */
+#[derive(Debug, PartialEq)]
+pub enum Resolved {
+    File(String),
+    Directory(String),
+    Redirect(String),
+    Denied,
+    NotFound,
+}
+
+/**
+ * 把url路径拆成解码后的路径段，`.`和空段会被忽略，
+ * 出现`..`或者段内含有分隔符时视为非法路径
+ */
+fn segments(url_path: &str) -> Option<Vec<String>> {
+    let mut segs = Vec::new();
+    for raw in url_path.split('/') {
+        let seg = percent_decode(raw)?;
+        match seg.as_str() {
+            "" | "." => continue,
+            ".." => return None,
+            s if s.contains(['/', '\\', '\0']) => return None,
+            _ => segs.push(seg),
+        }
+    }
+    Some(segs)
+}
+
+/**
+ * # 静态路径解析
+ * 把请求路径映射到根目录下任意深度的文件：
+ * - 目录并且以`/`结尾：依次尝试`index`中的默认页，都没有时按`autoindex`生成列表
+ * - 目录但不以`/`结尾：301重定向到带`/`的地址
+ * - 没有扩展名且找不到文件：在`html_fallback`开启时尝试`<path>.html`
+ *
+ * 最终命中的路径还要经过隐藏文件、备份文件和符号链接检查，不通过时返回`Denied`
+ */
+pub fn resolve(resource: &Resource, opts: &StaticOptions) -> Resolved {
+    match resolve_path(resource, opts) {
+        Resolved::File(rel) | Resolved::Directory(rel)
+            if rel.split('/').any(|seg| opts.is_denied_name(seg)) || !opts.check_symlinks(&rel) =>
+        {
+            Resolved::Denied
+        }
+        resolved => resolved,
+    }
+}
+
+fn resolve_path(resource: &Resource, opts: &StaticOptions) -> Resolved {
+    let url_path = resource.path();
+    let segs = match segments(url_path) {
+        Some(segs) => segs,
+        None => return Resolved::NotFound,
+    };
+    if segs.iter().any(|seg| opts.is_denied_name(seg)) {
+        return Resolved::Denied;
+    }
+    let rel = segs.join("/");
+
+    if opts.is_dir(&rel) {
+        if !url_path.ends_with('/') {
+            // 用规范化后的路径段拼地址，原始路径`//name`会被浏览器当成另一个主机
+            let path: String = segs.iter().map(|seg| format!("/{}", percent_encode(seg))).collect();
+            let location = match resource.query() {
+                Some(q) => format!("{}/?{}", path, q),
+                None => format!("{}/", path),
+            };
+            return Resolved::Redirect(location);
+        }
+        let index = opts.index.iter()
+            .map(|index| join(&rel, index))
+            .find(|candidate| opts.is_file(candidate));
+        return match index {
+            Some(index) => Resolved::File(index),
+            None if opts.autoindex => Resolved::Directory(rel),
+            None => Resolved::NotFound,
+        };
+    }
+
+    if opts.is_file(&rel) {
+        return Resolved::File(rel);
+    }
+
+    let has_ext = Path::new(&rel).extension().is_some();
+    if opts.html_fallback && !has_ext && !rel.is_empty() {
+        let candidate = format!("{}.html", rel);
+        if opts.is_file(&candidate) {
+            return Resolved::File(candidate);
+        }
+    }
+    Resolved::NotFound
+}
+
+/**
+ * `Accept-Encoding`中是否接受`coding`，`q=0`表示明确拒绝
+ */
+pub fn accepts_encoding(accept_encoding: &str, coding: &str) -> bool {
+    accept_encoding.split(',').any(|item| {
+        let mut parts = item.split(';');
+        let name = parts.next().unwrap_or("").trim();
+        let refused = parts.any(|p| matches!(p.trim(), "q=0" | "q=0.0" | "q=0.00" | "q=0.000"));
+        name.eq_ignore_ascii_case(coding) && !refused
+    })
+}
+
+/**
+ * # 预压缩文件
+ * 根据`Accept-Encoding`查找`rel`的预压缩版本，br优先于gzip，
+ * 返回(预压缩文件的相对路径, `Content-Encoding`)
+ */
+pub fn precompressed(rel: &str, accept_encoding: &str, opts: &StaticOptions) -> Option<(String, &'static str)> {
+    if !opts.precompressed || opts.embedded {
+        return None;
+    }
+    [("br", "br"), ("gzip", "gz")].iter()
+        .filter(|(coding, _)| accepts_encoding(accept_encoding, coding))
+        .map(|(coding, ext)| (format!("{}.{}", rel, ext), *coding))
+        .find(|(candidate, _)| opts.root.join(candidate).is_file() && opts.check_symlinks(candidate))
+}
+
+fn join(dir: &str, file: &str) -> String {
+    if dir.is_empty() {
+        file.to_string()
+    } else {
+        format!("{}/{}", dir, file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /**
+     * 测试用的临时目录，每个测试一个，结束时(包括断言失败)删除
+     */
+    pub(super) struct TempRoot(pub PathBuf);
+
+    impl TempRoot {
+        pub fn new(name: &str) -> TempRoot {
+            let path = env::temp_dir().join(format!("httpserver-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            TempRoot(path)
+        }
+    }
+
+    impl Drop for TempRoot {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /**
+     * 显式给出所有选项，不受运行测试时的环境变量影响
+     */
+    pub(super) fn options(root: PathBuf) -> StaticOptions {
+        StaticOptions {
+            root,
+            embedded: false,
+            index: vec!["index.html".to_string()],
+            html_fallback: true,
+            autoindex: false,
+            show_hidden: false,
+            symlinks: SymlinkPolicy::WithinRoot,
+            deny_hidden: true,
+            deny_backup: true,
+            precompressed: false,
+            deny_status: "404",
+        }
+    }
+
+    fn fixture(name: &str) -> (TempRoot, StaticOptions) {
+        let dir = TempRoot::new(name);
+        let root = dir.0.clone();
+        fs::create_dir_all(root.join("docs/img")).unwrap();
+        fs::write(root.join("index.html"), "home").unwrap();
+        fs::write(root.join("about.html"), "about").unwrap();
+        fs::write(root.join("docs/index.html"), "docs").unwrap();
+        fs::write(root.join("docs/img/hutao.jpg"), "jpg").unwrap();
+        (dir, options(root))
+    }
+
+    fn resolve_url(path: &str, opts: &StaticOptions) -> Resolved {
+        resolve(&Resource::Path(path.to_string()), opts)
+    }
+
+    #[test]
+    fn test_resolve_nested_and_index() {
+        let (_dir, opts) = fixture("nested");
+        assert_eq!(resolve_url("/", &opts), Resolved::File("index.html".into()));
+        assert_eq!(resolve_url("/docs/", &opts), Resolved::File("docs/index.html".into()));
+        assert_eq!(resolve_url("/docs/img/hutao.jpg", &opts), Resolved::File("docs/img/hutao.jpg".into()));
+        assert_eq!(resolve_url("/docs?x=1", &opts), Resolved::Redirect("/docs/?x=1".into()));
+        assert_eq!(resolve_url("//docs", &opts), Resolved::Redirect("/docs/".into()));
+        assert_eq!(resolve_url("/./docs/img", &opts), Resolved::Redirect("/docs/img/".into()));
+        assert_eq!(resolve_url("/docs/img/", &opts), Resolved::NotFound);
+        let autoindex = StaticOptions { autoindex: true, ..opts.clone() };
+        assert_eq!(resolve_url("/docs/img/", &autoindex), Resolved::Directory("docs/img".into()));
+        assert_eq!(resolve_url("/docs/../../etc/passwd", &opts), Resolved::NotFound);
+        assert_eq!(resolve_url("/docs/%2e%2e/index.html", &opts), Resolved::NotFound);
+    }
+
+    #[test]
+    fn test_precompressed() {
+        let (_dir, mut opts) = fixture("precompressed");
+        fs::write(opts.root.join("about.html.gz"), "gz").unwrap();
+        assert_eq!(precompressed("about.html", "gzip, br", &opts), None);
+        opts.precompressed = true;
+        assert_eq!(precompressed("about.html", "gzip, br", &opts), Some(("about.html.gz".into(), "gzip")));
+        assert_eq!(precompressed("about.html", "gzip;q=0, br", &opts), None);
+        assert_eq!(precompressed("index.html", "gzip", &opts), None);
+    }
+
+    #[test]
+    fn test_resolve_html_fallback() {
+        let (_dir, mut opts) = fixture("fallback");
+        assert_eq!(resolve_url("/about", &opts), Resolved::File("about.html".into()));
+        opts.html_fallback = false;
+        assert_eq!(resolve_url("/about", &opts), Resolved::NotFound);
+    }
+}
+
+#[cfg(all(test, unix))]
+mod policy_tests {
+    use super::*;
+    use super::tests::{options, TempRoot};
+    use std::os::unix::fs::symlink;
+
+    #[test]
+    fn test_hidden_backup_and_symlinks() {
+        let dir = TempRoot::new("policy");
+        let base = &dir.0;
+        let root = base.join("public");
+        fs::create_dir_all(root.join(".git")).unwrap();
+        fs::create_dir_all(root.join(".well-known")).unwrap();
+        fs::write(root.join(".env"), "SECRET=1").unwrap();
+        fs::write(root.join(".git/config"), "").unwrap();
+        fs::write(root.join(".well-known/security.txt"), "").unwrap();
+        fs::write(root.join("index.html~"), "").unwrap();
+        fs::write(root.join("page.html"), "").unwrap();
+        fs::write(base.join("outside.txt"), "").unwrap();
+        symlink(base.join("outside.txt"), root.join("escape.txt")).unwrap();
+        symlink(root.join("page.html"), root.join("alias.html")).unwrap();
+
+        let mut opts = options(root);
+        let get = |path: &str, opts: &StaticOptions| resolve(&Resource::Path(path.to_string()), opts);
+
+        assert_eq!(get("/.env", &opts), Resolved::Denied);
+        assert_eq!(get("/.git/config", &opts), Resolved::Denied);
+        assert_eq!(get("/index.html~", &opts), Resolved::Denied);
+        assert_eq!(get("/.well-known/security.txt", &opts), Resolved::File(".well-known/security.txt".into()));
+        assert_eq!(get("/escape.txt", &opts), Resolved::Denied);
+        assert_eq!(get("/alias.html", &opts), Resolved::File("alias.html".into()));
+
+        opts.symlinks = SymlinkPolicy::Never;
+        assert_eq!(get("/alias.html", &opts), Resolved::Denied);
+        opts.symlinks = SymlinkPolicy::Always;
+        assert_eq!(get("/escape.txt", &opts), Resolved::File("escape.txt".into()));
+        opts.deny_hidden = false;
+        assert_eq!(get("/.env", &opts), Resolved::File(".env".into()));
+    }
+}
@@ -0,0 +1,290 @@
+use super::config::{ConfigError, TlsConfig};
+use super::handler::Handler;
+use super::listener::Connection;
+use http::httprequest::{HttpRequst, Resource};
+use http::httpresponse::{HttpResponse, ResponseBody};
+use std::io;
+
+#[cfg(feature = "tls")]
+use rustls::server::{ClientHello, ResolvesServerCert};
+#[cfg(feature = "tls")]
+use rustls::sign::CertifiedKey;
+#[cfg(feature = "tls")]
+use std::path::Path;
+#[cfg(feature = "tls")]
+use std::sync::Arc;
+
+/**
+ * 是否编译了`tls`特性
+ */
+pub fn available() -> bool {
+    cfg!(feature = "tls")
+}
+
+/**
+# TlsAcceptor
+按[`TlsConfig`]加载好的证书和rustls配置，用于把接受的连接包装成TLS连接。
+没有编译`tls`特性时[`TlsAcceptor::from_config`]总是返回错误
+```rust,ignore
+let acceptor = TlsAcceptor::from_config(config.tls.as_ref().unwrap())?;
+let server = Server::new("[::]:443", router).with_tls(acceptor);
+```
+ */
+#[derive(Clone)]
+pub struct TlsAcceptor {
+    #[cfg(feature = "tls")]
+    config: Arc<rustls::ServerConfig>,
+}
+
+impl TlsAcceptor {
+    #[cfg(feature = "tls")]
+    pub fn from_config(tls: &TlsConfig) -> Result<TlsAcceptor, ConfigError> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let default = load_key(&provider, &tls.cert, &tls.key).map_err(|e| ConfigError::invalid("tls", e))?;
+        let mut names = Vec::new();
+        for (i, sni) in tls.sni.iter().enumerate() {
+            let key = load_key(&provider, &sni.cert, &sni.key).map_err(|e| ConfigError::invalid(format!("tls.sni[{}]", i), e))?;
+            for name in &sni.names {
+                names.push((name.to_ascii_lowercase(), key.clone()));
+            }
+        }
+
+        let mut config = rustls::ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .map_err(|e| ConfigError::invalid("tls", e.to_string()))?
+            .with_no_client_auth()
+            .with_cert_resolver(Arc::new(SniResolver { default, names }));
+        config.alpn_protocols = tls.alpn.iter().map(|p| p.as_bytes().to_vec()).collect();
+        Ok(TlsAcceptor { config: Arc::new(config) })
+    }
+
+    #[cfg(not(feature = "tls"))]
+    pub fn from_config(_tls: &TlsConfig) -> Result<TlsAcceptor, ConfigError> {
+        Err(ConfigError::invalid("tls", "this binary was built without the `tls` feature"))
+    }
+
+    /**
+     * 在`conn`上完成TLS握手，握手使用连接已经设置的读写超时
+     */
+    #[cfg(feature = "tls")]
+    pub fn accept(&self, conn: Connection) -> io::Result<Connection> {
+        let session = rustls::ServerConnection::new(self.config.clone()).map_err(io::Error::other)?;
+        let mut stream = rustls::StreamOwned::new(session, conn);
+        while stream.conn.is_handshaking() {
+            stream.conn.complete_io(&mut stream.sock)?;
+        }
+        Ok(Connection::Tls(Box::new(stream)))
+    }
+
+    #[cfg(not(feature = "tls"))]
+    pub fn accept(&self, _conn: Connection) -> io::Result<Connection> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "this binary was built without the `tls` feature"))
+    }
+}
+
+/**
+ * 读取PEM格式的证书链和私钥，并检查两者是否匹配
+ */
+#[cfg(feature = "tls")]
+fn load_key(provider: &rustls::crypto::CryptoProvider, cert: &Path, key: &Path) -> Result<Arc<CertifiedKey>, String> {
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+
+    let certs = CertificateDer::pem_file_iter(cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| format!("cannot read certificates from {}: {}", cert.display(), e))?;
+    if certs.is_empty() {
+        return Err(format!("{} does not contain any certificate", cert.display()));
+    }
+    let private = PrivateKeyDer::from_pem_file(key)
+        .map_err(|e| format!("cannot read private key from {}: {}", key.display(), e))?;
+    CertifiedKey::from_der(certs, private, provider)
+        .map(Arc::new)
+        .map_err(|e| format!("{} and {}: {}", cert.display(), key.display(), e))
+}
+
+/**
+ * # 按SNI选择证书
+ * 先精确匹配，再匹配`*.example.com`形式的通配符(只匹配一级)，都没有时使用默认证书
+ */
+#[cfg(feature = "tls")]
+#[derive(Debug)]
+struct SniResolver {
+    default: Arc<CertifiedKey>,
+    names: Vec<(String, Arc<CertifiedKey>)>,
+}
+
+#[cfg(feature = "tls")]
+impl SniResolver {
+    fn find(&self, server_name: &str) -> Option<&Arc<CertifiedKey>> {
+        let name = server_name.to_ascii_lowercase();
+        let exact = self.names.iter().find(|(pattern, _)| *pattern == name);
+        let wildcard = || self.names.iter().find(|(pattern, _)| {
+            match (pattern.strip_prefix("*."), name.split_once('.')) {
+                (Some(suffix), Some((_, rest))) => suffix == rest,
+                _ => false,
+            }
+        });
+        exact.or_else(wildcard).map(|(_, key)| key)
+    }
+}
+
+#[cfg(feature = "tls")]
+impl ResolvesServerCert for SniResolver {
+    fn resolve(&self, hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let key = hello.server_name().and_then(|name| self.find(name)).unwrap_or(&self.default);
+        Some(key.clone())
+    }
+}
+
+/**
+# HttpsRedirect
+把明文请求永久跳转(308)到同一主机的HTTPS地址，保留路径和查询串，
+`port`为HTTPS监听的端口，443时不写进地址
+```rust,ignore
+let mut router = Router::new();
+router.not_found(HttpsRedirect::new(8443));
+```
+ */
+pub struct HttpsRedirect {
+    port: u16,
+}
+
+impl HttpsRedirect {
+    pub fn new(port: u16) -> Self {
+        HttpsRedirect { port }
+    }
+}
+
+/**
+ * 校验`Host`请求头是`host[:port]`，返回去掉端口后的主机。
+ * 主机只能是域名、IPv4地址或方括号中的IPv6地址，带`/`、`@`、空白等字符时返回`None`，
+ * 避免把请求跳转到客户端构造的其它地址
+ */
+fn host_without_port(host: &str) -> Option<&str> {
+    let (name, port) = match host.strip_prefix('[') {
+        Some(rest) => {
+            let (literal, after) = rest.split_once(']')?;
+            if literal.is_empty() || !literal.chars().all(|c| c.is_ascii_hexdigit() || c == ':' || c == '.') {
+                return None;
+            }
+            let port = if after.is_empty() { "" } else { after.strip_prefix(':')? };
+            (&host[..literal.len() + 2], port)
+        }
+        None => {
+            let (name, port) = host.split_once(':').unwrap_or((host, ""));
+            if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | '_')) {
+                return None;
+            }
+            (name, port)
+        }
+    };
+    (port.len() <= 5 && port.chars().all(|c| c.is_ascii_digit())).then_some(name)
+}
+
+impl Handler for HttpsRedirect {
+    fn handle(&self, req: &HttpRequst) -> HttpResponse {
+        let host = match req.header("Host").and_then(host_without_port) {
+            Some(host) => host,
+            None => return HttpResponse::new("400", None, Some(ResponseBody::Text("400 Bad Request".into()))),
+        };
+        let port = if self.port == 443 { String::new() } else { format!(":{}", self.port) };
+        let Resource::Path(target) = &req.resource;
+        let mut resp = HttpResponse::new("308", None, None);
+        resp.set_header("Location", &format!("https://{}{}{}", host, port, target));
+        resp
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_https_redirect() {
+        let redirect = |port: u16, raw: &str| {
+            let resp = HttpsRedirect::new(port).handle(&raw.to_string().into());
+            (resp.status_code().to_string(), resp.header("Location").map(String::from))
+        };
+        assert_eq!(
+            redirect(443, "GET /a?b=1 HTTP/1.1\r\nHost: example.com:80\r\n\r\n"),
+            ("308".to_string(), Some("https://example.com/a?b=1".into()))
+        );
+        assert_eq!(
+            redirect(8443, "GET / HTTP/1.1\r\nHost: [::1]:8080\r\n\r\n"),
+            ("308".to_string(), Some("https://[::1]:8443/".into()))
+        );
+        assert_eq!(redirect(443, "GET / HTTP/1.1\r\n\r\n").0, "400");
+        // 只接受`host[:port]`，不能借跳转把客户端带到其它地址
+        for host in ["evil.com/x", "user@evil.com", "a b", "example.com:80x", "[::1", "[::1]x", "[evil.com]", ":80", "exa\\mple.com"] {
+            assert_eq!(redirect(443, &format!("GET / HTTP/1.1\r\nHost: {}\r\n\r\n", host)), ("400".to_string(), None), "{}", host);
+        }
+        assert_eq!(redirect(443, "GET / HTTP/1.1\r\nHost: [::1]\r\n\r\n").1, Some("https://[::1]/".into()));
+    }
+
+    #[cfg(feature = "tls")]
+    #[test]
+    fn test_sni_and_alpn() {
+        use super::super::config::SniCert;
+        use std::env;
+        use std::fs;
+        use std::io::{Read, Write};
+        use std::net::{TcpListener, TcpStream};
+        use std::thread;
+
+        let dir = env::temp_dir().join(format!("httpserver-tls-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut roots = rustls::RootCertStore::empty();
+        let mut generate = |name: &str| {
+            let rcgen::CertifiedKey { cert, key_pair } = rcgen::generate_simple_self_signed(vec![name.to_string()]).unwrap();
+            roots.add(cert.der().clone()).unwrap();
+            let (cert_path, key_path) = (dir.join(format!("{}.crt", name)), dir.join(format!("{}.key", name)));
+            fs::write(&cert_path, cert.pem()).unwrap();
+            fs::write(&key_path, key_pair.serialize_pem()).unwrap();
+            (cert_path, key_path)
+        };
+        let (cert, key) = generate("localhost");
+        let (other_cert, other_key) = generate("api.example.test");
+        let config = TlsConfig {
+            cert,
+            key,
+            sni: vec![SniCert { names: vec!["*.example.test".into()], cert: other_cert, key: other_key }],
+            alpn: vec!["http/1.1".into()],
+        };
+        let acceptor = TlsAcceptor::from_config(&config).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            for _ in 0..2 {
+                let (stream, _) = listener.accept().unwrap();
+                let mut conn = acceptor.accept(Connection::Tcp(stream)).unwrap();
+                let mut buf = [0; 4];
+                conn.read_exact(&mut buf).unwrap();
+                conn.write_all(&buf).unwrap();
+                conn.flush().unwrap();
+            }
+        });
+
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let mut client_config = rustls::ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        client_config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        let client_config = Arc::new(client_config);
+        // 证书与主机名不匹配时握手会失败，所以两个名字都能连通说明选对了证书
+        for name in ["localhost", "api.example.test"] {
+            let session = rustls::ClientConnection::new(client_config.clone(), name.to_string().try_into().unwrap()).unwrap();
+            let mut stream = rustls::StreamOwned::new(session, TcpStream::connect(addr).unwrap());
+            stream.write_all(b"ping").unwrap();
+            let mut buf = [0; 4];
+            stream.read_exact(&mut buf).unwrap();
+            assert_eq!(&buf, b"ping");
+            assert_eq!(stream.conn.alpn_protocol(), Some(&b"http/1.1"[..]));
+        }
+        server.join().unwrap();
+        let _ = fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,818 @@
+use super::listener::Connection;
+use http::httprequest::{HttpRequst, Method, Version};
+use http::httpresponse::{HttpResponse, ResponseBody};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/**
+ * 计算`Sec-WebSocket-Accept`时拼接在key后面的固定串，见RFC 6455 1.3
+ */
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/**
+ * 等待对端数据时检查待发送消息的间隔
+ */
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/**
+ * 主动关闭后等待对端回应Close帧的时间
+ */
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+const CONTINUATION: u8 = 0x0;
+const TEXT: u8 = 0x1;
+const BINARY: u8 = 0x2;
+const CLOSE: u8 = 0x8;
+const PING: u8 = 0x9;
+const PONG: u8 = 0xa;
+
+/** 正常关闭 */
+pub const NORMAL_CLOSURE: u16 = 1000;
+/** 服务端下线或者长时间没有回应 */
+pub const GOING_AWAY: u16 = 1001;
+/** 帧格式或者帧序列不符合协议 */
+pub const PROTOCOL_ERROR: u16 = 1002;
+/** 文本消息不是合法的UTF-8 */
+pub const INVALID_DATA: u16 = 1007;
+/** 帧或消息超过[`Limits`] */
+pub const MESSAGE_TOO_BIG: u16 = 1009;
+
+/**
+ * # WebSocket的大小、心跳和来源限制
+ * - `max_frame_bytes`: 单个帧载荷的最大字节数
+ * - `max_message_bytes`: 分片合并后整条消息的最大字节数
+ * - `ping_interval`: 这么久没有收到任何帧时发送Ping，再过同样的时间仍然没有回应就断开连接
+ * - `origins`: 除同源页面之外还允许握手的`Origin`，例如`https://example.com`，`*`表示任意来源
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Limits {
+    pub max_frame_bytes: usize,
+    pub max_message_bytes: usize,
+    pub ping_interval: Duration,
+    pub origins: Vec<String>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_frame_bytes: 64 * 1024,
+            max_message_bytes: 1024 * 1024,
+            ping_interval: Duration::from_secs(30),
+            origins: Vec::new(),
+        }
+    }
+}
+
+/**
+ * 一条完整的消息，分片、Ping/Pong和Close由[`WebSocket`]处理，不会交给处理器
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+#[derive(Debug)]
+pub enum WsError {
+    Io(io::Error),
+    /** 对端违反了协议，已经用对应的关闭码关闭连接 */
+    Protocol(u16, &'static str),
+    /** 连接已经关闭 */
+    Closed,
+}
+
+impl fmt::Display for WsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WsError::Io(e) => write!(f, "{}", e),
+            WsError::Protocol(code, reason) => write!(f, "{} ({})", reason, code),
+            WsError::Closed => write!(f, "connection closed"),
+        }
+    }
+}
+
+impl From<io::Error> for WsError {
+    fn from(e: io::Error) -> Self {
+        WsError::Io(e)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Frame {
+    fin: bool,
+    opcode: u8,
+    masked: bool,
+    payload: Vec<u8>,
+}
+
+/**
+ * # 解析一个帧
+ * 数据不够一个完整的帧时返回`None`，载荷超过`max`时不等数据读完就返回错误。
+ * 带掩码的载荷在这里还原
+ */
+fn decode_frame(buf: &[u8], max: usize) -> Result<Option<(Frame, usize)>, WsError> {
+    if buf.len() < 2 {
+        return Ok(None);
+    }
+    if buf[0] & 0x70 != 0 {
+        return Err(WsError::Protocol(PROTOCOL_ERROR, "reserved bits are set"));
+    }
+    let fin = buf[0] & 0x80 != 0;
+    let opcode = buf[0] & 0x0f;
+    let masked = buf[1] & 0x80 != 0;
+    let (len, mut pos) = match buf[1] & 0x7f {
+        126 if buf.len() < 4 => return Ok(None),
+        126 => (u16::from_be_bytes([buf[2], buf[3]]) as u64, 4),
+        127 if buf.len() < 10 => return Ok(None),
+        127 => (u64::from_be_bytes(buf[2..10].try_into().unwrap()), 10),
+        len => (len as u64, 2),
+    };
+    if opcode & 0x8 != 0 && (!fin || len > 125) {
+        return Err(WsError::Protocol(PROTOCOL_ERROR, "invalid control frame"));
+    }
+    if len > max as u64 {
+        return Err(WsError::Protocol(MESSAGE_TOO_BIG, "frame is too large"));
+    }
+    let len = len as usize;
+    let mask = if masked {
+        if buf.len() < pos + 4 {
+            return Ok(None);
+        }
+        pos += 4;
+        Some([buf[pos - 4], buf[pos - 3], buf[pos - 2], buf[pos - 1]])
+    } else {
+        None
+    };
+    if buf.len() < pos + len {
+        return Ok(None);
+    }
+    let mut payload = buf[pos..pos + len].to_vec();
+    if let Some(mask) = mask {
+        apply_mask(&mut payload, mask);
+    }
+    Ok(Some((Frame { fin, opcode, masked, payload }, pos + len)))
+}
+
+/**
+ * 编码一个帧，服务端发送的帧不带掩码(`mask`为`None`)
+ */
+fn encode_frame(fin: bool, opcode: u8, payload: &[u8], mask: Option<[u8; 4]>) -> Vec<u8> {
+    let mut out = Vec::with_capacity(payload.len() + 14);
+    out.push(if fin { 0x80 } else { 0 } | opcode);
+    let mask_bit = if mask.is_some() { 0x80 } else { 0 };
+    match payload.len() {
+        len @ 0..=125 => out.push(mask_bit | len as u8),
+        len @ 126..=0xffff => {
+            out.push(mask_bit | 126);
+            out.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            out.push(mask_bit | 127);
+            out.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    let start = out.len();
+    if let Some(mask) = mask {
+        out.extend_from_slice(&mask);
+    }
+    out.extend_from_slice(payload);
+    if let Some(mask) = mask {
+        apply_mask(&mut out[start + 4..], mask);
+    }
+    out
+}
+
+fn apply_mask(data: &mut [u8], mask: [u8; 4]) {
+    for (i, b) in data.iter_mut().enumerate() {
+        *b ^= mask[i % 4];
+    }
+}
+
+/**
+ * 解析Close帧的载荷：没有载荷时关闭码为空；关闭码必须是可以出现在帧中的值，原因必须是UTF-8
+ */
+fn parse_close(payload: &[u8]) -> Result<Option<u16>, WsError> {
+    if payload.is_empty() {
+        return Ok(None);
+    }
+    if payload.len() < 2 {
+        return Err(WsError::Protocol(PROTOCOL_ERROR, "invalid close payload"));
+    }
+    let code = u16::from_be_bytes([payload[0], payload[1]]);
+    let valid = matches!(code, 1000..=1003 | 1007..=1014 | 3000..=4999);
+    if !valid {
+        return Err(WsError::Protocol(PROTOCOL_ERROR, "invalid close code"));
+    }
+    if std::str::from_utf8(&payload[2..]).is_err() {
+        return Err(WsError::Protocol(INVALID_DATA, "close reason is not valid UTF-8"));
+    }
+    Ok(Some(code))
+}
+
+/**
+# WebSocket
+升级完成后的连接，以消息为单位收发。[`WebSocket::recv`]等待对端消息的同时会发送
+其他线程通过[`WebSocket::sender`]或[`Broadcast`]投递的消息，并自动回应Ping、
+发送心跳和完成关闭握手；离开作用域时以1000正常关闭
+```rust,ignore
+upgrade(req, Limits::default(), |mut ws| {
+    while let Ok(Some(msg)) = ws.recv() {
+        if ws.send(msg).is_err() {
+            break;
+        }
+    }
+})
+```
+ */
+pub struct WebSocket {
+    io: Connection,
+    buf: Vec<u8>,
+    limits: Limits,
+    fragments: Option<(u8, Vec<u8>)>,
+    outbox: Receiver<Message>,
+    sender: Sender<Message>,
+    last_seen: Instant,
+    pinged: bool,
+    closed: bool,
+}
+
+impl WebSocket {
+    /**
+     * `buf`是握手请求之后已经读到的数据
+     */
+    pub fn new(io: Connection, buf: Vec<u8>, limits: Limits) -> Self {
+        let (sender, outbox) = mpsc::channel();
+        WebSocket {
+            io,
+            buf,
+            limits,
+            fragments: None,
+            outbox,
+            sender,
+            last_seen: Instant::now(),
+            pinged: false,
+            closed: false,
+        }
+    }
+
+    /**
+     * 可以在其他线程使用的发送端，消息在本连接下一次[`WebSocket::recv`]时发出
+     */
+    pub fn sender(&self) -> Sender<Message> {
+        self.sender.clone()
+    }
+
+    /**
+     * 等待下一条消息，对端关闭后返回`Ok(None)`。
+     * 对端违反协议时先用对应的关闭码关闭连接再返回错误
+     */
+    pub fn recv(&mut self) -> Result<Option<Message>, WsError> {
+        if self.closed {
+            return Ok(None);
+        }
+        let result = self.next_message();
+        match &result {
+            Err(WsError::Protocol(code, reason)) => {
+                let _ = self.write_close(*code, reason);
+                self.closed = true;
+            }
+            Err(_) => self.closed = true,
+            Ok(_) => (),
+        }
+        result
+    }
+
+    pub fn send(&mut self, msg: Message) -> Result<(), WsError> {
+        if self.closed {
+            return Err(WsError::Closed);
+        }
+        let result = match &msg {
+            Message::Text(text) => self.write_frame(TEXT, text.as_bytes()),
+            Message::Binary(data) => self.write_frame(BINARY, data),
+        };
+        if result.is_err() {
+            self.closed = true;
+        }
+        Ok(result?)
+    }
+
+    /**
+     * 发送Close帧并等待对端回应，最多等待[`CLOSE_TIMEOUT`]
+     */
+    pub fn close(&mut self, code: u16, reason: &str) -> Result<(), WsError> {
+        if self.closed {
+            return Ok(());
+        }
+        self.closed = true;
+        self.write_close(code, reason)?;
+        let _ = self.io.set_read_timeout(Some(CLOSE_TIMEOUT));
+        let deadline = Instant::now() + CLOSE_TIMEOUT;
+        let mut chunk = [0; 4096];
+        while Instant::now() < deadline {
+            while let Some((frame, used)) = decode_frame(&self.buf, self.limits.max_frame_bytes)? {
+                self.buf.drain(..used);
+                if frame.opcode == CLOSE {
+                    return Ok(());
+                }
+            }
+            match self.io.read(&mut chunk)? {
+                0 => break,
+                n => self.buf.extend_from_slice(&chunk[..n]),
+            }
+        }
+        Ok(())
+    }
+
+    fn next_message(&mut self) -> Result<Option<Message>, WsError> {
+        let _ = self.io.set_read_timeout(Some(POLL_INTERVAL));
+        let mut chunk = [0; 4096];
+        loop {
+            while let Ok(msg) = self.outbox.try_recv() {
+                self.send(msg)?;
+            }
+            while let Some((frame, used)) = decode_frame(&self.buf, self.limits.max_frame_bytes)? {
+                self.buf.drain(..used);
+                self.last_seen = Instant::now();
+                self.pinged = false;
+                let msg = self.on_frame(frame)?;
+                if self.closed {
+                    return Ok(None);
+                }
+                if msg.is_some() {
+                    return Ok(msg);
+                }
+            }
+            match self.io.read(&mut chunk) {
+                Ok(0) => return Err(WsError::Io(io::ErrorKind::UnexpectedEof.into())),
+                Ok(n) => self.buf.extend_from_slice(&chunk[..n]),
+                Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+                    self.keep_alive()?;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    /**
+     * 空闲超过`ping_interval`时发送Ping，Ping之后再空闲同样的时间就认为连接已经断开
+     */
+    fn keep_alive(&mut self) -> Result<(), WsError> {
+        let idle = self.last_seen.elapsed();
+        if idle >= self.limits.ping_interval * 2 {
+            return Err(WsError::Io(io::ErrorKind::TimedOut.into()));
+        }
+        if idle >= self.limits.ping_interval && !self.pinged {
+            self.write_frame(PING, b"")?;
+            self.pinged = true;
+        }
+        Ok(())
+    }
+
+    fn on_frame(&mut self, frame: Frame) -> Result<Option<Message>, WsError> {
+        if !frame.masked {
+            return Err(WsError::Protocol(PROTOCOL_ERROR, "client frames must be masked"));
+        }
+        match frame.opcode {
+            PING => self.write_frame(PONG, &frame.payload)?,
+            PONG => (),
+            CLOSE => {
+                // 回应同样的关闭码，然后关闭连接
+                let code = parse_close(&frame.payload)?;
+                let payload = code.map(|c| c.to_be_bytes().to_vec()).unwrap_or_default();
+                self.write_frame(CLOSE, &payload)?;
+                self.closed = true;
+            }
+            TEXT | BINARY => {
+                if self.fragments.is_some() {
+                    return Err(WsError::Protocol(PROTOCOL_ERROR, "expected a continuation frame"));
+                }
+                if frame.fin {
+                    return self.message(frame.opcode, frame.payload).map(Some);
+                }
+                self.check_size(frame.payload.len())?;
+                self.fragments = Some((frame.opcode, frame.payload));
+            }
+            CONTINUATION => {
+                let Some((opcode, mut data)) = self.fragments.take() else {
+                    return Err(WsError::Protocol(PROTOCOL_ERROR, "unexpected continuation frame"));
+                };
+                data.extend_from_slice(&frame.payload);
+                if frame.fin {
+                    return self.message(opcode, data).map(Some);
+                }
+                self.check_size(data.len())?;
+                self.fragments = Some((opcode, data));
+            }
+            _ => return Err(WsError::Protocol(PROTOCOL_ERROR, "unknown opcode")),
+        }
+        Ok(None)
+    }
+
+    fn check_size(&self, len: usize) -> Result<(), WsError> {
+        if len > self.limits.max_message_bytes {
+            return Err(WsError::Protocol(MESSAGE_TOO_BIG, "message is too large"));
+        }
+        Ok(())
+    }
+
+    fn message(&self, opcode: u8, data: Vec<u8>) -> Result<Message, WsError> {
+        self.check_size(data.len())?;
+        if opcode == BINARY {
+            return Ok(Message::Binary(data));
+        }
+        String::from_utf8(data)
+            .map(Message::Text)
+            .map_err(|_| WsError::Protocol(INVALID_DATA, "text message is not valid UTF-8"))
+    }
+
+    fn write_frame(&mut self, opcode: u8, payload: &[u8]) -> io::Result<()> {
+        self.io.write_all(&encode_frame(true, opcode, payload, None))?;
+        self.io.flush()
+    }
+
+    fn write_close(&mut self, code: u16, reason: &str) -> io::Result<()> {
+        let mut payload = code.to_be_bytes().to_vec();
+        payload.extend_from_slice(reason.as_bytes());
+        payload.truncate(125);
+        self.write_frame(CLOSE, &payload)
+    }
+}
+
+impl Drop for WebSocket {
+    fn drop(&mut self) {
+        let _ = self.close(NORMAL_CLOSURE, "");
+    }
+}
+
+/**
+# Broadcast
+向所有订阅的连接发送同一条消息，连接关闭后自动退订
+```rust,ignore
+let updates = Arc::new(Broadcast::new());
+// 处理器中
+updates.subscribe(&ws);
+// 数据变化时
+updates.send(Message::Text(json));
+```
+ */
+#[derive(Default)]
+pub struct Broadcast {
+    subscribers: Mutex<Vec<Sender<Message>>>,
+}
+
+impl Broadcast {
+    pub fn new() -> Self {
+        Broadcast::default()
+    }
+
+    pub fn subscribe(&self, ws: &WebSocket) {
+        self.subscribers.lock().unwrap().push(ws.sender());
+    }
+
+    /**
+     * 不通过WebSocket订阅，例如事件流，返回的接收端被丢弃后自动退订
+     */
+    pub fn receiver(&self) -> Receiver<Message> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers.lock().unwrap().push(sender);
+        receiver
+    }
+
+    /**
+     * 发送给所有仍然打开的连接，返回收到消息的连接数
+     */
+    pub fn send(&self, msg: Message) -> usize {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        subscribers.retain(|s| s.send(msg.clone()).is_ok());
+        subscribers.len()
+    }
+}
+
+/**
+ * 升级后运行的会话，在单独的线程中执行
+ */
+pub type Session = Box<dyn FnOnce(WebSocket) + Send>;
+
+/**
+ * # 升级槽
+ * 服务端在分发每个HTTP/1.1请求前放进请求的扩展表，[`upgrade`]把会话存进来；
+ * 响应是101时服务端取出会话，把连接交给它
+ */
+#[derive(Default)]
+pub struct Upgrade {
+    session: Mutex<Option<(Limits, Session)>>,
+}
+
+impl Upgrade {
+    pub fn take(&self) -> Option<(Limits, Session)> {
+        self.session.lock().unwrap().take()
+    }
+}
+
+/**
+ * # 处理WebSocket握手
+ * 检查`Upgrade`、`Connection`、`Sec-WebSocket-Version`和`Sec-WebSocket-Key`，
+ * 成功时返回101，响应发出后`session`在单独的线程中接管连接；
+ * 不是WebSocket请求时返回426，key格式不对返回400。
+ * 浏览器的WebSocket不受同源策略限制并且会带上cookie，所以带`Origin`的握手只接受同源页面
+ * 和[`Limits::origins`]列出的来源，其它返回403；不带`Origin`的不是浏览器发起的，照常接受
+ ```rust,ignore
+ router.get("/live", |req: &HttpRequst| {
+     upgrade(req, Limits::default(), |mut ws| {
+         let _ = ws.send(Message::Text("hello".into()));
+     })
+ });
+ ```
+ */
+pub fn upgrade<F>(req: &HttpRequst, limits: Limits, session: F) -> HttpResponse
+where
+    F: FnOnce(WebSocket) + Send + 'static,
+{
+    let has_token = |name: &str, token: &str| req.header(name)
+        .is_some_and(|v| v.split(',').any(|t| t.trim().eq_ignore_ascii_case(token)));
+    let refuse = |code: &'static str, text: &str| {
+        let mut resp = HttpResponse::new(code, None, Some(ResponseBody::Text(text.to_string())));
+        if code == "426" {
+            resp.set_header("Upgrade", "websocket");
+            resp.set_header("Sec-WebSocket-Version", "13");
+        }
+        resp
+    };
+    if req.method != Method::Get || req.version != Version::V1_1
+        || !has_token("Upgrade", "websocket") || !has_token("Connection", "upgrade")
+    {
+        return refuse("426", "this endpoint only accepts WebSocket connections");
+    }
+    if req.header("Sec-WebSocket-Version") != Some("13") {
+        return refuse("426", "unsupported Sec-WebSocket-Version");
+    }
+    let key = match req.header("Sec-WebSocket-Key") {
+        Some(key) if base64_decode(key).is_some_and(|k| k.len() == 16) => key,
+        _ => return refuse("400", "invalid Sec-WebSocket-Key"),
+    };
+    if let Some(origin) = req.header("Origin") {
+        let allowed = limits.origins.iter().any(|o| o == "*" || o.eq_ignore_ascii_case(origin))
+            || req.header("Host").is_some_and(|host| same_origin(origin, host));
+        if !allowed {
+            return refuse("403", "cross-origin WebSocket connections are not allowed");
+        }
+    }
+    let Some(slot) = req.state::<Upgrade>() else {
+        return refuse("426", "this connection cannot be upgraded");
+    };
+    *slot.session.lock().unwrap() = Some((limits, Box::new(session)));
+
+    let mut resp = HttpResponse::new("101", Some(HashMap::new()), None);
+    resp.set_header("Upgrade", "websocket");
+    resp.set_header("Connection", "Upgrade");
+    resp.set_header("Sec-WebSocket-Accept", &accept_key(key));
+    resp
+}
+
+/**
+ * `Origin`(`scheme://host[:port]`)是否和请求的`Host`是同一个地址，默认端口可以省略
+ */
+fn same_origin(origin: &str, host: &str) -> bool {
+    let Some((scheme, authority)) = origin.split_once("://") else {
+        return false;
+    };
+    let default_port = match scheme.to_ascii_lowercase().as_str() {
+        "http" | "ws" => ":80",
+        "https" | "wss" => ":443",
+        _ => return false,
+    };
+    let normalize = |s: &str| s.strip_suffix(default_port).unwrap_or(s).to_ascii_lowercase();
+    normalize(authority) == normalize(host)
+}
+
+/**
+ * `Sec-WebSocket-Accept`：key拼接[`GUID`]后的SHA-1，再做base64编码
+ */
+fn accept_key(key: &str) -> String {
+    base64_encode(&sha1(format!("{}{}", key, GUID).as_bytes()))
+}
+
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+    let mut msg = data.to_vec();
+    msg.push(0x80);
+    while msg.len() % 64 != 56 {
+        msg.push(0);
+    }
+    msg.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+
+    for block in msg.chunks(64) {
+        let mut w = [0u32; 80];
+        for (i, word) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes(word.try_into().unwrap());
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5a827999),
+                20..=39 => (b ^ c ^ d, 0x6ed9eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6),
+            };
+            let t = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k).wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = t;
+        }
+        for (h, v) in h.iter_mut().zip([a, b, c, d, e]) {
+            *h = h.wrapping_add(v);
+        }
+    }
+    let mut out = [0; 20];
+    for (chunk, v) in out.chunks_mut(4).zip(h) {
+        chunk.copy_from_slice(&v.to_be_bytes());
+    }
+    out
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/**
+ * 解码带填充的标准base64，格式不对时返回`None`
+ */
+fn base64_decode(s: &str) -> Option<Vec<u8>> {
+    let s = s.as_bytes();
+    if !s.len().is_multiple_of(4) {
+        return None;
+    }
+    let mut out = Vec::with_capacity(s.len() / 4 * 3);
+    for (i, chunk) in s.chunks(4).enumerate() {
+        let last = i == s.len() / 4 - 1;
+        let pad = chunk.iter().rev().take_while(|&&b| b == b'=').count();
+        if pad > 2 || (pad > 0 && !last) {
+            return None;
+        }
+        let mut n = 0u32;
+        for &b in &chunk[..4 - pad] {
+            let v = BASE64.iter().position(|&c| c == b)? as u32;
+            n = n << 6 | v;
+        }
+        n <<= 6 * pad as u32;
+        out.extend_from_slice(&n.to_be_bytes()[1..4 - pad]);
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_accept_key() {
+        let hex: String = sha1(b"abc").iter().map(|b| format!("{:02x}", b)).collect();
+        assert_eq!(hex, "a9993e364706816aba3e25717850c26c9cd0d89d");
+        // RFC 6455 1.3中的例子
+        assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+        assert_eq!(base64_decode("dGhlIHNhbXBsZSBub25jZQ==").unwrap(), b"the sample nonce");
+        assert_eq!(base64_encode(b"ab"), "YWI=");
+        assert_eq!(base64_decode("YWI="), Some(b"ab".to_vec()));
+        assert_eq!(base64_decode("YW=I"), None);
+        assert_eq!(base64_decode("YWI"), None);
+    }
+
+    #[test]
+    fn test_frames() {
+        for len in [0, 125, 126, 70_000] {
+            let payload = vec![7u8; len];
+            let encoded = encode_frame(false, BINARY, &payload, Some([1, 2, 3, 4]));
+            let (frame, used) = decode_frame(&encoded, 100_000).unwrap().unwrap();
+            assert_eq!(used, encoded.len());
+            assert_eq!(frame, Frame { fin: false, opcode: BINARY, masked: true, payload });
+            assert!(decode_frame(&encoded[..encoded.len() - 1], 100_000).unwrap().is_none());
+        }
+        let unmasked = encode_frame(true, TEXT, b"hi", None);
+        assert_eq!(unmasked, b"\x81\x02hi");
+        assert!(matches!(decode_frame(b"\xc1\x00", 10), Err(WsError::Protocol(PROTOCOL_ERROR, _))));
+        assert!(matches!(decode_frame(&encode_frame(false, PING, b"", None), 10), Err(WsError::Protocol(PROTOCOL_ERROR, _))));
+        assert!(matches!(decode_frame(&encode_frame(true, TEXT, &[0; 11], None), 10), Err(WsError::Protocol(MESSAGE_TOO_BIG, _))));
+        assert!(matches!(parse_close(&[0x03, 0xed]), Err(WsError::Protocol(PROTOCOL_ERROR, _))));
+        assert_eq!(parse_close(&[0x03, 0xe8, b'o', b'k']).unwrap(), Some(NORMAL_CLOSURE));
+    }
+
+    #[test]
+    fn test_upgrade_handshake() {
+        let raw = "GET /live HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: keep-alive, Upgrade\r\n\
+            Sec-WebSocket-Version: 13\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n";
+        let mut req: HttpRequst = raw.to_string().into();
+        let slot = Arc::new(Upgrade::default());
+        req.extensions.insert(slot.clone());
+        let resp = upgrade(&req, Limits::default(), |_| ());
+        assert_eq!(resp.status_code(), "101");
+        assert_eq!(resp.header("Sec-WebSocket-Accept"), Some("s3pPLMBiTxaQ9kYGzzhZRbK+xOo="));
+        assert!(slot.take().is_some());
+
+        let resp = upgrade(&raw.replace("Version: 13", "Version: 8").into(), Limits::default(), |_| ());
+        assert_eq!((resp.status_code(), resp.header("Sec-WebSocket-Version")), ("426", Some("13")));
+        let resp = upgrade(&raw.replace("Upgrade: websocket\r\n", "").into(), Limits::default(), |_| ());
+        assert_eq!(resp.status_code(), "426");
+        let resp = upgrade(&raw.replace("dGhlIHNhbXBsZSBub25jZQ==", "dGhl").into(), Limits::default(), |_| ());
+        assert_eq!(resp.status_code(), "400");
+
+        // 浏览器从其它站点发起的握手被拒绝，同源和允许的来源照常接受
+        let from = |origin: &str| raw.replace("Host: localhost\r\n", &format!("Host: localhost\r\nOrigin: {}\r\n", origin));
+        let status = |origin: &str, limits: Limits| {
+            let mut req: HttpRequst = from(origin).into();
+            req.extensions.insert(Arc::new(Upgrade::default()));
+            upgrade(&req, limits, |_| ()).status_code()
+        };
+        assert_eq!(status("http://localhost", Limits::default()), "101");
+        assert_eq!(status("https://LOCALHOST:443", Limits::default()), "101");
+        assert_eq!(status("http://localhost:8080", Limits::default()), "403");
+        assert_eq!(status("https://evil.example", Limits::default()), "403");
+        assert_eq!(status("null", Limits::default()), "403");
+        let limits = Limits { origins: vec!["https://app.example".into()], ..Limits::default() };
+        assert_eq!(status("https://app.example", limits), "101");
+        assert_eq!(status("https://evil.example", Limits { origins: vec!["*".into()], ..Limits::default() }), "101");
+    }
+
+    #[test]
+    fn test_session_and_broadcast() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let updates = Arc::new(Broadcast::new());
+        let (ready, subscribed) = mpsc::channel();
+        let hub = updates.clone();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let limits = Limits { max_message_bytes: 16, ..Limits::default() };
+            let mut ws = WebSocket::new(Connection::Tcp(stream), Vec::new(), limits);
+            hub.subscribe(&ws);
+            ready.send(()).unwrap();
+            // 原样返回收到的消息，直到出错或者对端关闭
+            loop {
+                match ws.recv() {
+                    Ok(Some(msg)) => ws.send(msg).unwrap(),
+                    Ok(None) => return None,
+                    Err(e) => return Some(e.to_string()),
+                }
+            }
+        });
+
+        let mut client = TcpStream::connect(addr).unwrap();
+        let mut buf = Vec::new();
+        let mut read_frame = |client: &mut TcpStream| {
+            let mut chunk = [0; 256];
+            loop {
+                if let Some((frame, used)) = decode_frame(&buf, 1024).unwrap() {
+                    buf.drain(..used);
+                    return frame;
+                }
+                let n = client.read(&mut chunk).unwrap();
+                buf.extend_from_slice(&chunk[..n]);
+            }
+        };
+        let mask = Some([9, 8, 7, 6]);
+        // 分片的文本消息，中间夹一个Ping
+        client.write_all(&encode_frame(false, TEXT, b"hel", mask)).unwrap();
+        client.write_all(&encode_frame(true, PING, b"p", mask)).unwrap();
+        client.write_all(&encode_frame(true, CONTINUATION, "lo 世界".as_bytes(), mask)).unwrap();
+        let pong = read_frame(&mut client);
+        assert_eq!((pong.opcode, pong.payload), (PONG, b"p".to_vec()));
+        let echo = read_frame(&mut client);
+        assert_eq!((echo.opcode, echo.payload), (TEXT, "hello 世界".as_bytes().to_vec()));
+
+        subscribed.recv().unwrap();
+        assert_eq!(updates.send(Message::Text("update".into())), 1);
+        let update = read_frame(&mut client);
+        assert_eq!((update.opcode, update.payload), (TEXT, b"update".to_vec()));
+
+        // 超过max_message_bytes时以1009关闭
+        client.write_all(&encode_frame(true, BINARY, &[0; 17], mask)).unwrap();
+        let close = read_frame(&mut client);
+        assert_eq!((close.opcode, &close.payload[..2]), (CLOSE, &MESSAGE_TOO_BIG.to_be_bytes()[..]));
+        assert_eq!(server.join().unwrap().as_deref(), Some("message is too large (1009)"));
+        assert_eq!(updates.send(Message::Text("gone".into())), 0);
+    }
+}
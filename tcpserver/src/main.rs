@@ -12,7 +12,7 @@ fn main() {
         println!("Connection established");
 
         let mut buffer = [0; 1024];
-        stream.read(&mut buffer).unwrap();
-        stream.write(&mut buffer).unwrap();
+        let n = stream.read(&mut buffer).unwrap();
+        stream.write_all(&buffer[..n]).unwrap();
     }
 }